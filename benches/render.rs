@@ -0,0 +1,313 @@
+//! Benchmarks of the render hot paths, run with `cargo bench` or `cargo bench -- <filter>`
+//!
+//! Criterion compares every case to the previous run, kept in `target/criterion`, a named baseline
+//! is saved with `cargo bench -- --save-baseline <name>` and compared to with `--baseline <name>`.
+//! The clock step also prints its large allocations per step, which time alone doesn't show.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{Local, TimeZone};
+use criterion::{criterion_group, criterion_main, Criterion};
+use desktop_background::{
+    clock::MockClock,
+    decode,
+    render::{tint, BackgroundRenderer, ClockHours, ColorizeMode, MaskSource, Scaling},
+    text::{Align, Anchor},
+    tint::ClockColor,
+    Command,
+};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, Rgba, RgbaImage};
+
+/// How long each case is measured for, the slow ones take their 10 samples in any case
+const MEASURE_TIME: Duration = Duration::from_secs(2);
+const SAMPLES: usize = 10;
+
+const RESOLUTIONS: [(&str, u32, u32); 3] = [
+    ("1080p", 1920, 1080),
+    ("1440p", 2560, 1440),
+    ("4k", 3840, 2160),
+];
+const FILTERS: [(&str, FilterType); 5] = [
+    ("nearest", FilterType::Nearest),
+    ("triangle", FilterType::Triangle),
+    ("catmull-rom", FilterType::CatmullRom),
+    ("gaussian", FilterType::Gaussian),
+    ("lanczos3", FilterType::Lanczos3),
+];
+
+/// Allocations at least this large are counted, frames and whole decoded images
+const LARGE_ALLOCATION: usize = 1024 * 1024;
+
+static LARGE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The system allocator, counting large allocations
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() >= LARGE_ALLOCATION {
+            LARGE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.size() >= LARGE_ALLOCATION {
+            LARGE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size >= LARGE_ALLOCATION {
+            LARGE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A deterministic rgba test image with gradients and some structure for the encoder to work on
+fn synthetic_image(width: u32, height: u32, seed: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        let checker = ((x / 32 + y / 32 + seed) % 2) as u8 * 64;
+        Rgba([
+            (x * 255 / width) as u8 ^ checker,
+            (y * 255 / height) as u8,
+            ((x + y + seed * 17) % 256) as u8,
+            255 - checker,
+        ])
+    })
+}
+
+fn encode_png(image: &RgbaImage) -> Vec<u8> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    png
+}
+
+fn bench_tint(criterion: &mut Criterion) {
+    let rainbow = ClockColor::parse("RAINBOW", 720.0).unwrap();
+    for (resolution, width, height) in RESOLUTIONS {
+        let image = synthetic_image(width, height, 0);
+        let mut frame = image.to_vec();
+
+        criterion.bench_function(&format!("tint/fixed/{resolution}"), |bencher| {
+            bencher.iter(|| {
+                frame.copy_from_slice(&image);
+                tint(&mut frame, [0.8, 0.5, 0.2, 1.0], [0, 0, 0]);
+            })
+        });
+        let mut millis = 0;
+        criterion.bench_function(&format!("tint/rainbow/{resolution}"), |bencher| {
+            bencher.iter(|| {
+                millis += 100;
+                frame.copy_from_slice(&image);
+                tint(&mut frame, rainbow.at(millis, millis), [0, 0, 0]);
+            })
+        });
+        criterion.bench_function(&format!("copy/{resolution}"), |bencher| {
+            bencher.iter(|| frame.copy_from_slice(&image))
+        });
+    }
+}
+
+fn bench_decode(criterion: &mut Criterion) {
+    for (resolution, width, height) in RESOLUTIONS {
+        let png = encode_png(&synthetic_image(width, height, 0));
+        criterion.bench_function(&format!("decode/png/{resolution}"), |bencher| {
+            bencher.iter(|| image::load_from_memory_with_format(&png, ImageFormat::Png).unwrap())
+        });
+    }
+}
+
+/// Load a png file through a buffered reader like the image crate does and mapped into memory
+fn bench_load(criterion: &mut Criterion) {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    for (resolution, width, height) in RESOLUTIONS {
+        let path = dir.join(format!("{resolution}.png"));
+        std::fs::write(&path, encode_png(&synthetic_image(width, height, 0))).unwrap();
+        criterion.bench_function(&format!("load/buffered/{resolution}"), |bencher| {
+            bencher.iter(|| image::open(&path).unwrap())
+        });
+        criterion.bench_function(&format!("load/mapped/{resolution}"), |bencher| {
+            bencher.iter(|| decode::open(&path).unwrap())
+        });
+    }
+}
+
+fn bench_resize(criterion: &mut Criterion) {
+    let upscale = DynamicImage::ImageRgba8(synthetic_image(1920, 1080, 0));
+    let downscale = DynamicImage::ImageRgba8(synthetic_image(2560, 1440, 0));
+    for (filter_name, filter) in FILTERS {
+        criterion.bench_function(&format!("resize/{filter_name}/1080p-to-1440p"), |bencher| {
+            bencher.iter(|| upscale.resize_exact(2560, 1440, filter))
+        });
+        criterion.bench_function(&format!("resize/{filter_name}/1440p-to-1080p"), |bencher| {
+            bencher.iter(|| downscale.resize_exact(1920, 1080, filter))
+        });
+    }
+}
+
+/// A tinted 1080p clock over 60 frames in `dir`, rendered once so the read-ahead fills, with its
+/// clock and frame
+///
+/// The frames are generated up front and read back from the page cache, so the time is spent
+/// decoding and rendering rather than waiting for a disk.
+fn clock_renderer(dir: &Path) -> (Arc<MockClock>, BackgroundRenderer, Vec<u8>) {
+    let (width, height) = (1920, 1080);
+    std::fs::create_dir_all(dir.join("frames")).unwrap();
+    for second in 0..60 {
+        let path = dir.join(format!("frames/{second:02}.png"));
+        std::fs::write(path, encode_png(&synthetic_image(width, height, second))).unwrap();
+    }
+
+    let clock = Arc::new(MockClock::new(
+        Local.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+    ));
+    let mut renderer = Command::ClockImage {
+        dir: dir.to_path_buf(),
+        file_template: "frames/%S.png".to_string(),
+        clock_step: 1000,
+        hours: ClockHours::Twelve,
+        hour_dirs: Default::default(),
+        offset: None,
+        timezone: None,
+        clock_color: Some("RAINBOW".to_string()),
+        color_cycle: None,
+        scaling: Scaling::Stretch,
+        margin_color: None,
+        underlay: None,
+        colorize: ColorizeMode::Multiply,
+        mask_source: MaskSource::Alpha,
+        interpolate: None,
+        sheets: false,
+        no_validate: true,
+    }
+    .into_renderer_with_clock(width, height, None, clock.clone())
+    .unwrap();
+
+    let mut frame = vec![0; width as usize * height as usize * 4];
+    renderer.render(&mut frame, width, height).unwrap();
+    (clock, renderer, frame)
+}
+
+/// Advance a tinted 1080p clock by one step, which loads, places and tints one frame, and count
+/// the large allocations of the steps
+///
+/// The clock is only set up if the case passes the filter of the run.
+fn bench_clock_step(criterion: &mut Criterion) {
+    let (width, height) = (1920, 1080);
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let mut clock_step = None;
+    let (mut steps, mut allocations) = (0, 0);
+    criterion.bench_function("clock/step/1080p", |bencher| {
+        let (clock, renderer, frame) = clock_step.get_or_insert_with(|| clock_renderer(dir));
+        bencher.iter_custom(|iterations| {
+            let before = LARGE_ALLOCATIONS.load(Ordering::Relaxed);
+            let start = Instant::now();
+            for _ in 0..iterations {
+                clock.advance(chrono::Duration::seconds(1));
+                renderer.render(frame, width, height).unwrap();
+            }
+            let elapsed = start.elapsed();
+            allocations += LARGE_ALLOCATIONS.load(Ordering::Relaxed) - before;
+            steps += iterations;
+            elapsed
+        })
+    });
+    if steps > 0 {
+        println!(
+            "{name:<24} {per_step:.2} large allocations per step",
+            name = "clock/step/1080p",
+            per_step = allocations as f64 / steps as f64,
+        );
+    }
+}
+
+/// Advance a 4k text clock layered over an image by a minute, which draws the new time and
+/// copies the frame to the front buffer like an output does
+fn bench_text_clock(criterion: &mut Criterion) {
+    let (width, height) = (3840, 2160);
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("background.png");
+    std::fs::write(&path, encode_png(&synthetic_image(width, height, 0))).unwrap();
+
+    let clock = Arc::new(MockClock::new(
+        Local.with_ymd_and_hms(2024, 1, 15, 12, 0, 0).unwrap(),
+    ));
+    let mut renderer = Command::Layer {
+        layers: vec![
+            Command::StaticImage {
+                path,
+                scaling: Scaling::Stretch,
+                margin_color: None,
+                watch: false,
+                span: false,
+            },
+            Command::TextOverlay {
+                template: "{time:%H:%M}".to_string(),
+                font: PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests/data/Cantarell-Regular.ttf"),
+                size: 160.0,
+                color: "F0E0A0".parse().unwrap(),
+                position: Anchor::BottomRight,
+                align: Align::Right,
+                offset_x: 0,
+                offset_y: 0,
+                margin: 64,
+            },
+        ],
+    }
+    .into_renderer_with_clock(width, height, None, clock.clone())
+    .unwrap();
+
+    let mut back = vec![0; width as usize * height as usize * 4];
+    let mut front = back.clone();
+    renderer.render(&mut back, width, height).unwrap();
+    criterion.bench_function("text/layers/4k", |bencher| {
+        bencher.iter(|| {
+            clock.advance(chrono::Duration::minutes(1));
+            let damage = renderer.render_damage(&mut back, width, height).unwrap();
+            damage.copy(&mut front, &back, width);
+        })
+    });
+}
+
+fn config() -> Criterion {
+    Criterion::default()
+        .measurement_time(MEASURE_TIME)
+        .sample_size(SAMPLES)
+}
+
+criterion_group! {
+    name = benches;
+    config = config();
+    targets = bench_tint,
+        bench_decode,
+        bench_load,
+        bench_resize,
+        bench_clock_step,
+        bench_text_clock
+}
+criterion_main!(benches);
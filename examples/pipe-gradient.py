@@ -0,0 +1,35 @@
+#!/usr/bin/env python3
+"""An example frame producer for the pipe command, a gradient scrolling to the right
+
+    desktop-background <socket> pipe "python3 examples/pipe-gradient.py" --fps 10
+
+The daemon runs the command with the frame size in the WIDTH and HEIGHT environment variables
+and reads WIDTH * HEIGHT * 4 bytes of raw rgba pixels per frame from its standard output, row
+by row from the top left. Frames written faster than the frame rate are dropped, so a producer
+should pace itself. Whatever it writes to its standard error ends up in the daemon's log.
+"""
+
+import os
+import sys
+import time
+
+width = int(os.environ["WIDTH"])
+height = int(os.environ["HEIGHT"])
+fps = 10
+
+print(f"producing {width}x{height} frames", file=sys.stderr)
+out = sys.stdout.buffer
+shift = 0
+try:
+    while True:
+        row = bytearray()
+        for x in range(width):
+            value = (x + shift) * 255 // width % 256
+            row += bytes((value, 64, 255 - value, 255))
+        out.write(bytes(row) * height)
+        out.flush()
+        shift = (shift + width // 100 + 1) % width
+        time.sleep(1 / fps)
+except BrokenPipeError:
+    # The daemon stopped reading, eg because another background was set
+    pass
@@ -0,0 +1,87 @@
+//! An example producer of shared frames for the shm command, a bar moving across the screen
+//!
+//! ```sh
+//! cargo run --example shm_producer -- /dev/shm/background 1920 1080 &
+//! desktop-background <socket> shm /dev/shm/background 1920 1080
+//! ```
+//!
+//! The memory layout is described in `desktop_background::shm`.
+
+use std::{
+    fs::OpenOptions,
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use desktop_background::shm::{file_size, HEADER_SIZE, MAGIC, SEQUENCE_OFFSET, VERSION};
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(path), Some(width), Some(height)) = (args.next(), args.next(), args.next()) else {
+        anyhow::bail!("usage: shm_producer <path> <width> <height>");
+    };
+    let (width, height): (u32, u32) = (width.parse()?, height.parse()?);
+
+    let len = file_size(width, height);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)?;
+    file.set_len(len as u64)?;
+    // SAFETY: the file was just sized to `len` bytes and is only written by this process
+    let mapping = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            file.as_raw_fd(),
+            0,
+        )
+    };
+    if mapping == libc::MAP_FAILED {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // SAFETY: the mapping is `len` bytes long and never unmapped
+    let shared = unsafe { std::slice::from_raw_parts_mut(mapping.cast::<u8>(), len) };
+    // SAFETY: the page aligned mapping holds an aligned u64 at the sequence offset
+    let sequence = unsafe {
+        &*mapping
+            .cast::<u8>()
+            .add(SEQUENCE_OFFSET)
+            .cast::<AtomicU64>()
+    };
+
+    for (offset, value) in [(0, MAGIC), (4, VERSION), (8, width), (12, height)] {
+        shared[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+    }
+    sequence.store(0, Ordering::Release);
+
+    let frame_size = width as usize * height as usize * 4;
+    let bar_width = (width / 20).max(1);
+    for next in 1u64.. {
+        // Write the buffer the daemon isn't reading, then publish it
+        let start = HEADER_SIZE + (next % 2) as usize * frame_size;
+        let bar_x = (next as u32 * 4) % width;
+        for (idx, pixel) in shared[start..start + frame_size]
+            .chunks_exact_mut(4)
+            .enumerate()
+        {
+            let x = idx as u32 % width;
+            let in_bar = x.wrapping_sub(bar_x) < bar_width;
+            pixel.copy_from_slice(&if in_bar {
+                [255, 200, 40, 255]
+            } else {
+                [20, 30, (x * 80 / width) as u8, 255]
+            });
+        }
+        sequence.store(next, Ordering::Release);
+        std::thread::sleep(FRAME_INTERVAL);
+    }
+    Ok(())
+}
@@ -0,0 +1,14 @@
+//! Feeds arbitrary bytes to the daemon's receive path, run with `cargo fuzz run request`
+
+#![no_main]
+
+use desktop_background::ipc;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Decoding and validating a request may fail but must never panic or allocate past the limit
+    if let Ok(request) = ipc::read_request(&mut &data[..]) {
+        let _ = request.command.is_background();
+        let _ = request.command.spans();
+    }
+});
@@ -0,0 +1,112 @@
+//! An example renderer plugin filling the background with a color cycling through all hues
+//!
+//! Build it with `cargo build --release` and load it with
+//! `desktop-background plugin target/release/libcolor_cycler.so "period=60"`, where the period
+//! is the number of seconds of one hue rotation.
+//!
+//! The plugin depends on nothing but the C interface described in the `plugin` module of
+//! desktop-background, so it could be written in any language.
+
+use std::ffi::{c_char, c_void, CStr};
+
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+pub static desktop_background_abi_version: u32 = 1;
+
+/// How long one hue rotation takes if the period isn't given
+const DEFAULT_PERIOD_MILLIS: i64 = 60 * 1000;
+
+struct ColorCycler {
+    period_millis: i64,
+    /// The color of the last rendered frame
+    color: Option<[u8; 3]>,
+}
+
+/// Parse parameters like `period=30` into the period in milliseconds
+fn parse_params(params: &str) -> Option<i64> {
+    let mut period_millis = DEFAULT_PERIOD_MILLIS;
+    for param in params.split(',').filter(|param| !param.trim().is_empty()) {
+        match param.trim().split_once('=')? {
+            ("period", seconds) => {
+                let seconds: f64 = seconds.trim().parse().ok()?;
+                if seconds.is_nan() || seconds < 0.001 {
+                    return None;
+                }
+                period_millis = (seconds * 1000.0) as i64;
+            }
+            _ => return None,
+        }
+    }
+    Some(period_millis)
+}
+
+/// The fully saturated color of `hue` in [0, 1)
+fn hue_to_rgb(hue: f64) -> [u8; 3] {
+    let sector = hue * 6.0;
+    let rising = ((sector % 1.0) * 255.0) as u8;
+    let falling = 255 - rising;
+    match sector as u32 {
+        0 => [255, rising, 0],
+        1 => [falling, 255, 0],
+        2 => [0, 255, rising],
+        3 => [0, falling, 255],
+        4 => [rising, 0, 255],
+        _ => [255, 0, falling],
+    }
+}
+
+/// # Safety
+///
+/// `params` has to be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn desktop_background_init(
+    _width: u32,
+    _height: u32,
+    params: *const c_char,
+) -> *mut c_void {
+    let params = CStr::from_ptr(params).to_string_lossy();
+    match parse_params(&params) {
+        Some(period_millis) => Box::into_raw(Box::new(ColorCycler {
+            period_millis,
+            color: None,
+        }))
+        .cast(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `state` has to come from [`desktop_background_init`] and `frame` has to hold
+/// `width * height` rgba pixels.
+#[no_mangle]
+pub unsafe extern "C" fn desktop_background_render(
+    state: *mut c_void,
+    frame: *mut u8,
+    width: u32,
+    height: u32,
+    time_millis: i64,
+) -> i32 {
+    let cycler = &mut *state.cast::<ColorCycler>();
+    let hue = time_millis.rem_euclid(cycler.period_millis) as f64 / cycler.period_millis as f64;
+    let color = hue_to_rgb(hue);
+    if cycler.color == Some(color) {
+        return 0;
+    }
+
+    let frame = std::slice::from_raw_parts_mut(frame, width as usize * height as usize * 4);
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel[..3].copy_from_slice(&color);
+        pixel[3] = 255;
+    }
+    cycler.color = Some(color);
+    1
+}
+
+/// # Safety
+///
+/// `state` has to come from [`desktop_background_init`] and can't be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn desktop_background_destroy(state: *mut c_void) {
+    drop(Box::from_raw(state.cast::<ColorCycler>()));
+}
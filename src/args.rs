@@ -0,0 +1,424 @@
+//! Values of command line options with a format of their own, checked when the command line is
+//! parsed
+//!
+//! Commands hold them in the form the daemon receives them in, so a command sent by another
+//! program is checked like one from the command line when it is deserialized.
+
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::colors::parse_color;
+
+/// The units of durations from the largest to the smallest, with their length in milliseconds
+const DURATION_UNITS: [(&str, u64); 4] = [("h", 3_600_000), ("m", 60_000), ("s", 1000), ("ms", 1)];
+
+/// A color like `#203040`, `navy` or `rgb(32,48,64)`, kept as given to show it the same way
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ColorArg {
+    text: String,
+    rgba: [f32; 4],
+}
+
+impl ColorArg {
+    /// The rgba components from 0 to 1
+    pub fn rgba(&self) -> [f32; 4] {
+        self.rgba
+    }
+
+    /// The 8 bit rgb components of the color, which has to be opaque
+    pub fn rgb8(&self) -> anyhow::Result<[u8; 3]> {
+        let [r, g, b, alpha] = self.rgba;
+        if alpha < 1.0 {
+            anyhow::bail!("the color {text:?} should be opaque", text = self.text);
+        }
+        Ok([r, g, b].map(|c| (c * 255.0).round() as u8))
+    }
+}
+
+impl FromStr for ColorArg {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        Ok(ColorArg {
+            text: string.to_string(),
+            rgba: parse_color(string).map_err(|error| error.to_string())?,
+        })
+    }
+}
+
+impl TryFrom<String> for ColorArg {
+    type Error = String;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl From<ColorArg> for String {
+    fn from(color: ColorArg) -> Self {
+        color.text
+    }
+}
+
+impl Display for ColorArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// A duration like `5s`, `1h30m` or `250ms` in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DurationArg(pub u64);
+
+impl DurationArg {
+    /// The duration as a [`Duration`]
+    pub fn as_duration(self) -> Duration {
+        Duration::from_millis(self.0)
+    }
+}
+
+impl FromStr for DurationArg {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        if string.starts_with(['+', '-']) {
+            return Err(format!(
+                "{string:?} is not a duration like 5s or 1h30m, it can't have a sign"
+            ));
+        }
+        parse_millis(string, string).map(DurationArg)
+    }
+}
+
+impl Display for DurationArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("0s");
+        }
+        let mut rest = self.0;
+        for (unit, millis) in DURATION_UNITS {
+            if rest >= millis {
+                write!(f, "{count}{unit}", count = rest / millis)?;
+                rest %= millis;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A duration which may be negative like `+5m` or `-1h30m`, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SignedDurationArg(pub i64);
+
+impl FromStr for SignedDurationArg {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (sign, rest) = match string.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, string.strip_prefix('+').unwrap_or(string)),
+        };
+        let millis = i64::try_from(parse_millis(rest, string)?)
+            .map_err(|_| format!("the duration {string:?} is too long"))?;
+        Ok(SignedDurationArg(sign * millis))
+    }
+}
+
+impl Display for SignedDurationArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { '-' } else { '+' };
+        write!(f, "{sign}{}", DurationArg(self.0.unsigned_abs()))
+    }
+}
+
+/// Parse the milliseconds of an unsigned duration `string`, which is part of the argument
+/// `whole` named in errors
+///
+/// The units go from hours down to milliseconds, each given once, and the amount of a unit after
+/// a larger one has to be less than one of the larger unit.
+fn parse_millis(string: &str, whole: &str) -> Result<u64, String> {
+    if string.is_empty() {
+        return Err(format!("{whole:?} is not a duration like 5s or 1h30m"));
+    }
+
+    let mut rest = string;
+    let mut millis: u64 = 0;
+    let mut previous: Option<usize> = None;
+    let mut overflowing = None;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!(
+                "{whole:?} is not a duration like 5s or 1h30m, expected a number before {rest:?}"
+            ));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("the duration {whole:?} is too long"))?;
+        rest = &rest[digits..];
+
+        let len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let unit = &rest[..len];
+        rest = &rest[len..];
+        let idx = match unit {
+            "" => {
+                return Err(format!(
+                    "{value} in {whole:?} has no unit, expected h, m, s or ms like {value}s"
+                ))
+            }
+            unit if unit.starts_with(['.', ',']) => {
+                return Err(format!(
+                    "{whole:?} has a fraction, use smaller units instead like 1m30s for 1.5m"
+                ))
+            }
+            unit => DURATION_UNITS
+                .iter()
+                .position(|(name, _)| *name == unit)
+                .ok_or_else(|| {
+                    format!("unknown duration unit {unit:?} in {whole:?}, expected h, m, s or ms")
+                })?,
+        };
+        if let Some(previous) = previous {
+            if idx <= previous {
+                return Err(format!(
+                    "the units of {whole:?} should each be given once, from hours down to \
+                     milliseconds like 1h30m"
+                ));
+            }
+            if value.saturating_mul(DURATION_UNITS[idx].1) >= DURATION_UNITS[idx - 1].1 {
+                overflowing.get_or_insert(format!(
+                    "{value}{unit} in {whole:?} should be less than {limit}{unit} after a larger \
+                     unit",
+                    limit = DURATION_UNITS[idx - 1].1 / DURATION_UNITS[idx].1,
+                ));
+            }
+        }
+        previous = Some(idx);
+
+        millis = value
+            .checked_mul(DURATION_UNITS[idx].1)
+            .and_then(|value| millis.checked_add(value))
+            .ok_or_else(|| format!("the duration {whole:?} is too long"))?;
+    }
+
+    match overflowing {
+        Some(part) => Err(format!(
+            "{part}, did you mean {sign}{millis}?",
+            sign = &whole[..whole.len() - string.len()],
+            millis = DurationArg(millis),
+        )),
+        None => Ok(millis),
+    }
+}
+
+/// A size in pixels like `1920x1080`, neither side 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ResolutionArg {
+    /// The number of pixels in a row
+    pub width: u32,
+    /// The number of rows
+    pub height: u32,
+}
+
+impl FromStr for ResolutionArg {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let (width, height) = string
+            .split_once(['x', 'X'])
+            .ok_or_else(|| format!("{string:?} is not a resolution like 1920x1080"))?;
+        let side = |side: &str, name: &str| {
+            side.parse::<u32>().map_err(|_| {
+                format!("the {name} {side:?} of {string:?} should be a whole number of pixels")
+            })
+        };
+        let (width, height) = (side(width, "width")?, side(height, "height")?);
+        if width == 0 || height == 0 {
+            return Err(format!(
+                "the resolution {string:?} is empty, its width and height should be at least 1"
+            ));
+        }
+        Ok(ResolutionArg { width, height })
+    }
+}
+
+impl TryFrom<String> for ResolutionArg {
+    type Error = String;
+
+    fn try_from(string: String) -> Result<Self, Self::Error> {
+        string.parse()
+    }
+}
+
+impl From<ResolutionArg> for String {
+    fn from(resolution: ResolutionArg) -> Self {
+        resolution.to_string()
+    }
+}
+
+impl Display for ResolutionArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json;
+
+    #[test]
+    fn parses_colors() {
+        let color = "navy".parse::<ColorArg>().unwrap();
+        assert_eq!(color.to_string(), "navy");
+        assert_eq!(color.rgb8().unwrap(), [0, 0, 0x80]);
+        assert_eq!(
+            "#FF804080".parse::<ColorArg>().unwrap().rgba(),
+            [255, 0x80, 0x40, 0x80].map(|c| c as f32 / 255.0)
+        );
+        assert!("#FF804080".parse::<ColorArg>().unwrap().rgb8().is_err());
+        assert_eq!(
+            "rgb(32, 48,64)"
+                .parse::<ColorArg>()
+                .unwrap()
+                .rgb8()
+                .unwrap(),
+            [32, 48, 64]
+        );
+
+        for invalid in ["", "#ggg", "#GG0000", "#FF80", "rgb(1,2)", "12345"] {
+            let error = invalid.parse::<ColorArg>().unwrap_err();
+            assert!(error.contains(&format!("{invalid:?}")), "{error}");
+        }
+        assert!("nvay"
+            .parse::<ColorArg>()
+            .unwrap_err()
+            .contains("did you mean navy?"));
+    }
+
+    #[test]
+    fn parses_durations() {
+        let parse = |string: &str| string.parse::<DurationArg>().map(|duration| duration.0);
+        assert_eq!(parse("5s"), Ok(5000));
+        assert_eq!(parse("250ms"), Ok(250));
+        assert_eq!(parse("1h30m"), Ok(90 * 60 * 1000));
+        assert_eq!(parse("90m"), Ok(90 * 60 * 1000));
+        assert_eq!(parse("1m59s999ms"), Ok(119_999));
+        assert_eq!(parse("0s"), Ok(0));
+        assert_eq!(parse("007s"), Ok(7000));
+
+        let error = |string: &str| parse(string).unwrap_err();
+        assert_eq!(
+            error("1h90m"),
+            "90m in \"1h90m\" should be less than 60m after a larger unit, did you mean 2h30m?"
+        );
+        assert!(error("1m60s").contains("did you mean 2m?"));
+        assert!(error("1s1000ms").contains("did you mean 2s?"));
+        assert!(error("5").contains("no unit"));
+        assert!(error("1h30").contains("30 in \"1h30\" has no unit"));
+        assert!(error("1.5h").contains("fraction"));
+        assert!(error("5d").contains("unknown duration unit \"d\""));
+        assert!(error("5 s").contains("unknown duration unit \" s\""));
+        assert!(error("30s1m").contains("from hours down"));
+        assert!(error("1m1m").contains("from hours down"));
+        assert!(error("h").contains("expected a number"));
+        assert!(error("").contains("not a duration"));
+        assert!(error("+5s").contains("sign"));
+        assert!(error("-5s").contains("sign"));
+        assert!(error("99999999999999999999ms").contains("too long"));
+        assert!(error("9999999999999999h").contains("too long"));
+    }
+
+    #[test]
+    fn parses_signed_durations() {
+        let parse = |string: &str| {
+            string
+                .parse::<SignedDurationArg>()
+                .map(|duration| duration.0)
+        };
+        assert_eq!(parse("+5m"), Ok(5 * 60 * 1000));
+        assert_eq!(parse("5m"), Ok(5 * 60 * 1000));
+        assert_eq!(parse("-1h30m"), Ok(-90 * 60 * 1000));
+        assert_eq!(parse("-0s"), Ok(0));
+        assert_eq!(
+            parse("-1h90m").unwrap_err(),
+            "90m in \"-1h90m\" should be less than 60m after a larger unit, did you mean -2h30m?"
+        );
+        for invalid in ["", "-", "+", "--5m", "+-5m", "9223372036854775808ms"] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn formats_durations_which_parse_back() {
+        assert_eq!(DurationArg(0).to_string(), "0s");
+        assert_eq!(DurationArg(5_400_000).to_string(), "1h30m");
+        assert_eq!(DurationArg(3_600_001).to_string(), "1h1ms");
+        assert_eq!(DurationArg(100 * 3_600_000).to_string(), "100h");
+        assert_eq!(SignedDurationArg(-90_000).to_string(), "-1m30s");
+        assert_eq!(SignedDurationArg(0).to_string(), "+0s");
+        for millis in [0, 1, 999, 1000, 59_999, 60_000, 86_399_999, u64::MAX] {
+            let duration = DurationArg(millis);
+            assert_eq!(duration.to_string().parse(), Ok(duration));
+        }
+        for millis in [i64::MIN + 1, -1, 0, 1, i64::MAX] {
+            let duration = SignedDurationArg(millis);
+            assert_eq!(duration.to_string().parse(), Ok(duration));
+        }
+    }
+
+    #[test]
+    fn parses_resolutions() {
+        let parse = |string: &str| {
+            string
+                .parse::<ResolutionArg>()
+                .map(|resolution| (resolution.width, resolution.height))
+        };
+        assert_eq!(parse("1920x1080"), Ok((1920, 1080)));
+        assert_eq!(parse("1X1"), Ok((1, 1)));
+        assert_eq!(
+            parse("0x0").unwrap_err(),
+            "the resolution \"0x0\" is empty, its width and height should be at least 1"
+        );
+        assert!(parse("1920x0").unwrap_err().contains("empty"));
+        assert!(parse("1920").unwrap_err().contains("not a resolution"));
+        assert!(parse("axb").unwrap_err().contains("the width \"a\""));
+        assert!(parse("1920x-1").unwrap_err().contains("the height \"-1\""));
+        assert!(parse("1920x1080x2").unwrap_err().contains("height"));
+        assert!(parse(" 1920x1080").is_err());
+        assert!(parse("99999999999x1").is_err());
+        assert_eq!(
+            ResolutionArg {
+                width: 800,
+                height: 600
+            }
+            .to_string(),
+            "800x600"
+        );
+    }
+
+    #[test]
+    fn checks_values_sent_by_other_programs() {
+        fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, json::Error> {
+            json::from_value(json::parse(json)?)
+        }
+        let color: ColorArg = from_json("\"navy\"").unwrap();
+        assert_eq!(json::to_string(&color).unwrap().trim(), "\"navy\"");
+        assert!(from_json::<ColorArg>("\"#ggg\"").is_err());
+        assert_eq!(from_json::<DurationArg>("5000").unwrap(), DurationArg(5000));
+        assert_eq!(
+            json::to_string(&SignedDurationArg(-5)).unwrap().trim(),
+            "-5"
+        );
+        assert!(from_json::<ResolutionArg>("\"0x0\"").is_err());
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    io::IsTerminal,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use chrono::Local;
+use image::RgbaImage;
+use rayon::prelude::*;
+
+use crate::{
+    clock::MockClock,
+    command::{Command, WarmArgs},
+    decode,
+    pool::BufferPool,
+    render::{BackgroundRenderer, FrameLayout},
+    source::{FrameSource, Warmed},
+    template::FrameTemplate,
+};
+
+/// The start of every cached frame, followed by its width and height as little endian u32
+const MAGIC: &[u8; 8] = b"DBFRAME1";
+const HEADER_LEN: usize = MAGIC.len() + 8;
+/// How often the progress bar is drawn again
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_WIDTH: usize = 40;
+
+/// Tells apart the files of workers writing at the same time
+static PARTIAL_FILES: AtomicU64 = AtomicU64::new(0);
+
+/// The directory of cached clock frames, in `$XDG_CACHE_HOME` or `~/.cache`
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache_home.join("desktop-background/frames"))
+}
+
+/// Mix `bytes` into the 64 bit FNV-1a hash `hash`, stable across builds unlike the std hasher
+pub fn fnv(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// The start of an FNV-1a hash
+pub const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// Clock frames decoded and placed into frames of one size before, by the `warm-cache` command
+///
+/// A cached frame is looked up by the file it was decoded from, identified by its device, inode,
+/// size and modification time, and by the size and layout it was placed with. Changing a frame
+/// file, the resolution or the scaling, margins and underlay of the clock misses the cache.
+pub struct FrameCache {
+    dir: PathBuf,
+    /// The fingerprint of the layout the frames are placed with, taken on the first lookup
+    layout: Option<u64>,
+}
+
+impl FrameCache {
+    /// The cache in `dir`
+    pub fn at(dir: PathBuf) -> Self {
+        FrameCache { dir, layout: None }
+    }
+
+    /// The cache in the default directory, if frames have been cached there
+    pub fn open() -> Option<Self> {
+        cache_dir().filter(|dir| dir.is_dir()).map(FrameCache::at)
+    }
+
+    /// The file the frame decoded from `path` is cached in, when placed with `layout` into a
+    /// `width` x `height` frame
+    ///
+    /// Shared by the lookup of the daemon and the cache warming so both agree on the keys.
+    fn entry(
+        &mut self,
+        path: &Path,
+        layout: &FrameLayout,
+        width: u32,
+        height: u32,
+    ) -> std::io::Result<PathBuf> {
+        let metadata = std::fs::metadata(path)?;
+        let layout = *self.layout.get_or_insert_with(|| layout.fingerprint());
+        let key = [
+            metadata.dev(),
+            metadata.ino(),
+            metadata.size(),
+            metadata.mtime() as u64,
+            metadata.mtime_nsec() as u64,
+            u64::from(width),
+            u64::from(height),
+            layout,
+        ]
+        .iter()
+        .fold(FNV_OFFSET, |hash, value| fnv(hash, &value.to_le_bytes()));
+        Ok(self.dir.join(format!("{key:016x}.frame")))
+    }
+
+    /// The cached frame decoded from `path`, if there is a complete one
+    pub fn lookup(
+        &mut self,
+        path: &Path,
+        layout: &FrameLayout,
+        width: u32,
+        height: u32,
+        pool: &mut BufferPool,
+    ) -> Option<RgbaImage> {
+        let entry = self.entry(path, layout, width, height).ok()?;
+        let bytes = decode::read(&entry).ok()?;
+        let header = bytes.get(..HEADER_LEN)?;
+        let size =
+            |offset: usize| u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap());
+        if &header[..MAGIC.len()] != MAGIC
+            || (size(MAGIC.len()), size(MAGIC.len() + 4)) != (width, height)
+        {
+            return None;
+        }
+        let pixels = bytes.get(HEADER_LEN..)?;
+        if pixels.len() != width as usize * height as usize * 4 {
+            return None;
+        }
+        let mut frame = pool.take(pixels.len());
+        frame.copy_from_slice(pixels);
+        RgbaImage::from_raw(width, height, frame)
+    }
+
+    /// Whether the frame decoded from `path` is cached
+    pub fn contains(
+        &mut self,
+        path: &Path,
+        layout: &FrameLayout,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<bool> {
+        let entry = self
+            .entry(path, layout, width, height)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        Ok(entry.is_file())
+    }
+
+    /// Cache `frame` decoded from `path`
+    ///
+    /// The frame is written to a partial file which is renamed once it is complete, so an
+    /// interrupted write never leaves a frame which can be looked up.
+    pub fn store(
+        &mut self,
+        path: &Path,
+        layout: &FrameLayout,
+        frame: &RgbaImage,
+    ) -> anyhow::Result<()> {
+        let (width, height) = frame.dimensions();
+        let entry = self
+            .entry(path, layout, width, height)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        let partial = entry.with_extension(format!(
+            "{pid}-{count}.partial",
+            pid = std::process::id(),
+            count = PARTIAL_FILES.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + frame.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&width.to_le_bytes());
+        bytes.extend_from_slice(&height.to_le_bytes());
+        bytes.extend_from_slice(frame);
+        std::fs::write(&partial, bytes)
+            .with_context(|| format!("could not write {}", partial.display()))?;
+        std::fs::rename(&partial, &entry)
+            .with_context(|| format!("could not write {}", entry.display()))
+    }
+
+    /// Remove the partial files left by interrupted warming
+    fn remove_partial(&self) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "partial")
+            {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// What warming the cache did
+pub struct WarmReport {
+    /// The clock directory whose frames were cached
+    pub dir: PathBuf,
+    /// How many frames were decoded and stored
+    pub stored: usize,
+    /// How many frames were cached already
+    pub cached: usize,
+    /// Animated files, which are played rather than cached
+    pub animated: usize,
+    /// The frames which are missing or can't be decoded
+    pub failed: Vec<(u32, String)>,
+}
+
+/// A progress bar on stderr, drawn if it is a terminal
+pub(crate) struct Progress {
+    total: usize,
+    done: AtomicUsize,
+    drawn: Mutex<Instant>,
+    terminal: bool,
+}
+
+impl Progress {
+    pub(crate) fn new(total: usize) -> Self {
+        Progress {
+            total,
+            done: AtomicUsize::new(0),
+            drawn: Mutex::new(Instant::now()),
+            terminal: std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub(crate) fn advance(&self) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.terminal {
+            return;
+        }
+        let mut drawn = self.drawn.lock().unwrap();
+        if done < self.total && drawn.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        *drawn = Instant::now();
+        let filled = done * PROGRESS_WIDTH / self.total.max(1);
+        eprint!(
+            "\r[{bar:<PROGRESS_WIDTH$}] {done}/{total}",
+            bar = "#".repeat(filled),
+            total = self.total
+        );
+        if done == self.total {
+            eprintln!();
+        }
+    }
+}
+
+/// Decode every frame of the clock of `args` and place it like the daemon would at the given
+/// resolution, storing the frames in the cache the daemon looks them up in
+///
+/// Frames cached before are skipped, so an interrupted warming continues where it stopped.
+pub fn warm(args: WarmArgs) -> anyhow::Result<WarmReport> {
+    let WarmArgs {
+        width,
+        height,
+        background,
+        jobs,
+    } = args;
+    let Command::ClockImage {
+        dir: frames_dir,
+        file_template,
+        hours,
+        hour_dirs,
+        sheets,
+        ..
+    } = &*background
+    else {
+        bail!("only the frames of clock images can be cached");
+    };
+    if *sheets {
+        bail!("frames of sprite sheets are cut from the sheet, they can't be cached");
+    }
+
+    // Created like the daemon creates it, so the frames are placed the same way
+    let clock = std::sync::Arc::new(MockClock::new(Local::now()));
+    let (frames_dir, template) = (
+        frames_dir.clone(),
+        FrameTemplate::load(frames_dir, file_template, hour_dirs, *hours)?,
+    );
+    let BackgroundRenderer::ClockImage {
+        clock_step,
+        cycle,
+        layout,
+        ..
+    } = background.into_renderer_with_clock(width, height, None, clock)?
+    else {
+        unreachable!("a clock image command creates a clock image renderer");
+    };
+
+    let dir = cache_dir().context("neither XDG_CACHE_HOME nor HOME is set")?;
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("could not create {dir}", dir = dir.display()))?;
+    let cache = FrameCache::at(dir.clone());
+    cache.remove_partial();
+
+    let frames: Vec<u32> = template
+        .frames(&frames_dir, clock_step, cycle)
+        .into_iter()
+        .map(|(millis, _)| millis)
+        .collect();
+    eprintln!(
+        "caching {count} frames of {width}x{height} pixels, up to {size} MiB, in {dir}",
+        count = frames.len(),
+        size = frames.len() as u64 * u64::from(width) * u64::from(height) * 4 / (1 << 20),
+        dir = dir.display(),
+    );
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()
+        .context("could not start the workers")?;
+    let progress = Progress::new(frames.len());
+    let results: Vec<(u32, anyhow::Result<Warmed>)> = pool.install(|| {
+        frames
+            .par_iter()
+            .map_init(
+                || {
+                    let source = FrameSource::new(frames_dir.clone(), template.clone(), false)
+                        .with_cache(Some(FrameCache::at(dir.clone())));
+                    (source, layout.clone())
+                },
+                |(source, layout), &millis| {
+                    let warmed = source.warm(millis, layout, width, height);
+                    progress.advance();
+                    (millis, warmed)
+                },
+            )
+            .collect()
+    });
+
+    let mut report = WarmReport {
+        dir,
+        stored: 0,
+        cached: 0,
+        animated: 0,
+        failed: Vec::new(),
+    };
+    for (millis, warmed) in results {
+        match warmed {
+            Ok(Warmed::Stored) => report.stored += 1,
+            Ok(Warmed::Cached) => report.cached += 1,
+            Ok(Warmed::Animated) => report.animated += 1,
+            Err(error) => report.failed.push((millis, format!("{error:#}"))),
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::{render::Scaling, template::HourDirs};
+
+    #[test]
+    fn looks_up_warmed_frames() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("frames/0")).unwrap();
+        for (second, color) in [(0, [255, 0, 0, 255]), (1, [0, 0, 255, 128])] {
+            RgbaImage::from_pixel(8, 6, Rgba(color))
+                .save(dir.join(format!("frames/0/{second:02}.png")))
+                .unwrap();
+        }
+        let source = || {
+            FrameSource::new(
+                dir.join("frames"),
+                FrameTemplate::parse("%S.png", &HourDirs::default()).unwrap(),
+                false,
+            )
+            .with_cache(Some(FrameCache::at(dir.join("cache"))))
+        };
+        std::fs::create_dir_all(dir.join("cache")).unwrap();
+        let layout = || FrameLayout::with_color(Scaling::Fit, [0, 40, 0], 20, 10);
+
+        let mut warming = source();
+        for millis in [0, 1000] {
+            assert!(matches!(
+                warming.warm(millis, &mut layout(), 20, 10).unwrap(),
+                Warmed::Stored
+            ));
+            assert!(matches!(
+                warming.warm(millis, &mut layout(), 20, 10).unwrap(),
+                Warmed::Cached
+            ));
+        }
+        assert!(warming.warm(2000, &mut layout(), 20, 10).is_err());
+
+        let path = dir.join("frames/0/01.png");
+        let decoded = layout().place(&image::open(&path).unwrap(), 20, 10);
+        let mut cache = FrameCache::at(dir.join("cache"));
+        assert_eq!(
+            cache.lookup(&path, &layout(), 20, 10, &mut BufferPool::default()),
+            Some(decoded.clone())
+        );
+        assert_eq!(source().load(1000, &mut layout(), 20, 10).unwrap(), decoded);
+        // Other sizes and layouts miss
+        assert_eq!(
+            cache.lookup(&path, &layout(), 20, 11, &mut BufferPool::default()),
+            None
+        );
+        let other = FrameLayout::with_color(Scaling::Fit, [0, 0, 0], 20, 10);
+        assert_eq!(
+            FrameCache::at(dir.join("cache")).lookup(
+                &path,
+                &other,
+                20,
+                10,
+                &mut BufferPool::default()
+            ),
+            None
+        );
+
+        // A truncated entry is ignored
+        let entry = cache.entry(&path, &layout(), 20, 10).unwrap();
+        let bytes = std::fs::read(&entry).unwrap();
+        std::fs::write(&entry, &bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(
+            cache.lookup(&path, &layout(), 20, 10, &mut BufferPool::default()),
+            None
+        );
+    }
+}
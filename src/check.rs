@@ -0,0 +1,194 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use rayon::prelude::*;
+
+use crate::{command::CheckArgs, decode, render::validate_clock_step, template::FrameTemplate};
+
+/// How many paths of each kind of problem are listed in the summary
+const MAX_LISTED: usize = 20;
+
+/// What checking a clock directory found
+pub struct CheckReport {
+    /// The clock directory checked
+    pub dir: PathBuf,
+    /// How many frame files the clock loads
+    pub expected: usize,
+    /// Frame files the clock loads which don't exist
+    pub missing: Vec<PathBuf>,
+    /// Frame files without any content, usually left by an interrupted render
+    pub empty: Vec<PathBuf>,
+    /// Frame files whose image header can't be read, with the error
+    pub corrupt: Vec<(PathBuf, String)>,
+    /// How many of the frames have each size
+    pub sizes: BTreeMap<(u32, u32), usize>,
+}
+
+/// What was found at one frame path
+enum Frame {
+    Missing,
+    Empty,
+    Corrupt(String),
+    Size(u32, u32),
+}
+
+impl CheckReport {
+    /// Whether a clock image can show every frame, frames of different sizes are placed one by
+    /// one and don't keep it from being shown
+    pub fn is_usable(&self) -> bool {
+        self.missing.is_empty() && self.empty.is_empty() && self.corrupt.is_empty()
+    }
+
+    /// A summary listing the first few paths of every problem
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!(
+            "{expected} frames expected in {dir}",
+            expected = self.expected,
+            dir = self.dir.display()
+        )];
+        let mut list = |kind: &str, paths: Vec<String>| {
+            lines.push(format!("{kind}: {count}", count = paths.len()));
+            lines.extend(
+                paths
+                    .iter()
+                    .take(MAX_LISTED)
+                    .map(|path| format!("  {path}")),
+            );
+            if paths.len() > MAX_LISTED {
+                lines.push(format!(
+                    "  and {more} more",
+                    more = paths.len() - MAX_LISTED
+                ));
+            }
+        };
+        let display = |paths: &[PathBuf]| {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect()
+        };
+        list("missing", display(&self.missing));
+        list("empty", display(&self.empty));
+        list(
+            "undecodable",
+            self.corrupt
+                .iter()
+                .map(|(path, error)| format!("{path}: {error}", path = path.display()))
+                .collect(),
+        );
+        let sizes: Vec<String> = self
+            .sizes
+            .iter()
+            .map(|((width, height), count)| format!("{width}x{height} ({count} frames)"))
+            .collect();
+        match sizes.len() {
+            0 => {}
+            1 => lines.push(format!("size: {size}", size = sizes[0])),
+            _ => lines.push(format!(
+                "inconsistent sizes: {sizes}",
+                sizes = sizes.join(", ")
+            )),
+        }
+        lines.join("\n")
+    }
+}
+
+/// Every frame file the clock of `args` loads in one cycle, in the order it loads them, through
+/// the same template a clock image uses
+pub(crate) fn expected_frames(args: &CheckArgs) -> anyhow::Result<Vec<PathBuf>> {
+    validate_clock_step(args.clock_step)?;
+    let template =
+        FrameTemplate::load(&args.dir, &args.file_template, &args.hour_dirs, args.hours)?;
+    Ok(template
+        .frames(&args.dir, args.clock_step, args.hours.cycle_millis())
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Look up every frame file the clock of `args` loads in one cycle and read the header of each one
+pub fn check(args: CheckArgs) -> anyhow::Result<CheckReport> {
+    let frames = expected_frames(&args)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()
+        .context("could not start the workers")?;
+    let found: Vec<Frame> = pool.install(|| {
+        frames
+            .par_iter()
+            .map(|path| match std::fs::metadata(path) {
+                Err(_) => Frame::Missing,
+                Ok(metadata) if metadata.len() == 0 => Frame::Empty,
+                Ok(_) => match decode::probe(path) {
+                    Ok((width, height)) => Frame::Size(width, height),
+                    Err(error) => Frame::Corrupt(format!("{error:#}")),
+                },
+            })
+            .collect()
+    });
+
+    let mut report = CheckReport {
+        dir: args.dir,
+        expected: frames.len(),
+        missing: Vec::new(),
+        empty: Vec::new(),
+        corrupt: Vec::new(),
+        sizes: BTreeMap::new(),
+    };
+    for (path, frame) in frames.into_iter().zip(found) {
+        match frame {
+            Frame::Missing => report.missing.push(path),
+            Frame::Empty => report.empty.push(path),
+            Frame::Corrupt(error) => report.corrupt.push((path, error)),
+            Frame::Size(width, height) => *report.sizes.entry((width, height)).or_default() += 1,
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::{render::ClockHours, template::HourDirs};
+
+    #[test]
+    fn finds_missing_empty_and_corrupt_frames() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for hour in 0..12 {
+            std::fs::create_dir_all(dir.join(hour.to_string())).unwrap();
+            let size = if hour == 11 { 6 } else { 4 };
+            RgbaImage::from_pixel(size, 4, Rgba([0, 0, 0, 255]))
+                .save(dir.join(format!("{hour}/00.png")))
+                .unwrap();
+        }
+        std::fs::remove_file(dir.join("3/00.png")).unwrap();
+        std::fs::write(dir.join("4/00.png"), []).unwrap();
+        std::fs::write(dir.join("5/00.png"), b"not a png").unwrap();
+
+        let report = check(CheckArgs {
+            dir: dir.to_path_buf(),
+            file_template: "%M.png".to_string(),
+            clock_step: 1000 * 60 * 30,
+            hours: ClockHours::Twelve,
+            hour_dirs: HourDirs::default(),
+            jobs: Some(2),
+        })
+        .unwrap();
+        assert!(!report.is_usable());
+        // Every hour has a frame at minute 0 and a missing one at minute 30
+        assert_eq!(report.expected, 24);
+        assert_eq!(report.missing.len(), 13);
+        assert_eq!(report.missing[0], dir.join("0/30.png"));
+        assert_eq!(report.empty, [dir.join("4/00.png")]);
+        assert_eq!(report.corrupt.len(), 1);
+        assert_eq!(report.corrupt[0].0, dir.join("5/00.png"));
+        assert_eq!(report.sizes, BTreeMap::from([((4, 4), 8), ((6, 4), 1)]));
+        assert!(report
+            .summary()
+            .contains("inconsistent sizes: 4x4 (8 frames), 6x4 (1 frames)"));
+    }
+}
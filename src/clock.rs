@@ -0,0 +1,48 @@
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, Local};
+
+/// Where renderers read the current time from
+pub trait Clock {
+    /// The current time
+    fn now(&self) -> DateTime<Local>;
+}
+
+/// The time of the system, the clock of the daemon
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// A clock which stands still until it is set or advanced, for tests and previews
+pub struct MockClock {
+    now: Mutex<DateTime<Local>>,
+}
+
+impl MockClock {
+    /// A clock standing at `now`
+    pub fn new(now: DateTime<Local>) -> Self {
+        MockClock {
+            now: Mutex::new(now),
+        }
+    }
+
+    /// Put the clock at `now`
+    pub fn set(&self, now: DateTime<Local>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    /// Move the clock by `duration`, backwards if it is negative
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Local> {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,330 @@
+use anyhow::bail;
+
+/// The formats a color can be given in
+pub const FORMAT: &str = "< <css name> | #RGB | #RRGGBB | #RRGGBBAA | rgb(<r>,<g>,<b>) >";
+
+/// The most edits a misspelled color name may be away from the name suggested instead
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// The named colors of CSS, sorted by name
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xF0F8FF),
+    ("antiquewhite", 0xFAEBD7),
+    ("aqua", 0x00FFFF),
+    ("aquamarine", 0x7FFFD4),
+    ("azure", 0xF0FFFF),
+    ("beige", 0xF5F5DC),
+    ("bisque", 0xFFE4C4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xFFEBCD),
+    ("blue", 0x0000FF),
+    ("blueviolet", 0x8A2BE2),
+    ("brown", 0xA52A2A),
+    ("burlywood", 0xDEB887),
+    ("cadetblue", 0x5F9EA0),
+    ("chartreuse", 0x7FFF00),
+    ("chocolate", 0xD2691E),
+    ("coral", 0xFF7F50),
+    ("cornflowerblue", 0x6495ED),
+    ("cornsilk", 0xFFF8DC),
+    ("crimson", 0xDC143C),
+    ("cyan", 0x00FFFF),
+    ("darkblue", 0x00008B),
+    ("darkcyan", 0x008B8B),
+    ("darkgoldenrod", 0xB8860B),
+    ("darkgray", 0xA9A9A9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xA9A9A9),
+    ("darkkhaki", 0xBDB76B),
+    ("darkmagenta", 0x8B008B),
+    ("darkolivegreen", 0x556B2F),
+    ("darkorange", 0xFF8C00),
+    ("darkorchid", 0x9932CC),
+    ("darkred", 0x8B0000),
+    ("darksalmon", 0xE9967A),
+    ("darkseagreen", 0x8FBC8F),
+    ("darkslateblue", 0x483D8B),
+    ("darkslategray", 0x2F4F4F),
+    ("darkslategrey", 0x2F4F4F),
+    ("darkturquoise", 0x00CED1),
+    ("darkviolet", 0x9400D3),
+    ("deeppink", 0xFF1493),
+    ("deepskyblue", 0x00BFFF),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1E90FF),
+    ("firebrick", 0xB22222),
+    ("floralwhite", 0xFFFAF0),
+    ("forestgreen", 0x228B22),
+    ("fuchsia", 0xFF00FF),
+    ("gainsboro", 0xDCDCDC),
+    ("ghostwhite", 0xF8F8FF),
+    ("gold", 0xFFD700),
+    ("goldenrod", 0xDAA520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xADFF2F),
+    ("grey", 0x808080),
+    ("honeydew", 0xF0FFF0),
+    ("hotpink", 0xFF69B4),
+    ("indianred", 0xCD5C5C),
+    ("indigo", 0x4B0082),
+    ("ivory", 0xFFFFF0),
+    ("khaki", 0xF0E68C),
+    ("lavender", 0xE6E6FA),
+    ("lavenderblush", 0xFFF0F5),
+    ("lawngreen", 0x7CFC00),
+    ("lemonchiffon", 0xFFFACD),
+    ("lightblue", 0xADD8E6),
+    ("lightcoral", 0xF08080),
+    ("lightcyan", 0xE0FFFF),
+    ("lightgoldenrodyellow", 0xFAFAD2),
+    ("lightgray", 0xD3D3D3),
+    ("lightgreen", 0x90EE90),
+    ("lightgrey", 0xD3D3D3),
+    ("lightpink", 0xFFB6C1),
+    ("lightsalmon", 0xFFA07A),
+    ("lightseagreen", 0x20B2AA),
+    ("lightskyblue", 0x87CEFA),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xB0C4DE),
+    ("lightyellow", 0xFFFFE0),
+    ("lime", 0x00FF00),
+    ("limegreen", 0x32CD32),
+    ("linen", 0xFAF0E6),
+    ("magenta", 0xFF00FF),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66CDAA),
+    ("mediumblue", 0x0000CD),
+    ("mediumorchid", 0xBA55D3),
+    ("mediumpurple", 0x9370DB),
+    ("mediumseagreen", 0x3CB371),
+    ("mediumslateblue", 0x7B68EE),
+    ("mediumspringgreen", 0x00FA9A),
+    ("mediumturquoise", 0x48D1CC),
+    ("mediumvioletred", 0xC71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xF5FFFA),
+    ("mistyrose", 0xFFE4E1),
+    ("moccasin", 0xFFE4B5),
+    ("navajowhite", 0xFFDEAD),
+    ("navy", 0x000080),
+    ("oldlace", 0xFDF5E6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6B8E23),
+    ("orange", 0xFFA500),
+    ("orangered", 0xFF4500),
+    ("orchid", 0xDA70D6),
+    ("palegoldenrod", 0xEEE8AA),
+    ("palegreen", 0x98FB98),
+    ("paleturquoise", 0xAFEEEE),
+    ("palevioletred", 0xDB7093),
+    ("papayawhip", 0xFFEFD5),
+    ("peachpuff", 0xFFDAB9),
+    ("peru", 0xCD853F),
+    ("pink", 0xFFC0CB),
+    ("plum", 0xDDA0DD),
+    ("powderblue", 0xB0E0E6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xFF0000),
+    ("rosybrown", 0xBC8F8F),
+    ("royalblue", 0x4169E1),
+    ("saddlebrown", 0x8B4513),
+    ("salmon", 0xFA8072),
+    ("sandybrown", 0xF4A460),
+    ("seagreen", 0x2E8B57),
+    ("seashell", 0xFFF5EE),
+    ("sienna", 0xA0522D),
+    ("silver", 0xC0C0C0),
+    ("skyblue", 0x87CEEB),
+    ("slateblue", 0x6A5ACD),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xFFFAFA),
+    ("springgreen", 0x00FF7F),
+    ("steelblue", 0x4682B4),
+    ("tan", 0xD2B48C),
+    ("teal", 0x008080),
+    ("thistle", 0xD8BFD8),
+    ("tomato", 0xFF6347),
+    ("turquoise", 0x40E0D0),
+    ("violet", 0xEE82EE),
+    ("wheat", 0xF5DEB3),
+    ("white", 0xFFFFFF),
+    ("whitesmoke", 0xF5F5F5),
+    ("yellow", 0xFFFF00),
+    ("yellowgreen", 0x9ACD32),
+];
+
+/// Parse a color of the format [`FORMAT`] into rgba components from 0 to 1
+///
+/// Names are matched ignoring case and `transparent` is transparent black. Hex colors may leave
+/// out the `#` like `FF8040`.
+pub fn parse_color(string: &str) -> anyhow::Result<[f32; 4]> {
+    let trimmed = string.trim();
+    if let Some(components) = trimmed
+        .strip_prefix("rgb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        return parse_rgb_function(components)
+            .ok_or_else(|| anyhow::anyhow!("{string:?} should be like rgb(255,128,0)"));
+    }
+    let hex_error = || {
+        anyhow::anyhow!(
+            "{string:?} should have 3, 6 or 8 hex digits like #F80, #FF8000 or #FF800080"
+        )
+    };
+    if let Some(hex) = trimmed.strip_prefix('#') {
+        return parse_hex(hex).ok_or_else(hex_error);
+    }
+    // Without a # only the longer forms, which are no names
+    if matches!(trimmed.len(), 6 | 8) && trimmed.chars().all(|c| c.is_ascii_hexdigit()) {
+        return parse_hex(trimmed).ok_or_else(hex_error);
+    }
+    if trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(hex_error());
+    }
+
+    let name = trimmed.to_ascii_lowercase();
+    if name == "transparent" {
+        return Ok([0.0; 4]);
+    }
+    if let Ok(idx) = NAMED_COLORS.binary_search_by_key(&name.as_str(), |(name, _)| name) {
+        let [_, r, g, b] = NAMED_COLORS[idx].1.to_be_bytes();
+        return Ok([r, g, b, 255].map(|c| c as f32 / 255.0));
+    }
+    match nearest_name(&name) {
+        Some(suggestion) => bail!("unknown color {string:?}, did you mean {suggestion}?"),
+        None => bail!("{string:?} is not a color, expected {FORMAT}"),
+    }
+}
+
+/// Parse a color like [`parse_color`] which has to be opaque, into rgb components from 0 to 1
+pub fn parse_opaque_color(string: &str) -> anyhow::Result<[f32; 3]> {
+    let [r, g, b, alpha] = parse_color(string)?;
+    if alpha < 1.0 {
+        bail!("the color {string:?} should be opaque");
+    }
+    Ok([r, g, b])
+}
+
+/// The components of 3, 6 or 8 hex digits
+fn parse_hex(hex: &str) -> Option<[f32; 4]> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits = u32::from_str_radix(hex, 16).ok()?;
+    let [r, g, b, a] = match hex.len() {
+        3 => [digits >> 8, digits >> 4, digits, 0xF].map(|digit| (digit & 0xF) as u8 * 17),
+        6 => (digits << 8 | 0xFF).to_be_bytes(),
+        8 => digits.to_be_bytes(),
+        _ => return None,
+    };
+    Some([r, g, b, a].map(|c| c as f32 / 255.0))
+}
+
+/// The components of `r,g,b` from 0 to 255
+fn parse_rgb_function(components: &str) -> Option<[f32; 4]> {
+    let components = components
+        .split(',')
+        .map(|component| component.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [r, g, b] = components[..] else {
+        return None;
+    };
+    Some([r, g, b, 255].map(|c| c as f32 / 255.0))
+}
+
+/// The color name `name` was most likely meant to be if it is a typo
+fn nearest_name(name: &str) -> Option<&'static str> {
+    NAMED_COLORS
+        .iter()
+        .map(|(candidate, _)| (edit_distance(name, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// How many characters have to be inserted, removed, replaced or swapped with their neighbor to
+/// turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    distances[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let replaced = distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = replaced
+                .min(distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_color_format() {
+        let rgb = |r: u8, g: u8, b: u8, a: u8| [r, g, b, a].map(|c| c as f32 / 255.0);
+        assert_eq!(parse_color("red").unwrap(), rgb(255, 0, 0, 255));
+        assert_eq!(
+            parse_color("RebeccaPurple").unwrap(),
+            rgb(0x66, 0x33, 0x99, 255)
+        );
+        assert_eq!(parse_color("transparent").unwrap(), [0.0; 4]);
+        assert_eq!(parse_color("#F80").unwrap(), rgb(255, 0x88, 0, 255));
+        assert_eq!(parse_color("#FF8040").unwrap(), rgb(255, 0x80, 0x40, 255));
+        assert_eq!(parse_color("ff8040").unwrap(), rgb(255, 0x80, 0x40, 255));
+        assert_eq!(
+            parse_color("#FF804080").unwrap(),
+            rgb(255, 0x80, 0x40, 0x80)
+        );
+        assert_eq!(parse_color("rgb(1, 2,3)").unwrap(), rgb(1, 2, 3, 255));
+
+        for invalid in [
+            "",
+            "#",
+            "#FF80",
+            "12345",
+            "rgb(1,2)",
+            "rgb(1,2,256)",
+            "#GG0000",
+        ] {
+            assert!(parse_color(invalid).is_err(), "{invalid}");
+        }
+        // Names made of hex digits are still names
+        assert!(parse_color("bad").is_err());
+        assert!(NAMED_COLORS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    }
+
+    #[test]
+    fn suggests_the_nearest_name() {
+        let error = parse_color("lightblu").unwrap_err().to_string();
+        assert_eq!(error, "unknown color \"lightblu\", did you mean lightblue?");
+        let error = parse_color("gren").unwrap_err().to_string();
+        assert!(error.contains("did you mean green?"), "{error}");
+        let error = parse_color("nothing like it").unwrap_err().to_string();
+        assert!(error.contains("is not a color"), "{error}");
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("nvay", "navy"), 1);
+    }
+
+    #[test]
+    fn converts_opaque_colors() {
+        assert_eq!(parse_opaque_color("white").unwrap(), [1.0; 3]);
+        assert!(parse_opaque_color("#20304080").is_err());
+        assert!(parse_opaque_color("transparent").is_err());
+    }
+}
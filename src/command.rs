@@ -0,0 +1,2310 @@
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use chrono::Datelike;
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand, ValueEnum};
+use image::{Rgba, RgbaImage};
+use pixels::wgpu::SurfaceError;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::{
+    args::{ColorArg, DurationArg, ResolutionArg, SignedDurationArg},
+    clock::{Clock, SystemClock},
+    damage::Damage,
+    decode::{self, DecodeOptions, HighDepth, ToneMap},
+    download::{fetch_image, is_url},
+    hands::ClockHands,
+    latest::{newest_image, LatestWatcher},
+    pipe::FramePipe,
+    plugin::Plugin,
+    render::{
+        day_millis, detect_clock_step, validate_clock_dir, BackgroundRenderer, ClockHours,
+        Colorize, ColorizeMode, FrameLayout, MaskSource, Scaling, MILLIS_PER_HOUR,
+        MILLIS_PER_MINUTE, MILLIS_PER_SECOND,
+    },
+    resample::{Encoding, ResampleFilter},
+    script::Script,
+    shm::SharedFrame,
+    slideshow::{Order, Playlist},
+    source::FrameSource,
+    span::OutputSpan,
+    template::{FrameTemplate, HourDirs},
+    text::{Align, Anchor, TextStyle, TextTemplate},
+    tint::ClockColor,
+    watch::ImageWatcher,
+    weekly::{day_renderer, missing_days},
+};
+
+/// The largest font size of text overlays in pixels
+const MAX_FONT_SIZE: f32 = 4096.0;
+/// How many commands a layer command may hold, each layer has a frame of its own
+const MAX_COMMANDS: usize = 32;
+/// The largest width and height of frames shared by another process
+const MAX_SHARED_FRAME_SIZE: u32 = 16384;
+/// The most colors a palette can have
+const MAX_PALETTE_SIZE: usize = 64;
+/// The most commands a batch file can hold
+const MAX_BATCH_SIZE: usize = 256;
+/// How long a slideshow shows each image by default
+const DEFAULT_SLIDESHOW_INTERVAL: DurationArg = DurationArg(5 * 60 * 1000);
+/// How long a schedule cross-fades between two entries by default
+const DEFAULT_SCHEDULE_FADE: DurationArg = DurationArg(1000);
+/// How often renderers are advanced by default and the range of rates they can be advanced at
+pub const DEFAULT_FPS: f32 = 20.0;
+const MIN_FPS: f32 = 0.1;
+const MAX_FPS: f32 = 240.0;
+
+/// How the desktop program is started
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct StartArgs {
+    /// Desktop resolution width in pixels
+    #[arg()]
+    pub width: u32,
+    /// Desktop resolution height in pixels
+    #[arg()]
+    pub height: u32,
+    /// Window class name
+    #[arg()]
+    pub window_class: String,
+    /// The resolution is in physical pixels and the background is rendered at the full
+    /// resolution of scaled outputs, the default
+    #[arg(long, conflicts_with = "logical")]
+    pub physical: bool,
+    /// The resolution is in logical pixels and the background is rendered at the logical size
+    /// and scaled up on outputs with a whole scale factor, outputs with a fractional scale factor
+    /// are rendered at their physical size
+    #[arg(long)]
+    pub logical: bool,
+    /// Don't pause rendering while the login session is locked, for sessions without logind
+    #[arg(long)]
+    pub no_lock_detection: bool,
+    /// The windowing system to connect to
+    #[arg(long, value_enum, default_value_t)]
+    pub backend: Backend,
+    /// Present frames as soon as they are rendered instead of waiting for the next vertical
+    /// blank, can be changed later with the vsync command
+    #[arg(long)]
+    pub no_vsync: bool,
+    /// Which graphics adapter is preferred on systems with several, eg to use the dedicated GPU
+    /// that drives the outputs on hybrid graphics laptops
+    #[arg(long, value_enum, default_value_t)]
+    pub power_preference: PowerPreference,
+    /// Use the graphics adapter whose name contains this text, ignoring case
+    #[arg(long)]
+    pub adapter: Option<String>,
+    /// Render on a software adapter like llvmpipe, for machines without a usable GPU
+    ///
+    /// Built with the `cpu-fallback` feature, frames are presented by the CPU if no adapter can be
+    /// used at all.
+    #[arg(long)]
+    pub force_fallback_adapter: bool,
+    /// Show a panel with timing statistics in the top left corner, can be changed later with the
+    /// debug-overlay command
+    #[arg(long)]
+    pub debug_overlay: bool,
+    /// What happens when the resolution is larger than the graphics adapter supports
+    #[arg(long, value_enum, default_value_t)]
+    pub oversize: Oversize,
+    /// Open one window per connected output, each showing its own background, with the output
+    /// name appended to the window class like `<window_class>-DP-1`
+    ///
+    /// The resolution is taken from each output then, windows are opened and closed as outputs
+    /// are connected and disconnected.
+    #[arg(long)]
+    pub per_output: bool,
+    /// Open one window per connected output like --per-output, but render the background once at
+    /// the given resolution and show it on all of them
+    ///
+    /// Each output scales the frame up by the largest whole factor that fits and centers it,
+    /// outputs smaller than the resolution show its center.
+    #[arg(long)]
+    pub mirror: bool,
+    /// Don't show the backgrounds of the last run again, which are kept in
+    /// `$XDG_STATE_HOME/desktop-background/<socket_name>.json`
+    #[arg(long)]
+    pub no_restore: bool,
+    /// Stop the desktop program running on the socket and take its place, instead of failing
+    #[arg(long)]
+    pub replace: bool,
+    /// How many times per second the backgrounds are advanced, from 0.1 to 240
+    ///
+    /// This is a cap, backgrounds which can't change on their own like static images aren't
+    /// advanced at all until a command replaces them.
+    #[arg(long, default_value_t = DEFAULT_FPS, value_parser = parse_fps)]
+    #[serde(default = "default_fps")]
+    pub fps: f32,
+    /// Dither images with more than 8 bits per channel, like 16 bit PNG files, instead of
+    /// rounding them to 8 bits, against banding in smooth gradients
+    #[arg(long)]
+    #[serde(default)]
+    pub dither: bool,
+    /// How the brightness of HDR images, like Radiance HDR and OpenEXR files, is mapped to the
+    /// range of the display
+    #[arg(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub tone_map: ToneMap,
+    /// Scale the brightness of HDR images by 2 to this power before tone mapping, like `-1` for
+    /// half as bright
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    #[serde(default)]
+    pub exposure: f32,
+    /// Show the pixel values of images with an embedded color profile, like Display P3 or Adobe
+    /// RGB, as they are instead of converting them to sRGB
+    #[arg(long)]
+    #[serde(default)]
+    pub no_color_management: bool,
+}
+
+fn default_fps() -> f32 {
+    DEFAULT_FPS
+}
+
+/// Parse a frame rate between [`MIN_FPS`] and [`MAX_FPS`]
+fn parse_fps(string: &str) -> Result<f32, String> {
+    match string.parse::<f32>() {
+        Ok(fps) if (MIN_FPS..=MAX_FPS).contains(&fps) => Ok(fps),
+        _ => Err(format!(
+            "{string:?} is not a frame rate from {MIN_FPS} to {MAX_FPS}"
+        )),
+    }
+}
+
+/// How frames are rendered to files by the render-frames command
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct RenderArgs {
+    /// Frame width in pixels
+    #[arg()]
+    pub width: u32,
+    /// Frame height in pixels
+    #[arg()]
+    pub height: u32,
+    /// The background command to render like `"clock-image clock/ %H/%M.png"`
+    #[arg(value_parser = |string: &str| parse_layer(string).map(Box::new))]
+    #[serde(deserialize_with = "deserialize_nested")]
+    pub background: Box<Command>,
+    /// The directory the frames are written to as numbered PNG files
+    #[arg(long)]
+    pub out: PathBuf,
+    /// The time of day of the first frame like `17:42` or `17:42:05.250`, now if not set
+    #[arg(long, value_parser = parse_time_of_day)]
+    pub at: Option<u32>,
+    /// How many frames to render
+    #[arg(long, default_value_t = 1)]
+    pub count: u32,
+    /// The time between two frames like `1s` or `250ms`
+    #[arg(long, default_value_t = DurationArg(1000))]
+    pub step: DurationArg,
+}
+
+/// How the render-at command renders a single frame
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct RenderAtArgs {
+    /// The time of day to render the frame of like `14:37:05` or `14:37:05.250`
+    #[arg(value_parser = parse_time_of_day)]
+    pub time: u32,
+    /// The background command to render like `"clock-image clock/ %H/%M.png 1000"`
+    #[arg(value_parser = |string: &str| parse_layer(string).map(Box::new))]
+    #[serde(deserialize_with = "deserialize_nested")]
+    pub background: Box<Command>,
+    /// The PNG file the frame is written to
+    #[arg(long, short)]
+    pub out: PathBuf,
+    /// The frame size like `1920x1080`
+    #[arg(long, default_value = "1920x1080")]
+    pub resolution: ResolutionArg,
+}
+
+/// The background the check command checks
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct DryRunArgs {
+    /// Desktop resolution width in pixels
+    #[arg()]
+    pub width: u32,
+    /// Desktop resolution height in pixels
+    #[arg()]
+    pub height: u32,
+    /// The background command to check like `"static-image light.png --scaling fill"`
+    #[arg(value_parser = |string: &str| parse_layer(string).map(Box::new))]
+    #[serde(deserialize_with = "deserialize_nested")]
+    pub background: Box<Command>,
+}
+
+/// The background the preview command shows and how its time passes
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct PreviewArgs {
+    /// Window width in pixels
+    #[arg()]
+    pub width: u32,
+    /// Window height in pixels
+    #[arg()]
+    pub height: u32,
+    /// The background command to show like `"clock-image clock/ %H/%M.png"`
+    #[arg(value_parser = |string: &str| parse_layer(string).map(Box::new))]
+    #[serde(deserialize_with = "deserialize_nested")]
+    pub background: Box<Command>,
+    /// The time of day to start at like `17:42` or `17:42:05.250`, now if not set
+    #[arg(long, value_parser = parse_time_of_day)]
+    pub at: Option<u32>,
+    /// How many times faster than real time the time passes
+    #[arg(long, default_value_t = 1.0)]
+    pub speed: f64,
+    /// The windowing system to connect to
+    #[arg(long, value_enum, default_value_t)]
+    pub backend: Backend,
+}
+
+/// What the warm-cache command caches
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct WarmArgs {
+    /// Desktop resolution width in pixels
+    #[arg()]
+    pub width: u32,
+    /// Desktop resolution height in pixels
+    #[arg()]
+    pub height: u32,
+    /// The clock image command whose frames are cached like `"clock-image clock/ %H/%M.png"`
+    #[arg(value_parser = |string: &str| parse_layer(string).map(Box::new))]
+    #[serde(deserialize_with = "deserialize_nested")]
+    pub background: Box<Command>,
+    /// How many frames are decoded at the same time, one per CPU if not set
+    #[arg(long)]
+    pub jobs: Option<usize>,
+}
+
+/// Which clock frames the check-clock-dir and repair-clock-dir commands look at
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct CheckArgs {
+    /// The directory which contains the clock images
+    #[arg()]
+    pub dir: PathBuf,
+    /// The template path of the frames within `dir` or the `.toml` file mapping hours to
+    /// templates, like the one of clock-image
+    #[arg()]
+    pub file_template: String,
+    /// The clock step in milli seconds
+    #[arg()]
+    pub clock_step: u32,
+    /// The number of hours of one clock cycle
+    #[arg(long, value_enum, default_value_t)]
+    pub hours: ClockHours,
+    /// How the hour folders of templates without a directory are named, like for clock-image
+    #[arg(long, default_value_t)]
+    pub hour_dirs: HourDirs,
+    /// How many frames are read at the same time, one per CPU if not set
+    #[arg(long)]
+    pub jobs: Option<usize>,
+}
+
+/// How the resample-clock-dir command converts the frames of a clock directory
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct ResampleArgs {
+    /// The directory which contains the clock images
+    #[arg()]
+    pub src: PathBuf,
+    /// The directory the resampled frames are written to, in the same folders and under the same
+    /// names
+    #[arg()]
+    pub dst: PathBuf,
+    /// Desktop resolution width in pixels, frames are scaled down to the smallest size covering
+    /// the resolution while keeping their aspect ratio
+    #[arg(long)]
+    pub width: u32,
+    /// Desktop resolution height in pixels
+    #[arg(long)]
+    pub height: u32,
+    /// The filter the frames are resized with
+    #[arg(long, value_enum, default_value_t)]
+    pub filter: ResampleFilter,
+    /// The format the frames are written in, other formats than the source one change the
+    /// extension of the files and so of the file template
+    #[arg(long, value_enum, default_value_t)]
+    pub encode: Encoding,
+    /// How many frames are resampled at the same time, one per CPU if not set
+    #[arg(long)]
+    pub jobs: Option<usize>,
+}
+
+/// Which missing clock frames the repair-clock-dir command synthesizes
+#[derive(Debug, Clone, PartialEq, clap::Args, Serialize, Deserialize)]
+pub struct RepairArgs {
+    /// The clock directory and frames to repair
+    #[command(flatten)]
+    pub frames: CheckArgs,
+    /// The most consecutive missing frames which are interpolated, longer gaps stay missing
+    #[arg(long, default_value_t = 3)]
+    pub max_gap: usize,
+}
+
+/// A background of a schedule and the time of day it starts at, shown until the next entry starts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    /// The start in milliseconds since midnight
+    pub start: u32,
+    /// The background shown from the start on
+    pub background: Command,
+}
+
+/// A command of a batch file and the line it is on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchEntry {
+    /// The line number, counted from 1
+    pub line: usize,
+    /// The command on the line
+    pub command: Command,
+}
+
+impl ScheduleEntry {
+    /// The index of the entry shown at `day_millis`, the last one before the first entry starts
+    pub fn active(entries: &[ScheduleEntry], day_millis: u32) -> usize {
+        entries
+            .iter()
+            .rposition(|entry| entry.start <= day_millis)
+            .unwrap_or(entries.len().saturating_sub(1))
+    }
+}
+
+/// What happens when the resolution exceeds the maximum texture size of the graphics adapter
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Oversize {
+    /// Render at the resolution divided by the smallest whole factor that fits and scale it up
+    #[default]
+    Downscale,
+    /// Exit with an error naming the limit
+    Fail,
+}
+
+/// The graphics adapter preferred on systems with several
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum PowerPreference {
+    /// An integrated GPU that uses less power
+    #[default]
+    Low,
+    /// A dedicated GPU with more performance
+    High,
+}
+
+/// A presentation failure injected by the inject-fault command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Fault {
+    /// The frame isn't ready in time
+    Timeout,
+    /// The surface no longer matches the window
+    Outdated,
+    /// The surface is lost and has to be created again
+    Lost,
+    /// The graphics adapter ran out of memory
+    OutOfMemory,
+}
+
+impl From<Fault> for SurfaceError {
+    fn from(fault: Fault) -> Self {
+        match fault {
+            Fault::Timeout => SurfaceError::Timeout,
+            Fault::Outdated => SurfaceError::Outdated,
+            Fault::Lost => SurfaceError::Lost,
+            Fault::OutOfMemory => SurfaceError::OutOfMemory,
+        }
+    }
+}
+
+/// A windowing system
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Backend {
+    /// The Wayland protocol
+    #[default]
+    Wayland,
+    /// The X Window System
+    X11,
+}
+
+/// A command of the command line, either starting the daemon or sent to a running one
+#[derive(Debug, Clone, PartialEq, Subcommand, Serialize, Deserialize)]
+// Keep the description out of the command line help
+#[command(about = None, long_about = None)]
+pub enum Command {
+    /// Start the desktop program
+    Start(StartArgs),
+    /// Render a background to PNG files at given times, without a desktop program or GPU
+    RenderFrames(RenderArgs),
+    /// Render the frame a background shows at a time of day to a PNG file, without a desktop
+    /// program or GPU
+    RenderAt(RenderAtArgs),
+    /// Decode and place every frame of a clock image at a resolution ahead of time, into the
+    /// cache in `$XDG_CACHE_HOME/desktop-background/frames` the desktop program loads them from
+    WarmCache(WarmArgs),
+    /// Check that a clock directory holds every frame a clock image would load, without a
+    /// desktop program, exiting with an error if any is missing or can't be read
+    CheckClockDir(CheckArgs),
+    /// Write the missing frames of a clock directory by blending the nearest earlier and later
+    /// frames, without a desktop program, listing them in `.interpolated-frames` in the directory
+    RepairClockDir(RepairArgs),
+    /// Resize the frames of a clock directory for a resolution ahead of time into a new clock
+    /// directory, without a desktop program, skipping frames resized before
+    ResampleClockDir(ResampleArgs),
+    /// Check that the desktop program would show a background command, without sending it,
+    /// exiting with an error if it wouldn't
+    Check(DryRunArgs),
+    /// Show a background command in a regular window rather than on the desktop, with keys to
+    /// speed up the time and to jump to a time of day
+    Preview(PreviewArgs),
+    /// Close the running desktop program
+    Stop {
+        /// Wait until the desktop program has exited
+        #[arg(long)]
+        wait: bool,
+        /// How long to wait for the desktop program to exit, like `5s` or `500ms`
+        #[arg(long, default_value = "5s", requires = "wait")]
+        timeout: DurationArg,
+        /// Kill the desktop program if it hasn't exited within the timeout
+        #[arg(long, requires = "wait")]
+        force: bool,
+    },
+    /// Replace the running desktop program by a new start of its binary, showing the same
+    /// backgrounds, to take over an upgrade
+    Restart,
+    /// Load the current background from disk again
+    Reload,
+    /// Stop animating the background until it is resumed
+    Pause,
+    /// Continue animating a paused background
+    Resume,
+    /// Print the state of the running desktop program
+    Status,
+    /// Check that the desktop program responds, printing its process id, version and the time of
+    /// its last render tick on the monotonic clock
+    Ping,
+    /// Print a shell script which starts a desktop program like the running one and shows the
+    /// same backgrounds with the same settings
+    Export,
+    /// Save the backgrounds of all outputs as a profile, which can be loaded again later
+    SaveProfile {
+        /// The name of the profile, replacing a profile of the same name
+        #[arg()]
+        name: String,
+    },
+    /// Show the backgrounds saved in a profile, keeping the current ones if any of them can't
+    /// be shown
+    LoadProfile {
+        /// The name of the profile
+        #[arg()]
+        name: String,
+    },
+    /// List the saved profiles, without a desktop program
+    Profiles,
+    /// Send the commands of a file over one connection, one per line like on the command line
+    /// after the socket name, stopping at the first one which fails
+    Batch {
+        /// The file, `-` for stdin, empty lines and lines starting with `#` are skipped
+        #[arg()]
+        file: PathBuf,
+        /// Show all of the backgrounds or none of them if one can't be shown, the file can only
+        /// hold background commands then
+        #[arg(long)]
+        atomic: bool,
+        /// The commands of the file, read by the client
+        #[arg(skip)]
+        #[serde(deserialize_with = "deserialize_nested")]
+        commands: Vec<BatchEntry>,
+    },
+    /// Show the background shown before the current one again
+    Back,
+    /// Show the background shown after the current one again, after going back
+    Forward,
+    /// List the backgrounds shown recently, the current one marked by a *
+    History,
+    /// Show a background of the history again
+    HistoryJump {
+        /// The index of the background as listed by the history command
+        #[arg()]
+        index: usize,
+    },
+    /// Print the dominant colors of the shown background as hex codes, the most common first
+    Palette {
+        /// How many colors to find
+        #[arg(default_value_t = 8)]
+        count: usize,
+        /// Print the colors as a json array instead of one per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Turn vsync of the running desktop program on or off
+    Vsync {
+        /// Whether vsync is on
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Show or hide the panel with timing statistics of the running desktop program
+    DebugOverlay {
+        /// Whether the panel is shown
+        #[arg(action = clap::ArgAction::Set)]
+        enabled: bool,
+    },
+    /// Make the next presentation fail like the graphics driver would, to test the recovery
+    #[command(hide = true)]
+    InjectFault {
+        /// The failure of the next presentation
+        #[arg(value_enum)]
+        fault: Fault,
+    },
+    /// A static image background
+    StaticImage {
+        /// The image file to use, or an http or https url the desktop program downloads it from
+        #[arg()]
+        path: PathBuf,
+        /// How the image is scaled to the desktop resolution
+        #[arg(long, value_enum, default_value_t)]
+        scaling: Scaling,
+        /// The color of margins left uncovered by the image, a color like `#203040`, `navy` or
+        /// `rgb(32,48,64)`
+        #[arg(long)]
+        margin_color: Option<ColorArg>,
+        /// Reload the image whenever the file changes
+        #[arg(long)]
+        watch: bool,
+        /// Stretch the image over the bounding box of all outputs and show the part in front of
+        /// the output of this window, cropping the overflow
+        #[arg(long, conflicts_with = "scaling")]
+        span: bool,
+    },
+    /// A dynamically changing background image according to the time of day the
+    ClockImage {
+        /// The directory which contains the clock images
+        #[arg()]
+        dir: PathBuf,
+        /// The template path of the frames within `dir`, where the placeholders get replaced by
+        /// the time of the frame:
+        ///
+        /// - %H the hour, padded to 2 digits
+        /// - %M the minute, padded to 2 digits
+        /// - %S the second, padded to 2 digits
+        /// - %r the milliseconds within the hour, padded to 7 digits, in the range of 0
+        ///   (inclusive) - 3600000 (exclusive) starting over every hour
+        /// - %m the milliseconds within the cycle, not padded eg in the range of 0 (inclusive) -
+        ///   43200000 (exclusive) or 86400000 (exclusive) for 24 hour clocks
+        /// - %% a literal percent
+        ///
+        /// The zero-padding of a placeholder can be changed by a width like %08m for
+        /// milliseconds padded to 8 digits or %0H for an unpadded hour.
+        ///
+        /// Templates without a directory are looked up in the hour sub folders named by
+        /// `--hour-dirs`, by default "0" to "11" ("0" to "23" for 24 hour clocks). The folder and
+        /// the file name are both filled in from the same frame time, so at 03:00:01
+        /// `"frame_%m.png"` is `3/frame_10801000.png` while frame sets numbering the frames of
+        /// each hour folder from 0 use `"frame_%r.png"` for `3/frame_0001000.png`. Animated GIF
+        /// and APNG files are played by the delays of their frames for as long as the template
+        /// resolves to them, eg one animation per hour with `"clock.gif"`.
+        ///
+        /// A path ending in `.toml` within `dir` names a file with the template of most hours
+        /// and the ones replacing it in some hours of the cycle instead, like
+        /// `default = "%H/%M.png"` followed by an `[overrides]` table with `22 = "night_%m.png"`.
+        ///
+        /// # Example
+        /// `"clock_frame_%08m.png"` or `"%H/clock_%H_%M_%S.png"`
+        #[arg()]
+        file_template: String,
+        /// The clock step in milli seconds, detected from the names of the frames of the first
+        /// hour if 0 or not given
+        #[arg(default_value_t = 0)]
+        clock_step: u32,
+        /// The number of hours of one clock cycle
+        #[arg(long, value_enum, default_value_t)]
+        hours: ClockHours,
+        /// How the hour folders of templates without a directory are named:
+        /// < 0-11 | 1-12 | flat | <pattern> >
+        ///
+        /// `0-11` counts the hours of the cycle from 0 unpadded, `1-12` names them like a 12 hour
+        /// clock face from "01" to "12" with "12" after midnight and noon, `flat` looks the frames
+        /// up directly in the clock directory and a pattern like `hour_%02d` puts the hour of the
+        /// cycle in place of its `%d`.
+        #[arg(long, default_value_t)]
+        hour_dirs: HourDirs,
+        /// Shift the shown time by a duration like `+5m` or `-1h30m`, reduced to less than one
+        /// clock cycle
+        #[arg(long, allow_hyphen_values = true)]
+        offset: Option<SignedDurationArg>,
+        /// The IANA name of the timezone to show the time of, eg `Europe/Berlin`, instead of the
+        /// local one
+        #[arg(long)]
+        timezone: Option<String>,
+        /// The clock color:
+        /// < RAINBOW[:<saturation>,<value>,<period_minutes>,<phase_degrees>][@<AA>] |
+        /// GRADIENT:<color>-<color>[:<hh:mm>-<hh:mm>][@<AA>] | <color>[,<color>...][@<AA>] >
+        ///
+        /// Plain RAINBOW rotates once per clock cycle, `RAINBOW:1,1,720,0` for 12 hour clocks.
+        /// A list of colors like `red,#00FF00,blue` fades from each color to the next and back to
+        /// the first once per clock cycle or `--color-cycle`.
+        /// Colors are CSS names like `red`, hex like `#F80` or `#FF8040` or `rgb(255,128,64)`.
+        /// The alpha of `#RRGGBBAA` or 2 hex digits after an @ like `RAINBOW@80` set how strongly
+        /// the frames are tinted, blending with the untinted frames or with the margin color in
+        /// mask mode.
+        #[arg(long, short, value_parser = check_clock_color)]
+        clock_color: Option<String>,
+        /// How long a list of clock colors or a plain RAINBOW takes for one round, like `60m`,
+        /// instead of one clock cycle
+        #[arg(long, requires = "clock_color")]
+        color_cycle: Option<DurationArg>,
+        /// How the clock frames are scaled to the desktop resolution
+        #[arg(long, value_enum, default_value_t)]
+        scaling: Scaling,
+        /// The color of margins left uncovered by the clock frames and blended under
+        /// semi-transparent tinted frames, in mask mode the base background color, a color like
+        /// `#203040`, `navy` or `rgb(32,48,64)`
+        #[arg(long, conflicts_with = "underlay")]
+        margin_color: Option<ColorArg>,
+        /// An image shown in the margins left uncovered by the clock frames, in mask mode the
+        /// base background the clock is drawn over
+        #[arg(long, visible_alias = "under")]
+        underlay: Option<PathBuf>,
+        /// How the clock color is applied to the frames
+        #[arg(long, value_enum, default_value_t)]
+        colorize: ColorizeMode,
+        /// Which part of the frames is used as mask in mask mode
+        #[arg(long, value_enum, default_value_t)]
+        mask_source: MaskSource,
+        /// Cross-fade between consecutive frames over the given fraction of the clock step
+        #[arg(long, num_args = 0..=1, default_missing_value = "0.3")]
+        interpolate: Option<f32>,
+        /// Treat the file template as sprite sheets holding a grid of frames each, described by
+        /// a metadata file next to every sheet with the extension `.sheet`
+        #[arg(long)]
+        sheets: bool,
+        /// Skip checking that a sample of the clock frames exists, for slow network mounts
+        #[arg(long)]
+        no_validate: bool,
+    },
+    /// An analog clock of hand images turned over a clock face image
+    ClockHands {
+        /// The image of the clock face
+        #[arg()]
+        face: PathBuf,
+        /// The image of the hour hand, pointing up to 12 from the center of the image, mostly
+        /// transparent like the other hands
+        #[arg()]
+        hour_hand: PathBuf,
+        /// The image of the minute hand
+        #[arg()]
+        minute_hand: PathBuf,
+        /// The image of the second hand, the clock shows no seconds without one
+        #[arg(long)]
+        second_hand: Option<PathBuf>,
+        /// The point of the face image the hands turn around: <x>,<y> (pixels), the center of
+        /// the face by default
+        #[arg(long, value_parser = parse_point)]
+        center: Option<(u32, u32)>,
+        /// How the face is scaled to the desktop resolution, the hands are scaled like it
+        #[arg(long, value_enum, default_value_t)]
+        scaling: Scaling,
+        /// The color of margins left uncovered by the face, a color like `#203040`, `navy` or
+        /// `rgb(32,48,64)`
+        #[arg(long)]
+        margin_color: Option<ColorArg>,
+        /// The IANA name of the timezone to show the time of, eg `Europe/Berlin`, instead of the
+        /// local one
+        #[arg(long)]
+        timezone: Option<String>,
+    },
+    /// Text drawn over a transparent background, meant to be used as a layer
+    TextOverlay {
+        /// The text to show, supports the placeholders {time:<format>}, {date:<format>},
+        /// {hostname} and {uptime} where formats are strftime like, eg `{time:%H:%M}`
+        #[arg()]
+        template: String,
+        /// The font file (ttf or otf) to draw the text with
+        #[arg()]
+        font: PathBuf,
+        /// The font size in pixels
+        #[arg(long, short, default_value_t = 48.0)]
+        size: f32,
+        /// The text color, a color like `#FFFFFF`, `white` or `rgb(255,255,255)`
+        #[arg(long, short, default_value = "FFFFFF")]
+        color: ColorArg,
+        /// Where the text is placed on the desktop
+        #[arg(long, short, value_enum, default_value_t)]
+        position: Anchor,
+        /// How the lines of multi-line text are aligned
+        #[arg(long, value_enum, default_value_t)]
+        align: Align,
+        /// Horizontal offset from the position in pixels
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        offset_x: i32,
+        /// Vertical offset from the position in pixels
+        #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+        offset_y: i32,
+        /// The distance to the desktop edges in pixels
+        #[arg(long, short, default_value_t = 32)]
+        margin: u32,
+    },
+    /// Several backgrounds drawn on top of each other, blended by their alpha
+    Layer {
+        /// A background command like `--layer "static-image bg.png"`, the first one is at the
+        /// bottom
+        #[arg(long = "layer", required = true, value_parser = parse_layer)]
+        #[serde(deserialize_with = "deserialize_nested")]
+        layers: Vec<Command>,
+    },
+    /// The newest image matching a pattern like `shots/shot_2024-*.png`, where `*` matches any
+    /// number of characters and `?` a single one in the file name
+    LatestImage {
+        /// The pattern of the image files
+        #[arg()]
+        pattern: PathBuf,
+        /// Switch to newer matching images as they appear
+        #[arg(long)]
+        watch: bool,
+        /// How the images are scaled to the desktop resolution
+        #[arg(long, value_enum, default_value_t)]
+        scaling: Scaling,
+        /// The color of margins left uncovered by the images, a color like `#203040`, `navy` or
+        /// `rgb(32,48,64)`
+        #[arg(long)]
+        margin_color: Option<ColorArg>,
+    },
+    /// The images of a directory shown one after another, picking up images added to it
+    Slideshow {
+        /// The directory with the images
+        #[arg()]
+        dir: PathBuf,
+        /// How long each image is shown like `30s` or `1h30m`
+        #[arg(long, default_value_t = DEFAULT_SLIDESHOW_INTERVAL)]
+        interval: DurationArg,
+        /// The order the images are shown in
+        #[arg(long, value_enum, default_value_t)]
+        order: Order,
+        /// How the images are scaled to the desktop resolution
+        #[arg(long, value_enum, default_value_t)]
+        scaling: Scaling,
+        /// The color of margins left uncovered by the images, a color like `#203040`, `navy` or
+        /// `rgb(32,48,64)`
+        #[arg(long)]
+        margin_color: Option<ColorArg>,
+        /// Stretch each image over the bounding box of all outputs like `static-image --span`
+        #[arg(long, conflicts_with = "scaling")]
+        span: bool,
+    },
+    /// A different image on every day of the week, switched at midnight
+    ///
+    /// The directory holds images named after the days like `monday.png`, or folders named after
+    /// the days to pick a random image from, and a `default.*` image shown on days without one.
+    Weekly {
+        /// The directory with the images of the days
+        #[arg()]
+        dir: PathBuf,
+        /// How the images are scaled to the desktop resolution
+        #[arg(long, value_enum, default_value_t)]
+        scaling: Scaling,
+        /// The color of margins left uncovered by the images, a color like `#203040`, `navy` or
+        /// `rgb(32,48,64)`
+        #[arg(long)]
+        margin_color: Option<ColorArg>,
+    },
+    /// Backgrounds shown at different times of the day, like
+    /// `--at "07:00 static-image light.png" --at "19:00 static-image dark.png"`
+    Schedule {
+        /// The time of day an entry starts at followed by its background command, entries are
+        /// ordered by time and each one is shown until the next one starts
+        #[arg(long = "at", required = true, value_parser = parse_schedule_entry)]
+        #[serde(deserialize_with = "deserialize_nested")]
+        entries: Vec<ScheduleEntry>,
+        /// How long to cross-fade between two entries like `2s` or `500ms`, `0s` to switch at
+        /// once
+        #[arg(long, default_value_t = DEFAULT_SCHEDULE_FADE)]
+        fade: DurationArg,
+    },
+    /// A background drawn by a renderer plugin, a shared library implementing the interface of
+    /// `desktop_background::plugin`
+    Plugin {
+        /// The shared library of the plugin
+        #[arg()]
+        path: PathBuf,
+        /// Parameters passed to the plugin as they are, their meaning is up to the plugin
+        #[arg(default_value = "", allow_hyphen_values = true)]
+        params: String,
+    },
+    /// A background drawn by a Lua script defining `render(frame, time_millis)`, see
+    /// `desktop_background::script` for the functions it can use
+    ///
+    /// The script is loaded again when its file changes. Needs the `lua` feature.
+    Script {
+        /// The Lua file of the script
+        #[arg()]
+        path: PathBuf,
+    },
+    /// Frames read from the standard output of a command, which gets the frame size in the
+    /// `WIDTH` and `HEIGHT` environment variables and writes `WIDTH * HEIGHT * 4` bytes of raw
+    /// rgba pixels per frame
+    ///
+    /// Frames coming faster than the frame rate are dropped and the last frame stays on screen
+    /// when the command exits. The standard error of the command is logged by the daemon.
+    Pipe {
+        /// The shell command writing the frames
+        #[arg()]
+        command: String,
+        /// How many frames are shown per second at most, the daemon renders 20 times per second
+        #[arg(long, default_value_t = 20)]
+        fps: u32,
+        /// Start the command again whenever it exits, waiting longer after every quick exit
+        #[arg(long)]
+        restart: bool,
+    },
+    /// Frames another process writes into a memory mapped file, double buffered with a
+    /// sequence number as described in `desktop_background::shm`
+    ///
+    /// Frames of another size than the desktop are centered on it.
+    Shm {
+        /// The shared file, eg in /dev/shm
+        #[arg()]
+        path: PathBuf,
+        /// The width of the frames in the file
+        #[arg()]
+        width: u32,
+        /// The height of the frames in the file
+        #[arg()]
+        height: u32,
+    },
+}
+
+thread_local! {
+    /// How deep the command being deserialized is nested in others
+    static NESTING_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Deserialize the commands held by another command, refusing to nest deeper than
+/// [`MAX_COMMANDS`] levels so a hostile message can't overflow the stack
+fn deserialize_nested<'de, D: Deserializer<'de>, T: Deserialize<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    let depth = NESTING_DEPTH.get();
+    if depth >= MAX_COMMANDS {
+        return Err(serde::de::Error::custom(format!(
+            "commands are nested deeper than {MAX_COMMANDS} levels"
+        )));
+    }
+    NESTING_DEPTH.set(depth + 1);
+    let nested = T::deserialize(deserializer);
+    NESTING_DEPTH.set(depth);
+    nested
+}
+
+/// A background command given as a single argument
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct LayerArgs {
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Read the commands of a batch file, one per line in the grammar of the command line after the
+/// socket name, skipping empty lines and comments starting with `#`
+pub fn parse_batch(text: &str) -> anyhow::Result<Vec<BatchEntry>> {
+    text.lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, text)| {
+            let command = parse_layer(text).map_err(|error| {
+                anyhow::anyhow!("line {line}: {error}", error = error.trim_end())
+            })?;
+            Ok(BatchEntry { line, command })
+        })
+        .collect()
+}
+
+fn parse_layer(string: &str) -> Result<Command, String> {
+    LayerArgs::try_parse_from(split_words(string)?)
+        .map(|args| args.command)
+        .map_err(|error| error.render().to_string())
+}
+
+/// Parse a schedule entry like `07:00 static-image light.png`
+fn parse_schedule_entry(string: &str) -> Result<ScheduleEntry, String> {
+    let (time, background) = string
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("{string:?} is not a time of day followed by a background"))?;
+    Ok(ScheduleEntry {
+        start: parse_time_of_day(time)?,
+        background: parse_layer(background)?,
+    })
+}
+
+/// The clock step of the frames in `dir`, which can't be detected from sprite sheets
+fn detected_clock_step(
+    dir: &Path,
+    file_template: &FrameTemplate,
+    sheets: bool,
+) -> anyhow::Result<u32> {
+    if sheets {
+        bail!("the clock step of sprite sheets can't be detected, pass it after the file template");
+    }
+    detect_clock_step(dir, file_template)
+}
+
+/// Look up a timezone by its IANA name like `Europe/Berlin`
+fn parse_timezone(name: &str) -> anyhow::Result<Tz> {
+    name.parse()
+        .map_err(|_| anyhow::anyhow!("unknown timezone {name:?}"))
+}
+
+/// Parse a point like `512,384`
+fn parse_point(string: &str) -> Result<(u32, u32), String> {
+    string
+        .split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or_else(|| format!("{string:?} is not a point like 512,384"))
+}
+
+/// Check a clock color on the command line, keeping it as given
+fn check_clock_color(string: &str) -> Result<String, String> {
+    // The cycle only sets the period of a plain rainbow or a list of colors
+    ClockColor::parse(string, 720.0)
+        .map(|_| string.to_string())
+        .map_err(|error| format!("{error:#}"))
+}
+
+/// Format milliseconds since midnight like `07:00` or `07:00:30.500`, leaving out zero seconds
+pub fn format_time_of_day(millis: u32) -> String {
+    let (hour, minute) = (millis / MILLIS_PER_HOUR, millis / MILLIS_PER_MINUTE % 60);
+    let (second, millis) = (millis / MILLIS_PER_SECOND % 60, millis % MILLIS_PER_SECOND);
+    match (second, millis) {
+        (0, 0) => format!("{hour:02}:{minute:02}"),
+        (_, 0) => format!("{hour:02}:{minute:02}:{second:02}"),
+        _ => format!("{hour:02}:{minute:02}:{second:02}.{millis:03}"),
+    }
+}
+
+/// Parse a time of day like `17:42`, `17:42:05` or `17:42:05.250` into milliseconds since midnight
+pub(crate) fn parse_time_of_day(string: &str) -> Result<u32, String> {
+    let invalid = || format!("{string:?} is not a time of day like 17:42 or 17:42:05.250");
+    let (time, millis) = match string.split_once('.') {
+        Some((time, millis)) if millis.len() == 3 => {
+            (time, millis.parse::<u32>().map_err(|_| invalid())?)
+        }
+        Some(_) => return Err(invalid()),
+        None => (string, 0),
+    };
+
+    let fields = time
+        .split(':')
+        .map(|field| field.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (hour, minute, second) = match fields[..] {
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return Err(invalid()),
+    };
+    if hour >= 24 || minute >= 60 || second >= 60 {
+        return Err(invalid());
+    }
+
+    Ok(hour * MILLIS_PER_HOUR + minute * MILLIS_PER_MINUTE + second * MILLIS_PER_SECOND + millis)
+}
+
+/// Whether `name` can be used as file name of a profile
+fn is_profile_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\0'])
+}
+
+/// Quote `word` for a shell unless it consists of characters a shell takes as they are, the
+/// quoting is also understood by [`split_words`]
+pub fn shell_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-+=./:,@%".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+    format!("'{word}'", word = word.replace('\'', "'\\''"))
+}
+
+/// The words of a command line, leaving out the options which have their default value
+struct Words(Vec<String>);
+
+impl Words {
+    fn new(subcommand: &str) -> Self {
+        Words(vec![subcommand.to_string()])
+    }
+
+    fn arg(&mut self, value: impl ToString) {
+        self.0.push(value.to_string());
+    }
+
+    fn flag(&mut self, name: &str, set: bool) {
+        if set {
+            self.0.push(format!("--{name}"));
+        }
+    }
+
+    fn option<T: PartialEq + ToString>(&mut self, name: &str, value: T, default: T) {
+        if value != default {
+            self.0.push(format!("--{name}"));
+            self.0.push(value.to_string());
+        }
+    }
+
+    fn optional(&mut self, name: &str, value: Option<impl ToString>) {
+        if let Some(value) = value {
+            self.0.push(format!("--{name}"));
+            self.0.push(value.to_string());
+        }
+    }
+
+    fn value_enum<T: ValueEnum + Default + PartialEq>(&mut self, name: &str, value: T) {
+        if value != T::default() {
+            let value = value.to_possible_value().expect("no value is skipped");
+            self.0.push(format!("--{name}"));
+            self.0.push(value.get_name().to_string());
+        }
+    }
+}
+
+impl StartArgs {
+    /// The command line words of the start command with these arguments, without `--replace`
+    pub fn to_args(&self) -> Vec<String> {
+        let mut words = Words::new("start");
+        words.arg(self.width);
+        words.arg(self.height);
+        words.arg(&self.window_class);
+        words.flag("logical", self.logical);
+        words.flag("no-lock-detection", self.no_lock_detection);
+        words.value_enum("backend", self.backend);
+        words.flag("no-vsync", self.no_vsync);
+        words.value_enum("power-preference", self.power_preference);
+        words.optional("adapter", self.adapter.as_ref());
+        words.flag("force-fallback-adapter", self.force_fallback_adapter);
+        words.flag("debug-overlay", self.debug_overlay);
+        words.value_enum("oversize", self.oversize);
+        words.flag("per-output", self.per_output);
+        words.flag("mirror", self.mirror);
+        words.flag("no-restore", self.no_restore);
+        words.option("fps", self.fps, DEFAULT_FPS);
+        words.flag("dither", self.dither);
+        words.value_enum("tone-map", self.tone_map);
+        words.option("exposure", self.exposure, 0.0);
+        words.flag("no-color-management", self.no_color_management);
+        words.0
+    }
+
+    /// How the daemon decodes images
+    pub fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            high_depth: HighDepth {
+                dither: self.dither,
+                tone_map: self.tone_map,
+                exposure: self.exposure,
+            },
+            color_management: !self.no_color_management,
+        }
+    }
+
+    /// The time between two ticks of the renderers
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.fps)
+    }
+}
+
+/// Split a string into words like a shell would, honoring quotes and backslash escapes
+pub(crate) fn split_words(string: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = string.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => {
+                let escaped = chars.next().ok_or("trailing backslash")?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            '\'' | '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') if c == '"' => {
+                            word.push(chars.next().ok_or("trailing backslash")?)
+                        }
+                        Some(inner) => word.push(inner),
+                        None => return Err(format!("unterminated {c} quote")),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+impl Command {
+    /// The urls of the images downloaded by the command, or by the commands it holds
+    pub fn urls(&self) -> Vec<&str> {
+        match self {
+            Command::StaticImage { path, .. } if is_url(path) => {
+                path.to_str().into_iter().collect()
+            }
+            Command::Layer { layers } => layers.iter().flat_map(Command::urls).collect(),
+            Command::Schedule { entries, .. } => entries
+                .iter()
+                .flat_map(|entry| entry.background.urls())
+                .collect(),
+            Command::Batch { commands, .. } => commands
+                .iter()
+                .flat_map(|entry| entry.command.urls())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The static image command showing `path` like this command shows the images it picks, `None`
+    /// for commands which don't pick images
+    pub fn showing(&self, path: &Path) -> Option<Command> {
+        let (scaling, margin_color, span) = match self {
+            Command::LatestImage {
+                scaling,
+                margin_color,
+                ..
+            }
+            | Command::Weekly {
+                scaling,
+                margin_color,
+                ..
+            } => (*scaling, margin_color, false),
+            Command::Slideshow {
+                scaling,
+                margin_color,
+                span,
+                ..
+            } => (*scaling, margin_color, *span),
+            _ => return None,
+        };
+        Some(Command::StaticImage {
+            path: path.to_path_buf(),
+            scaling,
+            margin_color: margin_color.clone(),
+            watch: false,
+            span,
+        })
+    }
+
+    /// Whether the command sets a background rather than controlling the daemon
+    pub fn is_background(&self) -> bool {
+        !matches!(
+            self,
+            Command::Start(_)
+                | Command::RenderFrames(_)
+                | Command::WarmCache(_)
+                | Command::CheckClockDir(_)
+                | Command::RepairClockDir(_)
+                | Command::ResampleClockDir(_)
+                | Command::Check(_)
+                | Command::RenderAt(_)
+                | Command::Preview(_)
+                | Command::Stop { .. }
+                | Command::Restart
+                | Command::Status
+                | Command::Ping
+                | Command::Export
+                | Command::SaveProfile { .. }
+                | Command::LoadProfile { .. }
+                | Command::Profiles
+                | Command::Batch { .. }
+                | Command::Back
+                | Command::Forward
+                | Command::History
+                | Command::HistoryJump { .. }
+                | Command::Palette { .. }
+                | Command::Reload
+                | Command::Pause
+                | Command::Resume
+                | Command::Vsync { .. }
+                | Command::DebugOverlay { .. }
+                | Command::InjectFault { .. }
+        )
+    }
+
+    /// Check the values the command line parser can't, before anything is loaded
+    ///
+    /// Commands sent by other programs may hold any value, so this keeps them from making the
+    /// daemon panic or allocate more than it can.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Command::ClockImage {
+                interpolate: Some(fraction),
+                ..
+            } if !(*fraction > 0.0 && *fraction <= 1.0) => {
+                bail!("interpolate should be a fraction of the clock step in (0, 1]");
+            }
+            Command::ClockImage {
+                color_cycle: Some(DurationArg(0)),
+                ..
+            } => {
+                bail!("the color cycle should be longer than 0ms");
+            }
+            Command::ClockImage {
+                file_template,
+                hour_dirs,
+                sheets: false,
+                ..
+            } if !FrameTemplate::is_mapping(file_template) => {
+                // Mappings are read from the clock directory, which may only exist on the daemon
+                let template = FrameTemplate::parse(file_template, hour_dirs)?;
+                if template.granularity() == u32::MAX {
+                    bail!(
+                        "the file template {file_template} names one file for the whole clock \
+                         cycle, it needs a placeholder for the time like %m, %r, %M or a folder \
+                         per hour\n\
+                         hint: use static-image to show a single image"
+                    );
+                }
+            }
+            Command::TextOverlay { size, .. } if !(*size > 0.0 && *size <= MAX_FONT_SIZE) => {
+                bail!("the font size of {size} should be positive and at most {MAX_FONT_SIZE}");
+            }
+            Command::SaveProfile { name } | Command::LoadProfile { name }
+                if !is_profile_name(name) =>
+            {
+                bail!(
+                    "{name:?} is not a profile name, which can't be empty, contain a / or start \
+                     with a ."
+                );
+            }
+            Command::StaticImage {
+                path, watch: true, ..
+            } if is_url(path) => {
+                bail!("only image files can be watched for changes, not urls");
+            }
+            Command::Palette { count, .. } if !(1..=MAX_PALETTE_SIZE).contains(count) => {
+                bail!("a palette has between 1 and {MAX_PALETTE_SIZE} colors");
+            }
+            Command::Slideshow { interval, .. } if interval.0 < 1000 => {
+                bail!("the interval of a slideshow should be at least one second");
+            }
+            Command::Pipe { fps: 0, .. } => {
+                bail!("the frame rate of a pipe should be at least 1");
+            }
+            Command::Shm { width, height, .. }
+                if !(1..=MAX_SHARED_FRAME_SIZE).contains(width)
+                    || !(1..=MAX_SHARED_FRAME_SIZE).contains(height) =>
+            {
+                bail!(
+                    "shared frames should be between 1x1 and \
+                     {MAX_SHARED_FRAME_SIZE}x{MAX_SHARED_FRAME_SIZE} pixels"
+                );
+            }
+            Command::Batch {
+                atomic, commands, ..
+            } => {
+                if commands.len() > MAX_BATCH_SIZE {
+                    bail!("a batch holds at most {MAX_BATCH_SIZE} commands");
+                }
+                for BatchEntry { line, command } in commands {
+                    if matches!(
+                        command,
+                        Command::Start(_)
+                            | Command::RenderFrames(_)
+                            | Command::WarmCache(_)
+                            | Command::CheckClockDir(_)
+                            | Command::RepairClockDir(_)
+                            | Command::ResampleClockDir(_)
+                            | Command::Check(_)
+                            | Command::RenderAt(_)
+                            | Command::Preview(_)
+                            | Command::Profiles
+                            | Command::Batch { .. }
+                            | Command::Restart
+                            | Command::Ping
+                            | Command::Export
+                            | Command::Palette { .. }
+                    ) {
+                        bail!("line {line}: this command can't be part of a batch");
+                    }
+                    if *atomic && !command.is_background() {
+                        bail!("line {line}: an atomic batch can only hold background commands");
+                    }
+                    command.validate().with_context(|| format!("line {line}"))?;
+                }
+            }
+            Command::Layer { layers } => {
+                let count = self.count_commands();
+                if count > MAX_COMMANDS {
+                    bail!("a layer command holds at most {MAX_COMMANDS} commands, got {count}");
+                }
+                for layer in layers {
+                    if !layer.is_background() {
+                        bail!("only background commands can be used as layers");
+                    }
+                    layer.validate()?;
+                }
+            }
+            Command::Schedule { entries, .. } => {
+                let count = self.count_commands();
+                if count > MAX_COMMANDS {
+                    bail!("a schedule holds at most {MAX_COMMANDS} commands, got {count}");
+                }
+                if entries.is_empty() {
+                    bail!("a schedule needs at least one entry");
+                }
+                for pair in entries.windows(2) {
+                    if pair[0].start >= pair[1].start {
+                        bail!(
+                            "the schedule entry at {later} should start after the one at \
+                             {earlier}, entries are ordered by time and can't overlap",
+                            earlier = format_time_of_day(pair[0].start),
+                            later = format_time_of_day(pair[1].start),
+                        );
+                    }
+                }
+                for entry in entries {
+                    if entry.start >= 24 * MILLIS_PER_HOUR {
+                        bail!("schedule entries should start before midnight");
+                    }
+                    if !entry.background.is_background() {
+                        bail!("only background commands can be scheduled");
+                    }
+                    entry.background.validate()?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The number of commands including the layers of layer commands and scheduled commands
+    fn count_commands(&self) -> usize {
+        match self {
+            Command::Layer { layers } => {
+                1 + layers.iter().map(Command::count_commands).sum::<usize>()
+            }
+            Command::Schedule { entries, .. } => {
+                1 + entries
+                    .iter()
+                    .map(|entry| entry.background.count_commands())
+                    .sum::<usize>()
+            }
+            _ => 1,
+        }
+    }
+
+    /// A short description of the command like `static-image light.png`
+    pub fn summary(&self) -> String {
+        match self {
+            Command::StaticImage { path, .. } => format!("static-image {}", path.display()),
+            Command::ClockImage { dir, .. } => format!("clock-image {}", dir.display()),
+            Command::ClockHands { face, .. } => format!("clock-hands {}", face.display()),
+            Command::TextOverlay { template, .. } => format!("text-overlay {template:?}"),
+            Command::Layer { layers } => format!(
+                "layer {layers}",
+                layers = layers
+                    .iter()
+                    .map(|layer| format!("--layer {:?}", layer.summary()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Command::Plugin { path, .. } => format!("plugin {}", path.display()),
+            Command::Script { path } => format!("script {}", path.display()),
+            Command::Batch { file, .. } => format!("batch {}", file.display()),
+            Command::Pipe { command, .. } => format!("pipe {command:?}"),
+            Command::Shm { path, .. } => format!("shm {}", path.display()),
+            Command::LatestImage { pattern, .. } => format!("latest-image {}", pattern.display()),
+            Command::Slideshow { dir, .. } => format!("slideshow {}", dir.display()),
+            Command::Weekly { dir, .. } => format!("weekly {}", dir.display()),
+            Command::Schedule { entries, .. } => format!(
+                "schedule {entries}",
+                entries = entries
+                    .iter()
+                    .map(|entry| format!(
+                        "--at \"{start} {background}\"",
+                        start = format_time_of_day(entry.start),
+                        background = entry.background.summary()
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            command => format!("{command:?}"),
+        }
+    }
+
+    /// The command line words of a background command, which parse back into the same command,
+    /// `None` for other commands
+    pub fn to_args(&self) -> Option<Vec<String>> {
+        let words = match self {
+            Command::StaticImage {
+                path,
+                scaling,
+                margin_color,
+                watch,
+                span,
+            } => {
+                let mut words = Words::new("static-image");
+                words.arg(path.display());
+                words.value_enum("scaling", *scaling);
+                words.optional("margin-color", margin_color.as_ref());
+                words.flag("watch", *watch);
+                words.flag("span", *span);
+                words
+            }
+            Command::ClockImage {
+                dir,
+                file_template,
+                clock_step,
+                hours,
+                hour_dirs,
+                offset,
+                timezone,
+                clock_color,
+                color_cycle,
+                scaling,
+                margin_color,
+                underlay,
+                colorize,
+                mask_source,
+                interpolate,
+                sheets,
+                no_validate,
+            } => {
+                let mut words = Words::new("clock-image");
+                words.arg(dir.display());
+                words.arg(file_template);
+                words.arg(clock_step);
+                words.value_enum("hours", *hours);
+                words.option("hour-dirs", hour_dirs, &HourDirs::default());
+                words.optional("offset", *offset);
+                words.optional("timezone", timezone.as_ref());
+                words.optional("clock-color", clock_color.as_ref());
+                words.optional("color-cycle", *color_cycle);
+                words.value_enum("scaling", *scaling);
+                words.optional("margin-color", margin_color.as_ref());
+                words.optional("underlay", underlay.as_ref().map(|path| path.display()));
+                words.value_enum("colorize", *colorize);
+                words.value_enum("mask-source", *mask_source);
+                words.optional("interpolate", *interpolate);
+                words.flag("sheets", *sheets);
+                words.flag("no-validate", *no_validate);
+                words
+            }
+            Command::ClockHands {
+                face,
+                hour_hand,
+                minute_hand,
+                second_hand,
+                center,
+                scaling,
+                margin_color,
+                timezone,
+            } => {
+                let mut words = Words::new("clock-hands");
+                words.arg(face.display());
+                words.arg(hour_hand.display());
+                words.arg(minute_hand.display());
+                words.optional(
+                    "second-hand",
+                    second_hand.as_ref().map(|path| path.display()),
+                );
+                words.optional("center", center.map(|(x, y)| format!("{x},{y}")));
+                words.value_enum("scaling", *scaling);
+                words.optional("margin-color", margin_color.as_ref());
+                words.optional("timezone", timezone.as_ref());
+                words
+            }
+            Command::TextOverlay {
+                template,
+                font,
+                size,
+                color,
+                position,
+                align,
+                offset_x,
+                offset_y,
+                margin,
+            } => {
+                let mut words = Words::new("text-overlay");
+                words.arg(template);
+                words.arg(font.display());
+                words.option("size", *size, 48.0);
+                words.option("color", color.to_string(), "FFFFFF".to_string());
+                words.value_enum("position", *position);
+                words.value_enum("align", *align);
+                words.option("offset-x", *offset_x, 0);
+                words.option("offset-y", *offset_y, 0);
+                words.option("margin", *margin, 32);
+                words
+            }
+            Command::Layer { layers } => {
+                let mut words = Words::new("layer");
+                for layer in layers {
+                    words.optional("layer", Some(layer.command_line()?));
+                }
+                words
+            }
+            Command::LatestImage {
+                pattern,
+                watch,
+                scaling,
+                margin_color,
+            } => {
+                let mut words = Words::new("latest-image");
+                words.arg(pattern.display());
+                words.flag("watch", *watch);
+                words.value_enum("scaling", *scaling);
+                words.optional("margin-color", margin_color.as_ref());
+                words
+            }
+            Command::Slideshow {
+                dir,
+                interval,
+                order,
+                scaling,
+                margin_color,
+                span,
+            } => {
+                let mut words = Words::new("slideshow");
+                words.arg(dir.display());
+                words.option("interval", *interval, DEFAULT_SLIDESHOW_INTERVAL);
+                words.value_enum("order", *order);
+                words.value_enum("scaling", *scaling);
+                words.optional("margin-color", margin_color.as_ref());
+                words.flag("span", *span);
+                words
+            }
+            Command::Weekly {
+                dir,
+                scaling,
+                margin_color,
+            } => {
+                let mut words = Words::new("weekly");
+                words.arg(dir.display());
+                words.value_enum("scaling", *scaling);
+                words.optional("margin-color", margin_color.as_ref());
+                words
+            }
+            Command::Schedule { entries, fade } => {
+                let mut words = Words::new("schedule");
+                for entry in entries {
+                    let at = format!(
+                        "{start} {background}",
+                        start = format_time_of_day(entry.start),
+                        background = entry.background.command_line()?
+                    );
+                    words.optional("at", Some(at));
+                }
+                words.option("fade", *fade, DEFAULT_SCHEDULE_FADE);
+                words
+            }
+            Command::Plugin { path, params } => {
+                let mut words = Words::new("plugin");
+                words.arg(path.display());
+                if !params.is_empty() {
+                    words.arg(params);
+                }
+                words
+            }
+            Command::Script { path } => {
+                let mut words = Words::new("script");
+                words.arg(path.display());
+                words
+            }
+            Command::Pipe {
+                command,
+                fps,
+                restart,
+            } => {
+                let mut words = Words::new("pipe");
+                words.arg(command);
+                words.option("fps", *fps, 20);
+                words.flag("restart", *restart);
+                words
+            }
+            Command::Shm {
+                path,
+                width,
+                height,
+            } => {
+                let mut words = Words::new("shm");
+                words.arg(path.display());
+                words.arg(width);
+                words.arg(height);
+                words
+            }
+            _ => return None,
+        };
+        Some(words.0)
+    }
+
+    /// The words of [`Command::to_args`] quoted for a shell and joined by spaces
+    pub fn command_line(&self) -> Option<String> {
+        let words = self.to_args()?;
+        Some(
+            words
+                .iter()
+                .map(|word| shell_quote(word))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )
+    }
+
+    /// Detect the clock step of clock images given none, also within layers, schedules and
+    /// batches, returns a message naming each detected step
+    ///
+    /// The step is detected like the renderer would, sending the command with the detected step
+    /// keeps a frame set changed later from being read differently.
+    pub fn detect_clock_steps(&mut self) -> anyhow::Result<Vec<String>> {
+        let mut detected = Vec::new();
+        match self {
+            Command::ClockImage {
+                dir,
+                file_template,
+                clock_step: clock_step @ 0,
+                hours,
+                hour_dirs,
+                sheets,
+                ..
+            } => {
+                let template = FrameTemplate::load(dir, file_template, hour_dirs, *hours)?;
+                *clock_step = detected_clock_step(dir, &template, *sheets)?;
+                detected.push(format!(
+                    "detected a clock step of {clock_step}ms in {dir}",
+                    dir = dir.display()
+                ));
+            }
+            Command::Layer { layers } => {
+                for layer in layers {
+                    detected.extend(layer.detect_clock_steps()?);
+                }
+            }
+            Command::Schedule { entries, .. } => {
+                for entry in entries {
+                    detected.extend(entry.background.detect_clock_steps()?);
+                }
+            }
+            Command::Batch { commands, .. } => {
+                for BatchEntry { line, command } in commands {
+                    detected.extend(
+                        command
+                            .detect_clock_steps()
+                            .with_context(|| format!("line {line}"))?,
+                    );
+                }
+            }
+            _ => {}
+        }
+        Ok(detected)
+    }
+
+    /// Whether the background depends on the layout of the outputs
+    pub fn spans(&self) -> bool {
+        match self {
+            Command::StaticImage { span, .. } | Command::Slideshow { span, .. } => *span,
+            Command::Layer { layers } => layers.iter().any(Command::spans),
+            Command::Schedule { entries, .. } => {
+                entries.iter().any(|entry| entry.background.spans())
+            }
+            _ => false,
+        }
+    }
+
+    /// Create the renderer of a background command for a `width` x `height` desktop, `span` is
+    /// the place of the window's output among all outputs if known
+    pub fn into_renderer(
+        self,
+        width: u32,
+        height: u32,
+        span: Option<OutputSpan>,
+    ) -> anyhow::Result<BackgroundRenderer> {
+        self.into_renderer_with_clock(width, height, span, Arc::new(SystemClock))
+    }
+
+    /// Create the renderer like [`Self::into_renderer`], reading the time from `clock`
+    pub fn into_renderer_with_clock(
+        self,
+        width: u32,
+        height: u32,
+        span: Option<OutputSpan>,
+        clock: Arc<dyn Clock>,
+    ) -> anyhow::Result<BackgroundRenderer> {
+        self.validate()?;
+        match self {
+            Command::StaticImage {
+                path,
+                scaling,
+                margin_color,
+                watch,
+                span: spanned,
+            } => {
+                let span = match (spanned, span) {
+                    (false, _) => None,
+                    (true, Some(span)) => Some(span),
+                    (true, None) => bail!(
+                        "the layout of the outputs is not known yet, can't span the image over them"
+                    ),
+                };
+                let margin_color = margin_color
+                    .as_ref()
+                    .map(ColorArg::rgb8)
+                    .transpose()?
+                    .unwrap_or_default();
+                let mut layout = FrameLayout::with_color(scaling, margin_color, width, height);
+                let image = if is_url(&path) || span.is_some() {
+                    let mut image = if is_url(&path) {
+                        fetch_image(&path.to_string_lossy())?
+                    } else {
+                        decode::open(&path)?
+                    };
+                    // Cropping to the part of the output needs the image at its full size
+                    if let Some(span) = span {
+                        image = span.crop(&image);
+                    }
+                    layout.place(&image, width, height)
+                } else {
+                    layout.load(&path, width, height)?
+                };
+
+                let watcher = watch
+                    .then(|| {
+                        ImageWatcher::spawn(
+                            path.clone(),
+                            scaling,
+                            margin_color,
+                            span,
+                            width,
+                            height,
+                        )
+                    })
+                    .transpose()?;
+
+                Ok(BackgroundRenderer::StaticImage {
+                    path,
+                    image,
+                    redraw: true,
+                    watcher,
+                })
+            }
+            Command::ClockImage {
+                dir,
+                file_template,
+                clock_step,
+                hours,
+                hour_dirs,
+                offset,
+                timezone,
+                clock_color,
+                color_cycle,
+                scaling,
+                margin_color,
+                underlay,
+                colorize,
+                mask_source,
+                interpolate,
+                sheets,
+                no_validate,
+            } => {
+                let file_template = FrameTemplate::load(&dir, &file_template, &hour_dirs, hours)?;
+                if file_template.hour_dirs().is_none() && hour_dirs != HourDirs::default() {
+                    eprintln!(
+                        "warning: ignoring --hour-dirs {hour_dirs}, the file template \
+                         {file_template} names its own folders"
+                    );
+                }
+                let clock_step = match clock_step {
+                    0 => detected_clock_step(&dir, &file_template, sheets)?,
+                    clock_step => clock_step,
+                };
+                validate_clock_dir(&dir, &file_template, hours, clock_step, !no_validate)?;
+                let timezone = timezone.as_deref().map(parse_timezone).transpose()?;
+
+                let cycle = hours.cycle_millis();
+                let offset = offset.map_or(0, |offset| offset.0);
+                if offset.unsigned_abs() >= cycle as u64 {
+                    eprintln!(
+                        "warning: clock offset of {offset}ms is longer than the {hours} hour cycle, \
+                         using {reduced}ms",
+                        hours = hours.count(),
+                        reduced = offset % cycle as i64,
+                    );
+                }
+                let offset = offset.rem_euclid(cycle as i64) as u32;
+                let color = clock_color
+                    .as_deref()
+                    .map(|color| {
+                        let color_cycle = color_cycle.map_or(cycle as u64, |cycle| cycle.0);
+                        ClockColor::parse(color, color_cycle as f32 / 60.0 / 1000.0)
+                    })
+                    .transpose()?;
+
+                let margin_color = margin_color
+                    .as_ref()
+                    .map(ColorArg::rgb8)
+                    .transpose()?
+                    .unwrap_or_default();
+                let underlay = underlay
+                    .map(|underlay| {
+                        anyhow::Ok(image::imageops::resize(
+                            &decode::open(&underlay)?,
+                            width,
+                            height,
+                            image::imageops::FilterType::Triangle,
+                        ))
+                    })
+                    .transpose()?;
+
+                let (layout, colorize) = match colorize {
+                    ColorizeMode::Multiply => (
+                        match underlay {
+                            Some(underlay) => FrameLayout::new(scaling, underlay),
+                            None => FrameLayout::with_color(scaling, margin_color, width, height),
+                        },
+                        Colorize::Multiply { base: margin_color },
+                    ),
+                    ColorizeMode::Mask => (
+                        FrameLayout::new(scaling, RgbaImage::new(width, height)),
+                        Colorize::Mask {
+                            source: mask_source,
+                            base: underlay.unwrap_or_else(|| {
+                                RgbaImage::from_pixel(
+                                    width,
+                                    height,
+                                    Rgba([margin_color[0], margin_color[1], margin_color[2], 255]),
+                                )
+                            }),
+                        },
+                    ),
+                };
+
+                Ok(BackgroundRenderer::ClockImage {
+                    source: FrameSource::new(dir, file_template, sheets),
+                    clock_step,
+                    cycle,
+                    offset,
+                    timezone,
+                    buffered_images: VecDeque::new(),
+                    color,
+                    layout,
+                    colorize,
+                    missing_frames: Default::default(),
+                    interpolate,
+                    previous_image: None,
+                    fading: false,
+                    last_millis: None,
+                    load_time: None,
+                    clock,
+                })
+            }
+            Command::ClockHands {
+                face,
+                hour_hand,
+                minute_hand,
+                second_hand,
+                center,
+                scaling,
+                margin_color,
+                timezone,
+            } => {
+                let margin_color = margin_color
+                    .as_ref()
+                    .map(ColorArg::rgb8)
+                    .transpose()?
+                    .unwrap_or_default();
+                let hands = ClockHands::load(
+                    &face,
+                    &hour_hand,
+                    &minute_hand,
+                    second_hand.as_deref(),
+                    center,
+                    FrameLayout::with_color(scaling, margin_color, width, height),
+                    timezone.as_deref().map(parse_timezone).transpose()?,
+                    width,
+                    height,
+                )?;
+                Ok(BackgroundRenderer::ClockHands { hands, clock })
+            }
+            Command::TextOverlay {
+                template,
+                font,
+                size,
+                color,
+                position,
+                align,
+                offset_x,
+                offset_y,
+                margin,
+            } => Ok(BackgroundRenderer::TextOverlay {
+                template: TextTemplate::parse(&template)?,
+                font: ab_glyph::FontVec::try_from_vec(std::fs::read(&font)?).map_err(|_| {
+                    anyhow::anyhow!("{font} is not a valid font file", font = font.display())
+                })?,
+                style: TextStyle {
+                    size,
+                    color: color.rgb8()?,
+                    anchor: position,
+                    align,
+                    offset: (offset_x, offset_y),
+                    margin,
+                },
+                text: None,
+                drawn: Damage::Full,
+                clock,
+            }),
+            Command::Plugin { path, params } => Ok(BackgroundRenderer::Plugin {
+                plugin: Plugin::load(&path, &params, width, height)?,
+                clock,
+            }),
+            Command::Script { path } => Ok(BackgroundRenderer::Script {
+                script: Script::load(&path, width, height)?,
+                clock,
+            }),
+            Command::Pipe {
+                command,
+                fps,
+                restart,
+            } => Ok(BackgroundRenderer::Pipe {
+                pipe: FramePipe::spawn(command, fps, restart, width, height)?,
+            }),
+            Command::Shm {
+                path,
+                width: shared_width,
+                height: shared_height,
+            } => Ok(BackgroundRenderer::Shm {
+                shared: SharedFrame::open(&path, shared_width, shared_height)?,
+            }),
+            Command::Layer { layers } => Ok(BackgroundRenderer::Stack {
+                layers: layers
+                    .into_iter()
+                    .map(|layer| {
+                        Ok((
+                            layer.into_renderer_with_clock(width, height, span, clock.clone())?,
+                            vec![0; width as usize * height as usize * 4],
+                        ))
+                    })
+                    .collect::<anyhow::Result<_>>()?,
+            }),
+            Command::LatestImage {
+                pattern,
+                watch,
+                scaling,
+                margin_color,
+            } => {
+                let margin_color = margin_color
+                    .as_ref()
+                    .map(ColorArg::rgb8)
+                    .transpose()?
+                    .unwrap_or_default();
+                let (path, modified, image) =
+                    newest_image(&pattern, scaling, margin_color, width, height)?;
+                let watcher = watch
+                    .then(|| {
+                        LatestWatcher::spawn(
+                            pattern.clone(),
+                            path.clone(),
+                            modified,
+                            scaling,
+                            margin_color,
+                            width,
+                            height,
+                        )
+                    })
+                    .transpose()?;
+                Ok(BackgroundRenderer::LatestImage {
+                    pattern,
+                    path,
+                    image,
+                    redraw: true,
+                    watcher,
+                })
+            }
+            Command::Slideshow {
+                dir,
+                interval,
+                order,
+                scaling,
+                margin_color,
+                span: spanned,
+            } => {
+                let span = match (spanned, span) {
+                    (false, _) => None,
+                    (true, Some(span)) => Some(span),
+                    (true, None) => bail!(
+                        "the layout of the outputs is not known yet, can't span the images over \
+                         them"
+                    ),
+                };
+                let mut playlist = Playlist::new(dir, order)?;
+                let (path, renderer) =
+                    playlist.next_renderer(scaling, &margin_color, span, width, height)?;
+                Ok(BackgroundRenderer::Slideshow {
+                    playlist,
+                    interval,
+                    scaling,
+                    margin_color,
+                    span,
+                    path,
+                    shown_at: clock.now(),
+                    renderer: Box::new(renderer),
+                    clock,
+                })
+            }
+            Command::Weekly {
+                dir,
+                scaling,
+                margin_color,
+            } => {
+                if !dir.is_dir() {
+                    bail!("{dir} is not a directory", dir = dir.display());
+                }
+                let date = clock.now().date_naive();
+                let (path, renderer) = day_renderer(
+                    &dir,
+                    date.weekday(),
+                    scaling,
+                    margin_color.clone(),
+                    width,
+                    height,
+                )?;
+                let missing_days = missing_days(&dir);
+                if !missing_days.is_empty() {
+                    eprintln!(
+                        "warning: {dir} has no images for {days}, showing its default image then",
+                        dir = dir.display(),
+                        days = missing_days.join(", "),
+                    );
+                }
+                Ok(BackgroundRenderer::Weekly {
+                    dir,
+                    scaling,
+                    margin_color,
+                    missing_days,
+                    date,
+                    path,
+                    renderer: Box::new(renderer),
+                    clock,
+                })
+            }
+            Command::Schedule { entries, fade } => {
+                // The current entry is loaded right away so its errors reach the client
+                let active = ScheduleEntry::active(&entries, day_millis(clock.now(), None));
+                let renderer = entries[active]
+                    .background
+                    .clone()
+                    .into_renderer_with_clock(width, height, span, clock.clone())?;
+                Ok(BackgroundRenderer::Schedule {
+                    entries,
+                    active,
+                    renderer: Box::new(renderer),
+                    entry_frame: vec![0; width as usize * height as usize * 4],
+                    fade,
+                    fade_from: None,
+                    span,
+                    clock,
+                })
+            }
+            command @ (Command::Start(_)
+            | Command::RenderFrames(_)
+            | Command::WarmCache(_)
+            | Command::CheckClockDir(_)
+            | Command::RepairClockDir(_)
+            | Command::ResampleClockDir(_)
+            | Command::Check(_)
+            | Command::RenderAt(_)
+            | Command::Preview(_)
+            | Command::Stop { .. }
+            | Command::Restart
+            | Command::Status
+            | Command::Ping
+            | Command::Export
+            | Command::SaveProfile { .. }
+            | Command::LoadProfile { .. }
+            | Command::Profiles
+            | Command::Batch { .. }
+            | Command::Back
+            | Command::Forward
+            | Command::History
+            | Command::HistoryJump { .. }
+            | Command::Palette { .. }
+            | Command::Reload
+            | Command::Pause
+            | Command::Resume
+            | Command::Vsync { .. }
+            | Command::DebugOverlay { .. }
+            | Command::InjectFault { .. }) => {
+                bail!("only background commands can be shown, not {command:?}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_batch_files() {
+        let text = "# evening\n\nstatic-image night.png\n  pause\nreload --bogus\n";
+        let error = parse_batch(text).unwrap_err().to_string();
+        assert!(error.starts_with("line 5: "), "{error}");
+
+        let entries = parse_batch(&text.replace(" --bogus", "")).unwrap();
+        let lines: Vec<usize> = entries.iter().map(|entry| entry.line).collect();
+        assert_eq!(lines, [3, 4, 5]);
+        assert_eq!(entries[1].command, Command::Pause);
+
+        let batch = Command::Batch {
+            file: PathBuf::from("-"),
+            atomic: true,
+            commands: entries,
+        };
+        let error = batch.validate().unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "line 4: an atomic batch can only hold background commands"
+        );
+    }
+
+    #[test]
+    fn finds_the_images_to_download() {
+        let text = "static-image https://example.com/day.png\n\
+                    layer --layer 'static-image night.png' \
+                    --layer 'static-image http://example.com/stars.png'\n\
+                    static-image http-images/moon.png";
+        let batch = Command::Batch {
+            file: PathBuf::from("-"),
+            atomic: false,
+            commands: parse_batch(text).unwrap(),
+        };
+        assert_eq!(
+            batch.urls(),
+            [
+                "https://example.com/day.png",
+                "http://example.com/stars.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_picked_images_like_the_command_picking_them() {
+        let slideshow =
+            parse_layer("slideshow photos --interval 1m --scaling fit --margin-color red");
+        let shown = slideshow
+            .unwrap()
+            .showing(Path::new("photos/beach.png"))
+            .unwrap();
+        assert_eq!(
+            shown,
+            parse_layer("static-image photos/beach.png --scaling fit --margin-color red").unwrap()
+        );
+        let clock = parse_layer("clock-image clock %H/%M.png").unwrap();
+        assert_eq!(clock.showing(Path::new("clock/00/00.png")), None);
+    }
+
+    #[test]
+    fn refuses_to_show_commands_which_are_not_backgrounds() {
+        let text = "start 1920 1080 class\n\
+                    render-frames 10 10 'static-image a.png' --out frames\n\
+                    warm-cache 10 10 'clock-image clock %H/%M.png'\n\
+                    stop\nrestart\nreload\npause\nresume\nstatus\nping\nexport\n\
+                    save-profile day\nload-profile day\nprofiles\nbatch commands.txt\n\
+                    back\nforward\nhistory\nhistory-jump 2\npalette\nvsync true\n\
+                    debug-overlay false\ninject-fault timeout";
+        let entries = parse_batch(text).unwrap();
+        assert_eq!(entries.len(), 23);
+        for BatchEntry { line, command } in entries {
+            assert!(!command.is_background(), "line {line}");
+            let Err(error) = command.into_renderer(1, 1, None) else {
+                panic!("line {line} was shown");
+            };
+            let error = error.to_string();
+            assert!(
+                error.starts_with("only background commands can be shown"),
+                "line {line}: {error}"
+            );
+        }
+
+        // Nor does a layer blank the background
+        let layer = parse_layer("layer --layer 'start 10 10 class' --layer 'static-image a.png'");
+        let Err(error) = layer.unwrap().into_renderer(1, 1, None) else {
+            panic!("the layer was shown");
+        };
+        let error = format!("{error:#}");
+        assert!(error.contains("only background commands"), "{error}");
+    }
+
+    #[test]
+    fn formats_command_lines_which_parse_back() {
+        for line in [
+            "clock-image 'clocks/my clock' '%H/%M.png' 50 --hours 24 --offset -1h30m \
+             --clock-color RAINBOW --interpolate 0.5",
+            "clock-image clocks %M.png 60000 --clock-color 'FF0000, rgb(0,255,0),navy@80' \
+             --color-cycle 1h30m",
+            "schedule --at \"07:00 static-image light.png\" --at '19:30 text-overlay \
+             \"it'\\''s {time:%H:%M}\" font.ttf -c 00FF00' --fade 0s",
+            "text-overlay hi font.ttf --offset-x -5 --size 12.5",
+            "slideshow photos --interval 1h30m --order shuffle --span",
+            "plugin lib.so --speed=2",
+            "script background.lua",
+        ] {
+            let command = parse_layer(line).unwrap();
+            let formatted = command.command_line().unwrap();
+            assert_eq!(parse_layer(&formatted).unwrap(), command, "{formatted}");
+        }
+        assert_eq!(Command::Pause.to_args(), None);
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn takes_durations_with_units() {
+        let interval = |line: &str| match parse_layer(line) {
+            Ok(command @ Command::Slideshow { interval, .. }) => {
+                command.validate().map(|()| interval.as_duration())
+            }
+            Ok(command) => panic!("{line} is not a slideshow: {command:?}"),
+            Err(error) => Err(anyhow::anyhow!(error)),
+        };
+        assert_eq!(interval("slideshow photos").unwrap().as_secs(), 300);
+        assert_eq!(
+            interval("slideshow photos --interval 5m")
+                .unwrap()
+                .as_secs(),
+            300
+        );
+        assert!(interval("slideshow photos --interval 300").is_err());
+        assert!(interval("slideshow photos --interval 999ms").is_err());
+
+        let Ok(Command::Schedule { fade, .. }) =
+            parse_layer("schedule --at '07:00 static-image a.png'")
+        else {
+            panic!("the schedule was not parsed");
+        };
+        assert_eq!(fade, DurationArg(1000));
+    }
+
+    #[test]
+    fn limits_the_frame_rate() {
+        assert_eq!(parse_fps("60"), Ok(60.0));
+        assert_eq!(parse_fps("0.1"), Ok(0.1));
+        assert!(parse_fps("0.05").is_err());
+        assert!(parse_fps("241").is_err());
+        assert!(parse_fps("NaN").is_err());
+    }
+
+    #[test]
+    fn looks_up_timezones_by_name() {
+        assert_eq!(parse_timezone("Europe/Berlin").unwrap(), Tz::Europe__Berlin);
+        let error = parse_timezone("Europe/Atlantis").unwrap_err();
+        assert_eq!(error.to_string(), "unknown timezone \"Europe/Atlantis\"");
+        assert!(parse_timezone("../etc/passwd").is_err());
+    }
+
+    #[test]
+    fn rejects_templates_naming_one_file_for_the_whole_cycle() {
+        let validate = |line: &str| parse_layer(line).unwrap().validate();
+        assert!(validate("clock-image clock 'frame_%r.png' --hour-dirs flat").is_ok());
+        assert!(validate("clock-image clock 'clock.gif'").is_ok());
+        assert!(validate("clock-image clock 'night/clock.png'").is_err());
+        assert!(validate("clock-image clock 'clock.png' --hour-dirs flat").is_err());
+        assert!(validate("clock-image clock 'clock.png' --hour-dirs flat --sheets").is_ok());
+        assert!(validate("clock-image clock '%q.png'").is_err());
+    }
+}
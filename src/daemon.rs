@@ -0,0 +1,1803 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    os::{fd::AsRawFd, unix::fs::DirBuilderExt},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use chrono::{DateTime, Local};
+use interprocess::local_socket::{LocalSocketListener, LocalSocketStream};
+use pixels::wgpu::PresentMode;
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{Event, WindowEvent},
+    event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy, EventLoopWindowTarget},
+    monitor::MonitorHandle,
+    platform::{wayland::EventLoopBuilderExtWayland, x11::EventLoopBuilderExtX11},
+};
+
+use crate::{
+    command::{Backend, BatchEntry, Command, StartArgs},
+    decode,
+    download::{self, Prefetched},
+    export::{OutputSetup, Setup},
+    gpu::GpuOptions,
+    history::{History, HistoryEntry},
+    ipc::{
+        claim_socket, read_request, runtime_dir, socket_path, write_message, Claimed, Reply,
+        Request,
+    },
+    output::{buffer_size, open_window, Mirror, OutputWindow},
+    palette,
+    render::BackgroundRenderer,
+    restart::{self, Handover},
+    signals,
+    state::{self, profile_path, state_path, SavedState},
+};
+
+/// How often the connected outputs are checked for hotplug and mode changes
+const MONITOR_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a ping waits for the event loop to tick before reporting it as not responding
+const PING_WAIT: Duration = Duration::from_millis(250);
+/// The most requests handled in one tick, the rest wait for the next one so a burst of commands
+/// doesn't hold up rendering for long
+const MAX_REQUESTS_PER_TICK: usize = 32;
+/// How long a client may take to send its request, it holds up the requests accepted after it
+/// until then
+const REQUEST_READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The desktop program, which shows the background and answers the commands sent to its socket
+pub struct Daemon {
+    start: StartArgs,
+    socket_name: String,
+    /// Where the socket is bound, see [`socket_path`]
+    socket_path: String,
+    socket: LocalSocketListener,
+    /// What was taken over from the daemon this one replaced by a restart
+    handover: Option<Handover>,
+    /// The file advertising an abstract socket name to `list`, see [`state::advertise`]
+    advertisement: Option<PathBuf>,
+}
+
+impl Daemon {
+    /// Listen for commands on the socket `socket_name`, creating the runtime directory for plain
+    /// names
+    ///
+    /// Blocks the termination signals on the calling thread, so it must be called before any other
+    /// thread is started for them to be handled by the daemon.
+    pub fn bind(start: StartArgs, socket_name: &str) -> anyhow::Result<Self> {
+        signals::block()?;
+        decode::configure(start.decode_options());
+        let handover = Handover::take();
+        let socket_path = socket_path(socket_name)?;
+        if let Some(dir) = runtime_dir().filter(|dir| Path::new(&socket_path).starts_with(dir)) {
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .with_context(|| format!("could not create {dir}", dir = dir.display()))?;
+        }
+        // The socket file of the replaced daemon is left behind, its listener is closed already
+        if handover.is_some() {
+            if !socket_path.starts_with('@') {
+                let _ = std::fs::remove_file(&socket_path);
+            }
+        } else if claim_socket(socket_name, start.replace)? == Claimed::Replaced {
+            eprintln!("stopped the desktop program running on {socket_name}");
+        }
+        let socket = LocalSocketListener::bind(socket_path.as_str())
+            .with_context(|| format!("could not listen on {socket_path}"))?;
+        // Abstract sockets leave no file behind, but have no file to be listed by either
+        let advertisement = socket_path.starts_with('@').then(|| {
+            state::advertise(&socket_path)
+                .map_err(|error| {
+                    eprintln!("warning: could not advertise {socket_path} to list: {error:#}")
+                })
+                .ok()
+        });
+        Ok(Daemon {
+            start,
+            socket_name: socket_name.to_string(),
+            socket_path,
+            socket,
+            handover,
+            advertisement: advertisement.flatten(),
+        })
+    }
+
+    /// Open the windows and show the background until the daemon is stopped
+    pub fn run(mut self) -> anyhow::Result<()> {
+        let result = run(
+            &self.start,
+            self.socket,
+            &self.socket_name,
+            self.handover.as_mut(),
+        );
+        if let (Err(error), Some(handover)) = (&result, self.handover.as_mut()) {
+            handover.reply(&Err(format!(
+                "the restarted desktop program failed: {error:#}"
+            )));
+        }
+
+        remove_socket(&self.socket_path, self.advertisement.as_deref());
+        result
+    }
+
+    /// Wait for SIGTERM or SIGINT without opening any window or answering commands, then clean
+    /// up like [`Daemon::run`] does after a shutdown signal
+    ///
+    /// The shutdown path of the daemon for tests, which have no display to open windows on.
+    #[doc(hidden)]
+    pub fn wait_for_shutdown(self) -> anyhow::Result<()> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        signals::spawn(move |event| sender.send(event).is_ok());
+        while let Ok(event) = receiver.recv() {
+            if let DaemonEvent::Shutdown(signal) = event {
+                eprintln!("received signal {signal}, shutting down");
+                break;
+            }
+        }
+        remove_socket(&self.socket_path, self.advertisement.as_deref());
+        Ok(())
+    }
+}
+
+/// Remove the file of the socket at `socket_path` or the advertisement of an abstract socket
+fn remove_socket(socket_path: &str, advertisement: Option<&Path>) {
+    // The listener is never dropped by the blocked accept thread, remove its file here
+    if !socket_path.starts_with('@') {
+        let _ = std::fs::remove_file(socket_path);
+    }
+    if let Some(path) = advertisement {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Events sent to the event loop from background threads
+pub enum DaemonEvent {
+    /// A client sent a request to the control socket, read on a thread of its own, with the
+    /// position of the connection in accept order and `None` if it was answered there already
+    Connection(u64, Option<Box<Connection>>),
+    /// Accepting connections on the control socket failed
+    AcceptFailed(std::io::Error),
+    /// Wakes the event loop up to tell whether it still responds
+    Wake,
+    /// The login session was locked or unlocked
+    #[cfg_attr(not(feature = "lock-detection"), allow(dead_code))]
+    Locked(bool),
+    /// A termination signal was received
+    Shutdown(libc::c_int),
+    /// SIGHUP was received
+    Reload,
+    /// The images of a request were downloaded
+    Downloaded(Box<PendingDownload>),
+}
+
+/// Milliseconds on the monotonic clock of the system, comparable between processes
+fn monotonic_millis() -> u64 {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: the time is written to a valid location
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut time) };
+    time.tv_sec as u64 * 1000 + time.tv_nsec as u64 / 1_000_000
+}
+
+/// When the event loop last completed a tick and what the outputs show, shared with the threads
+/// answering pings so they can tell a busy event loop from a healthy one
+#[derive(Clone)]
+struct Heartbeat {
+    last_tick: Arc<AtomicU64>,
+    started: Instant,
+    /// One line per output, updated by the event loop when the backgrounds change
+    outputs: Arc<Mutex<Vec<String>>>,
+}
+
+impl Heartbeat {
+    fn new() -> Self {
+        Heartbeat {
+            last_tick: Arc::new(AtomicU64::new(monotonic_millis())),
+            started: Instant::now(),
+            outputs: Arc::default(),
+        }
+    }
+
+    /// Record a completed tick
+    fn beat(&self) {
+        self.last_tick.store(monotonic_millis(), Ordering::Relaxed);
+    }
+
+    /// Record the size and background of every output, those of the mirror in mirror mode
+    fn show(&self, outputs: &HashMap<String, OutputWindow>, mirror: Option<&Mirror>) {
+        let describe = |name: &str, width: u32, height: u32, command: Option<&Command>| {
+            format!(
+                "output {name}: {width}x{height} {background}",
+                background = command.map_or("none".to_string(), Command::summary)
+            )
+        };
+        let lines = match mirror {
+            Some(mirror) => vec![describe(
+                "mirror",
+                mirror.width,
+                mirror.height,
+                mirror.command.as_ref(),
+            )],
+            None => {
+                let mut names: Vec<&String> = outputs.keys().collect();
+                names.sort();
+                names
+                    .into_iter()
+                    .map(|name| {
+                        let output = &outputs[name];
+                        describe(name, output.width, output.height, output.command.as_ref())
+                    })
+                    .collect()
+            }
+        };
+        *self.outputs.lock().unwrap() = lines;
+    }
+
+    /// The reply to a ping, the event loop is woken up and given a moment to tick first since it
+    /// sleeps while the background doesn't change
+    fn ping(&self, proxy: &EventLoopProxy<DaemonEvent>) -> Reply {
+        let asked = monotonic_millis();
+        let awake = proxy.send_event(DaemonEvent::Wake).is_ok();
+        let deadline = Instant::now() + PING_WAIT;
+        while awake && self.last_tick.load(Ordering::Relaxed) < asked && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let last_tick = self.last_tick.load(Ordering::Relaxed);
+        let event_loop = if last_tick >= asked {
+            "responding".to_string()
+        } else {
+            format!(
+                "not responding, last tick {age} ms ago",
+                age = monotonic_millis().saturating_sub(last_tick)
+            )
+        };
+        let mut reply = format!(
+            "pid: {pid}\nversion: {version}\nuptime: {uptime}\nlast tick: {last_tick}\n\
+             event loop: {event_loop}",
+            pid = std::process::id(),
+            version = env!("CARGO_PKG_VERSION"),
+            uptime = self.started.elapsed().as_secs(),
+        );
+        for line in self.outputs.lock().unwrap().iter() {
+            reply = format!("{reply}\n{line}");
+        }
+        Ok(reply)
+    }
+}
+
+/// A client connection and the request read from it
+type Connection = (LocalSocketStream, anyhow::Result<Request>);
+
+/// Make reads from `stream` fail after `timeout`
+fn set_read_timeout(stream: &LocalSocketStream, timeout: Duration) -> std::io::Result<()> {
+    let time = libc::timeval {
+        tv_sec: timeout.as_secs() as libc::time_t,
+        tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+    };
+    // SAFETY: the option value is a timeval as SO_RCVTIMEO expects, with its size
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &time as *const libc::timeval as *const libc::c_void,
+            std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Read the request of a client and hand it to the event loop, pings are answered right here so
+/// they are answered while the event loop is busy
+fn read_connection(
+    sequence: u64,
+    mut stream: LocalSocketStream,
+    proxy: EventLoopProxy<DaemonEvent>,
+    heartbeat: Heartbeat,
+) {
+    if let Err(error) = set_read_timeout(&stream, REQUEST_READ_TIMEOUT) {
+        eprintln!("warning: could not limit the time to read a request: {error}");
+    }
+    let request = read_request(&mut stream);
+    if let Ok(Request {
+        command: Command::Ping,
+        ..
+    }) = request
+    {
+        if let Err(error) = write_message(&mut stream, &heartbeat.ping(&proxy)) {
+            eprintln!("could not send reply: {error}");
+        }
+        let _ = proxy.send_event(DaemonEvent::Connection(sequence, None));
+        return;
+    }
+    let _ = proxy.send_event(DaemonEvent::Connection(
+        sequence,
+        Some(Box::new((stream, request))),
+    ));
+}
+
+/// The requests read on the connection threads, handed out in the order their connections were
+/// accepted in, which the threads may finish reading them out of
+struct RequestQueue<T> {
+    /// The requests read before all of those accepted earlier, by position in accept order
+    queued: BTreeMap<u64, Option<T>>,
+    /// The position of the next request to hand out
+    next: u64,
+}
+
+impl<T> RequestQueue<T> {
+    fn new() -> Self {
+        RequestQueue {
+            queued: BTreeMap::new(),
+            next: 0,
+        }
+    }
+
+    /// Add the request of the connection accepted at `sequence`, `None` if it needs no handling
+    fn push(&mut self, sequence: u64, request: Option<T>) {
+        self.queued.insert(sequence, request);
+    }
+
+    /// Take up to `max` of the requests which are next in accept order
+    fn ready(&mut self, max: usize) -> Vec<T> {
+        let mut ready = Vec::new();
+        while ready.len() < max {
+            let Some(request) = self.queued.remove(&self.next) else {
+                break;
+            };
+            self.next += 1;
+            ready.extend(request);
+        }
+        ready
+    }
+
+    /// Whether the next request in accept order is waiting already
+    fn has_ready(&self) -> bool {
+        self.queued.contains_key(&self.next)
+    }
+}
+
+/// Which of the requests handled in one tick are superseded by a later background command for the
+/// same output or for all outputs, only the last one of them needs to be shown
+///
+/// Other commands, and requests which couldn't be read, are never superseded and keep the
+/// background commands before them from being superseded, they may depend on them.
+fn superseded(requests: &[Option<&Request>]) -> Vec<bool> {
+    let mut superseded = vec![false; requests.len()];
+    // The outputs covered by the background commands after the current one
+    let mut all_covered = false;
+    let mut covered: Vec<Option<&str>> = Vec::new();
+    for (request, superseded) in requests.iter().zip(&mut superseded).rev() {
+        match request {
+            Some(Request {
+                output, command, ..
+            }) if command.is_background() => {
+                *superseded = all_covered || covered.contains(&output.as_deref());
+                all_covered |= output.is_none();
+                covered.push(output.as_deref());
+            }
+            _ => {
+                all_covered = false;
+                covered.clear();
+            }
+        }
+    }
+    superseded
+}
+
+/// A request waiting for the images it shows, which are downloaded on a thread of their own so the
+/// event loop keeps rendering meanwhile
+pub struct PendingDownload {
+    stream: LocalSocketStream,
+    request: Request,
+    /// The position of the request in the order they are handed out, a later request changing the
+    /// outputs it addresses meanwhile supersedes it
+    position: u64,
+    /// Taken by the renderers instead of downloading the images again, until the request is handled
+    images: Vec<Prefetched>,
+}
+
+impl PendingDownload {
+    /// Download the images at `urls` on a thread of its own, then hand the request back to the
+    /// event loop through `proxy`, or reply with the error if a download fails
+    fn start(mut self, urls: Vec<String>, proxy: EventLoopProxy<DaemonEvent>) {
+        thread::spawn(
+            move || match urls.iter().map(|url| download::prefetch(url)).collect() {
+                Ok(images) => {
+                    self.images = images;
+                    let _ = proxy.send_event(DaemonEvent::Downloaded(Box::new(self)));
+                }
+                Err(error) => {
+                    let reply: Reply = Err(log_failure(error));
+                    if let Err(error) = write_message(&mut self.stream, &reply) {
+                        eprintln!("could not send reply: {error}");
+                    }
+                }
+            },
+        );
+    }
+}
+
+/// A palette request waiting for the next render of the output its colors are taken from
+struct PendingPalette {
+    stream: LocalSocketStream,
+    /// The output the colors are taken from, the mirrored frame in mirror mode
+    output: String,
+    count: usize,
+    json: bool,
+}
+
+impl PendingPalette {
+    /// Find the colors of `frame` and reply with them on a worker thread, finding them in a
+    /// large frame takes longer than a tick
+    fn answer(self, frame: &[u8], width: u32, height: u32) {
+        let samples = palette::sample(frame, width, height);
+        let PendingPalette {
+            mut stream,
+            count,
+            json,
+            ..
+        } = self;
+        thread::spawn(move || {
+            let reply: Reply = Ok(palette::describe(&palette::extract(&samples, count), json));
+            if let Err(error) = write_message(&mut stream, &reply) {
+                eprintln!("could not send reply: {error}");
+            }
+        });
+    }
+
+    /// Reply with an error instead of the colors
+    fn fail(mut self, message: &str) {
+        if let Err(error) = write_message(&mut self.stream, &Reply::Err(message.to_string())) {
+            eprintln!("could not send reply: {error}");
+        }
+    }
+}
+
+/// A request sent with `--wait`, answered once a frame of its background was presented on all of
+/// the outputs it was sent to
+struct PendingShown {
+    stream: LocalSocketStream,
+    reply: String,
+    /// The outputs still waited for, with their count of presented frames when the request came
+    outputs: Vec<(String, u64)>,
+}
+
+/// Answer the waiting requests whose backgrounds are shown by now, outputs which are gone aren't
+/// waited for any more
+fn answer_shown(pending: &mut Vec<PendingShown>, outputs: &HashMap<String, OutputWindow>) {
+    let mut idx = 0;
+    while idx < pending.len() {
+        pending[idx].outputs.retain(|(name, count)| {
+            outputs
+                .get(name)
+                .is_some_and(|output| !output.presented_since(*count))
+        });
+        if !pending[idx].outputs.is_empty() {
+            idx += 1;
+            continue;
+        }
+        let PendingShown {
+            mut stream, reply, ..
+        } = pending.remove(idx);
+        if let Err(error) = write_message(&mut stream, &Reply::Ok(reply)) {
+            eprintln!("could not send reply: {error}");
+        }
+    }
+}
+
+/// Advance every renderer on the next update, after they were replaced or resynced, as their next
+/// ticks may be a whole clock step away or not due at all
+fn tick_all(outputs: &mut HashMap<String, OutputWindow>, mirror: Option<&mut Mirror>) {
+    outputs.values_mut().for_each(OutputWindow::tick_now);
+    if let Some(mirror) = mirror {
+        mirror.tick_now();
+    }
+}
+
+/// The name, position and size of an output
+type MonitorState = (Option<String>, PhysicalPosition<i32>, PhysicalSize<u32>);
+
+/// The outputs currently connected
+fn connected_monitors(elwt: &EventLoopWindowTarget<DaemonEvent>) -> Vec<MonitorState> {
+    elwt.available_monitors()
+        .map(|monitor| (monitor.name(), monitor.position(), monitor.size()))
+        .collect()
+}
+
+/// The name of an output, made up from its position among all outputs if it has none
+fn output_name(monitor: &MonitorHandle, idx: usize) -> String {
+    monitor.name().unwrap_or_else(|| format!("output-{idx}"))
+}
+
+/// Open a window on every connected output which has none yet and close the windows of outputs
+/// which were disconnected, new windows show `command`
+fn sync_outputs(
+    outputs: &mut HashMap<String, OutputWindow>,
+    elwt: &EventLoopWindowTarget<DaemonEvent>,
+    start: &StartArgs,
+    gpu: GpuOptions,
+    command: Option<&Command>,
+) {
+    let monitors: Vec<_> = elwt
+        .available_monitors()
+        .enumerate()
+        .map(|(idx, monitor)| (output_name(&monitor, idx), monitor))
+        .collect();
+
+    outputs.retain(|name, _| {
+        let connected = monitors.iter().any(|(monitor, _)| monitor == name);
+        if !connected {
+            eprintln!("output {name} disconnected, closing its window");
+        }
+        connected
+    });
+
+    for (name, monitor) in monitors {
+        if outputs.contains_key(&name) {
+            continue;
+        }
+        let (width, height) = if start.mirror {
+            (start.width, start.height)
+        } else {
+            buffer_size(monitor.size(), monitor.scale_factor(), start.logical)
+        };
+        let window_class = format!("{class}-{name}", class = start.window_class);
+        let opened = open_window(elwt, start.backend, &window_class).and_then(|window| {
+            let mut output = OutputWindow::new(
+                window,
+                width,
+                height,
+                start.logical,
+                start.mirror,
+                gpu,
+                start.debug_overlay,
+            )?;
+            if let Some(command) = command {
+                output.renderer = output.create_renderer(command.clone())?;
+                output.command = Some(command.clone());
+            }
+            Ok(output)
+        });
+        match opened {
+            Ok(output) => {
+                eprintln!("opened window {window_class} on output {name}");
+                outputs.insert(name, output);
+            }
+            Err(error) => eprintln!("could not open a window on output {name}: {error:#}"),
+        }
+    }
+}
+
+/// The names of the outputs a command is meant for, all of them if `output` is not set
+fn select_outputs(
+    outputs: &HashMap<String, OutputWindow>,
+    output: Option<&str>,
+) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = outputs.keys().cloned().collect();
+    names.sort();
+    match output {
+        None => Ok(names),
+        Some(output) if outputs.contains_key(output) => Ok(vec![output.to_string()]),
+        Some(output) => Err(format!(
+            "no output named {output:?}, the outputs are {names}",
+            names = names.join(", ")
+        )),
+    }
+}
+
+fn describe_monitors(monitors: &[MonitorState]) -> String {
+    monitors
+        .iter()
+        .map(|(name, position, size)| {
+            format!(
+                "{name} {width}x{height}+{x}+{y}",
+                name = name.as_deref().unwrap_or("unnamed"),
+                width = size.width,
+                height = size.height,
+                x = position.x,
+                y = position.y,
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Connect to the windowing system, explaining the common reasons why that fails
+pub(crate) fn build_event_loop(backend: Backend) -> anyhow::Result<EventLoop<DaemonEvent>> {
+    let mut builder = EventLoopBuilder::<DaemonEvent>::with_user_event();
+    let (builder, variable, other) = match backend {
+        Backend::Wayland => (builder.with_wayland(), "WAYLAND_DISPLAY", "x11"),
+        Backend::X11 => (builder.with_x11(), "DISPLAY", "wayland"),
+    };
+
+    builder.build().map_err(|error| {
+        let display = match std::env::var(variable) {
+            Ok(display) => format!("{variable} is {display:?}"),
+            Err(_) => format!("{variable} is not set"),
+        };
+        anyhow::anyhow!(
+            "could not connect to the {backend:?} display server: {error}\n\
+             hint: are you running under a {backend:?} session? {display}, try --backend {other} \
+             if the session uses the other display server"
+        )
+    })
+}
+
+/// Show the backgrounds saved by the last run again, those which can't be shown any more are
+/// logged and left out
+fn restore(
+    saved: &SavedState,
+    outputs: &mut HashMap<String, OutputWindow>,
+    mirror: Option<&mut Mirror>,
+) -> Option<Command> {
+    let mut default_command = None;
+    if let Some(command) = saved.default.as_ref() {
+        let restored = match mirror {
+            Some(mirror) => mirror.create_renderer(command.clone()).map(|renderer| {
+                mirror.renderer = renderer;
+                mirror.command = Some(command.clone());
+            }),
+            None => outputs
+                .values()
+                .map(|output| output.create_renderer(command.clone()))
+                .collect::<anyhow::Result<Vec<_>>>()
+                .map(|renderers| {
+                    for (output, renderer) in outputs.values_mut().zip(renderers) {
+                        output.renderer = renderer;
+                        output.command = Some(command.clone());
+                    }
+                }),
+        };
+        match restored {
+            Ok(()) => default_command = Some(command.clone()),
+            Err(error) => eprintln!("warning: could not restore the last background: {error:#}"),
+        }
+    }
+
+    for (name, command) in &saved.outputs {
+        let Some(output) = outputs.get_mut(name) else {
+            continue;
+        };
+        match output.create_renderer(command.clone()) {
+            Ok(renderer) => {
+                output.renderer = renderer;
+                output.command = Some(command.clone());
+            }
+            Err(error) => eprintln!(
+                "warning: could not restore the last background of output {name}: {error:#}"
+            ),
+        }
+    }
+
+    default_command
+}
+
+/// The reply naming the file chosen by a background, empty if it didn't choose one
+fn chosen(renderer: &BackgroundRenderer) -> String {
+    renderer.chosen_file().map_or(String::new(), |path| {
+        format!("showing {path}", path = path.display())
+    })
+}
+
+/// The replies to the commands of a batch which have something to say, one per line
+fn join_replies(replies: Vec<String>) -> String {
+    replies
+        .into_iter()
+        .filter(|reply| !reply.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Fail if single outputs are addressed in mirror mode
+fn check_mirror_output(mirror: Option<&Mirror>, output: Option<&str>) -> anyhow::Result<()> {
+    if mirror.is_some() && output.is_some() {
+        bail!(
+            "the outputs show the same background in mirror mode and can't be addressed one by one"
+        );
+    }
+    Ok(())
+}
+
+/// Show `command` on the `selected` outputs or in the mirror, replacing the renderers only if all
+/// of them can be created, returns the reply naming the file chosen by the background if any
+fn apply_background(
+    command: &Command,
+    output: Option<&str>,
+    selected: &[String],
+    outputs: &mut HashMap<String, OutputWindow>,
+    mirror: Option<&mut Mirror>,
+) -> anyhow::Result<String> {
+    check_mirror_output(mirror.as_deref(), output)?;
+    if let Some(mirror) = mirror {
+        mirror.renderer = mirror.create_renderer(command.clone())?;
+        mirror.command = Some(command.clone());
+        return Ok(chosen(&mirror.renderer));
+    }
+
+    let renderers = selected
+        .iter()
+        .map(|name| outputs[name].create_renderer(command.clone()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let reply = renderers.first().map_or(String::new(), chosen);
+    for (name, renderer) in selected.iter().zip(renderers) {
+        let output = outputs.get_mut(name).unwrap();
+        output.renderer = renderer;
+        output.command = Some(command.clone());
+    }
+    Ok(reply)
+}
+
+/// Show the backgrounds of an atomic batch on the `selected` outputs or in the mirror one after
+/// the other, creating all renderers before replacing any of them so either all of the
+/// backgrounds are shown or none, returns the replies naming the files chosen by them
+fn apply_atomic(
+    commands: &[BatchEntry],
+    output: Option<&str>,
+    selected: &[String],
+    outputs: &mut HashMap<String, OutputWindow>,
+    mut mirror: Option<&mut Mirror>,
+) -> anyhow::Result<Vec<String>> {
+    check_mirror_output(mirror.as_deref(), output)?;
+    let renderers = commands
+        .iter()
+        .map(|BatchEntry { line, command }| {
+            match mirror.as_deref() {
+                Some(mirror) => vec![mirror.create_renderer(command.clone())],
+                None => selected
+                    .iter()
+                    .map(|name| outputs[name].create_renderer(command.clone()))
+                    .collect(),
+            }
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .with_context(|| format!("line {line}, none of the backgrounds are shown"))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut replies = Vec::new();
+    for (BatchEntry { command, .. }, renderers) in commands.iter().zip(renderers) {
+        replies.push(renderers.first().map_or(String::new(), chosen));
+        match mirror.as_deref_mut() {
+            Some(mirror) => {
+                for renderer in renderers {
+                    mirror.renderer = renderer;
+                    mirror.command = Some(command.clone());
+                }
+            }
+            None => {
+                for (name, renderer) in selected.iter().zip(renderers) {
+                    let output = outputs.get_mut(name).unwrap();
+                    output.renderer = renderer;
+                    output.command = Some(command.clone());
+                }
+            }
+        }
+    }
+    Ok(replies)
+}
+
+/// Show the backgrounds of the profile `name` and return it, changing nothing if any of them
+/// can't be shown
+fn load_profile(
+    name: &str,
+    outputs: &mut HashMap<String, OutputWindow>,
+    mirror: Option<&mut Mirror>,
+) -> anyhow::Result<SavedState> {
+    let path = profile_path(name)?;
+    if !path.exists() {
+        bail!("there is no profile named {name:?}");
+    }
+    let profile = SavedState::load_existing(&path)
+        .with_context(|| format!("could not load the profile {name:?}"))?;
+    let failed = || format!("could not show the profile {name:?}");
+
+    // Create all renderers before replacing any of them
+    match mirror {
+        Some(mirror) => {
+            let renderer = profile
+                .default
+                .clone()
+                .map(|command| mirror.create_renderer(command))
+                .transpose()
+                .with_context(failed)?;
+            mirror.renderer = renderer.unwrap_or(BackgroundRenderer::None);
+            mirror.command = profile.default.clone();
+        }
+        None => {
+            let renderers = outputs
+                .iter()
+                .map(|(output_name, output)| {
+                    let command = profile
+                        .outputs
+                        .get(output_name)
+                        .or(profile.default.as_ref())
+                        .cloned();
+                    let renderer = command
+                        .clone()
+                        .map(|command| output.create_renderer(command))
+                        .transpose()
+                        .with_context(|| {
+                            format!("{failed} on output {output_name}", failed = failed())
+                        })?;
+                    Ok((output_name.clone(), command, renderer))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            for (output_name, command, renderer) in renderers {
+                let output = outputs.get_mut(&output_name).unwrap();
+                output.renderer = renderer.unwrap_or(BackgroundRenderer::None);
+                output.command = command;
+            }
+        }
+    }
+
+    Ok(profile)
+}
+
+/// What the event loop works on, shared by the handling of window events and of commands
+struct DaemonState<'a> {
+    start: &'a StartArgs,
+    gpu: GpuOptions,
+    outputs: HashMap<String, OutputWindow>,
+    /// The frame shown on every output in mirror mode
+    mirror: Option<Mirror>,
+    /// The backgrounds kept across restarts
+    saved: SavedState,
+    /// Where the backgrounds are kept, `None` if neither XDG_STATE_HOME nor HOME is set
+    state_path: Option<PathBuf>,
+    /// The last command sent to all outputs, shown on outputs connected later
+    default_command: Option<Command>,
+    history: History,
+    /// Paused by a command
+    paused: bool,
+    /// Paused while the session is locked
+    locked: bool,
+    reloads: u64,
+    last_reload: Option<DateTime<Local>>,
+    /// The requests handed out so far, their position in this order tells which one is later
+    requests: u64,
+    /// The position of the request which last changed the background of each output, and of the
+    /// mirror under `None`
+    changed_by: HashMap<Option<String>, u64>,
+    /// The command of each output, and of the mirror under `None`, whose renderer picks the files
+    /// it shows with the file it showed last
+    chosen: HashMap<Option<String>, (Command, PathBuf)>,
+}
+
+impl DaemonState<'_> {
+    /// Keep `command`, shown on `output` or all outputs, for the next start and in the history
+    fn record(&mut self, command: Command, output: Option<&str>) {
+        self.saved.record(&command, output);
+        if output.is_none() && self.mirror.is_none() {
+            self.default_command = Some(command.clone());
+        }
+        self.history.record(HistoryEntry {
+            output: output.map(str::to_string),
+            command,
+        });
+    }
+
+    /// Write the saved backgrounds to the state file if there is one, failures are only logged
+    fn save(&self) {
+        if let Some(Err(error)) = self.state_path.as_deref().map(|path| self.saved.save(path)) {
+            eprintln!("warning: could not save the background: {error:#}");
+        }
+    }
+
+    /// The outputs `output` addresses, or `None` for the mirror
+    fn targets(&self, output: Option<&str>) -> Vec<Option<String>> {
+        match self.mirror {
+            Some(_) => vec![None],
+            None => select_outputs(&self.outputs, output)
+                .unwrap_or_default()
+                .into_iter()
+                .map(Some)
+                .collect(),
+        }
+    }
+
+    /// The command shown on each output, and in the mirror under `None`
+    fn shown(&self) -> HashMap<Option<String>, Option<Command>> {
+        self.outputs
+            .iter()
+            .map(|(name, output)| (Some(name.clone()), output.command.clone()))
+            .chain(
+                self.mirror
+                    .iter()
+                    .map(|mirror| (None, mirror.command.clone())),
+            )
+            .collect()
+    }
+
+    /// The urls of the images `command` sent to `output` downloads, including those of the
+    /// background a history command goes back to and of the backgrounds a reload creates again
+    fn urls(&self, command: &Command, output: Option<&str>) -> Vec<String> {
+        let target = match command {
+            Command::Back => self.history.relative(-1).ok(),
+            Command::Forward => self.history.relative(1).ok(),
+            Command::HistoryJump { index } => Some(*index),
+            _ => None,
+        };
+        let mut urls: Vec<String> = match command {
+            Command::Back | Command::Forward | Command::HistoryJump { .. } => target
+                .and_then(|index| self.history.get(index).ok())
+                .map_or(Vec::new(), |entry| {
+                    entry.command.urls().into_iter().map(String::from).collect()
+                }),
+            Command::Reload => self
+                .targets(output)
+                .iter()
+                .filter_map(|target| match target {
+                    Some(name) => self.outputs[name].command.as_ref(),
+                    None => self.mirror.as_ref()?.command.as_ref(),
+                })
+                .flat_map(Command::urls)
+                .map(String::from)
+                .collect(),
+            command => command.urls().into_iter().map(String::from).collect(),
+        };
+        urls.sort();
+        urls.dedup();
+        urls
+    }
+
+    /// Record the files the renderers moved on to as static images in the history, so it goes
+    /// back to them rather than only to the command which picked them
+    fn record_chosen(&mut self) {
+        let renderers: Vec<_> = match self.mirror.as_ref() {
+            Some(mirror) => vec![(None, mirror.command.as_ref(), &mirror.renderer)],
+            None => self
+                .outputs
+                .iter()
+                .map(|(name, output)| (Some(name), output.command.as_ref(), &output.renderer))
+                .collect(),
+        };
+        let mut moved = Vec::new();
+        for (name, command, renderer) in renderers {
+            let target = name.cloned();
+            let (Some(command), Some(path)) = (command, renderer.chosen_file()) else {
+                self.chosen.remove(&target);
+                continue;
+            };
+            match self.chosen.get(&target) {
+                Some((shown, last)) if shown == command && last == path => continue,
+                // The file a renderer starts with is shown by the command itself
+                Some((shown, _)) if shown == command => {
+                    let output = match self.default_command.as_ref() {
+                        Some(default) if default == command => None,
+                        _ => target.clone(),
+                    };
+                    moved.extend(
+                        command
+                            .showing(path)
+                            .map(|command| HistoryEntry { output, command }),
+                    );
+                }
+                _ => {}
+            }
+            self.chosen
+                .insert(target, (command.clone(), path.to_path_buf()));
+        }
+        for entry in moved {
+            self.history.record(entry);
+        }
+    }
+
+    /// Resync every renderer, after rendering was paused
+    fn resync(&mut self) {
+        self.outputs
+            .values_mut()
+            .for_each(|output| output.renderer.resync());
+        if let Some(mirror) = self.mirror.as_mut() {
+            mirror.renderer.resync();
+        }
+    }
+}
+
+/// Log a failed command and turn it into the error reply
+fn log_failure(error: anyhow::Error) -> String {
+    eprintln!("{error:#}");
+    format!("{error:#}")
+}
+
+/// Show the backgrounds of an atomic batch sent to `output`, all of them or none
+fn handle_atomic(
+    state: &mut DaemonState,
+    commands: Vec<BatchEntry>,
+    output: Option<&str>,
+) -> Result<String, String> {
+    let selected = select_outputs(&state.outputs, output)?;
+    let replies = apply_atomic(
+        &commands,
+        output,
+        &selected,
+        &mut state.outputs,
+        state.mirror.as_mut(),
+    )
+    .map_err(log_failure)?;
+    for BatchEntry { command, .. } in commands {
+        state.record(command, output);
+    }
+    state.save();
+    Ok(join_replies(replies))
+}
+
+/// Handle `request` read from `stream` at `position` in the order of requests and reply to it, once
+/// a frame of its background was presented if the client waits for that
+fn respond(
+    state: &mut DaemonState,
+    position: u64,
+    mut stream: LocalSocketStream,
+    request: anyhow::Result<Request>,
+    pending_shown: &mut Vec<PendingShown>,
+    elwt: &EventLoopWindowTarget<DaemonEvent>,
+) {
+    // Answered once a frame of the background was presented instead
+    let waiting = match &request {
+        Ok(Request {
+            output,
+            command,
+            wait: true,
+        }) if command.is_background() => select_outputs(&state.outputs, output.as_deref()).ok(),
+        _ => None,
+    };
+    let shown = state.shown();
+    let reply = match request {
+        Ok(Request {
+            output,
+            command:
+                Command::Batch {
+                    atomic: true,
+                    commands,
+                    ..
+                },
+            ..
+        }) => handle_atomic(state, commands, output.as_deref()),
+        // The commands of a batch one after the other, up to the first one which fails
+        Ok(Request {
+            output,
+            command: Command::Batch { commands, .. },
+            ..
+        }) => {
+            let mut replies = Vec::new();
+            let mut failed = None;
+            for BatchEntry { line, command } in commands {
+                match handle_command(state, command, output.as_deref(), &stream, elwt) {
+                    Ok(reply) => replies.push(reply),
+                    Err(error) => {
+                        failed = Some(format!("line {line}: {error}"));
+                        break;
+                    }
+                }
+            }
+            match failed {
+                Some(error) => Err(error),
+                None => Ok(join_replies(replies)),
+            }
+        }
+        Ok(Request {
+            output, command, ..
+        }) => handle_command(state, command, output.as_deref(), &stream, elwt),
+        Err(error) => Err(log_failure(error)),
+    };
+    for (target, command) in state.shown() {
+        if shown.get(&target) != Some(&command) {
+            state.changed_by.insert(target, position);
+        }
+    }
+
+    match (reply, waiting) {
+        (Ok(reply), Some(selected)) => {
+            if let Some(mirror) = state.mirror.as_mut() {
+                mirror.tick_now();
+            }
+            let outputs = selected
+                .into_iter()
+                .map(|name| {
+                    let count = state.outputs.get_mut(&name).unwrap().await_frame();
+                    (name, count)
+                })
+                .collect();
+            pending_shown.push(PendingShown {
+                stream,
+                reply,
+                outputs,
+            });
+        }
+        (reply, _) => {
+            if let Err(error) = write_message(&mut stream, &reply) {
+                eprintln!("could not send reply: {error}");
+            }
+        }
+    }
+}
+
+/// Run a single `command` sent to `output`, or to all outputs, and return the reply
+///
+/// `stream` is the connection the command came on, which a restart hands over to the new daemon.
+fn handle_command(
+    state: &mut DaemonState,
+    command: Command,
+    output: Option<&str>,
+    stream: &LocalSocketStream,
+    elwt: &EventLoopWindowTarget<DaemonEvent>,
+) -> Result<String, String> {
+    let selected = select_outputs(&state.outputs, output)?;
+    match command {
+        Command::Start(_) => Err(
+            "a desktop program is running on this socket already, send it a background command"
+                .to_string(),
+        ),
+        Command::RenderFrames(_)
+        | Command::WarmCache(_)
+        | Command::CheckClockDir(_)
+        | Command::RepairClockDir(_)
+        | Command::ResampleClockDir(_)
+        | Command::Check(_)
+        | Command::RenderAt(_)
+        | Command::Preview(_)
+        | Command::Profiles => Err("this command runs without a desktop program".to_string()),
+        Command::SaveProfile { .. } | Command::LoadProfile { .. } if output.is_some() => {
+            Err("a profile holds the backgrounds of all outputs".to_string())
+        }
+        Command::SaveProfile { name } => profile_path(&name)
+            .and_then(|path| state.saved.save(&path))
+            .map(|_| String::new())
+            .map_err(log_failure),
+        Command::LoadProfile { name } => {
+            let profile = load_profile(&name, &mut state.outputs, state.mirror.as_mut())
+                .map_err(log_failure)?;
+            state.default_command = profile.default.clone();
+            state.saved = profile;
+            state.save();
+            Ok(String::new())
+        }
+        Command::Stop { .. } => {
+            elwt.exit();
+            Ok(String::new())
+        }
+        Command::Ping => Err("pings are answered by the connection thread".to_string()),
+        Command::Restart => {
+            eprintln!("restarting");
+            // The new daemon replies once it shows the backgrounds
+            match restart::exec(&state.saved, stream) {
+                Ok(never) => match never {},
+                Err(error) => Err(log_failure(error)),
+            }
+        }
+        Command::Status => {
+            let mut status = format!(
+                "present mode: {present_mode:?}\nfps: {fps}\npaused: {paused}\nlocked: {locked}\n\
+                 reloads: {reloads}\nlast reload: {last_reload}",
+                present_mode = state.gpu.present_mode,
+                fps = state.start.fps,
+                paused = state.paused,
+                locked = state.locked,
+                reloads = state.reloads,
+                last_reload = state
+                    .last_reload
+                    .map_or("never".to_string(), |time| time.format("%F %T").to_string()),
+            );
+            if let Some(mirror) = state.mirror.as_ref() {
+                status = format!(
+                    "{status}\nmirror:\n  {mirror}",
+                    mirror = mirror.status().replace('\n', "\n  ")
+                );
+            }
+            for name in &selected {
+                status = format!(
+                    "{status}\noutput {name}:\n  {output}",
+                    output = state.outputs[name].status().replace('\n', "\n  ")
+                );
+            }
+            Ok(status)
+        }
+        Command::Export => {
+            let mut names: Vec<&String> = state.outputs.keys().collect();
+            names.sort();
+            let setup = Setup {
+                start: state.start.clone(),
+                outputs: names
+                    .into_iter()
+                    .map(|name| OutputSetup {
+                        name: name.clone(),
+                        command: state.outputs[name].command.clone(),
+                        debug_overlay: state.outputs[name].overlay.is_some(),
+                    })
+                    .collect(),
+                mirror: state
+                    .mirror
+                    .as_ref()
+                    .and_then(|mirror| mirror.command.clone()),
+                vsync: state.gpu.present_mode != PresentMode::AutoNoVsync,
+                paused: state.paused,
+            };
+            setup.to_json().map_err(|error| format!("{error:#}"))
+        }
+        Command::Reload => {
+            let reloaded = selected
+                .iter()
+                .try_for_each(|name| state.outputs.get_mut(name).unwrap().reload())
+                .and_then(|_| state.mirror.as_mut().map_or(Ok(()), Mirror::reload));
+            state.reloads += 1;
+            state.last_reload = Some(Local::now());
+            reloaded.map(|_| String::new()).map_err(log_failure)
+        }
+        Command::Vsync { enabled } => {
+            let previous = state.gpu;
+            state.gpu.present_mode = if enabled {
+                PresentMode::AutoVsync
+            } else {
+                PresentMode::AutoNoVsync
+            };
+            let gpu = state.gpu;
+            match state
+                .outputs
+                .values_mut()
+                .try_for_each(|output| output.rebuild_pixels(gpu))
+            {
+                Ok(()) => Ok(String::new()),
+                Err(error) => {
+                    // Go back to the previous mode, the next tick exits if that fails
+                    state.gpu = previous;
+                    for output in state.outputs.values_mut() {
+                        if let Err(error) = output.rebuild_pixels(previous) {
+                            eprintln!("{error:#}");
+                        }
+                    }
+                    Err(log_failure(error))
+                }
+            }
+        }
+        Command::InjectFault { fault } => {
+            for name in &selected {
+                let output = state.outputs.get_mut(name).unwrap();
+                output.injected_fault = Some(fault.into());
+                output.window.request_redraw();
+            }
+            Ok(String::new())
+        }
+        Command::DebugOverlay { enabled } => {
+            for name in &selected {
+                state.outputs.get_mut(name).unwrap().set_overlay(enabled);
+            }
+            Ok(String::new())
+        }
+        Command::Pause => {
+            state.paused = true;
+            Ok(String::new())
+        }
+        Command::Resume => {
+            if state.paused && !state.locked {
+                state.resync();
+            }
+            state.paused = false;
+            Ok(String::new())
+        }
+        Command::History => Ok(state.history.describe()),
+        Command::Palette { .. } if selected.is_empty() => {
+            Err("there is no output to take the colors from".to_string())
+        }
+        Command::Back | Command::Forward | Command::HistoryJump { .. } if output.is_some() => {
+            Err("the history goes back to the outputs its backgrounds were sent to".to_string())
+        }
+        Command::Back | Command::Forward | Command::HistoryJump { .. } => {
+            let index = match command {
+                Command::Back => state.history.relative(-1),
+                Command::Forward => state.history.relative(1),
+                Command::HistoryJump { index } => Ok(index),
+                _ => unreachable!(),
+            }
+            .map_err(log_failure)?;
+            let entry = state.history.get(index).map_err(log_failure)?.clone();
+            let selected = select_outputs(&state.outputs, entry.output.as_deref())?;
+            let reply = apply_background(
+                &entry.command,
+                entry.output.as_deref(),
+                &selected,
+                &mut state.outputs,
+                state.mirror.as_mut(),
+            )
+            .map_err(log_failure)?;
+            state.saved.record(&entry.command, entry.output.as_deref());
+            state.save();
+            if entry.output.is_none() && state.mirror.is_none() {
+                state.default_command = Some(entry.command);
+            }
+            state.history.jump(index);
+            Ok(reply)
+        }
+        command => {
+            let reply = apply_background(
+                &command,
+                output,
+                &selected,
+                &mut state.outputs,
+                state.mirror.as_mut(),
+            )
+            .map_err(log_failure)?;
+            state.record(command, output);
+            state.save();
+            Ok(reply)
+        }
+    }
+}
+
+fn run(
+    start: &StartArgs,
+    socket: LocalSocketListener,
+    socket_name: &str,
+    mut handover: Option<&mut Handover>,
+) -> anyhow::Result<()> {
+    let gpu = GpuOptions::new(start)?;
+    // The shortest time between two renders, renderers which don't change are advanced less often
+    let tick = start.tick_interval();
+    let event_loop = build_event_loop(start.backend)?;
+
+    // Accept connections on a separate thread so the event loop can sleep while occluded
+    let proxy = event_loop.create_proxy();
+    let heartbeat = Heartbeat::new();
+    let connection_heartbeat = heartbeat.clone();
+    thread::spawn(move || {
+        for (sequence, connection) in (0..).zip(socket.incoming()) {
+            match connection {
+                Ok(stream) => {
+                    let proxy = proxy.clone();
+                    let heartbeat = connection_heartbeat.clone();
+                    thread::spawn(move || read_connection(sequence, stream, proxy, heartbeat));
+                }
+                Err(error) => {
+                    let _ = proxy.send_event(DaemonEvent::AcceptFailed(error));
+                    break;
+                }
+            }
+        }
+    });
+
+    let proxy = event_loop.create_proxy();
+    signals::spawn(move |event| proxy.send_event(event).is_ok());
+
+    #[cfg(feature = "lock-detection")]
+    if !start.no_lock_detection {
+        crate::lock::spawn(event_loop.create_proxy());
+    }
+
+    let mut outputs: HashMap<String, OutputWindow> = HashMap::new();
+    let mut monitors = connected_monitors(&event_loop);
+    let mut mirror = start.mirror.then(|| Mirror::new(start.width, start.height));
+    if start.per_output || start.mirror {
+        sync_outputs(&mut outputs, &event_loop, start, gpu, None);
+        if outputs.is_empty() {
+            bail!("could not open a window on any output");
+        }
+    } else {
+        let window = open_window(&event_loop, start.backend, &start.window_class)?;
+        let name = window
+            .current_monitor()
+            .and_then(|monitor| monitor.name())
+            .unwrap_or_else(|| "default".to_string());
+        let output = OutputWindow::new(
+            window,
+            start.width,
+            start.height,
+            start.logical,
+            false,
+            gpu,
+            start.debug_overlay,
+        )?;
+        outputs.insert(name, output);
+    }
+
+    // The backgrounds of the last run or the replaced daemon, the file is only written once a
+    // background is set
+    let state_path = state_path(socket_name);
+    let handed_over = handover.as_mut().and_then(|handover| handover.state.take());
+    let saved = match (handed_over, state_path.as_deref()) {
+        (Some(saved), _) => saved,
+        (None, Some(path)) if !start.no_restore => SavedState::load(path).unwrap_or_else(|error| {
+            eprintln!("warning: could not restore the last backgrounds: {error:#}");
+            SavedState::default()
+        }),
+        _ => SavedState::default(),
+    };
+    if state_path.is_none() {
+        eprintln!("warning: neither XDG_STATE_HOME nor HOME is set, backgrounds won't be kept");
+    }
+
+    let default_command = restore(&saved, &mut outputs, mirror.as_mut());
+    heartbeat.show(&outputs, mirror.as_ref());
+    if let Some(handover) = handover {
+        handover.reply(&Ok(format!(
+            "restarted as process {pid}",
+            pid = std::process::id()
+        )));
+    }
+    let mut history = History::default();
+    if let Some(command) = default_command.clone() {
+        history.record(HistoryEntry {
+            output: None,
+            command,
+        });
+    }
+    let mut state = DaemonState {
+        start,
+        gpu,
+        outputs,
+        mirror,
+        saved,
+        state_path,
+        default_command,
+        history,
+        paused: false,
+        locked: false,
+        reloads: 0,
+        last_reload: None,
+        requests: 0,
+        changed_by: HashMap::new(),
+        chosen: HashMap::new(),
+    };
+    let mut pending_palettes: Vec<PendingPalette> = Vec::new();
+    let mut pending_shown: Vec<PendingShown> = Vec::new();
+    let mut downloaded: Vec<Box<PendingDownload>> = Vec::new();
+    let download_proxy = event_loop.create_proxy();
+    let mut requests: RequestQueue<Connection> = RequestQueue::new();
+    let mut last_monitor_poll = Instant::now();
+
+    event_loop
+        .run(move |event, elwt| match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => elwt.exit(),
+            Event::WindowEvent { window_id, event } => {
+                let Some(output) = state
+                    .outputs
+                    .values_mut()
+                    .find(|output| output.window.id() == window_id)
+                else {
+                    return;
+                };
+                match output.handle_event(event, state.gpu, !state.paused && !state.locked) {
+                    Ok(presented) => {
+                        if presented && !pending_shown.is_empty() {
+                            answer_shown(&mut pending_shown, &state.outputs);
+                        }
+                    }
+                    Err(error) => {
+                        eprintln!("{error}");
+                        elwt.exit();
+                    }
+                }
+            }
+            Event::UserEvent(DaemonEvent::Shutdown(signal)) => {
+                eprintln!("received signal {signal}, shutting down");
+                elwt.exit();
+            }
+            Event::UserEvent(DaemonEvent::Reload) => {
+                eprintln!("received SIGHUP, reloading the background");
+                let reloaded: Vec<_> = state
+                    .outputs
+                    .values_mut()
+                    .map(OutputWindow::reload)
+                    .chain(state.mirror.as_mut().map(Mirror::reload))
+                    .collect();
+                for result in reloaded {
+                    if let Err(error) = result {
+                        eprintln!(
+                            "could not reload the background, keeping the previous one: {error:#}"
+                        );
+                    }
+                }
+                tick_all(&mut state.outputs, state.mirror.as_mut());
+                state.reloads += 1;
+                state.last_reload = Some(Local::now());
+            }
+            Event::UserEvent(DaemonEvent::Locked(now_locked)) => {
+                if state.locked && !now_locked && !state.paused {
+                    state.resync();
+                    tick_all(&mut state.outputs, state.mirror.as_mut());
+                }
+                state.locked = now_locked;
+            }
+            Event::UserEvent(DaemonEvent::AcceptFailed(error)) => {
+                eprintln!("{error}");
+                elwt.exit();
+            }
+            Event::UserEvent(DaemonEvent::Wake) => {}
+            Event::UserEvent(DaemonEvent::Downloaded(download)) => downloaded.push(download),
+            Event::UserEvent(DaemonEvent::Connection(sequence, connection)) => {
+                requests.push(sequence, connection.map(|connection| *connection));
+            }
+            Event::AboutToWait => {
+                // Handle the requests read since the last tick in the order they were accepted,
+                // before rendering so their backgrounds show in this tick
+                let ready = requests.ready(MAX_REQUESTS_PER_TICK);
+                let mut handled = !ready.is_empty();
+                let superseded = superseded(
+                    &ready
+                        .iter()
+                        .map(|(_, request)| request.as_ref().ok())
+                        .collect::<Vec<_>>(),
+                );
+                for ((mut stream, request), superseded) in ready.into_iter().zip(superseded) {
+                    // Creating the renderer of a background which is replaced right away is wasted
+                    if superseded {
+                        let reply: Reply = Ok("superseded by a later command".to_string());
+                        if let Err(error) = write_message(&mut stream, &reply) {
+                            eprintln!("could not send reply: {error}");
+                        }
+                        continue;
+                    }
+                    // Answered once the output rendered, so a background set just before shows up
+                    if let Ok(Request {
+                        output,
+                        command: Command::Palette { count, json },
+                        ..
+                    }) = &request
+                    {
+                        match select_outputs(&state.outputs, output.as_deref()) {
+                            Ok(selected) if !selected.is_empty() => {
+                                let name = selected[0].clone();
+                                match state.mirror.as_mut() {
+                                    Some(mirror) => mirror.tick_now(),
+                                    None => state.outputs.get_mut(&name).unwrap().tick_now(),
+                                }
+                                pending_palettes.push(PendingPalette {
+                                    stream,
+                                    output: name,
+                                    count: *count,
+                                    json: *json,
+                                });
+                                continue;
+                            }
+                            _ => {}
+                        }
+                    }
+                    let urls = match &request {
+                        Ok(Request {
+                            output, command, ..
+                        }) => state.urls(command, output.as_deref()),
+                        Err(_) => Vec::new(),
+                    };
+                    let position = state.requests;
+                    state.requests += 1;
+                    match request {
+                        // Shown once its images are downloaded, without holding up rendering
+                        Ok(request) if !urls.is_empty() => PendingDownload {
+                            stream,
+                            request,
+                            position,
+                            images: Vec::new(),
+                        }
+                        .start(urls, download_proxy.clone()),
+                        request => respond(
+                            &mut state,
+                            position,
+                            stream,
+                            request,
+                            &mut pending_shown,
+                            elwt,
+                        ),
+                    }
+                }
+                // A later request changing the outputs while the images were downloaded supersedes
+                // the request
+                for download in downloaded.drain(..) {
+                    let PendingDownload {
+                        mut stream,
+                        request,
+                        position,
+                        images,
+                    } = *download;
+                    let superseded = state
+                        .targets(request.output.as_deref())
+                        .iter()
+                        .any(|target| state.changed_by.get(target) > Some(&position));
+                    if superseded {
+                        let reply: Reply = Ok("superseded by a later command".to_string());
+                        if let Err(error) = write_message(&mut stream, &reply) {
+                            eprintln!("could not send reply: {error}");
+                        }
+                        continue;
+                    }
+                    respond(
+                        &mut state,
+                        position,
+                        stream,
+                        Ok(request),
+                        &mut pending_shown,
+                        elwt,
+                    );
+                    drop(images);
+                    handled = true;
+                }
+
+                let DaemonState {
+                    outputs,
+                    mirror,
+                    gpu,
+                    default_command,
+                    paused,
+                    locked,
+                    ..
+                } = &mut state;
+                let gpu = *gpu;
+                if handled {
+                    tick_all(outputs, mirror.as_mut());
+                    heartbeat.show(outputs, mirror.as_ref());
+                }
+
+                if last_monitor_poll.elapsed() >= MONITOR_POLL_INTERVAL {
+                    last_monitor_poll = Instant::now();
+                    let connected = connected_monitors(elwt);
+                    if connected != monitors {
+                        eprintln!("outputs changed: {}", describe_monitors(&connected));
+                        monitors = connected;
+                        if start.per_output || start.mirror {
+                            sync_outputs(outputs, elwt, start, gpu, default_command.as_ref());
+                        }
+                        for output in outputs.values_mut() {
+                            output
+                                .pending_size
+                                .get_or_insert(output.window.inner_size());
+                        }
+                        heartbeat.show(outputs, mirror.as_ref());
+                    }
+                }
+
+                // Wake up for the earliest tick of all outputs
+                let active = !*paused && !*locked;
+                let mut next_tick: Option<Instant> = None;
+                let mut mirrored = None;
+                if let Some(mirror) = mirror.as_mut() {
+                    let visible = outputs.values().any(|output| !output.occluded);
+                    match mirror.update(tick, active && visible) {
+                        Ok((changed, due)) => {
+                            next_tick = due;
+                            mirrored = changed.then_some(mirror.frame.as_slice());
+                        }
+                        Err(error) => {
+                            eprintln!("mirror: {error:#}");
+                            elwt.exit();
+                            return;
+                        }
+                    }
+                }
+                for (name, output) in outputs.iter_mut() {
+                    match output.update(gpu, tick, active, mirrored) {
+                        Ok(Some(due)) => {
+                            next_tick = Some(next_tick.map_or(due, |next_tick| next_tick.min(due)))
+                        }
+                        Ok(None) => {}
+                        Err(error) => {
+                            eprintln!("output {name}: {error:#}");
+                            elwt.exit();
+                            return;
+                        }
+                    }
+                }
+                for pending in pending_palettes.drain(..) {
+                    match mirror.as_ref() {
+                        Some(mirror) => pending.answer(&mirror.frame, mirror.width, mirror.height),
+                        None => match outputs
+                            .get(&pending.output)
+                            .and_then(|output| Some((output.frame()?, output.width, output.height)))
+                        {
+                            Some((frame, width, height)) => pending.answer(frame, width, height),
+                            None => pending.fail("the output is gone"),
+                        },
+                    }
+                }
+                if !pending_shown.is_empty() {
+                    answer_shown(&mut pending_shown, outputs);
+                }
+                state.record_chosen();
+                if requests.has_ready() {
+                    next_tick = Some(Instant::now());
+                }
+                heartbeat.beat();
+                // Keep polling monitors while every output is idle where a window is opened per
+                // monitor, a single window is resized by the window manager instead
+                if start.per_output || start.mirror {
+                    let poll_due = last_monitor_poll + MONITOR_POLL_INTERVAL;
+                    next_tick =
+                        Some(next_tick.map_or(poll_due, |next_tick| next_tick.min(poll_due)));
+                }
+                elwt.set_control_flow(match next_tick {
+                    Some(next_tick) => ControlFlow::WaitUntil(next_tick),
+                    None => ControlFlow::Wait,
+                });
+            }
+            _ => {}
+        })
+        .unwrap();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn hands_out_a_burst_of_requests_in_accept_order() {
+        let mut requests = RequestQueue::new();
+        // The threads finish reading in a different order than the connections were accepted
+        let mut sequences: Vec<u64> = (0..40).collect();
+        sequences.swap(0, 5);
+        sequences.swap(12, 39);
+        for sequence in sequences {
+            // Connection 3 was a ping, answered on its thread
+            requests.push(sequence, (sequence != 3).then_some(sequence));
+        }
+
+        let first = requests.ready(MAX_REQUESTS_PER_TICK);
+        assert_eq!(first.len(), MAX_REQUESTS_PER_TICK);
+        assert!(first.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(!first.contains(&3));
+        assert!(requests.has_ready());
+
+        // The last request of the burst comes last, in the next tick
+        let second = requests.ready(MAX_REQUESTS_PER_TICK);
+        assert_eq!(second.last(), Some(&39));
+        assert!(!requests.has_ready());
+    }
+
+    #[test]
+    fn waits_for_requests_accepted_earlier() {
+        let mut requests = RequestQueue::new();
+        requests.push(1, Some("second"));
+        assert!(requests.ready(MAX_REQUESTS_PER_TICK).is_empty());
+        requests.push(0, Some("first"));
+        assert_eq!(requests.ready(MAX_REQUESTS_PER_TICK), ["first", "second"]);
+    }
+
+    #[test]
+    fn supersedes_backgrounds_replaced_in_the_same_tick() {
+        let request = |output: Option<&str>, command| Request {
+            output: output.map(str::to_string),
+            command,
+            wait: false,
+        };
+        let image = |path: &str| Command::StaticImage {
+            path: PathBuf::from(path),
+            scaling: Default::default(),
+            margin_color: None,
+            watch: false,
+            span: false,
+        };
+        let requests = [
+            // Still shown on the outputs other than DP-1 and HDMI-1
+            request(None, image("a.png")),
+            request(Some("DP-1"), image("b.png")),
+            request(Some("HDMI-1"), image("c.png")),
+            request(Some("DP-1"), image("d.png")),
+            request(None, Command::Status),
+            request(Some("HDMI-1"), image("e.png")),
+            request(None, image("f.png")),
+            request(None, Command::Pause),
+        ];
+        let requests: Vec<_> = requests.iter().map(Some).collect();
+        assert_eq!(
+            superseded(&requests),
+            [false, true, false, false, false, true, false, false]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::ops::Range;
+
+/// A rectangle of pixels within a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    /// The column of the left edge
+    pub x: u32,
+    /// The row of the top edge
+    pub y: u32,
+    /// The number of columns
+    pub width: u32,
+    /// The number of rows
+    pub height: u32,
+}
+
+impl Rect {
+    /// The rectangle between the corners `min` and `max`, exclusive, clipped to a `width` x
+    /// `height` frame, `None` if nothing of it is inside
+    pub fn clipped(min: (i64, i64), max: (i64, i64), width: u32, height: u32) -> Option<Rect> {
+        let (x, y) = (min.0.max(0), min.1.max(0));
+        let (end_x, end_y) = (max.0.min(width as i64), max.1.min(height as i64));
+        (x < end_x && y < end_y).then(|| Rect {
+            x: x as u32,
+            y: y as u32,
+            width: (end_x - x) as u32,
+            height: (end_y - y) as u32,
+        })
+    }
+
+    /// The smallest rectangle holding both
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+
+    /// The byte ranges of the rows of the rectangle within an rgba frame `frame_width` pixels wide
+    pub fn rows(self, frame_width: u32) -> impl Iterator<Item = Range<usize>> {
+        let stride = frame_width as usize * 4;
+        let (x, len) = (self.x as usize * 4, self.width as usize * 4);
+        (self.y as usize..(self.y + self.height) as usize).map(move |row| {
+            let start = row * stride + x;
+            start..start + len
+        })
+    }
+}
+
+/// Which part of the frame a render changed
+///
+/// Renderers report a rectangle only if they know for sure nothing outside of it changed, anything
+/// else is reported as a change of the whole frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Damage {
+    /// No pixel changed
+    None,
+    /// Only pixels inside the rectangle changed
+    Rect(Rect),
+    /// Any pixel may have changed
+    Full,
+}
+
+impl Damage {
+    /// The whole frame if it `changed`
+    pub fn changed(changed: bool) -> Damage {
+        if changed {
+            Damage::Full
+        } else {
+            Damage::None
+        }
+    }
+
+    /// The part changed by either
+    pub fn union(self, other: Damage) -> Damage {
+        match (self, other) {
+            (Damage::Full, _) | (_, Damage::Full) => Damage::Full,
+            (Damage::Rect(a), Damage::Rect(b)) => Damage::Rect(a.union(b)),
+            (Damage::Rect(rect), Damage::None) | (Damage::None, Damage::Rect(rect)) => {
+                Damage::Rect(rect)
+            }
+            (Damage::None, Damage::None) => Damage::None,
+        }
+    }
+
+    /// The byte ranges of the damaged rows within an rgba frame `width` pixels wide and as long as
+    /// `len` bytes
+    pub fn rows(self, width: u32, len: usize) -> impl Iterator<Item = Range<usize>> {
+        let (rect, full) = match self {
+            Damage::None => (None, None),
+            Damage::Rect(rect) => (Some(rect), None),
+            Damage::Full => (None, Some(0..len)),
+        };
+        rect.into_iter()
+            .flat_map(move |rect| rect.rows(width))
+            .chain(full)
+    }
+
+    /// Copy the damaged part of the rgba frame `src` into `dst`, both `width` pixels wide
+    pub fn copy(self, dst: &mut [u8], src: &[u8], width: u32) {
+        for row in self.rows(width, dst.len()) {
+            dst[row.clone()].copy_from_slice(&src[row]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_only_damaged_rows() {
+        let rect = Rect::clipped((-2, 1), (2, 9), 4, 3).unwrap();
+        assert_eq!(
+            rect,
+            Rect {
+                x: 0,
+                y: 1,
+                width: 2,
+                height: 2
+            }
+        );
+        assert_eq!(Rect::clipped((4, 0), (6, 3), 4, 3), None);
+
+        let src: Vec<u8> = (0..48).collect();
+        let mut dst = vec![0; 48];
+        Damage::Rect(rect).copy(&mut dst, &src, 4);
+        let copied: Vec<usize> = (0..48).filter(|&idx| dst[idx] != 0).collect();
+        assert_eq!(copied, (16..24).chain(32..40).collect::<Vec<_>>());
+
+        let other = Rect {
+            x: 3,
+            y: 0,
+            width: 1,
+            height: 1,
+        };
+        assert_eq!(
+            Damage::Rect(rect).union(Damage::Rect(other)),
+            Damage::Rect(Rect {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 3
+            })
+        );
+        assert_eq!(Damage::None.union(Damage::Full), Damage::Full);
+        Damage::Full.copy(&mut dst, &src, 4);
+        assert_eq!(dst, src);
+    }
+}
@@ -0,0 +1,792 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    ops::Deref,
+    path::Path,
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+
+use image::{
+    codecs::webp::WebPDecoder,
+    io::{Limits, Reader},
+    ColorType, DynamicImage, GrayAlphaImage, GrayImage, ImageDecoder, ImageError, ImageFormat,
+    ImageResult, RgbImage, RgbaImage,
+};
+use memmap2::Mmap;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{icc::ToSrgb, pool::BufferPool};
+
+/// Files smaller than this are read into memory, mapping them costs more than copying them
+const MAP_THRESHOLD: u64 = 256 * 1024;
+
+/// A 4x4 ordered dither pattern, the order in which the thresholds of a cell are reached
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// The options set by the daemon
+static OPTIONS: OnceLock<DecodeOptions> = OnceLock::new();
+
+/// How the daemon turns images into the 8 bit sRGB pixels of frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeOptions {
+    /// How images with more than 8 bits per channel are reduced
+    pub high_depth: HighDepth,
+    /// Convert images with an embedded color profile to sRGB
+    pub color_management: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            high_depth: HighDepth::default(),
+            color_management: true,
+        }
+    }
+}
+
+/// How images with more than 8 bits per channel are reduced to the 8 bits of a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HighDepth {
+    /// Add an ordered dither pattern before cutting off the lower bits instead of rounding, against
+    /// banding in smooth gradients
+    pub dither: bool,
+    /// How HDR images are mapped to the range of the display
+    pub tone_map: ToneMap,
+    /// Scale the brightness of HDR images by 2 to this power before tone mapping
+    pub exposure: f32,
+}
+
+/// How the linear brightness of HDR images is mapped to the range of the display
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ToneMap {
+    /// Compress highlights with the Reinhard operator, which maps a brightness c to c / (1 + c)
+    #[default]
+    Reinhard,
+    /// Clip everything brighter than white
+    Clip,
+}
+
+/// Decode images with `options` from now on, only the first call has an effect
+pub fn configure(options: DecodeOptions) {
+    let _ = OPTIONS.set(options);
+}
+
+fn options() -> DecodeOptions {
+    OPTIONS.get().copied().unwrap_or_default()
+}
+
+/// The contents of an image file, mapped into memory if it is large
+pub enum FileBytes {
+    /// The file read into memory
+    Read(Vec<u8>),
+    /// The file mapped into memory
+    Mapped(Mmap),
+}
+
+impl Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            FileBytes::Read(bytes) => bytes,
+            FileBytes::Mapped(map) => map,
+        }
+    }
+}
+
+impl AsRef<[u8]> for FileBytes {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+/// Map the file at `path` into memory, or read it if it is small or can't be mapped, like on some
+/// FUSE file systems
+///
+/// A mapped file which is truncated while it is decoded kills the process with SIGBUS instead of
+/// failing the decoding. Images replaced by writing a new file and renaming it over the old one
+/// are safe, the mapping keeps the old file.
+pub fn read(path: &Path) -> std::io::Result<FileBytes> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len >= MAP_THRESHOLD {
+        // SAFETY: the mapping is only read, see above for files changing underneath it
+        if let Ok(map) = unsafe { Mmap::map(&file) } {
+            return Ok(FileBytes::Mapped(map));
+        }
+    }
+    let mut bytes = Vec::with_capacity(len as usize);
+    file.read_to_end(&mut bytes)?;
+    Ok(FileBytes::Read(bytes))
+}
+
+/// Decode the image at `path`, in the format its contents start with or else the one its extension
+/// names
+///
+/// Animated images are decoded to their first frame.
+pub fn open(path: &Path) -> anyhow::Result<DynamicImage> {
+    decode(path, &read(path)?, &mut BufferPool::default())
+}
+
+/// Decode the image `bytes` downloaded from `url` like [`open`] decodes a file, the url stands in
+/// for its name
+pub fn decode_bytes(url: &str, bytes: &[u8]) -> anyhow::Result<DynamicImage> {
+    decode(Path::new(url), bytes, &mut BufferPool::default())
+}
+
+/// The size of the image at `path` as it is shown, read from its header without decoding it
+///
+/// A file whose header reads fine may still fail to decode, but this finds truncated and mislabeled
+/// files without the cost of decoding them.
+pub fn probe(path: &Path) -> anyhow::Result<(u32, u32)> {
+    let bytes = read(path)?;
+    let mut reader = Reader::new(Cursor::new(&*bytes));
+    reader.set_format(format(path, &bytes)?);
+    let size = reader.into_dimensions().with_context(|| {
+        format!(
+            "could not read the image header of {path}",
+            path = path.display()
+        )
+    })?;
+    Ok(oriented_size(size, exif_orientation(&bytes)))
+}
+
+/// The format of an image file
+fn format(path: &Path, bytes: &[u8]) -> anyhow::Result<ImageFormat> {
+    // Downloaded images are often named after another format than the one they are in, only
+    // formats without a signature, like TGA, are taken from the extension
+    image::guess_format(bytes)
+        .or_else(|_| ImageFormat::from_path(path))
+        .map_err(|_| anyhow::anyhow!("{} is not in any supported image format", path.display()))
+}
+
+fn decode(path: &Path, bytes: &[u8], pool: &mut BufferPool) -> anyhow::Result<DynamicImage> {
+    let format = format(path, bytes)?;
+    let mut reader = Reader::new(Cursor::new(bytes));
+    reader.set_format(format);
+    let image = reader
+        .into_decoder()
+        .and_then(|decoder| from_decoder(decoder, path, pool))
+        .map_err(|error| match error {
+            ImageError::Unsupported(_)
+                if format == ImageFormat::Avif && cfg!(not(feature = "avif")) =>
+            {
+                anyhow::anyhow!(
+                    "{} is an AVIF image, which can only be shown when built with the avif feature",
+                    path.display()
+                )
+            }
+            ImageError::Unsupported(_) => anyhow::anyhow!(
+                "{} is a {format:?} image, which can't be decoded: {error}",
+                path.display()
+            ),
+            error => anyhow::Error::new(error).context(format!(
+                "could not decode the {format:?} image {}",
+                path.display()
+            )),
+        })?;
+
+    if format == ImageFormat::WebP
+        && WebPDecoder::new(Cursor::new(bytes)).is_ok_and(|decoder| decoder.has_animation())
+    {
+        eprintln!(
+            "{} is an animated WebP image, showing its first frame",
+            path.display()
+        );
+    }
+    Ok(orient(image, exif_orientation(bytes), pool))
+}
+
+/// The EXIF orientation of a JPEG file, from 1 for upright to 8, or 1 if it has none
+///
+/// Only the orientation tag of the first image file directory is read, which is where cameras
+/// write it.
+fn exif_orientation(bytes: &[u8]) -> u16 {
+    jpeg_exif(bytes)
+        .and_then(tiff_orientation)
+        .filter(|orientation| (1..=8).contains(orientation))
+        .unwrap_or(1)
+}
+
+/// The TIFF structure in the EXIF segment of a JPEG file
+fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut rest = bytes.strip_prefix(&[0xFF, 0xD8])?;
+    loop {
+        let [0xFF, marker, high, low, ..] = *rest else {
+            return None;
+        };
+        match marker {
+            // Fill bytes before a marker
+            0xFF => rest = &rest[1..],
+            // The image data starts, all metadata segments come before it
+            0xDA | 0xD9 => return None,
+            _ => {
+                let len = usize::from(u16::from_be_bytes([high, low]));
+                let segment = rest.get(4..2 + len)?;
+                if let Some(tiff) = segment.strip_prefix(b"Exif\0\0").filter(|_| marker == 0xE1) {
+                    return Some(tiff);
+                }
+                rest = &rest[2 + len..];
+            }
+        }
+    }
+}
+
+/// The value of the orientation tag in the first image file directory of `tiff`
+fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |at: usize| {
+        let bytes = tiff.get(at..at + 2)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let u32_at = |at: usize| {
+        let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    };
+
+    let directory = u32_at(4)? as usize;
+    // Each entry is a tag, a type, a count and a value, which is a short for the orientation
+    (0..usize::from(u16_at(directory)?))
+        .map(|idx| directory + 2 + idx * 12)
+        .find(|&entry| u16_at(entry) == Some(0x0112))
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+/// Rotate and flip `image` stored with the EXIF `orientation` to show it upright, the buffer of
+/// a stored image which is replaced goes back to `pool`
+fn orient(image: DynamicImage, orientation: u16, pool: &mut BufferPool) -> DynamicImage {
+    let oriented = match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => return image,
+    };
+    if let Some(buffer) = into_buffer(image) {
+        pool.give(buffer);
+    }
+    oriented
+}
+
+/// The size of a `size` image as it is shown with the EXIF `orientation`, or the size it is
+/// stored at for a shown `size`
+fn oriented_size(size: (u32, u32), orientation: u16) -> (u32, u32) {
+    match orientation {
+        5..=8 => (size.1, size.0),
+        _ => size,
+    }
+}
+
+/// Decode all of `decoder` into a buffer taken from `pool` if the image has 8 bits per channel,
+/// other images are decoded into a new buffer
+///
+/// The colors of images with an embedded color profile are converted to sRGB, unless the daemon
+/// turned color management off. `path` names the image in warnings.
+pub(crate) fn from_decoder(
+    mut decoder: impl ImageDecoder,
+    path: &Path,
+    pool: &mut BufferPool,
+) -> ImageResult<DynamicImage> {
+    // Refuse images larger than the default allocation limit, like the image crate does
+    Limits::default().reserve(decoder.total_bytes())?;
+    let options = options();
+    let profile = match options.color_management {
+        true => decoder.icc_profile().ok().flatten(),
+        false => None,
+    };
+    let (width, height) = decoder.dimensions();
+    let color = decoder.color_type();
+    if !matches!(
+        color,
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8
+    ) {
+        let image = DynamicImage::from_decoder(decoder)?;
+        let image = reduce_depth_pooled(image, options.high_depth, pool);
+        return Ok(to_srgb(image, profile.as_deref(), path));
+    }
+    let mut buffer = pool.take(decoder.total_bytes() as usize);
+    decoder.read_image(&mut buffer)?;
+    let image = match color {
+        ColorType::L8 => GrayImage::from_raw(width, height, buffer).map(DynamicImage::ImageLuma8),
+        ColorType::La8 => {
+            GrayAlphaImage::from_raw(width, height, buffer).map(DynamicImage::ImageLumaA8)
+        }
+        ColorType::Rgb8 => RgbImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgb8),
+        _ => RgbaImage::from_raw(width, height, buffer).map(DynamicImage::ImageRgba8),
+    };
+    let image = image.expect("the buffer has the size of the image");
+    Ok(to_srgb(image, profile.as_deref(), path))
+}
+
+/// Convert the colors of the rgb `image` from the color space of the ICC `profile` to sRGB, gray
+/// images and images without a profile are returned as they are
+fn to_srgb(mut image: DynamicImage, profile: Option<&[u8]>, path: &Path) -> DynamicImage {
+    let (Some(profile), DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_)) =
+        (profile, &image)
+    else {
+        return image;
+    };
+    match ToSrgb::new(profile) {
+        Ok(Some(to_srgb)) => match &mut image {
+            DynamicImage::ImageRgb8(image) => to_srgb.apply(image, 3),
+            DynamicImage::ImageRgba8(image) => to_srgb.apply(image, 4),
+            _ => unreachable!("only rgb images are converted"),
+        },
+        Ok(None) => {}
+        Err(reason) => eprintln!(
+            "warning: showing the colors of {path} as sRGB, its color profile can't be used \
+             because {reason}",
+            path = path.display()
+        ),
+    }
+    image
+}
+
+/// Reduce an image with more than 8 bits per channel to an 8 bit rgba image, images with 8 bits
+/// are returned as they are
+///
+/// 16 bit channels are rounded, or dithered. Float images are taken to be HDR images in linear
+/// light, like Radiance HDR and OpenEXR files, which are tone mapped and encoded as sRGB.
+pub fn reduce_depth(image: DynamicImage, high_depth: HighDepth) -> DynamicImage {
+    reduce_depth_pooled(image, high_depth, &mut BufferPool::default())
+}
+
+/// Reduce the depth of `image` like [`reduce_depth`], into a buffer from `pool`
+fn reduce_depth_pooled(
+    image: DynamicImage,
+    high_depth: HighDepth,
+    pool: &mut BufferPool,
+) -> DynamicImage {
+    if matches!(
+        image,
+        DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_)
+    ) {
+        return image;
+    }
+    let (width, height) = (image.width(), image.height());
+    let mut buffer = pool.take(width as usize * height as usize * 4);
+    match image {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            let image = image.into_rgba32f();
+            let scale = high_depth.exposure.exp2();
+            quantize(&mut buffer, width, high_depth.dither, |idx| {
+                let pixel = &image.as_raw()[idx * 4..idx * 4 + 4];
+                let [r, g, b] =
+                    [0, 1, 2].map(|channel| tone_map(pixel[channel] * scale, high_depth.tone_map));
+                [r, g, b, pixel[3].clamp(0.0, 1.0)].map(|channel| channel * 255.0)
+            });
+        }
+        image => {
+            let image = image.into_rgba16();
+            quantize(&mut buffer, width, high_depth.dither, |idx| {
+                let pixel = &image.as_raw()[idx * 4..idx * 4 + 4];
+                [0, 1, 2, 3].map(|channel| pixel[channel] as f32 / 257.0)
+            });
+        }
+    }
+    let image = RgbaImage::from_raw(width, height, buffer);
+    DynamicImage::ImageRgba8(image.expect("the buffer has the size of the image"))
+}
+
+/// Fill the rgba `frame`, `width` pixels wide, with the channels `value` returns for each pixel
+/// index, from 0 to 255, rounded or dithered
+///
+/// Only the color channels are dithered, alpha is always rounded.
+fn quantize(frame: &mut [u8], width: u32, dither: bool, value: impl Fn(usize) -> [f32; 4] + Sync) {
+    let width = width as usize;
+    frame
+        .par_chunks_mut((width * 4).max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let threshold = match dither {
+                    true => (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0,
+                    false => 0.5,
+                };
+                let value = value(y * width + x);
+                for channel in 0..3 {
+                    pixel[channel] = (value[channel] + threshold).clamp(0.0, 255.0) as u8;
+                }
+                pixel[3] = (value[3] + 0.5).clamp(0.0, 255.0) as u8;
+            }
+        });
+}
+
+/// Map the linear brightness `value` of an HDR image to the sRGB encoded range from 0 to 1
+fn tone_map(value: f32, tone_map: ToneMap) -> f32 {
+    let value = value.max(0.0);
+    let mapped = match tone_map {
+        ToneMap::Reinhard => value / (1.0 + value),
+        ToneMap::Clip => value.min(1.0),
+    };
+    if mapped <= 0.003_130_8 {
+        mapped * 12.92
+    } else {
+        1.055 * mapped.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The buffer holding the pixels of `image`, to return to a pool once it isn't needed any more
+pub(crate) fn into_buffer(image: DynamicImage) -> Option<Vec<u8>> {
+    match image {
+        DynamicImage::ImageLuma8(image) => Some(image.into_raw()),
+        DynamicImage::ImageLumaA8(image) => Some(image.into_raw()),
+        DynamicImage::ImageRgb8(image) => Some(image.into_raw()),
+        DynamicImage::ImageRgba8(image) => Some(image.into_raw()),
+        _ => None,
+    }
+}
+
+/// Decode the image at `path`, at a reduced size if that is cheaper and still large enough
+///
+/// Images are turned upright by their EXIF orientation, and `target` maps the size of the upright
+/// image to the size it will be scaled to. A reduced image is never
+/// smaller than that in either dimension, so scaling it to the target afterwards looks like
+/// scaling the full image. Only JPEG files are reduced, while decoding, with the `fast-jpeg`
+/// feature.
+pub fn open_scaled(
+    path: &Path,
+    target: impl FnOnce((u32, u32)) -> (u32, u32),
+) -> anyhow::Result<DynamicImage> {
+    open_scaled_pooled(path, target, &mut BufferPool::default())
+}
+
+/// Decode the image at `path` like [`open_scaled`], into a buffer from `pool` unless it is reduced
+#[cfg_attr(not(feature = "fast-jpeg"), allow(unused_variables))]
+pub(crate) fn open_scaled_pooled(
+    path: &Path,
+    target: impl FnOnce((u32, u32)) -> (u32, u32),
+    pool: &mut BufferPool,
+) -> anyhow::Result<DynamicImage> {
+    #[cfg(feature = "fast-jpeg")]
+    if path.extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("jpg") || extension.eq_ignore_ascii_case("jpeg")
+    }) {
+        return jpeg::open_scaled(path, target, pool);
+    }
+    decode(path, &read(path)?, pool)
+}
+
+#[cfg(feature = "fast-jpeg")]
+mod jpeg {
+    use std::path::Path;
+
+    use anyhow::Context;
+    use image::{DynamicImage, GrayImage, RgbImage};
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    use crate::pool::BufferPool;
+
+    /// The size of a `size` image decoded at the smallest DCT scale, 1/8, 1/4 or 1/2, which is
+    /// not smaller than `target`, if any is
+    pub fn reduced_size(size: (u32, u32), target: (u32, u32)) -> Option<(u32, u32)> {
+        [1, 2, 4]
+            .into_iter()
+            .map(|eighths| {
+                (
+                    (size.0 * eighths).div_ceil(8),
+                    (size.1 * eighths).div_ceil(8),
+                )
+            })
+            .find(|&(width, height)| width >= target.0 && height >= target.1)
+    }
+
+    /// Decode a JPEG file with DCT scaling if it is shown at half its size or smaller, falling
+    /// back to the image crate for the full size and pixel formats other than RGB and gray
+    pub fn open_scaled(
+        path: &Path,
+        target: impl FnOnce((u32, u32)) -> (u32, u32),
+        pool: &mut BufferPool,
+    ) -> anyhow::Result<DynamicImage> {
+        let bytes = super::read(path)?;
+        let orientation = super::exif_orientation(&bytes);
+        let mut decoder = Decoder::new(&*bytes);
+        let reduced = decoder
+            .read_info()
+            .ok()
+            .and_then(|()| decoder.info())
+            .filter(|info| matches!(info.pixel_format, PixelFormat::RGB24 | PixelFormat::L8))
+            .and_then(|info| {
+                let size = (u32::from(info.width), u32::from(info.height));
+                let target = target(super::oriented_size(size, orientation));
+                reduced_size(size, super::oriented_size(target, orientation))
+            });
+
+        if let Some((width, height)) = reduced {
+            // Both dimensions fit, the image is at most 65535 pixels wide and high
+            decoder.scale(width as u16, height as u16)?;
+            let pixels = decoder.decode()?;
+            let info = decoder.info().context("the decoded JPEG has no size")?;
+            let (width, height) = (u32::from(info.width), u32::from(info.height));
+            let image = match info.pixel_format {
+                PixelFormat::RGB24 => {
+                    RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+                }
+                _ => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+            };
+            let image = image.with_context(|| {
+                format!("{} decoded to fewer pixels than its size", path.display())
+            })?;
+            let profile = super::options()
+                .color_management
+                .then(|| decoder.icc_profile())
+                .flatten();
+            let image = super::to_srgb(image, profile.as_deref(), path);
+            return Ok(super::orient(image, orientation, pool));
+        }
+        super::decode(path, &bytes, pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn maps_large_files_and_reads_small_ones() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut rng = fastrand::Rng::with_seed(7);
+        for (name, size, mapped) in [("small.png", 16, false), ("noise", 512, true)] {
+            let image = RgbImage::from_fn(size, size, |_, _| Rgb([rng.u8(..), rng.u8(..), 0]));
+            let path = dir.join(name);
+            image.save_with_format(&path, ImageFormat::Png).unwrap();
+
+            let bytes = read(&path).unwrap();
+            assert_eq!(matches!(bytes, FileBytes::Mapped(_)), mapped, "{name}");
+            assert_eq!(*bytes, std::fs::read(&path).unwrap());
+            // Without an extension the format is taken from the contents
+            assert_eq!(open(&path).unwrap().to_rgb8(), image);
+        }
+    }
+
+    #[test]
+    fn decodes_the_format_the_contents_are_in() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let image = RgbaImage::from_fn(5, 3, |x, y| {
+            image::Rgba([x as u8 * 50, y as u8 * 80, 7, 255])
+        });
+        // A WebP file named like a JPEG one
+        let webp = dir.join("download.jpg");
+        image.save_with_format(&webp, ImageFormat::WebP).unwrap();
+        assert_eq!(open(&webp).unwrap().to_rgba8(), image);
+
+        let avif = dir.join("photo.avif");
+        std::fs::write(&avif, b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf").unwrap();
+        let error = open(&avif).unwrap_err().to_string();
+        #[cfg(not(feature = "avif"))]
+        assert!(error.contains("is an AVIF image"), "{error}");
+        #[cfg(feature = "avif")]
+        assert!(error.contains("Avif"), "{error}");
+
+        let text = dir.join("notes.txt");
+        std::fs::write(&text, "not an image").unwrap();
+        let error = open(&text).unwrap_err().to_string();
+        assert!(
+            error.ends_with("is not in any supported image format"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn converts_colors_with_a_profile_to_srgb() {
+        use image::codecs::jpeg::JpegEncoder;
+
+        use crate::icc::tests::{profile, DISPLAY_P3};
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 100)
+            .encode_image(&RgbImage::from_pixel(16, 16, Rgb([200, 150, 100])))
+            .unwrap();
+        let mut segment = b"ICC_PROFILE\0\x01\x01".to_vec();
+        segment.extend(profile(DISPLAY_P3));
+        let marker = [[0xFF, 0xE2], (segment.len() as u16 + 2).to_be_bytes()].concat();
+
+        let plain = dir.join("plain.jpg");
+        std::fs::write(&plain, &jpeg).unwrap();
+        let p3 = dir.join("p3.jpg");
+        std::fs::write(&p3, [&jpeg[..2], &marker, &segment, &jpeg[2..]].concat()).unwrap();
+        for (path, expected) in [(plain, [200, 150, 100]), (p3, [209, 147, 91])] {
+            let pixel = open(&path).unwrap().to_rgb8()[(8, 8)];
+            for channel in 0..3 {
+                assert!(
+                    pixel[channel].abs_diff(expected[channel]) <= 2,
+                    "{path:?}: {pixel:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reduces_high_depth_images_to_8_bits() {
+        let rounded = |value: u16| {
+            let image = image::ImageBuffer::from_pixel(1, 1, image::Rgba([value, 0, 0, 65535]));
+            reduce_depth(DynamicImage::ImageRgba16(image), HighDepth::default()).to_rgba8()[(0, 0)]
+                [0]
+        };
+        assert_eq!(rounded(0), 0);
+        assert_eq!(rounded(100 * 257 + 128), 100);
+        assert_eq!(rounded(100 * 257 + 129), 101);
+        assert_eq!(rounded(65535), 255);
+
+        let hdr =
+            image::Rgb32FImage::from_raw(3, 1, vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 4.0, 0.5, -1.0])
+                .unwrap();
+        let mapped = |high_depth| {
+            reduce_depth(DynamicImage::ImageRgb32F(hdr.clone()), high_depth)
+                .to_rgba8()
+                .into_raw()
+        };
+        // Reinhard maps 1 to half as bright, 0.5 in linear light is 188 in sRGB
+        assert_eq!(
+            mapped(HighDepth::default()),
+            [0, 0, 0, 255, 188, 188, 188, 255, 231, 156, 0, 255]
+        );
+        let clipped = HighDepth {
+            tone_map: ToneMap::Clip,
+            exposure: -1.0,
+            ..HighDepth::default()
+        };
+        assert_eq!(
+            mapped(clipped),
+            [0, 0, 0, 255, 188, 188, 188, 255, 255, 137, 0, 255]
+        );
+
+        // Dithering averages to the exact value over the pattern
+        let image = image::ImageBuffer::from_pixel(4, 4, image::Rgb([100 * 257 + 64, 0, 0]));
+        let dithered = HighDepth {
+            dither: true,
+            ..HighDepth::default()
+        };
+        let reduced = reduce_depth(DynamicImage::ImageRgb16(image), dithered).to_rgba8();
+        let sum: u32 = reduced.pixels().map(|pixel| pixel[0] as u32).sum();
+        assert_eq!(sum, 100 * 16 + 4);
+    }
+
+    #[test]
+    fn reads_the_exif_orientation() {
+        for orientation in [3, 6, 8] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join(format!("tests/data/orientation-{orientation}.jpg"));
+            let bytes = std::fs::read(path).unwrap();
+            assert_eq!(exif_orientation(&bytes), orientation);
+            // Cut off inside the EXIF segment
+            assert_eq!(exif_orientation(&bytes[..30]), 1);
+        }
+        let png = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/input.png");
+        assert_eq!(exif_orientation(&std::fs::read(png).unwrap()), 1);
+
+        let stored =
+            DynamicImage::ImageRgb8(RgbImage::from_fn(3, 2, |x, y| Rgb([x as u8, y as u8, 0])));
+        let shown = orient(stored, 6, &mut BufferPool::default());
+        assert_eq!(shown.to_rgb8().get_pixel(0, 0), &Rgb([0, 1, 0]));
+        assert_eq!((shown.width(), shown.height()), (2, 3));
+    }
+
+    #[cfg(feature = "fast-jpeg")]
+    #[test]
+    fn picks_the_smallest_scale_covering_the_target() {
+        assert_eq!(
+            jpeg::reduced_size((6000, 4000), (2560, 1440)),
+            Some((3000, 2000))
+        );
+        assert_eq!(
+            jpeg::reduced_size((6000, 4000), (700, 500)),
+            Some((750, 500))
+        );
+        assert_eq!(jpeg::reduced_size((6000, 4000), (4000, 1000)), None);
+        assert_eq!(
+            jpeg::reduced_size((1001, 999), (100, 100)),
+            Some((126, 125))
+        );
+    }
+
+    #[cfg(feature = "fast-jpeg")]
+    #[test]
+    fn reduced_jpegs_look_like_full_ones() {
+        use image::codecs::jpeg::JpegEncoder;
+
+        use crate::render::{FrameLayout, Scaling};
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("gradient.jpg");
+        let source = RgbImage::from_fn(1600, 1200, |x, y| {
+            let ring = ((x as f64 - 800.0).hypot(y as f64 - 600.0) / 40.0).sin();
+            Rgb([
+                (x * 255 / 1599) as u8,
+                (y * 255 / 1199) as u8,
+                (127.0 + 100.0 * ring) as u8,
+            ])
+        });
+        let mut file = std::fs::File::create(&path).unwrap();
+        JpegEncoder::new_with_quality(&mut file, 90)
+            .encode_image(&source)
+            .unwrap();
+        drop(file);
+        // The same image turned upright from its side, reduced by its upright size
+        let turned = dir.join("turned.jpg");
+        let bytes = std::fs::read(&path).unwrap();
+        let fixture = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/orientation-6.jpg"),
+        )
+        .unwrap();
+        let exif_len = 2 + usize::from(u16::from_be_bytes([fixture[4], fixture[5]]));
+        std::fs::write(
+            &turned,
+            [&bytes[..2], &fixture[2..2 + exif_len], &bytes[2..]].concat(),
+        )
+        .unwrap();
+
+        for (path, scaling, width, height) in [
+            (&path, Scaling::Fit, 400, 300),
+            (&path, Scaling::Fill, 640, 360),
+            (&path, Scaling::Stretch, 350, 500),
+            (&turned, Scaling::Fill, 400, 300),
+        ] {
+            let mut layout = FrameLayout::with_color(scaling, [0, 0, 0], width, height);
+            let fast = layout.load(path, width, height).unwrap();
+            let slow = FrameLayout::with_color(scaling, [0, 0, 0], width, height).place(
+                &open(path).unwrap(),
+                width,
+                height,
+            );
+            assert_eq!(fast.dimensions(), slow.dimensions());
+
+            let differences: Vec<u32> = fast
+                .pixels()
+                .zip(slow.pixels())
+                .flat_map(|(a, b)| (0..4).map(move |c| a[c].abs_diff(b[c]) as u32))
+                .collect();
+            let mean = differences.iter().sum::<u32>() as f64 / differences.len() as f64;
+            let max = differences.iter().max().copied().unwrap_or(0);
+            assert!(mean < 1.5, "{scaling:?}: mean difference {mean}");
+            assert!(max <= 24, "{scaling:?}: largest difference {max}");
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::{path::Path, sync::Mutex};
+
+use image::DynamicImage;
+
+/// How long a download may take at most, in seconds
+#[cfg(feature = "http")]
+const DOWNLOAD_TIMEOUT: u32 = 20;
+/// The largest image which is downloaded, in bytes
+#[cfg(feature = "http")]
+const MAX_DOWNLOAD_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The images downloaded ahead by [`prefetch`], by url
+static PREFETCHED: Mutex<Vec<(String, DynamicImage)>> = Mutex::new(Vec::new());
+
+/// Whether `path` is an http or https url rather than a file
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// An image downloaded ahead of showing it, which [`fetch_image`] takes instead of downloading it
+/// again until this is dropped
+pub struct Prefetched {
+    url: String,
+}
+
+impl Drop for Prefetched {
+    fn drop(&mut self) {
+        let mut prefetched = PREFETCHED.lock().unwrap();
+        if let Some(index) = prefetched.iter().position(|(url, _)| *url == self.url) {
+            prefetched.remove(index);
+        }
+    }
+}
+
+/// Download the image at `url` ahead of showing it, so the renderers showing it don't wait for the
+/// download
+pub fn prefetch(url: &str) -> anyhow::Result<Prefetched> {
+    let image = download(url)?;
+    PREFETCHED.lock().unwrap().push((url.to_string(), image));
+    Ok(Prefetched {
+        url: url.to_string(),
+    })
+}
+
+/// The image at `url` decoded like image files, turned upright and converted to sRGB, downloaded
+/// unless it was prefetched
+pub fn fetch_image(url: &str) -> anyhow::Result<DynamicImage> {
+    let prefetched = PREFETCHED
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|(prefetched, _)| prefetched == url)
+        .map(|(_, image)| image.clone());
+    match prefetched {
+        Some(image) => Ok(image),
+        None => download(url),
+    }
+}
+
+/// Download the image at `url` and decode it
+///
+/// The download is done by `curl` so no http or tls library is needed, following redirects and
+/// failing on error responses.
+#[cfg(feature = "http")]
+fn download(url: &str) -> anyhow::Result<DynamicImage> {
+    use std::{
+        io::Read,
+        process::{Command, Stdio},
+    };
+
+    use anyhow::{bail, Context};
+
+    let mut curl = match crate::signals::unblocked(
+        Command::new("curl")
+            .args(["--silent", "--show-error", "--fail", "--location"])
+            .args(["--proto", "=http,https", "--max-redirs", "10"])
+            .args(["--max-time", &DOWNLOAD_TIMEOUT.to_string()])
+            .args(["--max-filesize", &MAX_DOWNLOAD_SIZE.to_string()])
+            .arg(url)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .spawn()
+    {
+        Ok(curl) => curl,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            bail!("curl is not installed, it is needed to download {url}")
+        }
+        Err(error) => return Err(error).context("could not run curl"),
+    };
+
+    // The size limit of curl only works if the server sends the length up front
+    let mut bytes = Vec::new();
+    let read = curl
+        .stdout
+        .take()
+        .expect("stdout is piped")
+        .take(MAX_DOWNLOAD_SIZE + 1)
+        .read_to_end(&mut bytes);
+    if bytes.len() as u64 > MAX_DOWNLOAD_SIZE {
+        let _ = curl.kill();
+        let _ = curl.wait();
+        bail!("{url} is larger than {MAX_DOWNLOAD_SIZE} bytes");
+    }
+    let mut stderr = String::new();
+    if let Some(mut pipe) = curl.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    let status = curl.wait()?;
+    read.with_context(|| format!("could not download {url}"))?;
+    if !status.success() {
+        bail!(
+            "could not download {url}: {error}",
+            error = stderr
+                .trim()
+                .strip_prefix("curl: ")
+                .unwrap_or(stderr.trim())
+        );
+    }
+
+    crate::decode::decode_bytes(url, &bytes)
+}
+
+/// Fail to download `url`, this build has no http support
+#[cfg(not(feature = "http"))]
+fn download(url: &str) -> anyhow::Result<DynamicImage> {
+    anyhow::bail!("can't download {url}, this build has no http support")
+}
+
+#[cfg(all(test, feature = "http"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_failed_downloads() {
+        // Nothing listens on port 9 of the loopback interface
+        let error = fetch_image("http://127.0.0.1:9/background.png").unwrap_err();
+        assert!(
+            error.to_string().contains("could not download"),
+            "{error:#}"
+        );
+    }
+
+    /// Serve `image` at `/image` for `requests` requests on a local port, redirecting `/moved`
+    /// there, returns the port
+    fn serve(image: Vec<u8>, requests: usize) -> u16 {
+        use std::{
+            io::{BufRead, BufReader, Write},
+            net::TcpListener,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+                let mut request = String::new();
+                BufReader::new(&stream).read_line(&mut request).unwrap();
+                if request.starts_with("GET /moved ") {
+                    write!(
+                        stream,
+                        "HTTP/1.1 302 Found\r\nLocation: /image\r\nContent-Length: 0\r\n\
+                         Connection: close\r\n\r\n"
+                    )
+                    .unwrap();
+                } else {
+                    write!(
+                        stream,
+                        "HTTP/1.1 200 OK\r\nContent-Length: {len}\r\nConnection: close\r\n\r\n",
+                        len = image.len()
+                    )
+                    .unwrap();
+                    stream.write_all(&image).unwrap();
+                }
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn follows_redirects() {
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([1, 2, 3, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let port = serve(png, 2);
+
+        let image = fetch_image(&format!("http://127.0.0.1:{port}/moved")).unwrap();
+        assert_eq!(image.to_rgba8().get_pixel(0, 0).0, [1, 2, 3, 255]);
+    }
+
+    #[test]
+    fn turns_downloaded_images_upright() {
+        let jpeg = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/orientation-6.jpg"),
+        )
+        .unwrap();
+        let stored = image::load_from_memory(&jpeg).unwrap();
+        let port = serve(jpeg, 1);
+
+        // Served without an extension or content type, the format comes from the contents
+        let image = fetch_image(&format!("http://127.0.0.1:{port}/image")).unwrap();
+        assert_eq!(
+            (image.width(), image.height()),
+            (stored.height(), stored.width())
+        );
+    }
+
+    #[test]
+    fn takes_prefetched_images() {
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([4, 5, 6, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        // The server answers once, later downloads fail
+        let port = serve(png, 1);
+        let url = format!("http://127.0.0.1:{port}/image");
+
+        let prefetched = prefetch(&url).unwrap();
+        for _ in 0..2 {
+            let image = fetch_image(&url).unwrap();
+            assert_eq!(image.to_rgba8().get_pixel(0, 0).0, [4, 5, 6, 255]);
+        }
+        drop(prefetched);
+        assert!(fetch_image(&url).is_err());
+    }
+
+    #[test]
+    fn recognizes_urls() {
+        assert!(is_url(Path::new("https://example.com/background.png")));
+        assert!(!is_url(Path::new("backgrounds/https://example.com")));
+    }
+}
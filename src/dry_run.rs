@@ -0,0 +1,193 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use clap::ValueEnum;
+
+use crate::{
+    command::{format_time_of_day, Command, DryRunArgs},
+    decode,
+    download::is_url,
+    render::{FrameLayout, Scaling},
+    template::FrameTemplate,
+};
+
+/// Check that the desktop program would show the background of `args`, without a desktop program
+///
+/// Each background is created like the daemon creates it, except that pipe commands aren't
+/// started, and one image of it is decoded to tell how it is scaled. Returns what was found, one
+/// line each.
+pub fn check(args: DryRunArgs) -> anyhow::Result<Vec<String>> {
+    let DryRunArgs {
+        width,
+        height,
+        mut background,
+    } = args;
+    if !background.is_background() {
+        bail!("only background commands can be checked");
+    }
+    background.validate()?;
+    let mut found = background.detect_clock_steps()?;
+    check_background(*background, width, height, &mut found)?;
+    Ok(found)
+}
+
+/// Create the renderer of `command` for a `width` x `height` desktop and describe the image it
+/// shows, checking layers and schedule entries one by one
+fn check_background(
+    command: Command,
+    width: u32,
+    height: u32,
+    found: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let summary = command.summary();
+    let mut command = match command {
+        Command::Layer { layers } => {
+            for (idx, layer) in layers.into_iter().enumerate() {
+                check_background(layer, width, height, found)
+                    .with_context(|| format!("layer {idx}"))?;
+            }
+            return Ok(());
+        }
+        Command::Schedule { entries, .. } => {
+            for entry in entries {
+                check_background(entry.background, width, height, found).with_context(|| {
+                    format!(
+                        "the entry at {start}",
+                        start = format_time_of_day(entry.start)
+                    )
+                })?;
+            }
+            return Ok(());
+        }
+        Command::Pipe { command, .. } => {
+            found.push(format!(
+                "{summary}: {command:?} is only started by the desktop program"
+            ));
+            return Ok(());
+        }
+        command => command,
+    };
+
+    // Watchers would outlive the check, the layout of the outputs is only known to the daemon
+    match &mut command {
+        Command::StaticImage { watch, span, .. } => {
+            if std::mem::take(span) {
+                found.push(format!(
+                    "{summary}: checked without spanning, the layout of the outputs is only \
+                     known to the desktop program"
+                ));
+            }
+            *watch = false;
+        }
+        Command::LatestImage { watch, .. } => *watch = false,
+        _ => {}
+    }
+    let scaling = match &command {
+        Command::StaticImage { scaling, .. }
+        | Command::ClockImage { scaling, .. }
+        | Command::ClockHands { scaling, .. }
+        | Command::LatestImage { scaling, .. }
+        | Command::Slideshow { scaling, .. }
+        | Command::Weekly { scaling, .. } => Some(*scaling),
+        _ => None,
+    };
+    let named = shown_image(&command)?;
+
+    let renderer = command
+        .into_renderer(width, height, None)
+        .with_context(|| format!("could not show {summary}"))?;
+    let shown = named.or_else(|| renderer.chosen_file().map(Path::to_path_buf));
+    if let (Some(path), Some(scaling)) = (shown, scaling) {
+        found.push(describe_scaling(&path, scaling, width, height)?);
+    } else {
+        found.push(format!("{summary}: ok"));
+    }
+    Ok(())
+}
+
+/// The image file a background shows first if the command names it, the frame at midnight of
+/// clock images
+fn shown_image(command: &Command) -> anyhow::Result<Option<PathBuf>> {
+    Ok(match command {
+        Command::StaticImage { path, .. } if !is_url(path) => Some(path.clone()),
+        Command::ClockImage {
+            dir,
+            file_template,
+            hours,
+            hour_dirs,
+            sheets: false,
+            ..
+        } => Some(FrameTemplate::load(dir, file_template, hour_dirs, *hours)?.path(dir, 0)),
+        Command::ClockHands { face, .. } => Some(face.clone()),
+        _ => None,
+    })
+}
+
+/// Decode the image at `path` and tell its size and the size it is shown at with `scaling`
+fn describe_scaling(
+    path: &Path,
+    scaling: Scaling,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<String> {
+    let image = decode::open(path)?;
+    let size = (image.width(), image.height());
+    let (shown_width, shown_height) =
+        FrameLayout::with_color(scaling, [0; 3], width, height).scaled_size(size, width, height);
+    let mut description = format!(
+        "{path}: {image_width}x{image_height}, {scaling} to {shown_width}x{shown_height} on \
+         {width}x{height}",
+        path = path.display(),
+        image_width = size.0,
+        image_height = size.1,
+        scaling = scaling
+            .to_possible_value()
+            .map_or(String::new(), |value| value.get_name().to_string()),
+    );
+    let scale = (shown_width as f64 / size.0 as f64).max(shown_height as f64 / size.1 as f64);
+    if scale > 1.0 {
+        description.push_str(&format!(", scaled up {scale:.2} times"));
+    }
+    Ok(description)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+    use crate::command::parse_batch;
+
+    #[test]
+    fn checks_backgrounds_without_a_desktop_program() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("0")).unwrap();
+        RgbaImage::from_pixel(40, 20, Rgba([0, 0, 0, 255]))
+            .save(dir.join("image.png"))
+            .unwrap();
+        let check = |background: &str| {
+            let background = background.replace("DIR", &dir.to_string_lossy());
+            check(DryRunArgs {
+                width: 80,
+                height: 60,
+                background: Box::new(parse_batch(&background)?.remove(0).command),
+            })
+        };
+
+        let found = check("static-image DIR/image.png --scaling fit").unwrap();
+        assert_eq!(
+            found,
+            [format!(
+                "{path}: 40x20, fit to 80x40 on 80x60, scaled up 2.00 times",
+                path = dir.join("image.png").display()
+            )]
+        );
+        let error = check("static-image DIR/missing.png").unwrap_err();
+        assert!(format!("{error:#}").contains("missing.png"), "{error:#}");
+        assert!(check("static-image DIR/image.png --margin-color nope").is_err());
+        assert!(check("pipe 'cat /dev/zero'").unwrap()[0].contains("only started"));
+        // The first hour folder holds no frame at midnight
+        assert!(check("clock-image DIR %M.png 60000 --no-validate").is_err());
+    }
+}
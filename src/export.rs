@@ -0,0 +1,242 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command::{shell_quote, Command, StartArgs},
+    json,
+};
+
+/// How a running daemon was started and what it shows, its reply to the export command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Setup {
+    /// The arguments the daemon was started with
+    pub start: StartArgs,
+    /// The outputs by name
+    pub outputs: Vec<OutputSetup>,
+    /// The background of the mirror in mirror mode
+    pub mirror: Option<Command>,
+    /// Whether vsync is on
+    pub vsync: bool,
+    /// Whether rendering is paused
+    pub paused: bool,
+}
+
+/// The background and the settings of one output of a running daemon
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputSetup {
+    /// The name of the output like `DP-1`
+    pub name: String,
+    /// The background sent to the output, the default one if not set
+    pub command: Option<Command>,
+    /// Whether the debug overlay is shown
+    pub debug_overlay: bool,
+}
+
+impl Setup {
+    /// The setup in the format of the reply to the export command
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(json::to_string(self)?)
+    }
+
+    /// Read the reply to the export command
+    pub fn from_json(reply: &str) -> anyhow::Result<Self> {
+        let value = json::parse(reply).context("could not parse the exported setup")?;
+        json::from_value(value).context("the exported setup is incomplete")
+    }
+}
+
+/// A shell script starting a desktop program on the socket `socket_name` like the one of `setup`
+/// and sending it the commands which show the same backgrounds with the same settings
+pub fn script(socket_name: &str, setup: &Setup) -> String {
+    let run = |args: &[String]| {
+        ["desktop-background", socket_name]
+            .into_iter()
+            .chain(args.iter().map(String::as_str))
+            .map(shell_quote)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+    let words = |words: &[&str]| {
+        words
+            .iter()
+            .map(|word| word.to_string())
+            .collect::<Vec<_>>()
+    };
+    let background = |command: &Command, output: Option<&str>| match command.to_args() {
+        Some(mut args) => {
+            if let Some(output) = output {
+                args.extend(words(&["--output", output]));
+            }
+            run(&args)
+        }
+        None => format!("# {summary} can't be exported", summary = command.summary()),
+    };
+
+    let mut lines = vec![
+        "#!/bin/sh".to_string(),
+        format!(
+            "# Exported from the desktop program on {socket_name} by `desktop-background \
+             {socket_name} export`"
+        ),
+        "set -e".to_string(),
+        String::new(),
+        format!("{start} &", start = run(&setup.start.to_args())),
+        format!(
+            "until {ping} >/dev/null 2>&1; do sleep 0.1; done",
+            ping = run(&words(&["ping"]))
+        ),
+    ];
+
+    if setup.vsync == setup.start.no_vsync {
+        lines.push(run(&words(&["vsync", &setup.vsync.to_string()])));
+    }
+    for output in &setup.outputs {
+        if output.debug_overlay != setup.start.debug_overlay {
+            let enabled = output.debug_overlay.to_string();
+            lines.push(run(&words(&[
+                "debug-overlay",
+                &enabled,
+                "--output",
+                &output.name,
+            ])));
+        }
+    }
+
+    let first = setup
+        .outputs
+        .first()
+        .and_then(|output| output.command.as_ref());
+    if let Some(mirror) = setup.mirror.as_ref() {
+        lines.push(background(mirror, None));
+    } else if let Some(first) = first.filter(|first| {
+        setup
+            .outputs
+            .iter()
+            .all(|output| output.command.as_ref() == Some(first))
+    }) {
+        lines.push(background(first, None));
+    } else {
+        for output in &setup.outputs {
+            if let Some(command) = output.command.as_ref() {
+                lines.push(background(command, Some(&output.name)));
+            }
+        }
+    }
+
+    if setup.paused {
+        lines.push(run(&words(&["pause"])));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Args {
+        #[arg()]
+        _socket_name: String,
+        #[arg(long, global = true)]
+        output: Option<String>,
+        #[command(subcommand)]
+        command: Command,
+    }
+
+    fn setup() -> Setup {
+        let image = |path: &str| Command::StaticImage {
+            path: path.into(),
+            scaling: Default::default(),
+            margin_color: Some("102030".parse().unwrap()),
+            watch: false,
+            span: false,
+        };
+        let Args {
+            command: Command::Start(start),
+            ..
+        } = Args::parse_from([
+            "desktop-background",
+            "main",
+            "start",
+            "1920",
+            "1080",
+            "desktop background",
+            "--per-output",
+            "--no-vsync",
+            "--fps",
+            "7.5",
+            "--dither",
+            "--tone-map",
+            "clip",
+            "--exposure",
+            "-1.5",
+            "--no-color-management",
+        ])
+        else {
+            unreachable!()
+        };
+        Setup {
+            start,
+            outputs: vec![
+                OutputSetup {
+                    name: "DP-1".to_string(),
+                    command: Some(image("My Pictures/it's a.png")),
+                    debug_overlay: true,
+                },
+                OutputSetup {
+                    name: "HDMI-1".to_string(),
+                    command: Some(Command::Layer {
+                        layers: vec![image("b.png"), image("c d.png")],
+                    }),
+                    debug_overlay: false,
+                },
+            ],
+            mirror: None,
+            vsync: false,
+            paused: true,
+        }
+    }
+
+    #[test]
+    fn exports_commands_which_parse_back() {
+        let setup = setup();
+        assert_eq!(Setup::from_json(&setup.to_json().unwrap()).unwrap(), setup);
+
+        let script = script("main", &setup);
+        let commands: Vec<(Option<String>, Command)> = script
+            .lines()
+            .filter(|line| line.starts_with("desktop-background "))
+            .map(|line| {
+                let line = line.trim_end_matches(" &");
+                let words = crate::command::split_words(line).unwrap();
+                let args = Args::try_parse_from(words).unwrap();
+                (args.output, args.command)
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                (None, Command::Start(setup.start.clone())),
+                (
+                    Some("DP-1".to_string()),
+                    Command::DebugOverlay { enabled: true }
+                ),
+                (
+                    Some("DP-1".to_string()),
+                    setup.outputs[0].command.clone().unwrap()
+                ),
+                (
+                    Some("HDMI-1".to_string()),
+                    setup.outputs[1].command.clone().unwrap()
+                ),
+                (None, Command::Pause),
+            ]
+        );
+        assert!(
+            script.contains("until desktop-background main ping"),
+            "{script}"
+        );
+    }
+}
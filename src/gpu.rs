@@ -0,0 +1,350 @@
+use anyhow::bail;
+use pixels::{
+    wgpu::{AdapterInfo, Backends, PresentMode, RequestAdapterOptions},
+    Pixels, PixelsBuilder, SurfaceTexture,
+};
+use winit::{dpi::PhysicalSize, window::Window};
+
+use crate::command::{Oversize, PowerPreference, StartArgs};
+#[cfg(feature = "cpu-fallback")]
+use crate::software::SoftwarePresenter;
+
+/// How the graphics adapter is picked and frames are presented
+#[derive(Debug, Clone, Copy)]
+pub struct GpuOptions {
+    pub backends: Backends,
+    pub power_preference: pixels::wgpu::PowerPreference,
+    pub force_fallback_adapter: bool,
+    pub present_mode: PresentMode,
+    pub oversize: Oversize,
+}
+
+impl GpuOptions {
+    /// The options given on start, picking the adapter named by `--adapter` if set
+    ///
+    /// Must be called before any other thread is started since the adapter is handed to pixels
+    /// through the `WGPU_ADAPTER_NAME` environment variable.
+    pub fn new(start: &StartArgs) -> anyhow::Result<Self> {
+        let mut backends =
+            pixels::wgpu::util::backend_bits_from_env().unwrap_or_else(Backends::all);
+        if let Some(name) = &start.adapter {
+            let adapter = select_adapter(name, backends)?;
+            std::env::set_var("WGPU_ADAPTER_NAME", &adapter.name);
+            // The same adapter can be listed once per backend
+            backends = adapter.backend.into();
+        }
+
+        Ok(GpuOptions {
+            backends,
+            power_preference: match start.power_preference {
+                PowerPreference::Low => pixels::wgpu::PowerPreference::LowPower,
+                PowerPreference::High => pixels::wgpu::PowerPreference::HighPerformance,
+            },
+            force_fallback_adapter: start.force_fallback_adapter,
+            present_mode: if start.no_vsync {
+                PresentMode::AutoNoVsync
+            } else {
+                PresentMode::AutoVsync
+            },
+            oversize: start.oversize,
+        })
+    }
+}
+
+impl Default for GpuOptions {
+    /// The options of a start without any of the graphics flags
+    fn default() -> Self {
+        GpuOptions {
+            backends: pixels::wgpu::util::backend_bits_from_env().unwrap_or_else(Backends::all),
+            power_preference: pixels::wgpu::PowerPreference::LowPower,
+            force_fallback_adapter: false,
+            present_mode: PresentMode::AutoVsync,
+            oversize: Oversize::default(),
+        }
+    }
+}
+
+/// The first adapter whose name contains `name` ignoring case, listing all adapters if none does
+pub fn select_adapter(name: &str, backends: Backends) -> anyhow::Result<AdapterInfo> {
+    let instance = pixels::wgpu::Instance::new(pixels::wgpu::InstanceDescriptor {
+        backends,
+        ..Default::default()
+    });
+    let adapters: Vec<_> = instance
+        .enumerate_adapters(backends)
+        .map(|adapter| adapter.get_info())
+        .collect();
+
+    let needle = name.to_lowercase();
+    if let Some(adapter) = adapters
+        .iter()
+        .find(|adapter| adapter.name.to_lowercase().contains(&needle))
+    {
+        return Ok(adapter.clone());
+    }
+
+    let available = if adapters.is_empty() {
+        " none".to_string()
+    } else {
+        adapters
+            .iter()
+            .map(|adapter| format!("\n  {}", describe_adapter(adapter)))
+            .collect()
+    };
+    bail!("no graphics adapter matches {name:?}, available adapters:{available}")
+}
+
+pub fn describe_adapter(info: &AdapterInfo) -> String {
+    format!(
+        "{name} ({device_type:?}, {backend:?} backend)",
+        name = info.name,
+        device_type = info.device_type,
+        backend = info.backend,
+    )
+}
+
+/// The buffer size for a `width` x `height` desktop on an adapter whose textures are at most
+/// `max_dimension` pixels wide and high
+///
+/// Oversized buffers are divided by a whole factor, so the surface scales them up without margins.
+pub fn fit_texture_limit(
+    width: u32,
+    height: u32,
+    max_dimension: u32,
+    oversize: Oversize,
+) -> anyhow::Result<(u32, u32)> {
+    if width <= max_dimension && height <= max_dimension {
+        return Ok((width, height));
+    }
+    if oversize == Oversize::Fail {
+        bail!(
+            "the resolution {width}x{height} exceeds the maximum texture size of the graphics \
+             adapter of {max_dimension}x{max_dimension}\n\
+             hint: pass --oversize downscale to render at a reduced resolution"
+        );
+    }
+
+    let factor = width.max(height).div_ceil(max_dimension);
+    let fitted = ((width / factor).max(1), (height / factor).max(1));
+    eprintln!(
+        "warning: the resolution {width}x{height} exceeds the maximum texture size of the graphics \
+         adapter of {max_dimension}x{max_dimension}, rendering at {fitted_width}x{fitted_height} \
+         scaled up by {factor}",
+        fitted_width = fitted.0,
+        fitted_height = fitted.1,
+    );
+    Ok(fitted)
+}
+
+/// The pixel buffer of a window and how it is presented
+#[allow(clippy::large_enum_variant)]
+pub enum Presenter {
+    /// Presented by the graphics adapter
+    Gpu(Pixels),
+    /// Presented by the CPU, since no graphics adapter could be used
+    #[cfg(feature = "cpu-fallback")]
+    Cpu(SoftwarePresenter),
+}
+
+impl Presenter {
+    /// The rgba buffer presented
+    pub fn frame(&self) -> &[u8] {
+        match self {
+            Presenter::Gpu(pixels) => pixels.frame(),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(software) => software.frame(),
+        }
+    }
+
+    /// The rgba buffer presented
+    pub fn frame_mut(&mut self) -> &mut [u8] {
+        match self {
+            Presenter::Gpu(pixels) => pixels.frame_mut(),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(software) => software.frame_mut(),
+        }
+    }
+
+    /// The width and height of the buffer
+    pub fn buffer_size(&self) -> (u32, u32) {
+        match self {
+            Presenter::Gpu(pixels) => (pixels.texture().width(), pixels.texture().height()),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(software) => software.buffer_size(),
+        }
+    }
+
+    /// The widest and highest buffer which can be presented
+    pub fn max_dimension(&self) -> u32 {
+        match self {
+            Presenter::Gpu(pixels) => pixels.device().limits().max_texture_dimension_2d,
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(_) => u32::MAX,
+        }
+    }
+
+    /// Present the buffer on a surface of `width` x `height` pixels from now on
+    pub fn resize_surface(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+        match self {
+            Presenter::Gpu(pixels) => Ok(pixels.resize_surface(width, height)?),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(software) => software.resize_surface(width, height),
+        }
+    }
+
+    /// Make the buffer `width` x `height` pixels
+    pub fn resize_buffer(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+        match self {
+            Presenter::Gpu(pixels) => Ok(pixels.resize_buffer(width, height)?),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(software) => {
+                software.resize_buffer(width, height);
+                Ok(())
+            }
+        }
+    }
+
+    /// Present the buffer
+    pub fn render(&mut self) -> Result<(), pixels::Error> {
+        match self {
+            Presenter::Gpu(pixels) => pixels.render(),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(software) => software
+                .render()
+                .map_err(|error| pixels::Error::UserDefined(error.to_string().into())),
+        }
+    }
+
+    /// A human readable description of what presents the buffer
+    pub fn describe(&self) -> String {
+        match self {
+            Presenter::Gpu(pixels) => describe_adapter(&pixels.adapter().get_info()),
+            #[cfg(feature = "cpu-fallback")]
+            Presenter::Cpu(_) => "none, presenting by the CPU".to_string(),
+        }
+    }
+}
+
+/// Create the pixel buffer for `window`, presented by the CPU if no graphics adapter can be used
+/// and the `cpu-fallback` feature is enabled
+///
+/// The buffer is reduced to the adapter's texture limit according to [`GpuOptions::oversize`], its
+/// actual size is [`Presenter::buffer_size`].
+pub fn build_pixels(
+    window: &Window,
+    surface_size: PhysicalSize<u32>,
+    width: u32,
+    height: u32,
+    gpu: GpuOptions,
+) -> anyhow::Result<Presenter> {
+    match build_gpu_pixels(window, surface_size, width, height, gpu) {
+        Ok(pixels) => Ok(Presenter::Gpu(pixels)),
+        #[cfg(feature = "cpu-fallback")]
+        Err(error) => {
+            eprintln!("warning: {error:#}\npresenting frames by the CPU instead");
+            SoftwarePresenter::new(window, surface_size, width, height)
+                .map(Presenter::Cpu)
+                .map_err(|error| error.context("could not present frames by the CPU either"))
+        }
+        #[cfg(not(feature = "cpu-fallback"))]
+        Err(error) => Err(error),
+    }
+}
+
+/// Create the pixel buffer for `window` presented by the graphics adapter, explaining the common
+/// reasons why that fails
+fn build_gpu_pixels(
+    window: &Window,
+    surface_size: PhysicalSize<u32>,
+    width: u32,
+    height: u32,
+    gpu: GpuOptions,
+) -> anyhow::Result<Pixels> {
+    // The limits are only known once the device is opened, start with a size every adapter supports
+    let guaranteed = pixels::wgpu::Limits::downlevel_webgl2_defaults().max_texture_dimension_2d;
+    let surface_texture = SurfaceTexture::new(surface_size.width, surface_size.height, window);
+    let initial_size = (width.min(guaranteed), height.min(guaranteed));
+    let mut pixels = PixelsBuilder::new(initial_size.0, initial_size.1, surface_texture)
+        .wgpu_backend(gpu.backends)
+        .request_adapter_options(RequestAdapterOptions {
+            power_preference: gpu.power_preference,
+            force_fallback_adapter: gpu.force_fallback_adapter,
+            compatible_surface: None,
+        })
+        .present_mode(gpu.present_mode)
+        .build()
+        .map_err(|error| match error {
+            pixels::Error::AdapterNotFound if gpu.force_fallback_adapter => anyhow::anyhow!(
+                "no software graphics adapter found\n\
+                     hint: install a software renderer like Mesa's llvmpipe or lavapipe"
+            ),
+            pixels::Error::AdapterNotFound => anyhow::anyhow!(
+                "no graphics adapter found\n\
+                     hint: check that Vulkan or OpenGL drivers for your GPU are installed or try \
+                     --force-fallback-adapter to render in software"
+            ),
+            pixels::Error::DeviceNotFound(error) => anyhow::anyhow!(
+                "could not open the graphics device: {error}\n\
+                     hint: another program may hold the GPU exclusively or the driver is too old"
+            ),
+            pixels::Error::CreateSurface(error) => anyhow::anyhow!(
+                "could not create a surface for the window: {error}\n\
+                     hint: the compositor may not support presenting from this graphics adapter"
+            ),
+            pixels::Error::InvalidTexture(error) => anyhow::anyhow!(
+                "the resolution {width}x{height} is not supported by the graphics adapter: {error}"
+            ),
+            error => anyhow::anyhow!("could not set up rendering: {error}"),
+        })?;
+
+    let max_dimension = pixels.device().limits().max_texture_dimension_2d;
+    let (fitted_width, fitted_height) =
+        fit_texture_limit(width, height, max_dimension, gpu.oversize)?;
+    if (fitted_width, fitted_height) != initial_size {
+        pixels
+            .resize_buffer(fitted_width, fitted_height)
+            .map_err(|error| {
+                anyhow::anyhow!(
+                    "the resolution {fitted_width}x{fitted_height} is not supported by the \
+                     graphics adapter: {error}"
+                )
+            })?;
+    }
+
+    let info = pixels.adapter().get_info();
+    eprintln!(
+        "adapter: {adapter}, driver {driver} {driver_info}",
+        adapter = describe_adapter(&info),
+        driver = info.driver,
+        driver_info = info.driver_info,
+    );
+    Ok(pixels)
+}
+
+/// Build the pixel buffer again with other options, which are fixed once it is built, returns
+/// whether the old frame was kept
+///
+/// The old buffer is dropped first since a window can only be presented to by one surface. Its
+/// frame is only copied into a buffer of the same size, otherwise it has to be drawn again.
+pub fn rebuild_pixels(
+    pixels: &mut Option<Presenter>,
+    window: &Window,
+    width: u32,
+    height: u32,
+    gpu: GpuOptions,
+) -> anyhow::Result<bool> {
+    let frame = pixels.take().map(|old| old.frame().to_vec());
+    let surface_size = Some(window.inner_size())
+        .filter(|size| size.width > 0 && size.height > 0)
+        .unwrap_or(PhysicalSize::new(width, height));
+    let mut rebuilt = build_pixels(window, surface_size, width, height, gpu)?;
+    let kept = match frame {
+        Some(frame) if frame.len() == rebuilt.frame().len() => {
+            rebuilt.frame_mut().copy_from_slice(&frame);
+            true
+        }
+        _ => false,
+    };
+    *pixels = Some(rebuilt);
+    Ok(kept)
+}
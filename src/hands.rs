@@ -0,0 +1,361 @@
+use std::{path::Path, time::Duration};
+
+use chrono::{DateTime, Local};
+use chrono_tz::Tz;
+use image::RgbaImage;
+
+use crate::{
+    damage::{Damage, Rect},
+    decode,
+    render::{
+        blend_pixel, day_millis, FrameLayout, MILLIS_PER_HOUR, MILLIS_PER_MINUTE, MILLIS_PER_SECOND,
+    },
+};
+
+/// An analog clock of hand images turned over a face image
+///
+/// The hands move once a second. Only the part of the frame the hands covered before and cover
+/// now is drawn again, from the face placed once.
+pub struct ClockHands {
+    /// The face placed into the frame
+    face: RgbaImage,
+    /// The hour, minute and second hand, drawn in this order
+    hands: Vec<Hand>,
+    /// The point of the frame the hands turn around
+    center: (f64, f64),
+    /// The zone the clock shows the time of, the local one if not set
+    timezone: Option<Tz>,
+    /// The second of the day the hands were last drawn at
+    drawn_at: Option<u32>,
+    /// Where the frame holds the hands drawn last, `None` before the face is drawn
+    drawn: Option<Damage>,
+}
+
+/// A hand image pointing up, cropped to its visible pixels
+struct Hand {
+    image: RgbaImage,
+    /// The point of the image the hand turns around, the center of the image before cropping
+    pivot: (f64, f64),
+    /// How long one turn takes in milliseconds
+    turn: u32,
+}
+
+impl Hand {
+    /// The hand of `image` without its transparent margins, which would only make the part of the
+    /// frame drawn again larger
+    fn cropped(image: RgbaImage, turn: u32) -> Self {
+        let pivot = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+        let visible = image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel[3] > 0)
+            .fold(None, |bounds: Option<(u32, u32, u32, u32)>, (x, y, _)| {
+                Some(bounds.map_or((x, y, x, y), |(min_x, min_y, max_x, max_y)| {
+                    (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y))
+                }))
+            });
+        let Some((min_x, min_y, max_x, max_y)) = visible else {
+            return Hand { image, pivot, turn };
+        };
+        Hand {
+            image: image::imageops::crop_imm(
+                &image,
+                min_x,
+                min_y,
+                max_x - min_x + 1,
+                max_y - min_y + 1,
+            )
+            .to_image(),
+            pivot: (pivot.0 - min_x as f64, pivot.1 - min_y as f64),
+            turn,
+        }
+    }
+
+    /// The hand turned clockwise by `angle` radians around `center`, as the rectangle of the frame
+    /// it covers
+    fn bounds(&self, center: (f64, f64), angle: f64, width: u32, height: u32) -> Option<Rect> {
+        let (sin, cos) = angle.sin_cos();
+        let (left, top) = (-self.pivot.0, -self.pivot.1);
+        let (right, bottom) = (
+            left + self.image.width() as f64,
+            top + self.image.height() as f64,
+        );
+        let corners = [(left, top), (right, top), (right, bottom), (left, bottom)]
+            .map(|(x, y)| (center.0 + x * cos - y * sin, center.1 + x * sin + y * cos));
+        let min = corners.iter().fold((f64::MAX, f64::MAX), |min, corner| {
+            (min.0.min(corner.0), min.1.min(corner.1))
+        });
+        let max = corners.iter().fold((f64::MIN, f64::MIN), |max, corner| {
+            (max.0.max(corner.0), max.1.max(corner.1))
+        });
+        // One more pixel around the edges the bilinear sampling reaches into
+        Rect::clipped(
+            (min.0.floor() as i64 - 1, min.1.floor() as i64 - 1),
+            (max.0.ceil() as i64 + 1, max.1.ceil() as i64 + 1),
+            width,
+            height,
+        )
+    }
+
+    /// Blend the hand turned clockwise by `angle` radians around `center` over the part of the
+    /// rgba `frame` inside `clip`
+    fn draw(&self, frame: &mut [u8], width: u32, center: (f64, f64), angle: f64, clip: Rect) {
+        let (sin, cos) = angle.sin_cos();
+        for y in clip.y..clip.y + clip.height {
+            for x in clip.x..clip.x + clip.width {
+                // Turned back onto the hand image, by the pixel centers
+                let (dx, dy) = (x as f64 + 0.5 - center.0, y as f64 + 0.5 - center.1);
+                let source_x = dx * cos + dy * sin + self.pivot.0 - 0.5;
+                let source_y = -dx * sin + dy * cos + self.pivot.1 - 0.5;
+                let pixel = self.sample(source_x, source_y);
+                if pixel[3] > 0 {
+                    let start = (y as usize * width as usize + x as usize) * 4;
+                    blend_pixel(&mut frame[start..start + 4], &pixel);
+                }
+            }
+        }
+    }
+
+    /// The hand image between its pixels, interpolated bilinearly with premultiplied alpha so the
+    /// colors of transparent pixels don't bleed into the edges
+    fn sample(&self, x: f64, y: f64) -> [u8; 4] {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let mut sum = [0.0; 4];
+        for (offset_x, offset_y, weight) in [
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ] {
+            let (px, py) = (x0 as i64 + offset_x, y0 as i64 + offset_y);
+            if weight == 0.0
+                || px < 0
+                || py < 0
+                || px >= self.image.width() as i64
+                || py >= self.image.height() as i64
+            {
+                continue;
+            }
+            let pixel = self.image.get_pixel(px as u32, py as u32).0;
+            let alpha = pixel[3] as f64 * weight;
+            for channel in 0..3 {
+                sum[channel] += pixel[channel] as f64 * alpha;
+            }
+            sum[3] += alpha;
+        }
+        if sum[3] < 0.5 {
+            return [0; 4];
+        }
+        [
+            (sum[0] / sum[3]).round() as u8,
+            (sum[1] / sum[3]).round() as u8,
+            (sum[2] / sum[3]).round() as u8,
+            sum[3].round() as u8,
+        ]
+    }
+}
+
+impl ClockHands {
+    /// Place the face with `layout` into `width` x `height` frames and scale the hands like it
+    ///
+    /// The hands turn around `center`, a point of the face image, or the center of the face.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
+        face: &Path,
+        hour_hand: &Path,
+        minute_hand: &Path,
+        second_hand: Option<&Path>,
+        center: Option<(u32, u32)>,
+        mut layout: FrameLayout,
+        timezone: Option<Tz>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let face_image = decode::open(face)?;
+        let face_size = (face_image.width(), face_image.height());
+        let placed_face = layout.place(&face_image, width, height);
+        let ((x, y), (placed_width, placed_height)) =
+            layout.placed().expect("the layout placed the face");
+        let scale = (
+            placed_width as f64 / face_size.0 as f64,
+            placed_height as f64 / face_size.1 as f64,
+        );
+
+        let (center_x, center_y) = center.map_or(
+            (face_size.0 as f64 / 2.0, face_size.1 as f64 / 2.0),
+            |(x, y)| (x as f64, y as f64),
+        );
+        let hands = [
+            (Some(hour_hand), 12 * MILLIS_PER_HOUR),
+            (Some(minute_hand), MILLIS_PER_HOUR),
+            (second_hand, MILLIS_PER_MINUTE),
+        ]
+        .into_iter()
+        .filter_map(|(path, turn)| Some((path?, turn)))
+        .map(|(path, turn)| {
+            let image = decode::open(path)?.into_rgba8();
+            let scaled = (
+                ((image.width() as f64 * scale.0).round() as u32).max(1),
+                ((image.height() as f64 * scale.1).round() as u32).max(1),
+            );
+            let image = if scaled == image.dimensions() {
+                image
+            } else {
+                image::imageops::resize(
+                    &image,
+                    scaled.0,
+                    scaled.1,
+                    image::imageops::FilterType::Triangle,
+                )
+            };
+            anyhow::Ok(Hand::cropped(image, turn))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+        Ok(ClockHands {
+            face: placed_face,
+            hands,
+            center: (x as f64 + center_x * scale.0, y as f64 + center_y * scale.1),
+            timezone,
+            drawn_at: None,
+            drawn: None,
+        })
+    }
+
+    /// The angle of each hand in radians clockwise from twelve at the time of day `millis`
+    fn angles(&self, millis: u32) -> impl Iterator<Item = f64> + '_ {
+        self.hands
+            .iter()
+            .map(move |hand| (millis % hand.turn) as f64 / hand.turn as f64 * std::f64::consts::TAU)
+    }
+
+    /// The time until the hands move next
+    pub fn next_frame_in(&self, now: DateTime<Local>) -> Duration {
+        let millis = day_millis(now, self.timezone.as_ref());
+        Duration::from_millis((MILLIS_PER_SECOND - millis % MILLIS_PER_SECOND).into())
+    }
+
+    /// Draw the face and hands on the next render
+    pub fn redraw(&mut self) {
+        self.drawn = None;
+    }
+
+    pub fn status(&self) -> String {
+        format!(
+            "hands: {count}\n\
+             timezone: {timezone}",
+            count = self.hands.len(),
+            timezone = self.timezone.map_or("local", Tz::name),
+        )
+    }
+
+    /// Draw the hands at `now` into the rgba `frame`, returning which part of it changed
+    pub fn render(
+        &mut self,
+        frame: &mut [u8],
+        width: u32,
+        height: u32,
+        now: DateTime<Local>,
+    ) -> Damage {
+        let second = day_millis(now, self.timezone.as_ref()) / MILLIS_PER_SECOND;
+        if frame.len() != self.face.len() || (self.drawn.is_some() && self.drawn_at == Some(second))
+        {
+            return Damage::None;
+        }
+        self.drawn_at = Some(second);
+
+        let angles: Vec<f64> = self.angles(second * MILLIS_PER_SECOND).collect();
+        let bounds: Vec<Option<Rect>> = self
+            .hands
+            .iter()
+            .zip(&angles)
+            .map(|(hand, angle)| hand.bounds(self.center, *angle, width, height))
+            .collect();
+        let covered = bounds.iter().flatten().fold(Damage::None, |damage, rect| {
+            damage.union(Damage::Rect(*rect))
+        });
+        // The face is copied again where the hands were and are, then the hands are drawn over it
+        let damage = match self.drawn.replace(covered) {
+            None => Damage::Full,
+            Some(drawn) => drawn.union(covered),
+        };
+        damage.copy(frame, &self.face, width);
+        for ((hand, angle), bounds) in self.hands.iter().zip(angles).zip(bounds) {
+            if let Some(bounds) = bounds {
+                hand.draw(frame, width, self.center, angle, bounds);
+            }
+        }
+        damage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone as _;
+    use image::Rgba;
+
+    use super::*;
+    use crate::render::Scaling;
+
+    #[test]
+    fn turns_the_hands_and_redraws_only_where_they_moved() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        RgbaImage::from_pixel(20, 20, Rgba([0, 0, 255, 255]))
+            .save(dir.join("face.png"))
+            .unwrap();
+        // Hands reaching from the center up to the top of their image
+        let hand = |length: u32, color| {
+            let mut image = RgbaImage::new(2, 2 * length);
+            for y in 0..length {
+                image.put_pixel(0, y, Rgba(color));
+                image.put_pixel(1, y, Rgba(color));
+            }
+            image
+        };
+        hand(4, [255, 0, 0, 255])
+            .save(dir.join("hour.png"))
+            .unwrap();
+        hand(8, [0, 255, 0, 255])
+            .save(dir.join("minute.png"))
+            .unwrap();
+
+        // The face is scaled up twice, like the hands
+        let (width, height) = (40, 40);
+        let mut hands = ClockHands::load(
+            &dir.join("face.png"),
+            &dir.join("hour.png"),
+            &dir.join("minute.png"),
+            None,
+            None,
+            FrameLayout::with_color(Scaling::Fit, [0, 0, 0], width, height),
+            None,
+            width,
+            height,
+        )
+        .unwrap();
+        let mut frame = vec![0; width as usize * height as usize * 4];
+        let pixel = |frame: &[u8], x: usize, y: usize| {
+            let start = (y * width as usize + x) * 4;
+            [frame[start], frame[start + 1], frame[start + 2]]
+        };
+
+        // At 3:00 the hour hand points right and the minute hand up
+        let three = Local.with_ymd_and_hms(2024, 5, 1, 3, 0, 0).unwrap();
+        assert_eq!(hands.render(&mut frame, width, height, three), Damage::Full);
+        assert_eq!(pixel(&frame, 26, 20), [255, 0, 0]);
+        assert_eq!(pixel(&frame, 20, 5), [0, 255, 0]);
+        assert_eq!(pixel(&frame, 5, 35), [0, 0, 255]);
+        assert_eq!(hands.render(&mut frame, width, height, three), Damage::None);
+
+        // A quarter hour later the minute hand points right, only its sweep is drawn again
+        let later = three + chrono::Duration::minutes(15);
+        let Damage::Rect(rect) = hands.render(&mut frame, width, height, later) else {
+            panic!("expected a damaged rectangle");
+        };
+        assert!(rect.x >= 17 && rect.x + rect.width >= 36, "{rect:?}");
+        assert!(rect.y <= 4 && rect.y + rect.height <= 26, "{rect:?}");
+        assert_eq!(pixel(&frame, 20, 5), [0, 0, 255]);
+        assert_eq!(pixel(&frame, 34, 20), [0, 255, 0]);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+
+use anyhow::bail;
+
+use crate::command::Command;
+
+/// How many backgrounds the history keeps, the oldest ones are forgotten first
+const MAX_HISTORY: usize = 50;
+
+/// A background which was shown and the output it was sent to, all if not set
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub output: Option<String>,
+    pub command: Command,
+}
+
+/// The backgrounds shown by the daemon, navigated like the history of a web browser
+#[derive(Default)]
+pub struct History {
+    entries: VecDeque<HistoryEntry>,
+    /// The index of the entry shown, only `None` while the history is empty
+    current: Option<usize>,
+}
+
+impl History {
+    /// Remember a background which was just shown, dropping the entries after the current one
+    pub fn record(&mut self, entry: HistoryEntry) {
+        if let Some(current) = self.current {
+            if self.entries[current] == entry {
+                return;
+            }
+            self.entries.truncate(current + 1);
+        }
+        self.entries.push_back(entry);
+        if self.entries.len() > MAX_HISTORY {
+            self.entries.pop_front();
+        }
+        self.current = Some(self.entries.len() - 1);
+    }
+
+    /// The entry `offset` entries away from the current one
+    pub fn relative(&self, offset: isize) -> anyhow::Result<usize> {
+        let Some(current) = self.current else {
+            bail!("no background has been shown yet");
+        };
+        match current.checked_add_signed(offset) {
+            Some(index) if index < self.entries.len() => Ok(index),
+            _ if offset < 0 => bail!("already at the oldest background of the history"),
+            _ => bail!("already at the newest background of the history"),
+        }
+    }
+
+    /// The entry at `index`, counted from the oldest one
+    pub fn get(&self, index: usize) -> anyhow::Result<&HistoryEntry> {
+        match self.entries.get(index) {
+            Some(entry) => Ok(entry),
+            None if self.entries.is_empty() => bail!("no background has been shown yet"),
+            None => bail!(
+                "there is no background {index} in the history, it holds 0 to {last}",
+                last = self.entries.len() - 1
+            ),
+        }
+    }
+
+    /// Make the entry at `index` the current one, after it was shown again
+    pub fn jump(&mut self, index: usize) {
+        self.current = Some(index);
+    }
+
+    /// The entries with their index, the current one marked by a `*`
+    pub fn describe(&self) -> String {
+        self.entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                format!(
+                    "{marker}{index:>3} {output}{command}",
+                    marker = if self.current == Some(index) {
+                        '*'
+                    } else {
+                        ' '
+                    },
+                    output = entry
+                        .output
+                        .as_ref()
+                        .map_or(String::new(), |output| format!("[{output}] ")),
+                    command = entry.command.summary(),
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn image(name: &str) -> HistoryEntry {
+        HistoryEntry {
+            output: None,
+            command: Command::StaticImage {
+                path: PathBuf::from(name),
+                scaling: Default::default(),
+                margin_color: None,
+                watch: false,
+                span: false,
+            },
+        }
+    }
+
+    #[test]
+    fn navigates_without_wrapping() {
+        let mut history = History::default();
+        assert!(history.relative(-1).is_err());
+        for name in ["a.png", "b.png", "b.png", "c.png"] {
+            history.record(image(name));
+        }
+        assert_eq!(history.entries.len(), 3);
+
+        let back = history.relative(-1).unwrap();
+        assert_eq!(history.get(back).unwrap(), &image("b.png"));
+        history.jump(back);
+        history.jump(history.relative(-1).unwrap());
+        let error = history.relative(-1).unwrap_err();
+        assert!(error.to_string().contains("oldest"), "{error}");
+        assert!(history.get(3).is_err());
+
+        // Showing something new forgets the entries after the current one
+        history.record(image("d.png"));
+        assert_eq!(history.entries, [image("a.png"), image("d.png")]);
+        assert!(history.relative(1).is_err());
+        assert!(history.describe().ends_with("*  1 static-image d.png"));
+    }
+
+    #[test]
+    fn forgets_the_oldest_entries() {
+        let mut history = History::default();
+        for idx in 0..MAX_HISTORY + 5 {
+            history.record(image(&format!("{idx}.png")));
+        }
+        assert_eq!(history.entries.len(), MAX_HISTORY);
+        assert_eq!(history.get(0).unwrap(), &image("5.png"));
+        assert_eq!(history.relative(0).unwrap(), MAX_HISTORY - 1);
+    }
+}
@@ -0,0 +1,321 @@
+use rayon::prelude::*;
+
+/// Linear light from the D50 connection space of ICC profiles to linear sRGB, Bradford adapted
+const XYZ_D50_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+/// How many linear values the sRGB encoding is tabulated for, enough to tell the darkest 8 bit
+/// values apart
+const ENCODE_STEPS: usize = 1 << 14;
+
+/// How many pixels are converted by one task
+const CHUNK_PIXELS: usize = 16 * 1024;
+
+/// The conversion of rgb pixels in the color space of an ICC profile to sRGB
+///
+/// Only matrix/TRC profiles are read, which is what Display P3, Adobe RGB and most other RGB
+/// profiles of photos and exported images are. Colors outside of sRGB are clipped.
+pub struct ToSrgb {
+    /// The linear light of each 8 bit value, per channel
+    linear: [[f32; 256]; 3],
+    /// Linear light in the color space of the profile to linear sRGB
+    matrix: [[f32; 3]; 3],
+    /// The 8 bit sRGB value of linear light from 0 to 1 in [`ENCODE_STEPS`] steps
+    encode: Vec<u8>,
+}
+
+impl ToSrgb {
+    /// The conversion for `profile`, `None` if it describes sRGB already
+    pub fn new(profile: &[u8]) -> Result<Option<ToSrgb>, String> {
+        let profile = Profile::parse(profile)?;
+        let tag = |signature: &[u8; 4]| {
+            profile
+                .tag(signature)
+                .ok_or_else(|| format!("it has no {} tag", String::from_utf8_lossy(signature)))
+        };
+
+        let mut linear = [[0.0; 256]; 3];
+        let mut colorants = [[0.0; 3]; 3];
+        for (channel, (curve, colorant)) in
+            [(b"rTRC", b"rXYZ"), (b"gTRC", b"gXYZ"), (b"bTRC", b"bXYZ")]
+                .into_iter()
+                .enumerate()
+        {
+            let curve = Curve::parse(tag(curve)?)?;
+            for (value, linear) in linear[channel].iter_mut().enumerate() {
+                *linear = curve.eval(value as f32 / 255.0);
+            }
+            let xyz = parse_xyz(tag(colorant)?)?;
+            for row in 0..3 {
+                colorants[row][channel] = xyz[row];
+            }
+        }
+        let matrix = multiply(XYZ_D50_TO_SRGB, colorants);
+
+        let srgb = Curve::Parametric(vec![2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045]);
+        let is_srgb = (0..3).all(|row| {
+            (0..3).all(|column| {
+                let identity = if row == column { 1.0 } else { 0.0 };
+                (matrix[row][column] - identity).abs() < 0.002
+            })
+        }) && linear.iter().all(|channel| {
+            channel
+                .iter()
+                .enumerate()
+                .all(|(value, linear)| (linear - srgb.eval(value as f32 / 255.0)).abs() < 0.001)
+        });
+        if is_srgb {
+            return Ok(None);
+        }
+
+        let encode = (0..ENCODE_STEPS)
+            .map(|step| {
+                let linear = step as f32 / (ENCODE_STEPS - 1) as f32;
+                let encoded = if linear <= 0.003_130_8 {
+                    linear * 12.92
+                } else {
+                    1.055 * linear.powf(1.0 / 2.4) - 0.055
+                };
+                (encoded * 255.0).round() as u8
+            })
+            .collect();
+        Ok(Some(ToSrgb {
+            linear,
+            matrix,
+            encode,
+        }))
+    }
+
+    /// Convert the colors of `pixels` with `channels` bytes per pixel in place, the first three of
+    /// which are red, green and blue
+    pub fn apply(&self, pixels: &mut [u8], channels: usize) {
+        pixels
+            .par_chunks_mut(CHUNK_PIXELS * channels)
+            .for_each(|chunk| {
+                for pixel in chunk.chunks_exact_mut(channels) {
+                    let linear =
+                        [0, 1, 2].map(|channel| self.linear[channel][pixel[channel] as usize]);
+                    for (channel, row) in self.matrix.iter().enumerate() {
+                        let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                        let step = (value.clamp(0.0, 1.0) * (ENCODE_STEPS - 1) as f32).round();
+                        pixel[channel] = self.encode[step as usize];
+                    }
+                }
+            });
+    }
+}
+
+/// The tag table of an ICC profile
+struct Profile<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Profile<'a> {
+    fn parse(bytes: &'a [u8]) -> Result<Self, String> {
+        if bytes.get(36..40) != Some(b"acsp") {
+            return Err("it is not an ICC profile".to_string());
+        }
+        if bytes.get(16..20) != Some(b"RGB ") {
+            return Err("it is not an RGB profile".to_string());
+        }
+        if bytes.get(20..24) != Some(b"XYZ ") {
+            return Err("its connection space is not XYZ".to_string());
+        }
+        Ok(Profile { bytes })
+    }
+
+    /// The data of the tag with `signature`
+    fn tag(&self, signature: &[u8; 4]) -> Option<&'a [u8]> {
+        let count = read_u32(self.bytes, 128)? as usize;
+        (0..count)
+            .map(|idx| 132 + idx * 12)
+            .find(|&entry| self.bytes.get(entry..entry + 4) == Some(signature))
+            .and_then(|entry| {
+                let offset = read_u32(self.bytes, entry + 4)? as usize;
+                let len = read_u32(self.bytes, entry + 8)? as usize;
+                self.bytes.get(offset..offset.checked_add(len)?)
+            })
+    }
+}
+
+/// A tone response curve, from encoded values to linear light
+enum Curve {
+    /// Linear interpolation between equally spaced values
+    Table(Vec<f32>),
+    /// The gamma and the other parameters of one of the parametric curve functions, by their count
+    Parametric(Vec<f32>),
+}
+
+impl Curve {
+    fn parse(tag: &[u8]) -> Result<Self, String> {
+        match tag.get(..4) {
+            Some(b"curv") => {
+                let count = read_u32(tag, 8).ok_or("its curve is cut off")? as usize;
+                let values = (0..count)
+                    .map(|idx| read_u16(tag, 12 + idx * 2))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("its curve is cut off")?;
+                Ok(match values[..] {
+                    [] => Curve::Parametric(vec![1.0]),
+                    [gamma] => Curve::Parametric(vec![gamma as f32 / 256.0]),
+                    _ => Curve::Table(values.iter().map(|&value| value as f32 / 65535.0).collect()),
+                })
+            }
+            Some(b"para") => {
+                let count = match read_u16(tag, 8) {
+                    Some(0) => 1,
+                    Some(1) => 3,
+                    Some(2) => 4,
+                    Some(3) => 5,
+                    Some(4) => 7,
+                    _ => return Err("it has an unknown parametric curve".to_string()),
+                };
+                let parameters = (0..count)
+                    .map(|idx| read_s15_fixed16(tag, 12 + idx * 4))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or("its curve is cut off")?;
+                Ok(Curve::Parametric(parameters))
+            }
+            _ => Err("its tone response curves are neither curves nor parametric".to_string()),
+        }
+    }
+
+    /// The linear light of the encoded value `x` from 0 to 1
+    fn eval(&self, x: f32) -> f32 {
+        let y = match self {
+            Curve::Table(values) => {
+                let position = x * (values.len() - 1) as f32;
+                let idx = (position as usize).min(values.len() - 2);
+                let fraction = position - idx as f32;
+                values[idx] * (1.0 - fraction) + values[idx + 1] * fraction
+            }
+            Curve::Parametric(parameters) => match parameters[..] {
+                [g] => x.powf(g),
+                [g, a, b] if x >= -b / a => (a * x + b).powf(g),
+                [_, _, _] => 0.0,
+                [g, a, b, c] if x >= -b / a => (a * x + b).powf(g) + c,
+                [_, _, _, c] => c,
+                [g, a, b, _, d] if x >= d => (a * x + b).powf(g),
+                [_, _, _, c, _] => c * x,
+                [g, a, b, _, d, e, _] if x >= d => (a * x + b).powf(g) + e,
+                [_, _, _, c, _, _, f] => c * x + f,
+                _ => unreachable!("parametric curves have 1, 3, 4, 5 or 7 parameters"),
+            },
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+/// The color of an XYZ tag
+fn parse_xyz(tag: &[u8]) -> Result<[f32; 3], String> {
+    if tag.get(..4) != Some(b"XYZ ") {
+        return Err("its colorants are not XYZ colors".to_string());
+    }
+    let xyz = [8, 12, 16].map(|at| read_s15_fixed16(tag, at));
+    match xyz {
+        [Some(x), Some(y), Some(z)] => Ok([x, y, z]),
+        _ => Err("its colorants are cut off".to_string()),
+    }
+}
+
+fn multiply(a: [[f32; 3]; 3], b: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    std::array::from_fn(|row| {
+        std::array::from_fn(|column| (0..3).map(|idx| a[row][idx] * b[idx][column]).sum())
+    })
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_s15_fixed16(bytes: &[u8], at: usize) -> Option<f32> {
+    Some(i32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?) as f32 / 65536.0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A matrix/TRC profile with the sRGB curve and the D50 adapted `colorants`
+    pub(crate) fn profile(colorants: [[f32; 3]; 3]) -> Vec<u8> {
+        let fixed = |value: f32| ((value * 65536.0).round() as i32).to_be_bytes();
+        let mut curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for parameter in [2.4, 1.0 / 1.055, 0.055 / 1.055, 1.0 / 12.92, 0.04045] {
+            curve.extend(fixed(parameter));
+        }
+        let xyz = |[x, y, z]: [f32; 3]| {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            tag.extend([x, y, z].into_iter().flat_map(fixed));
+            tag
+        };
+        let tags = [
+            (b"rXYZ", xyz(colorants[0])),
+            (b"gXYZ", xyz(colorants[1])),
+            (b"bXYZ", xyz(colorants[2])),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ];
+
+        let mut header = vec![0; 128];
+        header[16..20].copy_from_slice(b"RGB ");
+        header[20..24].copy_from_slice(b"XYZ ");
+        header[36..40].copy_from_slice(b"acsp");
+        let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+        let mut data = Vec::new();
+        let data_start = 128 + 4 + tags.len() * 12;
+        for (signature, tag) in tags {
+            table.extend(signature);
+            table.extend(((data_start + data.len()) as u32).to_be_bytes());
+            table.extend((tag.len() as u32).to_be_bytes());
+            data.extend(tag);
+        }
+        [header, table, data].concat()
+    }
+
+    /// The D50 adapted colorants of Display P3
+    pub(crate) const DISPLAY_P3: [[f32; 3]; 3] = [
+        [0.515_1, 0.241_2, -0.001_1],
+        [0.292_0, 0.692_2, 0.041_9],
+        [0.157_2, 0.066_6, 0.784_4],
+    ];
+
+    #[test]
+    fn converts_display_p3_to_srgb() {
+        let to_srgb = ToSrgb::new(&profile(DISPLAY_P3)).unwrap().unwrap();
+        let mut pixels = [
+            255, 255, 255, 7, 128, 128, 128, 7, 200, 150, 100, 7, 0, 255, 0, 7,
+        ];
+        to_srgb.apply(&mut pixels, 4);
+        // White and grays stay as they are, the reference is the P3 to sRGB matrix in linear light
+        assert_eq!(&pixels[..8], [255, 255, 255, 7, 128, 128, 128, 7]);
+        let expected = [209, 147, 91];
+        for (actual, expected) in pixels[8..11].iter().zip(expected) {
+            assert!(actual.abs_diff(expected) <= 1, "{:?}", &pixels[8..11]);
+        }
+        // Saturated P3 green is outside of sRGB
+        assert_eq!(&pixels[12..], [0, 255, 0, 7]);
+    }
+
+    #[test]
+    fn leaves_srgb_alone() {
+        let srgb = [
+            [0.436_1, 0.222_5, 0.013_9],
+            [0.385_1, 0.716_9, 0.097_1],
+            [0.143_1, 0.060_6, 0.714_1],
+        ];
+        assert!(ToSrgb::new(&profile(srgb)).unwrap().is_none());
+        assert!(ToSrgb::new(b"not a profile").is_err());
+
+        let mut cut_off = profile(DISPLAY_P3);
+        cut_off.truncate(cut_off.len() - 20);
+        assert!(ToSrgb::new(&cut_off).is_err());
+    }
+}
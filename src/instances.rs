@@ -0,0 +1,329 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    command::Command,
+    ipc::{is_refused, runtime_dir, send_timeout, Request},
+    json, state,
+};
+
+/// How long a daemon may take to answer the ping of the listing
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Whether a daemon found in the runtime directory is alive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InstanceState {
+    /// The daemon answered the ping
+    Running,
+    /// The socket accepts connections but no reply came
+    NotResponding,
+    /// Nothing listens on the socket any more, its daemon died without removing it
+    Stale,
+}
+
+/// An output of a running daemon
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputInfo {
+    /// The name of the output like `DP-1`
+    pub name: String,
+    /// The width in pixels
+    pub width: u32,
+    /// The height in pixels
+    pub height: u32,
+    /// A summary of the command of the background, `none` if there is none
+    pub background: String,
+}
+
+/// What a daemon replied to a ping
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DaemonInfo {
+    /// The process id of the daemon
+    pub pid: u32,
+    /// The version of the daemon
+    pub version: String,
+    /// Seconds since the daemon started
+    pub uptime: u64,
+    /// The outputs the daemon shows backgrounds on
+    pub outputs: Vec<OutputInfo>,
+}
+
+impl DaemonInfo {
+    /// Read the reply to a ping
+    pub fn parse(reply: &str) -> anyhow::Result<Self> {
+        let field = |name: &str| {
+            reply
+                .lines()
+                .find_map(|line| line.strip_prefix(name)?.strip_prefix(": "))
+                .with_context(|| format!("the reply to the ping has no {name}"))
+        };
+        let outputs = reply
+            .lines()
+            .filter_map(|line| {
+                let (name, rest) = line.strip_prefix("output ")?.split_once(": ")?;
+                let (size, background) = rest.split_once(' ')?;
+                let (width, height) = size.split_once('x')?;
+                Some(OutputInfo {
+                    name: name.to_string(),
+                    width: width.parse().ok()?,
+                    height: height.parse().ok()?,
+                    background: background.to_string(),
+                })
+            })
+            .collect();
+        Ok(DaemonInfo {
+            pid: field("pid")?
+                .parse()
+                .context("the process id is not a number")?,
+            version: field("version")?.to_string(),
+            uptime: field("uptime")?
+                .parse()
+                .context("the uptime is not a number")?,
+            outputs,
+        })
+    }
+}
+
+/// A daemon socket found in the runtime directory
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Instance {
+    /// The socket name to send commands to
+    pub name: String,
+    /// The path of the socket file
+    pub socket: String,
+    /// Whether the daemon is alive
+    pub state: InstanceState,
+    /// Set if the daemon is running
+    pub daemon: Option<DaemonInfo>,
+    /// Why the daemon didn't answer
+    pub error: Option<String>,
+    /// Whether the socket was removed because it is stale
+    pub pruned: bool,
+}
+
+/// Ping the daemon listening on `socket`
+fn ping(name: String, socket: &Path) -> Instance {
+    let request = Request {
+        output: None,
+        command: Command::Ping,
+        wait: false,
+    };
+    let socket = socket.to_string_lossy().into_owned();
+    let (state, daemon, error) = match send_timeout(&socket, &request, PING_TIMEOUT) {
+        Ok(Ok(reply)) => match DaemonInfo::parse(&reply) {
+            Ok(daemon) => (InstanceState::Running, Some(daemon), None),
+            Err(error) => (
+                InstanceState::NotResponding,
+                None,
+                Some(format!("{error:#}")),
+            ),
+        },
+        Ok(Err(error)) => (InstanceState::NotResponding, None, Some(error)),
+        Err(error) => {
+            let state = if is_refused(&error) {
+                InstanceState::Stale
+            } else {
+                InstanceState::NotResponding
+            };
+            (state, None, Some(format!("{error:#}")))
+        }
+    };
+    Instance {
+        name,
+        socket,
+        state,
+        daemon,
+        error,
+        pruned: false,
+    }
+}
+
+/// The sockets in the runtime directory by name, with the file to remove if they are stale
+fn runtime_sockets(dir: &Path) -> anyhow::Result<Vec<(String, PathBuf, PathBuf)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("could not list {dir}", dir = dir.display()))
+        }
+    };
+    Ok(entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path
+                .file_name()?
+                .to_str()?
+                .strip_suffix(".sock")?
+                .to_string();
+            Some((name, path.clone(), path))
+        })
+        .collect())
+}
+
+/// Ping the daemons with a socket in the runtime directory and those advertising an abstract
+/// socket, removing stale sockets and advertisements if `prune` is set, sorted by name
+pub fn list(prune: bool) -> anyhow::Result<Vec<Instance>> {
+    let dir = runtime_dir();
+    let advertised = state::advertised();
+    if dir.is_none() && advertised.is_empty() {
+        bail!("XDG_RUNTIME_DIR is not set, so there is no directory of sockets to list");
+    }
+
+    let mut sockets = match dir {
+        Some(dir) => runtime_sockets(&dir)?,
+        None => Vec::new(),
+    };
+    sockets.extend(
+        advertised
+            .into_iter()
+            .map(|(name, file)| (name.clone(), PathBuf::from(name), file)),
+    );
+    let mut instances: Vec<(Instance, PathBuf)> = sockets
+        .into_par_iter()
+        .map(|(name, socket, file)| (ping(name, &socket), file))
+        .collect();
+    instances.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+
+    if prune {
+        for (instance, file) in &mut instances {
+            if instance.state == InstanceState::Stale {
+                instance.pruned = std::fs::remove_file(file).is_ok();
+            }
+        }
+    }
+    Ok(instances
+        .into_iter()
+        .map(|(instance, _)| instance)
+        .collect())
+}
+
+/// Format seconds like `42s`, `5m12s`, `1h02m` or `3d04h`
+fn format_uptime(seconds: u64) -> String {
+    let (minutes, hours, days) = (seconds / 60, seconds / 3600, seconds / 86400);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, _) => format!("{minutes}m{seconds:02}s", seconds = seconds % 60),
+        (0, _, _) => format!("{hours}h{minutes:02}m", minutes = minutes % 60),
+        _ => format!("{days}d{hours:02}h", hours = hours % 24),
+    }
+}
+
+/// The instances as a table with one row per daemon, or as a json array
+pub fn describe(instances: &[Instance], json: bool) -> String {
+    if json {
+        let mut out = String::new();
+        json::to_value(&instances)
+            .expect("the instances are plain data")
+            .write(&mut out, 0);
+        return out;
+    }
+
+    let mut rows = vec![[
+        "NAME".to_string(),
+        "PID".to_string(),
+        "UPTIME".to_string(),
+        "RESOLUTION".to_string(),
+        "BACKGROUND".to_string(),
+    ]];
+    for instance in instances {
+        let row = match (&instance.daemon, instance.state) {
+            (Some(daemon), _) => [
+                instance.name.clone(),
+                daemon.pid.to_string(),
+                format_uptime(daemon.uptime),
+                daemon
+                    .outputs
+                    .iter()
+                    .map(|output| format!("{}x{}", output.width, output.height))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                daemon
+                    .outputs
+                    .iter()
+                    .map(|output| output.background.as_str())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ],
+            (None, state) => {
+                let state = match (state, instance.pruned) {
+                    (InstanceState::Stale, true) => "stale, removed",
+                    (InstanceState::Stale, false) => "stale",
+                    _ => "not responding",
+                };
+                [
+                    instance.name.clone(),
+                    "-".to_string(),
+                    "-".to_string(),
+                    "-".to_string(),
+                    format!("({state})"),
+                ]
+            }
+        };
+        rows.push(row);
+    }
+
+    let widths: Vec<usize> = (0..5)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            row.iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_ping_replies() {
+        let reply = "pid: 42\nversion: 0.1.0\nuptime: 3725\nlast tick: 1000\n\
+                     event loop: responding\noutput DP-1: 2560x1440 static-image a b.png\n\
+                     output HDMI-1: 1920x1080 none";
+        let daemon = DaemonInfo::parse(reply).unwrap();
+        assert_eq!(daemon.pid, 42);
+        assert_eq!(daemon.outputs.len(), 2);
+        assert_eq!(daemon.outputs[0].background, "static-image a b.png");
+        assert!(DaemonInfo::parse("version: 0.1.0").is_err());
+
+        let instances = [
+            Instance {
+                name: "main".to_string(),
+                socket: "/run/user/1000/desktop-background/main.sock".to_string(),
+                state: InstanceState::Running,
+                daemon: Some(daemon),
+                error: None,
+                pruned: false,
+            },
+            Instance {
+                name: "old".to_string(),
+                socket: "/run/user/1000/desktop-background/old.sock".to_string(),
+                state: InstanceState::Stale,
+                daemon: None,
+                error: Some("Connection refused".to_string()),
+                pruned: true,
+            },
+        ];
+        let table = describe(&instances, false);
+        assert!(
+            table.contains("main  42   1h02m   2560x1440,1920x1080"),
+            "{table}"
+        );
+        assert!(table.ends_with("(stale, removed)"), "{table}");
+        assert!(describe(&instances, true).contains("\"state\": \"stale\""));
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    io::{Read, Write},
+    os::unix::fs::FileTypeExt,
+    path::PathBuf,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use bincode::Options;
+use chrono::Local;
+use interprocess::local_socket::LocalSocketStream;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{args::DurationArg, command::Command, instances::DaemonInfo};
+
+/// How long to wait for a killed daemon to be gone
+const KILL_WAIT: Duration = Duration::from_secs(1);
+/// How long a daemon found on the socket of a starting one may take to answer a ping
+const CLAIM_PING_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long a daemon replaced by a starting one may take to exit
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The largest message in bytes, longer ones are rejected while they are read
+///
+/// Lengths are checked against the limit before anything is allocated for them, so a corrupt
+/// length can't make the daemon run out of memory.
+pub const MAX_MESSAGE_SIZE: u64 = 64 * 1024;
+
+/// The daemon's answer to a command, either a message to print or an error
+pub type Reply = Result<String, String>;
+
+/// A command sent to the daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    /// The output the command is meant for, all outputs if not set
+    pub output: Option<String>,
+    /// The command to run
+    pub command: Command,
+    /// Reply once the background of the command is shown rather than once it is set
+    pub wait: bool,
+}
+
+/// The error of [`send_timeout`] if the daemon didn't reply in time
+#[derive(Debug)]
+pub struct NoReply(pub Duration);
+
+impl std::fmt::Display for NoReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the desktop program didn't reply within {:?}", self.0)
+    }
+}
+
+impl std::error::Error for NoReply {}
+
+/// The encoding of messages, that of [`bincode::serialize`] limited to [`MAX_MESSAGE_SIZE`]
+fn encoding() -> impl Options {
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(MAX_MESSAGE_SIZE)
+}
+
+/// Write a bincode encoded `message` and flush it
+pub fn write_message<T: Serialize>(writer: &mut impl Write, message: &T) -> anyhow::Result<()> {
+    encoding().serialize_into(&mut *writer, message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read a bincode encoded message
+pub fn read_message<T: DeserializeOwned>(reader: &mut impl Read) -> bincode::Result<T> {
+    encoding().deserialize_from(reader)
+}
+
+/// Read a request and check its command with [`Command::validate`]
+pub fn read_request(reader: &mut impl Read) -> anyhow::Result<Request> {
+    let request: Request = read_message(reader)?;
+    request.command.validate()?;
+    Ok(request)
+}
+
+/// The directory of the sockets given by a plain name, `$XDG_RUNTIME_DIR/desktop-background`
+pub fn runtime_dir() -> Option<PathBuf> {
+    let runtime_dir = PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR")?);
+    runtime_dir
+        .is_absolute()
+        .then(|| runtime_dir.join("desktop-background"))
+}
+
+/// The socket the name `socket_name` stands for
+///
+/// A plain name is a socket in the [`runtime_dir`], paths and abstract names starting with `@`
+/// are used as they are, as are all names if there is no runtime directory. Abstract names are
+/// only supported on Linux.
+pub fn socket_path(socket_name: &str) -> anyhow::Result<String> {
+    if socket_name.starts_with('@') {
+        if cfg!(not(target_os = "linux")) {
+            bail!("abstract socket names like {socket_name} are only supported on Linux");
+        }
+        return Ok(socket_name.to_string());
+    }
+    if socket_name.contains('/') {
+        return Ok(socket_name.to_string());
+    }
+    Ok(match runtime_dir() {
+        Some(dir) => dir
+            .join(format!("{socket_name}.sock"))
+            .to_string_lossy()
+            .into_owned(),
+        None => socket_name.to_string(),
+    })
+}
+
+/// Send `request` to the daemon listening on the socket `socket_name` and wait for its reply
+pub fn send(socket_name: &str, request: &Request) -> anyhow::Result<Reply> {
+    let mut socket = LocalSocketStream::connect(socket_path(socket_name)?)?;
+    write_message(&mut socket, request)?;
+    Ok(read_message(&mut socket)?)
+}
+
+/// Send `request` like [`send`], failing if there is no reply within `timeout`
+pub fn send_timeout(
+    socket_name: &str,
+    request: &Request,
+    timeout: Duration,
+) -> anyhow::Result<Reply> {
+    let (sender, receiver) = mpsc::channel();
+    let socket_name = socket_name.to_string();
+    let request = request.clone();
+    thread::spawn(move || {
+        let _ = sender.send(send(&socket_name, &request));
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(reply) => reply,
+        Err(_) => Err(NoReply(timeout).into()),
+    }
+}
+
+/// Whether connecting failed because nothing listens on the socket
+pub(crate) fn is_refused(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some_and(|error| {
+        matches!(
+            error.kind(),
+            std::io::ErrorKind::ConnectionRefused | std::io::ErrorKind::NotFound
+        )
+    })
+}
+
+/// The process id of the daemon listening on `socket_name`, asked for with a ping
+pub fn daemon_pid(socket_name: &str, timeout: Duration) -> anyhow::Result<u32> {
+    let request = Request {
+        output: None,
+        command: Command::Ping,
+        wait: false,
+    };
+    let reply = send_timeout(socket_name, &request, timeout)?.map_err(anyhow::Error::msg)?;
+    reply
+        .lines()
+        .find_map(|line| line.strip_prefix("pid: ")?.parse().ok())
+        .ok_or_else(|| anyhow::anyhow!("the desktop program didn't tell its process id"))
+}
+
+/// Whether the process `pid` still runs
+fn is_running(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists
+    let exists = unsafe { libc::kill(pid as libc::pid_t, 0) } == 0;
+    exists || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Wait up to `timeout` for the process `pid` to exit, returns whether it did
+fn wait_for_exit(pid: u32, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while is_running(pid) {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    true
+}
+
+/// How a daemon was stopped by [`stop_and_wait`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// It exited by itself
+    Cleanly,
+    /// It didn't exit within the timeout and was killed
+    Killed,
+}
+
+/// Stop the daemon listening on `socket_name` and wait up to `timeout` for it to exit, killing it
+/// after that if `force` is set
+///
+/// A daemon which is stuck doesn't reply to the stop command, it is waited for all the same.
+pub fn stop_and_wait(socket_name: &str, timeout: Duration, force: bool) -> anyhow::Result<Stopped> {
+    let started = Instant::now();
+    let pid = daemon_pid(socket_name, timeout)?;
+    let request = Request {
+        output: None,
+        command: Command::Stop {
+            wait: true,
+            timeout: DurationArg(timeout.as_millis() as u64),
+            force,
+        },
+        wait: false,
+    };
+    if let Ok(Err(message)) = send_timeout(socket_name, &request, timeout) {
+        bail!(message);
+    }
+    if wait_for_exit(pid, timeout.saturating_sub(started.elapsed())) {
+        return Ok(Stopped::Cleanly);
+    }
+    if !force {
+        bail!("the desktop program (process {pid}) didn't exit within {timeout:?}");
+    }
+
+    // SAFETY: kill only sends the signal
+    unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+    if !wait_for_exit(pid, KILL_WAIT) {
+        bail!("could not kill the desktop program (process {pid})");
+    }
+    // A killed daemon leaves its socket file behind
+    if let Some(path) = socket_path(socket_name)
+        .ok()
+        .filter(|path| !path.starts_with('@'))
+    {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(Stopped::Killed)
+}
+
+/// What [`claim_socket`] found on the socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claimed {
+    /// Nothing was there
+    Fresh,
+    /// The socket file of a daemon which died was removed
+    Stale,
+    /// A running daemon was stopped
+    Replaced,
+}
+
+/// Make sure no daemon listens on the socket `socket_name` before a new one binds it
+///
+/// A socket file left behind by a daemon which died is removed. A running daemon is stopped if
+/// `replace` is set, otherwise it is named in the error.
+pub fn claim_socket(socket_name: &str, replace: bool) -> anyhow::Result<Claimed> {
+    let path = socket_path(socket_name)?;
+    let file = (!path.starts_with('@')).then(|| std::fs::symlink_metadata(&path).ok());
+    if let Some(None) = file {
+        return Ok(Claimed::Fresh);
+    }
+
+    let request = Request {
+        output: None,
+        command: Command::Ping,
+        wait: false,
+    };
+    let reply = match send_timeout(socket_name, &request, CLAIM_PING_TIMEOUT) {
+        Ok(reply) => reply,
+        Err(error) if is_refused(&error) => {
+            // Only sockets are removed, binding fails on anything else
+            return match file {
+                Some(Some(metadata)) if metadata.file_type().is_socket() => {
+                    std::fs::remove_file(&path)
+                        .with_context(|| format!("could not remove the stale socket {path}"))?;
+                    Ok(Claimed::Stale)
+                }
+                _ => Ok(Claimed::Fresh),
+            };
+        }
+        Err(error) => {
+            return Err(error).with_context(|| {
+                format!("a desktop program listens on {path} but doesn't respond")
+            })
+        }
+    };
+
+    if !replace {
+        let daemon = reply
+            .map_err(anyhow::Error::msg)
+            .and_then(|reply| DaemonInfo::parse(&reply));
+        match daemon {
+            Ok(daemon) => {
+                let started = Local::now() - chrono::Duration::seconds(daemon.uptime as i64);
+                bail!(
+                    "a desktop program is already running on {socket_name} (pid {pid}, started \
+                     {started}), start with --replace to replace it",
+                    pid = daemon.pid,
+                    started = started.format("%Y-%m-%d %H:%M:%S"),
+                );
+            }
+            Err(_) => bail!(
+                "a desktop program is already running on {socket_name}, start with --replace to \
+                 replace it"
+            ),
+        }
+    }
+    stop_and_wait(socket_name, REPLACE_TIMEOUT, false)?;
+    Ok(Claimed::Replaced)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::command::RenderArgs;
+
+    const STOP: Command = Command::Stop {
+        wait: false,
+        timeout: DurationArg(5000),
+        force: false,
+    };
+
+    fn stop() -> Request {
+        Request {
+            output: None,
+            command: STOP,
+            wait: false,
+        }
+    }
+
+    /// An encoded request of a layer command nested `depth` times around a stop command, built by
+    /// hand since encoding it would recurse as deep
+    fn nested_layers(depth: usize) -> Vec<u8> {
+        let layer = bincode::serialize(&Command::Layer { layers: Vec::new() }).unwrap();
+        let mut message = vec![0];
+        for _ in 0..depth {
+            message.extend_from_slice(&layer[..4]);
+            message.extend_from_slice(&1u64.to_le_bytes());
+        }
+        message.extend(bincode::serialize(&STOP).unwrap());
+        // Not waiting for the background
+        message.push(0);
+        message
+    }
+
+    #[test]
+    fn keeps_the_bincode_wire_format() {
+        let request = Request {
+            output: Some("DP-1".to_string()),
+            command: Command::Vsync { enabled: true },
+            wait: false,
+        };
+        let mut written = Vec::new();
+        write_message(&mut written, &request).unwrap();
+        assert_eq!(written, bincode::serialize(&request).unwrap());
+    }
+
+    #[test]
+    fn rejects_huge_length_prefixes() {
+        let mut message = bincode::serialize(&Request {
+            output: Some(String::new()),
+            command: STOP,
+            wait: false,
+        })
+        .unwrap();
+        // The length of the output name follows the option tag
+        message[1..9].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(read_message::<Request>(&mut message.as_slice()).is_err());
+    }
+
+    #[test]
+    fn rejects_messages_over_the_limit() {
+        let request = Request {
+            output: Some("x".repeat(MAX_MESSAGE_SIZE as usize)),
+            command: STOP,
+            wait: false,
+        };
+        let message = bincode::serialize(&request).unwrap();
+        assert!(read_message::<Request>(&mut message.as_slice()).is_err());
+        assert!(write_message(&mut Vec::new(), &request).is_err());
+    }
+
+    #[test]
+    fn accepts_messages_at_the_limit() {
+        let overhead = bincode::serialize(&stop()).unwrap().len() + 8;
+        let request = Request {
+            output: Some("x".repeat(MAX_MESSAGE_SIZE as usize - overhead)),
+            command: STOP,
+            wait: false,
+        };
+        let message = bincode::serialize(&request).unwrap();
+        assert_eq!(message.len() as u64, MAX_MESSAGE_SIZE);
+        assert!(read_message::<Request>(&mut message.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let message = bincode::serialize(&Request {
+            output: Some("DP-1".to_string()),
+            command: STOP,
+            wait: false,
+        })
+        .unwrap();
+        for len in 0..message.len() {
+            assert!(read_message::<Request>(&mut &message[..len]).is_err());
+        }
+    }
+
+    /// An encoded request of a render-frames command nested `depth` times around a stop command
+    fn nested_render_frames(depth: usize) -> Vec<u8> {
+        let args = |background| RenderArgs {
+            width: 1,
+            height: 1,
+            background: Box::new(background),
+            out: PathBuf::new(),
+            at: None,
+            count: 1,
+            step: DurationArg(1000),
+        };
+        let stop = bincode::serialize(&STOP).unwrap();
+        let inner = bincode::serialize(&Command::RenderFrames(args(STOP))).unwrap();
+        // The variant and the frame size come before the background, the other arguments after
+        let (prefix, suffix) = (&inner[..12], &inner[12 + stop.len()..]);
+        let mut message = vec![0];
+        for _ in 0..depth {
+            message.extend_from_slice(prefix);
+        }
+        message.extend_from_slice(&stop);
+        for _ in 0..depth {
+            message.extend_from_slice(suffix);
+        }
+        message.push(0);
+        message
+    }
+
+    #[test]
+    fn rejects_deeply_nested_render_frames() {
+        let message = nested_render_frames(64);
+        assert!(read_message::<Request>(&mut message.as_slice()).is_err());
+        let message = nested_render_frames(3);
+        assert!(read_message::<Request>(&mut message.as_slice()).is_ok());
+    }
+
+    #[test]
+    fn rejects_deeply_nested_layers() {
+        let message = nested_layers(MAX_MESSAGE_SIZE as usize / 16);
+        assert!(message.len() as u64 <= MAX_MESSAGE_SIZE);
+        assert!(read_message::<Request>(&mut message.as_slice()).is_err());
+
+        // Shallow nesting decodes and is refused for the stop command in its layers
+        let message = nested_layers(3);
+        assert!(read_message::<Request>(&mut message.as_slice()).is_ok());
+        assert!(read_request(&mut message.as_slice()).is_err());
+    }
+
+    #[test]
+    fn gives_up_on_a_daemon_which_doesnt_reply() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_name = dir.path().join("ipc.sock").to_str().unwrap().to_string();
+        let listener =
+            interprocess::local_socket::LocalSocketListener::bind(socket_name.as_str()).unwrap();
+        // Accepts the connection and never replies
+        thread::spawn(move || {
+            let _stream = listener.accept().unwrap();
+            thread::sleep(Duration::from_secs(5));
+        });
+
+        let error = send_timeout(&socket_name, &stop(), Duration::from_millis(100)).unwrap_err();
+        assert!(error.is::<NoReply>(), "{error:#}");
+    }
+}
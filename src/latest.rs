@@ -0,0 +1,216 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context};
+use image::RgbaImage;
+use notify::RecommendedWatcher;
+
+use crate::{
+    render::{FrameLayout, Scaling},
+    watch::{settled, watch_dir},
+};
+
+/// Whether the file `name` matches `pattern`, where `*` matches any number of characters and `?`
+/// a single one
+fn glob_match(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some('*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some('?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(expected), Some(actual)) if expected == actual => {
+            glob_match(&pattern[1..], &name[1..])
+        }
+        _ => false,
+    }
+}
+
+/// The files matching `pattern` with their modification time, the newest first
+///
+/// Only the file name part of the pattern may hold wildcards, like `shots/shot_2024-*.png`.
+fn matches(pattern: &Path) -> anyhow::Result<Vec<(PathBuf, SystemTime)>> {
+    let Some(file_pattern) = pattern.file_name().and_then(|name| name.to_str()) else {
+        bail!(
+            "{pattern} doesn't end in a file name",
+            pattern = pattern.display()
+        );
+    };
+    let file_pattern: Vec<char> = file_pattern.chars().collect();
+    let dir = match pattern.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("could not list {}", dir.display()))?;
+    let mut matches: Vec<(PathBuf, SystemTime)> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name: Vec<char> = entry.file_name().to_str()?.chars().collect();
+            let metadata = entry.metadata().ok()?;
+            (metadata.is_file() && glob_match(&file_pattern, &name))
+                .then(|| Some((entry.path(), metadata.modified().ok()?)))?
+        })
+        .collect();
+    matches.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| b.0.cmp(&a.0)));
+    Ok(matches)
+}
+
+/// Decode the newest image matching `pattern` and place it into a `width` x `height` frame,
+/// matches which can't be decoded are skipped with a warning
+pub fn newest_image(
+    pattern: &Path,
+    scaling: Scaling,
+    margin_color: [u8; 3],
+    width: u32,
+    height: u32,
+) -> anyhow::Result<(PathBuf, SystemTime, RgbaImage)> {
+    let matches = matches(pattern)?;
+    if matches.is_empty() {
+        bail!("no file matches {pattern}", pattern = pattern.display());
+    }
+    for (path, modified) in matches {
+        match FrameLayout::with_color(scaling, margin_color, width, height)
+            .load(&path, width, height)
+        {
+            Ok(frame) => return Ok((path, modified, frame)),
+            Err(error) => eprintln!(
+                "warning: skipping {path}, it can't be decoded: {error}",
+                path = path.display()
+            ),
+        }
+    }
+    bail!(
+        "none of the files matching {pattern} can be decoded",
+        pattern = pattern.display()
+    )
+}
+
+/// Watches the files matching a pattern on a background thread and decodes a newer match once it
+/// stopped changing
+///
+/// The thread ends once the watcher is dropped, with the file watch it waits on.
+pub struct LatestWatcher {
+    frames: Receiver<(PathBuf, RgbaImage)>,
+    _watcher: RecommendedWatcher,
+}
+
+impl LatestWatcher {
+    /// Watch `pattern` for files newer than `shown`, modified at `shown_modified`
+    pub fn spawn(
+        pattern: PathBuf,
+        shown: PathBuf,
+        shown_modified: SystemTime,
+        scaling: Scaling,
+        margin_color: [u8; 3],
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let file_pattern: Vec<char> = pattern
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .chars()
+            .collect();
+        let (watcher, changes) = watch_dir(&pattern, move |name| {
+            name.to_str()
+                .is_some_and(|name| glob_match(&file_pattern, &name.chars().collect::<Vec<_>>()))
+        })?;
+        let (sender, frames) = mpsc::channel();
+
+        thread::spawn(move || {
+            let mut current = (shown, shown_modified);
+            while settled(&changes) {
+                let newest = match matches(&pattern) {
+                    Ok(matches) => matches.into_iter().next(),
+                    Err(_) => continue,
+                };
+                let Some(newest) = newest.filter(|newest| *newest != current) else {
+                    continue;
+                };
+
+                let (path, _) = &newest;
+                match FrameLayout::with_color(scaling, margin_color, width, height)
+                    .load(path, width, height)
+                {
+                    Ok(frame) => {
+                        if sender.send((path.clone(), frame)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => eprintln!(
+                        "warning: skipping {path}, it can't be decoded: {error}",
+                        path = path.display(),
+                    ),
+                }
+                current = newest;
+            }
+        });
+
+        Ok(LatestWatcher {
+            frames,
+            _watcher: watcher,
+        })
+    }
+
+    /// The most recently decoded newer match, if one appeared since the last call
+    pub fn poll(&self) -> Option<(PathBuf, RgbaImage)> {
+        let mut latest = None;
+        loop {
+            match self.frames.try_recv() {
+                Ok(frame) => latest = Some(frame),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return latest,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use image::Rgba;
+
+    use super::*;
+
+    fn chars(string: &str) -> Vec<char> {
+        string.chars().collect()
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        let pattern = chars("shot_2024-*.png");
+        assert!(glob_match(&pattern, &chars("shot_2024-01-02.png")));
+        assert!(glob_match(&pattern, &chars("shot_2024-.png")));
+        assert!(!glob_match(&pattern, &chars("shot_2023-01-02.png")));
+        assert!(!glob_match(&pattern, &chars("shot_2024-01.png.tmp")));
+        assert!(glob_match(&chars("?.jpg"), &chars("a.jpg")));
+        assert!(!glob_match(&chars("?.jpg"), &chars("ab.jpg")));
+    }
+
+    #[test]
+    fn picks_the_newest_decodable_match() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let pattern = dir.join("shot-*.png");
+        assert!(newest_image(&pattern, Scaling::Stretch, [0; 3], 1, 1).is_err());
+
+        RgbaImage::from_pixel(1, 1, Rgba([1, 2, 3, 255]))
+            .save(dir.join("shot-1.png"))
+            .unwrap();
+        thread::sleep(Duration::from_millis(20));
+        std::fs::write(dir.join("shot-2.png"), b"not an image").unwrap();
+        RgbaImage::from_pixel(1, 1, Rgba([9, 9, 9, 255]))
+            .save(dir.join("other.png"))
+            .unwrap();
+
+        let (path, _, image) = newest_image(&pattern, Scaling::Stretch, [0; 3], 1, 1).unwrap();
+        assert_eq!(path, dir.join("shot-1.png"));
+        assert_eq!(image.get_pixel(0, 0).0, [1, 2, 3, 255]);
+    }
+}
@@ -0,0 +1,77 @@
+//! A desktop background program showing static images, clocks made of pre-rendered frames and
+//! text, controlled by commands sent over a local socket
+//!
+//! [`Daemon`] runs the desktop program, frontends send it a [`Request`] with [`ipc::send`] and
+//! get a [`Reply`] back.
+
+#![warn(missing_docs)]
+
+pub mod args;
+/// Clock frames decoded ahead into a cache directory, the warm-cache command
+pub mod cache;
+/// Finding missing, empty and unreadable frames of clock directories, the check-clock-dir command
+pub mod check;
+/// The time renderers draw, the system time or one set by tests and previews
+pub mod clock;
+mod colors;
+/// The commands of the command line and how backgrounds are created from them
+pub mod command;
+/// The desktop program, showing backgrounds on the outputs and running the commands sent to it
+pub mod daemon;
+/// The parts of frames changed by a render
+pub mod damage;
+/// Reading image files into the 8 bit sRGB pixels of frames
+pub mod decode;
+mod download;
+/// Checking background commands without a desktop program
+pub mod dry_run;
+/// The setup of a running daemon as json or as a script starting it again
+pub mod export;
+mod gpu;
+mod hands;
+mod history;
+mod icc;
+/// The daemons running for the current user, found by their sockets
+pub mod instances;
+/// Sending commands to the daemon over its local socket
+pub mod ipc;
+mod json;
+mod latest;
+#[cfg(feature = "lock-detection")]
+mod lock;
+mod output;
+mod overlay;
+mod palette;
+mod pipe;
+pub mod plugin;
+mod pool;
+/// Rendering backgrounds into image files or a preview window instead of the desktop
+pub mod preview;
+/// Renderers drawing backgrounds into the rgba frames of the desktop
+pub mod render;
+/// Filling gaps in clock directories with frames interpolated from their neighbors
+pub mod repair;
+/// Resizing the frames of clock directories ahead of time
+pub mod resample;
+mod restart;
+pub mod script;
+pub mod shm;
+mod signals;
+mod slideshow;
+#[cfg(feature = "cpu-fallback")]
+mod software;
+mod source;
+mod span;
+/// The backgrounds kept across restarts of the daemon
+pub mod state;
+mod template;
+/// Text drawn onto frames
+pub mod text;
+/// The colors clock frames are tinted with over time
+pub mod tint;
+mod watch;
+mod weekly;
+
+pub use command::{Command, StartArgs};
+pub use daemon::Daemon;
+pub use ipc::{Reply, Request};
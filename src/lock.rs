@@ -0,0 +1,94 @@
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use winit::event_loop::EventLoopProxy;
+
+use crate::{daemon::DaemonEvent, signals};
+
+/// How long to wait before connecting to the system bus again after the connection dropped
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Watch the logind session of this process for lock and unlock signals on a background thread
+///
+/// The signals are read from `gdbus monitor` so no D-Bus library is needed, the monitor is
+/// restarted whenever it exits.
+pub fn spawn(proxy: EventLoopProxy<DaemonEvent>) {
+    thread::spawn(move || loop {
+        match watch_session(&proxy) {
+            Ok(()) => eprintln!("lock detection: lost the system bus connection, reconnecting"),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("lock detection: gdbus is not installed, disabling lock detection");
+                return;
+            }
+            Err(error) => eprintln!("lock detection: {error}, retrying"),
+        }
+        if proxy.send_event(DaemonEvent::Locked(false)).is_err() {
+            return;
+        }
+        thread::sleep(RECONNECT_DELAY);
+    });
+}
+
+/// Forward lock changes of the session until the monitor exits or the event loop is gone
+fn watch_session(proxy: &EventLoopProxy<DaemonEvent>) -> std::io::Result<()> {
+    let session = session_path()?;
+    let mut monitor = signals::unblocked(
+        Command::new("gdbus")
+            .args(["monitor", "--system", "--dest", "org.freedesktop.login1"])
+            .args(["--object-path", &session])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null()),
+    )
+    .spawn()?;
+
+    let stdout = monitor.stdout.take().expect("stdout is piped");
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        let locked = if line.contains(".Session.Lock (") || line.contains("'LockedHint': <true>") {
+            true
+        } else if line.contains(".Session.Unlock (") || line.contains("'LockedHint': <false>") {
+            false
+        } else {
+            continue;
+        };
+
+        if proxy.send_event(DaemonEvent::Locked(locked)).is_err() {
+            let _ = monitor.kill();
+            break;
+        }
+    }
+
+    monitor.wait()?;
+    Ok(())
+}
+
+/// The object path of the logind session this process belongs to
+fn session_path() -> std::io::Result<String> {
+    let output = signals::unblocked(
+        Command::new("gdbus")
+            .args(["call", "--system", "--dest", "org.freedesktop.login1"])
+            .args(["--object-path", "/org/freedesktop/login1"])
+            .args(["--method", "org.freedesktop.login1.Manager.GetSessionByPID"])
+            .arg(std::process::id().to_string())
+            .stderr(Stdio::piped()),
+    )
+    .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "could not find the login session: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    // The reply looks like `(objectpath '/org/freedesktop/login1/session/_32',)`
+    let reply = String::from_utf8_lossy(&output.stdout);
+    reply
+        .split('\'')
+        .nth(1)
+        .map(str::to_string)
+        .ok_or_else(|| std::io::Error::other(format!("unexpected logind reply {reply:?}")))
+}
@@ -1,250 +1,329 @@
-mod render;
-
-use anyhow::bail;
-use clap::{Parser, Subcommand};
-use interprocess::local_socket::{LocalSocketListener, LocalSocketStream};
-use pixels::{wgpu::RequestAdapterOptions, Pixels, PixelsBuilder, SurfaceTexture};
-use render::BackgroundRenderer;
-use serde::{Deserialize, Serialize};
 use std::{
-    collections::VecDeque,
-    io::Write,
     path::PathBuf,
     time::{Duration, Instant},
 };
-use winit::{
-    event::{Event, WindowEvent},
-    event_loop::EventLoopBuilder,
-    platform::wayland::{EventLoopBuilderExtWayland, WindowBuilderExtWayland},
-    window::WindowBuilder,
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use desktop_background::{
+    args::DurationArg,
+    cache, check, command, dry_run, export, instances,
+    ipc::{self, NoReply, Stopped},
+    preview, repair, resample, state, Command, Daemon, Request,
 };
 
-const TICK_RATE: u64 = 50;
+/// How long a ping waits for the reply of the desktop program
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+/// The exit code when the desktop program had to be killed by `stop --force`, failing to stop it
+/// exits with 1 like any other error
+const EXIT_KILLED: i32 = 2;
+/// The exit code when the background set with `--wait` wasn't shown within the timeout
+const EXIT_NOT_SHOWN: i32 = 3;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    after_help = "Run `desktop-background list` to list the running desktop programs."
+)]
 struct Args {
-    /// The socket name
+    /// The socket name, a plain name is a socket in `$XDG_RUNTIME_DIR/desktop-background`, a path
+    /// or an abstract name starting with `@` is used as it is
     #[arg()]
     socket_name: String,
+    /// Send the command to the output with this name only, instead of all outputs
+    #[arg(long, global = true)]
+    output: Option<String>,
+    /// Write the dominant colors of a background to this file once it is shown, one hex code per
+    /// line
+    #[arg(long, global = true)]
+    export_palette: Option<PathBuf>,
+    /// Return once the background is decoded and shown on the outputs rather than once it is set
+    #[arg(long, global = true)]
+    wait: bool,
+    /// How long to wait for the background to be shown, like `10s` or `500ms`
+    #[arg(long, global = true, default_value = "10s", requires = "wait")]
+    wait_timeout: DurationArg,
     /// Command
     #[command(subcommand)]
     command: Command,
 }
 
-#[derive(Debug, Clone, Subcommand, Serialize, Deserialize)]
-enum Command {
-    /// Start the desktop program
-    Start {
-        /// Desktop resolution width in pixels
-        #[arg()]
-        width: u32,
-        /// Desktop resolution height in pixels
-        #[arg()]
-        height: u32,
-        /// Window class name
-        #[arg()]
-        window_class: String,
-    },
-    /// Close the running desktop program
-    Stop,
-    /// A static image background
-    StaticImage {
-        /// The image file to use
-        #[arg()]
-        path: PathBuf,
-    },
-    /// A dynamically changing background image according to the time of day the
-    ClockImage {
-        /// The directory which contains the clock images by hour in sub folders "0" to "11"
-        #[arg()]
-        dir: PathBuf,
-        /// The template file name where %m will get replaced by the current time in milliseconds
-        /// padded to 8 digits with 0's eg in the range of 0000000 (inclusive) - 43200000 (exclusive).
-        ///
-        /// # Example
-        /// `"clock_frame_%m.png"`
-        #[arg()]
-        file_template: String,
-        /// The clock step in milli seconds
-        #[arg(default_value_t = 100)]
-        clock_step: u32,
-        /// The clock color: < RAINBOW | ###### (rgb hex) >
-        #[arg(long, short)]
-        clock_color: Option<String>,
-    },
+/// List the desktop programs with a socket in `$XDG_RUNTIME_DIR/desktop-background` and those on
+/// abstract sockets
+#[derive(Parser)]
+#[command(name = "desktop-background list")]
+struct ListArgs {
+    /// Print a json array instead of a table
+    #[arg(long)]
+    json: bool,
+    /// Remove the sockets, or the advertisements of abstract sockets, of desktop programs which
+    /// aren't running any more
+    #[arg(long)]
+    prune: bool,
 }
 
-impl Command {
-    pub fn into_renderer(
-        self,
-        pixels: &mut Pixels,
-        width: u32,
-        height: u32,
-    ) -> anyhow::Result<render::BackgroundRenderer> {
-        match self {
-            Command::StaticImage { path } => {
-                let image = image::imageops::resize(
-                    &image::open(path)?,
-                    width,
-                    height,
-                    image::imageops::FilterType::Triangle,
-                );
-                pixels.frame_mut().copy_from_slice(&image);
-
-                Ok(BackgroundRenderer::None)
-            }
-            Command::ClockImage {
-                dir,
-                file_template,
-                clock_step,
-                clock_color,
-            } => {
-                let (rainbow, color) = match clock_color {
-                    Some(string) => {
-                        if string.to_uppercase() == "RAINBOW" {
-                            (true, None)
-                        } else {
-                            if string.len() > 6 {
-                                bail!(
-                                    "clock-color should be of the format < RAINBOW | ###### (rgb hex) >"
-                                )
-                            }
-
-                            let parsed = u32::from_str_radix(&string, 16)?;
-                            (
-                                false,
-                                Some([
-                                    ((parsed >> 16) & 0xFF) as f32 / 255.0,
-                                    ((parsed >> 8) & 0xFF) as f32 / 255.0,
-                                    (parsed & 0xFF) as f32 / 255.0,
-                                ]),
-                            )
-                        }
-                    }
-                    None => (false, None),
-                };
-
-                Ok(BackgroundRenderer::ClockImage {
-                    dir,
-                    file_template,
-                    clock_step,
-                    buffered_images: VecDeque::new(),
-                    rainbow,
-                    color,
-                })
-            }
-            _ => Ok(BackgroundRenderer::None),
+fn main() -> anyhow::Result<()> {
+    // The only command without a socket name, which the others are given first
+    if std::env::args_os().nth(1).is_some_and(|arg| arg == "list") {
+        let list = ListArgs::parse_from(std::env::args_os().skip(1));
+        let instances = instances::list(list.prune)?;
+        if !instances.is_empty() || list.json {
+            println!("{}", instances::describe(&instances, list.json));
         }
+        return Ok(());
     }
-}
 
-fn main() -> anyhow::Result<()> {
     let args = Args::parse();
 
     match args.command {
-        Command::Start {
-            width,
-            height,
-            window_class,
-        } => {
-            let socket = LocalSocketListener::bind(args.socket_name)?;
-            socket.set_nonblocking(true)?;
-
-            run(
-                &window_class,
-                width,
-                height,
-                BackgroundRenderer::None,
-                socket,
-            )?;
+        Command::Start(start) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            Daemon::bind(start, &args.socket_name)?.run()?;
         }
-        command => {
-            let mut socket = LocalSocketStream::connect(args.socket_name)?;
-            bincode::serialize_into(&mut socket, &command)?;
-            socket.flush()?;
-            drop(socket);
+        Command::RenderFrames(render) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            for path in preview::render_frames(render)? {
+                println!("{path}", path = path.display());
+            }
         }
-    }
-
-    Ok(())
-}
-
-fn run(
-    window_class: &str,
-    width: u32,
-    height: u32,
-    mut renderer: BackgroundRenderer,
-    socket: LocalSocketListener,
-) -> anyhow::Result<()> {
-    let event_loop = EventLoopBuilder::new().with_wayland().build().unwrap();
-    let window = WindowBuilder::new()
-        .with_name(window_class, window_class)
-        .build(&event_loop)
-        .unwrap();
-
-    let surface_texture = SurfaceTexture::new(width, height, &window);
-    let mut pixels = PixelsBuilder::new(width, height, surface_texture)
-        .request_adapter_options(RequestAdapterOptions {
-            power_preference: pixels::wgpu::PowerPreference::LowPower,
-            force_fallback_adapter: false,
-            compatible_surface: None,
-        })
-        .enable_vsync(true)
-        .build()
-        .unwrap();
-
-    event_loop
-        .run(move |event, elwt| match event {
-            Event::WindowEvent {
-                event: WindowEvent::CloseRequested,
-                ..
-            } => elwt.exit(),
-            Event::AboutToWait => {
-                match socket.accept() {
-                    Ok(stream) => {
-                        match bincode::deserialize_from::<_, Command>(stream) {
-                            Ok(Command::Stop) => {
-                                elwt.exit();
-                            }
-                            Ok(command) => {
-                                renderer = command
-                                    .into_renderer(&mut pixels, width, height)
-                                    .unwrap_or_else(|e| {
-                                        eprintln!("{e}");
-                                        elwt.exit();
-                                        BackgroundRenderer::None
-                                    });
-                            }
-                            Err(error) => {
-                                eprintln!("{error}");
-                                elwt.exit();
-                            }
-                        };
+        Command::RenderAt(render) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            let path = preview::render_at(render)?;
+            println!("{path}", path = path.display());
+        }
+        Command::WarmCache(warm) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            let report = cache::warm(warm)?;
+            eprintln!(
+                "cached {stored} frames in {dir}, {cached} were cached already",
+                stored = report.stored,
+                cached = report.cached,
+                dir = report.dir.display(),
+            );
+            if report.animated > 0 {
+                eprintln!(
+                    "{animated} animated files are played rather than cached",
+                    animated = report.animated
+                );
+            }
+            if !report.failed.is_empty() {
+                eprintln!("missing or corrupt frames:");
+                for (millis, error) in &report.failed {
+                    eprintln!("  {millis:08}: {error}");
+                }
+                bail!(
+                    "{failed} frames could not be cached",
+                    failed = report.failed.len()
+                );
+            }
+        }
+        Command::CheckClockDir(check) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            let report = check::check(check)?;
+            println!("{summary}", summary = report.summary());
+            if !report.is_usable() {
+                bail!(
+                    "the frames in {dir} can't all be shown",
+                    dir = report.dir.display()
+                );
+            }
+        }
+        Command::RepairClockDir(repair) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            let report = repair::repair(repair)?;
+            eprintln!(
+                "interpolated {written} frames, listed in {list}",
+                written = report.written.len(),
+                list = report.list.display(),
+            );
+            if !report.skipped.is_empty() {
+                eprintln!("gaps longer than --max-gap, left missing:");
+                for (first, len) in &report.skipped {
+                    eprintln!("  {len} frames from {first}", first = first.display());
+                }
+            }
+            if !report.failed.is_empty() {
+                eprintln!("gaps which could not be interpolated:");
+                for (first, error) in &report.failed {
+                    eprintln!("  {first}: {error}", first = first.display());
+                }
+                bail!(
+                    "{failed} gaps could not be interpolated",
+                    failed = report.failed.len()
+                );
+            }
+        }
+        Command::ResampleClockDir(resample) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            let report = resample::resample(resample)?;
+            let seconds = report.elapsed.as_secs_f64();
+            eprintln!(
+                "resampled {written} frames in {seconds:.1}s, {rate:.1} frames/s reading \
+                 {throughput:.1} MiB/s, {skipped} were resampled already",
+                written = report.written,
+                rate = report.written as f64 / seconds,
+                throughput = report.read as f64 / (1 << 20) as f64 / seconds,
+                skipped = report.skipped,
+            );
+            if report.copied > 0 {
+                eprintln!(
+                    "{copied} animated files were copied rather than resampled",
+                    copied = report.copied
+                );
+            }
+            if !report.failed.is_empty() {
+                eprintln!("frames which could not be resampled:");
+                for (path, error) in &report.failed {
+                    eprintln!("  {path}: {error}", path = path.display());
+                }
+                bail!(
+                    "{failed} frames could not be resampled",
+                    failed = report.failed.len()
+                );
+            }
+        }
+        Command::Check(check) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            for found in dry_run::check(check)? {
+                println!("{found}");
+            }
+        }
+        Command::Preview(preview) => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            preview::show(preview)?;
+        }
+        Command::Profiles => {
+            if args.output.is_some() {
+                bail!("--output selects the output of a command sent to a running desktop program");
+            }
+            for name in state::profiles()? {
+                println!("{name}");
+            }
+        }
+        Command::Ping => {
+            let request = Request {
+                output: args.output,
+                command: Command::Ping,
+                wait: false,
+            };
+            let sent = Instant::now();
+            match ipc::send_timeout(&args.socket_name, &request, PING_TIMEOUT)? {
+                Ok(message) => println!(
+                    "{message}\nround trip: {millis:.1} ms",
+                    millis = sent.elapsed().as_secs_f64() * 1000.0
+                ),
+                Err(message) => bail!(message),
+            }
+        }
+        Command::Export => {
+            let request = Request {
+                output: None,
+                command: Command::Export,
+                wait: false,
+            };
+            match ipc::send(&args.socket_name, &request)? {
+                Ok(reply) => println!(
+                    "{script}",
+                    script = export::script(&args.socket_name, &export::Setup::from_json(&reply)?)
+                ),
+                Err(message) => bail!(message),
+            }
+        }
+        Command::Stop {
+            wait: true,
+            timeout,
+            force,
+        } => {
+            let timeout = timeout.as_duration();
+            if ipc::stop_and_wait(&args.socket_name, timeout, force)? == Stopped::Killed {
+                eprintln!("the desktop program didn't exit within {timeout:?}, killed it");
+                std::process::exit(EXIT_KILLED);
+            }
+        }
+        mut command => {
+            // The daemon may run elsewhere, so the file is read here and sent along
+            if let Command::Batch { file, commands, .. } = &mut command {
+                let text = if file.as_os_str() == "-" {
+                    std::io::read_to_string(std::io::stdin())
+                        .context("could not read the batch from stdin")?
+                } else {
+                    std::fs::read_to_string(&*file)
+                        .with_context(|| format!("could not read {}", file.display()))?
+                };
+                *commands = command::parse_batch(&text)?;
+            }
+            // Checked by the daemon as well, but this doesn't need one running
+            command.validate()?;
+            for detected in command.detect_clock_steps()? {
+                eprintln!("{detected}");
+            }
+            if args.export_palette.is_some() && !command.is_background() {
+                bail!("--export-palette writes the colors of a background command");
+            }
+            if args.wait && !command.is_background() {
+                bail!("--wait waits for the background of a background command to be shown");
+            }
+            let request = Request {
+                output: args.output.clone(),
+                command,
+                wait: args.wait,
+            };
+            let reply = if args.wait {
+                let timeout = args.wait_timeout.as_duration();
+                match ipc::send_timeout(&args.socket_name, &request, timeout) {
+                    Err(error) if error.is::<NoReply>() => {
+                        eprintln!("the background wasn't shown within {timeout:?}");
+                        std::process::exit(EXIT_NOT_SHOWN);
                     }
-                    Err(error) => match error.kind() {
-                        std::io::ErrorKind::WouldBlock => {}
-                        _ => {
-                            eprintln!("{error}");
-                            elwt.exit();
-                        }
-                    },
+                    reply => reply?,
                 }
+            } else {
+                ipc::send(&args.socket_name, &request)?
+            };
+            match reply {
+                Ok(message) if message.is_empty() => {}
+                Ok(message) => println!("{message}"),
+                Err(message) => bail!(message),
+            }
 
-                renderer
-                    .render(&mut pixels, width, height)
-                    .unwrap_or_else(|e| {
-                        eprintln!("{e}");
-                        elwt.exit();
-                    });
-                pixels.render().unwrap();
-                elwt.set_control_flow(winit::event_loop::ControlFlow::WaitUntil(
-                    Instant::now() + Duration::from_millis(TICK_RATE),
-                ));
-            }
-            _ => {}
-        })
-        .unwrap();
+            if let Some(path) = args.export_palette {
+                let request = Request {
+                    output: args.output,
+                    command: Command::Palette {
+                        count: 8,
+                        json: false,
+                    },
+                    wait: false,
+                };
+                match ipc::send(&args.socket_name, &request)? {
+                    Ok(palette) => std::fs::write(&path, palette + "\n").with_context(|| {
+                        format!("could not write {path}", path = path.display())
+                    })?,
+                    Err(message) => bail!(message),
+                }
+            }
+        }
+    }
 
     Ok(())
 }
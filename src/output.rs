@@ -0,0 +1,644 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use pixels::wgpu::SurfaceError;
+use winit::{
+    dpi::PhysicalSize,
+    event::WindowEvent,
+    event_loop::EventLoopWindowTarget,
+    platform::{wayland::WindowBuilderExtWayland, x11::WindowBuilderExtX11},
+    window::{Window, WindowBuilder},
+};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    command::{Backend, Command},
+    daemon::DaemonEvent,
+    damage::Damage,
+    gpu::{build_pixels, fit_texture_limit, rebuild_pixels, GpuOptions, Presenter},
+    overlay::DebugOverlay,
+    render::BackgroundRenderer,
+    span::OutputSpan,
+};
+
+/// Open a background window with the given class
+pub fn open_window(
+    elwt: &EventLoopWindowTarget<DaemonEvent>,
+    backend: Backend,
+    window_class: &str,
+) -> anyhow::Result<Window> {
+    let builder = match backend {
+        Backend::Wayland => {
+            WindowBuilderExtWayland::with_name(WindowBuilder::new(), window_class, window_class)
+        }
+        Backend::X11 => {
+            WindowBuilderExtX11::with_name(WindowBuilder::new(), window_class, window_class)
+        }
+    };
+    builder.build(elwt).map_err(|error| {
+        anyhow::anyhow!(
+            "could not create the background window: {error}\n\
+             hint: the compositor may not allow clients to create windows of this kind"
+        )
+    })
+}
+
+/// Open a regular decorated and resizable window of `width` x `height` pixels, to preview a
+/// background in rather than showing it on the desktop
+pub fn open_preview_window(
+    elwt: &EventLoopWindowTarget<DaemonEvent>,
+    width: u32,
+    height: u32,
+    title: &str,
+) -> anyhow::Result<Window> {
+    WindowBuilder::new()
+        .with_title(title)
+        .with_inner_size(PhysicalSize::new(width, height))
+        .build(elwt)
+        .map_err(|error| anyhow::anyhow!("could not create the preview window: {error}"))
+}
+
+/// The buffer size for a surface of `size` physical pixels at `scale_factor`
+///
+/// Logical buffers are only used at whole scale factors, since the surface scales buffers up by
+/// whole factors only and the compositor would have to stretch them to fill fractionally scaled
+/// outputs. On Wayland winit sets the surface viewport to the logical size, so physical buffers are
+/// shown without any further scaling.
+pub fn buffer_size(size: PhysicalSize<u32>, scale_factor: f64, logical: bool) -> (u32, u32) {
+    if logical && scale_factor.fract() == 0.0 {
+        let logical_size = size.to_logical::<u32>(scale_factor);
+        (logical_size.width, logical_size.height)
+    } else {
+        (size.width, size.height)
+    }
+}
+
+/// A background window on one output together with the background shown in it
+pub struct OutputWindow {
+    pub window: Window,
+    pub pixels: Option<Presenter>,
+    /// The buffer size
+    pub width: u32,
+    pub height: u32,
+    /// The buffer is in logical pixels and scaled up on scaled outputs
+    logical: bool,
+    /// The buffer shows the [`Mirror`] frame and keeps its size when the surface is resized
+    mirrored: bool,
+    pub renderer: BackgroundRenderer,
+    /// The frame the renderer draws into, copied into the presented buffer once it is complete so
+    /// a half drawn frame is never shown, empty for mirrored outputs
+    back: Vec<u8>,
+    /// The command the current renderer was created from, to recreate it at a new size
+    pub command: Option<Command>,
+    /// The clock renderers are created with, the system clock unless previewing
+    pub clock: Arc<dyn Clock>,
+    /// The latest size from a burst of resize events, applied once per tick
+    pub pending_size: Option<PhysicalSize<u32>>,
+    /// Whether the window is hidden, eg because the output is blanked
+    pub occluded: bool,
+    span: Option<OutputSpan>,
+    pub overlay: Option<DebugOverlay>,
+    /// When the renderer is advanced next, independent of how often the window is redrawn
+    next_tick: Instant,
+    /// How the recovery from failed presentations has progressed since the last successful one
+    recovery: Recovery,
+    /// Present again on the next tick after a failed presentation
+    retry_present: bool,
+    /// A failure the next presentation reports instead of presenting, see [`Command::InjectFault`]
+    pub injected_fault: Option<SurfaceError>,
+    /// The renderer drew a frame which wasn't presented yet
+    drawn: bool,
+    /// How many drawn frames were presented, see [`OutputWindow::await_frame`]
+    frames_presented: u64,
+}
+
+/// The steps taken to recover from failed presentations, each one is tried once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Recovery {
+    None,
+    /// The surface was configured again
+    SurfaceRecreated,
+    /// The whole pixel buffer was built again
+    PixelsRebuilt,
+}
+
+impl OutputWindow {
+    /// Set up rendering into `window` with a buffer of up to `width` x `height` pixels, or the size
+    /// of the window if the compositor configured it already
+    pub fn new(
+        window: Window,
+        width: u32,
+        height: u32,
+        logical: bool,
+        mirrored: bool,
+        gpu: GpuOptions,
+        debug_overlay: bool,
+    ) -> anyhow::Result<Self> {
+        // Not configured yet on some compositors, the resize event follows later
+        let configured = Some(window.inner_size()).filter(|size| size.width > 0 && size.height > 0);
+        let physical_size = configured.unwrap_or(PhysicalSize::new(width, height));
+        let (width, height) = match configured {
+            Some(size) if !mirrored => {
+                let detected = buffer_size(size, window.scale_factor(), logical);
+                if detected != (width, height) {
+                    eprintln!(
+                        "warning: the resolution {width}x{height} differs from the size of the \
+                         window, using {detected_width}x{detected_height}",
+                        detected_width = detected.0,
+                        detected_height = detected.1,
+                    );
+                }
+                detected
+            }
+            _ => (width, height),
+        };
+        let logical_size = physical_size.to_logical::<u32>(window.scale_factor());
+        eprintln!(
+            "surface: {physical_width}x{physical_height} physical, {logical_width}x\
+             {logical_height} logical at scale {scale}, buffer {width}x{height} {unit}",
+            physical_width = physical_size.width,
+            physical_height = physical_size.height,
+            logical_width = logical_size.width,
+            logical_height = logical_size.height,
+            scale = window.scale_factor(),
+            unit = if logical { "logical" } else { "physical" },
+        );
+
+        let pixels = build_pixels(&window, physical_size, width, height, gpu)?;
+        if mirrored && pixels.buffer_size() != (width, height) {
+            anyhow::bail!(
+                "the mirrored resolution {width}x{height} exceeds the maximum texture size of the \
+                 graphics adapter"
+            );
+        }
+        let span = OutputSpan::of(&window);
+        let (width, height) = pixels.buffer_size();
+        Ok(OutputWindow {
+            width,
+            height,
+            pixels: Some(pixels),
+            window,
+            logical,
+            mirrored,
+            renderer: BackgroundRenderer::None,
+            back: back_buffer(mirrored, width, height),
+            command: None,
+            clock: Arc::new(SystemClock),
+            pending_size: None,
+            occluded: false,
+            span,
+            overlay: debug_overlay.then(DebugOverlay::new),
+            next_tick: Instant::now(),
+            recovery: Recovery::None,
+            retry_present: false,
+            injected_fault: None,
+            drawn: false,
+            frames_presented: 0,
+        })
+    }
+
+    /// Create the renderer of a background command for this output
+    pub fn create_renderer(&self, command: Command) -> anyhow::Result<BackgroundRenderer> {
+        command.into_renderer_with_clock(self.width, self.height, self.span, self.clock.clone())
+    }
+
+    /// Replace the renderer by a fresh one created from the current command, keeping it if that
+    /// fails
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        reload(
+            &mut self.renderer,
+            self.command.as_ref(),
+            self.width,
+            self.height,
+            self.span,
+            self.clock.clone(),
+        )
+    }
+
+    /// Build the pixel buffer again with other options, drawing the frame again unless it could be
+    /// kept
+    pub fn rebuild_pixels(&mut self, gpu: GpuOptions) -> anyhow::Result<()> {
+        self.window.request_redraw();
+        if !rebuild_pixels(&mut self.pixels, &self.window, self.width, self.height, gpu)? {
+            self.renderer.redraw();
+            self.tick_now();
+        }
+        Ok(())
+    }
+
+    /// Show or hide the debug overlay
+    pub fn set_overlay(&mut self, enabled: bool) {
+        if !enabled {
+            if let (Some(mut overlay), Some(pixels)) = (self.overlay.take(), self.pixels.as_mut()) {
+                overlay.restore(pixels.frame_mut(), self.width, self.height);
+                self.window.request_redraw();
+            }
+        } else if self.overlay.is_none() {
+            self.overlay = Some(DebugOverlay::new());
+            self.tick_now();
+        }
+    }
+
+    /// Present the frame rendered on the last tick
+    ///
+    /// Failures are recovered from by configuring the surface again and then by rebuilding the
+    /// pixel buffer, presenting again on the next tick, errors are returned once both failed.
+    pub fn present(&mut self, gpu: GpuOptions) -> anyhow::Result<()> {
+        // Redraws requested by the compositor don't advance the background
+        let Some(pixels) = self.pixels.as_mut() else {
+            return Ok(());
+        };
+        let result = match self.injected_fault.take() {
+            Some(fault) => Err(pixels::Error::Surface(fault)),
+            None => pixels.render(),
+        };
+        let (error, out_of_memory) = match result {
+            Ok(()) => {
+                self.recovery = Recovery::None;
+                if std::mem::take(&mut self.drawn) {
+                    self.frames_presented += 1;
+                }
+                return Ok(());
+            }
+            Err(pixels::Error::Surface(SurfaceError::Timeout)) => {
+                self.retry_present = true;
+                return Ok(());
+            }
+            Err(pixels::Error::Surface(error)) => {
+                (error.to_string(), error == SurfaceError::OutOfMemory)
+            }
+            Err(error) => (error.to_string(), false),
+        };
+
+        self.retry_present = true;
+        if self.recovery == Recovery::None && !out_of_memory {
+            eprintln!(
+                "warning: could not present the frame: {error}, configuring the surface again"
+            );
+            self.recovery = Recovery::SurfaceRecreated;
+            let size = self.window.inner_size();
+            if size.width > 0 && size.height > 0 {
+                pixels.resize_surface(size.width, size.height)?;
+            }
+        } else if self.recovery < Recovery::PixelsRebuilt {
+            eprintln!("warning: could not present the frame: {error}, rebuilding the pixel buffer");
+            self.recovery = Recovery::PixelsRebuilt;
+            self.rebuild_pixels(gpu)?;
+            // Draw the whole frame again rather than trusting what was copied from the old buffer
+            self.renderer.redraw();
+            self.tick_now();
+        } else {
+            anyhow::bail!("could not present the frame: {error}");
+        }
+        Ok(())
+    }
+
+    /// Handle an event of the window, resyncing the renderer when the window is shown again while
+    /// `active`, returns whether a frame was presented
+    ///
+    /// Errors can't be recovered from.
+    pub fn handle_event(
+        &mut self,
+        event: WindowEvent,
+        gpu: GpuOptions,
+        active: bool,
+    ) -> anyhow::Result<bool> {
+        match event {
+            WindowEvent::Resized(size) => self.pending_size = Some(size),
+            WindowEvent::RedrawRequested => {
+                self.present(gpu)?;
+                return Ok(true);
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                eprintln!("scale factor changed to {scale_factor}");
+                self.pending_size = Some(self.window.inner_size());
+            }
+            WindowEvent::Occluded(hidden) => {
+                if self.occluded && !hidden && active {
+                    // Whatever was buffered while hidden is stale by now
+                    self.renderer.resync();
+                    self.tick_now();
+                }
+                self.occluded = hidden;
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// Apply pending resizes and advance the renderer if its tick is due, at most every `tick` and
+    /// when its next frame is due, returns when the next tick is due unless the output is hidden,
+    /// `active` is unset or the renderer has no next frame
+    ///
+    /// A `mirrored` frame replaces the buffer contents right away. Errors can't be recovered from.
+    pub fn update(
+        &mut self,
+        gpu: GpuOptions,
+        tick: Duration,
+        active: bool,
+        mirrored: Option<&[u8]>,
+    ) -> anyhow::Result<Option<Instant>> {
+        let Some(pixels) = self.pixels.as_mut() else {
+            anyhow::bail!("the pixel buffer is gone");
+        };
+
+        let resized = self
+            .pending_size
+            .take()
+            .filter(|size| size.width > 0 && size.height > 0);
+        if let Some(size) = resized.filter(|_| self.mirrored) {
+            // The surface scales the mirrored frame up by whole factors and centers it
+            if let Err(error) = pixels.resize_surface(size.width, size.height) {
+                eprintln!("could not resize the surface: {error}");
+            }
+            self.window.request_redraw();
+        } else if let Some(size) = resized {
+            if let Err(error) = pixels.resize_surface(size.width, size.height) {
+                eprintln!("could not resize the surface: {error}");
+            }
+            let (buffer_width, buffer_height) =
+                buffer_size(size, self.window.scale_factor(), self.logical);
+            let buffer_size = fit_texture_limit(
+                buffer_width,
+                buffer_height,
+                pixels.max_dimension(),
+                gpu.oversize,
+            )?;
+
+            // Outputs may have moved without changing the size of this one
+            let output_span = OutputSpan::of(&self.window);
+            let respan =
+                output_span != self.span && self.command.as_ref().is_some_and(Command::spans);
+            self.span = output_span;
+
+            let buffer_resized = buffer_size != (self.width, self.height);
+            if buffer_resized {
+                (self.width, self.height) = buffer_size;
+                eprintln!(
+                    "surface: {physical_width}x{physical_height} physical, buffer \
+                     {width}x{height}",
+                    physical_width = size.width,
+                    physical_height = size.height,
+                    width = self.width,
+                    height = self.height,
+                );
+                pixels
+                    .resize_buffer(self.width, self.height)
+                    .map_err(|error| anyhow::anyhow!("could not resize the buffer: {error:#}"))?;
+                pixels.frame_mut().fill(0);
+                self.back = back_buffer(self.mirrored, self.width, self.height);
+            }
+            if buffer_resized || respan {
+                self.renderer = recreate_renderer(
+                    self.command.as_ref(),
+                    self.width,
+                    self.height,
+                    self.span,
+                    self.clock.clone(),
+                );
+                self.next_tick = Instant::now();
+            }
+            self.window.request_redraw();
+        }
+
+        if self.occluded || !active {
+            return Ok(None);
+        }
+
+        // Events wake the loop up between ticks too
+        let render_start = Instant::now();
+        if render_start < self.next_tick && mirrored.is_none() {
+            return Ok(Some(self.next_tick));
+        }
+        self.next_tick = render_start + tick;
+
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.restore(pixels.frame_mut(), self.width, self.height);
+        }
+        let changed = match mirrored {
+            Some(frame) if frame.len() != pixels.frame().len() => {
+                eprintln!(
+                    "warning: not drawing the mirrored frame of {len} bytes on the {width}x{height} \
+                     output",
+                    len = frame.len(),
+                    width = self.width,
+                    height = self.height
+                );
+                false
+            }
+            Some(frame) => {
+                pixels.frame_mut().copy_from_slice(frame);
+                true
+            }
+            None => {
+                // The front buffer holds the last frame, only what changed is copied over
+                let damage =
+                    self.renderer
+                        .render_damage(&mut self.back, self.width, self.height)?;
+                damage.copy(pixels.frame_mut(), &self.back, self.width);
+                damage != Damage::None
+            }
+        };
+        if let Some(overlay) = self.overlay.as_mut() {
+            overlay.record(render_start, render_start.elapsed(), changed);
+            overlay.draw(
+                pixels.frame_mut(),
+                self.width,
+                self.height,
+                self.renderer.load_time(),
+                self.renderer.buffered_frames(),
+                tick,
+            );
+        }
+        self.drawn |= changed;
+        if changed || self.overlay.is_some() || std::mem::take(&mut self.retry_present) {
+            self.window.request_redraw();
+        }
+
+        // The overlay measures every tick. Otherwise nothing changes before the renderer's next
+        // frame, or until a command or a resize wakes the loop up if it has none. A redraw
+        // requested above to retry a failed presentation wakes it as well.
+        if self.overlay.is_none() {
+            match self.renderer.next_frame_in() {
+                Some(due) => self.next_tick = render_start + tick.max(due),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(self.next_tick))
+    }
+
+    /// Advance the renderer on the next update even if its tick isn't due yet
+    pub fn tick_now(&mut self) {
+        self.next_tick = Instant::now();
+    }
+
+    /// Start waiting for the next frame the renderer draws to be presented, advancing the renderer
+    /// on the next update, returns the count to pass to [`OutputWindow::presented_since`]
+    ///
+    /// A drawn frame which wasn't presented yet is left out, it may still be one of the previous
+    /// renderer.
+    pub fn await_frame(&mut self) -> u64 {
+        self.drawn = false;
+        self.tick_now();
+        self.frames_presented
+    }
+
+    /// Whether a frame drawn after [`OutputWindow::await_frame`] returned `count` was presented
+    pub fn presented_since(&self, count: u64) -> bool {
+        self.frames_presented > count
+    }
+
+    /// The rgba frame shown in the window, only ever a completely drawn one
+    pub fn frame(&self) -> Option<&[u8]> {
+        self.pixels.as_ref().map(Presenter::frame)
+    }
+
+    /// A human readable description of the output and its renderer
+    pub fn status(&self) -> String {
+        format!(
+            "{renderer}\nbuffer: {width}x{height}\nsurface: {surface_width}x{surface_height}\n\
+             adapter: {adapter}\ndebug overlay: {overlay}",
+            renderer = self.renderer.status(),
+            width = self.width,
+            height = self.height,
+            surface_width = self.window.inner_size().width,
+            surface_height = self.window.inner_size().height,
+            adapter = self
+                .pixels
+                .as_ref()
+                .map_or("none".to_string(), Presenter::describe),
+            overlay = self.overlay.is_some(),
+        )
+    }
+}
+
+/// The buffer renderers of a `width` x `height` output draw into, mirrored outputs show the frame
+/// of the [`Mirror`] instead
+fn back_buffer(mirrored: bool, width: u32, height: u32) -> Vec<u8> {
+    if mirrored {
+        Vec::new()
+    } else {
+        vec![0; width as usize * height as usize * 4]
+    }
+}
+
+/// The background shown on all outputs in mirror mode, rendered once per tick at a fixed resolution
+pub struct Mirror {
+    pub renderer: BackgroundRenderer,
+    /// The command the current renderer was created from
+    pub command: Option<Command>,
+    /// The frame copied to all outputs
+    pub frame: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// When the renderer is advanced next
+    next_tick: Instant,
+}
+
+impl Mirror {
+    pub fn new(width: u32, height: u32) -> Self {
+        Mirror {
+            renderer: BackgroundRenderer::None,
+            command: None,
+            frame: vec![0; width as usize * height as usize * 4],
+            width,
+            height,
+            next_tick: Instant::now(),
+        }
+    }
+
+    /// Create the renderer of a background command for the mirrored frame
+    pub fn create_renderer(&self, command: Command) -> anyhow::Result<BackgroundRenderer> {
+        command.into_renderer(self.width, self.height, None)
+    }
+
+    /// Replace the renderer by a fresh one created from the current command, keeping it if that
+    /// fails
+    pub fn reload(&mut self) -> anyhow::Result<()> {
+        reload(
+            &mut self.renderer,
+            self.command.as_ref(),
+            self.width,
+            self.height,
+            None,
+            Arc::new(SystemClock),
+        )
+    }
+
+    /// Advance the renderer if its tick is due, at most every `tick` and when its next frame is
+    /// due, returns whether the frame changed and when the next tick is due unless `active` is
+    /// unset or the renderer has no next frame
+    pub fn update(
+        &mut self,
+        tick: Duration,
+        active: bool,
+    ) -> anyhow::Result<(bool, Option<Instant>)> {
+        if !active {
+            return Ok((false, None));
+        }
+        let now = Instant::now();
+        if now < self.next_tick {
+            return Ok((false, Some(self.next_tick)));
+        }
+        let changed = self
+            .renderer
+            .render(&mut self.frame, self.width, self.height)?;
+        let due = self.renderer.next_frame_in();
+        self.next_tick = now + tick.max(due.unwrap_or_default());
+        Ok((changed, due.map(|_| self.next_tick)))
+    }
+
+    /// Advance the renderer on the next update even if its tick isn't due yet
+    pub fn tick_now(&mut self) {
+        self.next_tick = Instant::now();
+    }
+
+    /// A human readable description of the mirrored background
+    pub fn status(&self) -> String {
+        format!(
+            "{renderer}\nbuffer: {width}x{height}",
+            renderer = self.renderer.status(),
+            width = self.width,
+            height = self.height,
+        )
+    }
+}
+
+/// Replace `renderer` by a fresh one created from `command`, keeping it if that fails
+fn reload(
+    renderer: &mut BackgroundRenderer,
+    command: Option<&Command>,
+    width: u32,
+    height: u32,
+    span: Option<OutputSpan>,
+    clock: Arc<dyn Clock>,
+) -> anyhow::Result<()> {
+    if let Some(command) = command {
+        *renderer = command
+            .clone()
+            .into_renderer_with_clock(width, height, span, clock)?;
+    }
+    Ok(())
+}
+
+/// Create the renderer of `command` again for a new desktop size or output layout
+fn recreate_renderer(
+    command: Option<&Command>,
+    width: u32,
+    height: u32,
+    span: Option<OutputSpan>,
+    clock: Arc<dyn Clock>,
+) -> BackgroundRenderer {
+    let Some(command) = command else {
+        return BackgroundRenderer::None;
+    };
+    command
+        .clone()
+        .into_renderer_with_clock(width, height, span, clock)
+        .unwrap_or_else(|error| {
+            eprintln!("could not recreate the background at {width}x{height}: {error:#}");
+            BackgroundRenderer::None
+        })
+}
@@ -0,0 +1,262 @@
+use std::{
+    io::{Cursor, Write},
+    time::{Duration, Instant},
+};
+
+use crate::render::blend_pixel;
+
+/// The width and height of a glyph in font pixels
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+/// How many frame pixels a font pixel covers in each direction
+const GLYPH_SCALE: usize = 2;
+/// The horizontal distance between two characters and the vertical one between two lines
+const ADVANCE: usize = (GLYPH_WIDTH + 1) * GLYPH_SCALE;
+const LINE_HEIGHT: usize = (GLYPH_HEIGHT + 2) * GLYPH_SCALE;
+/// The characters per line and lines of the panel
+const COLUMNS: usize = 15;
+const LINES: usize = 6;
+/// The space between the panel border and the text
+const PADDING: usize = 6;
+const PANEL_WIDTH: usize = 2 * PADDING + COLUMNS * ADVANCE - GLYPH_SCALE;
+const PANEL_HEIGHT: usize = 2 * PADDING + LINES * LINE_HEIGHT - 2 * GLYPH_SCALE;
+/// The distance of the panel to the top left desktop corner
+const MARGIN: usize = 8;
+const PANEL_COLOR: [u8; 4] = [0, 0, 0, 160];
+const TEXT_COLOR: [u8; 4] = [255, 255, 255, 255];
+/// How long frame changes are counted before the change rate is updated
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A 3x5 pixel glyph, each row holds the pixels from left to right in its lowest three bits
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// The glyphs of all printable ascii characters rasterized to coverage masks
+struct GlyphAtlas {
+    masks: [[bool; GLYPH_WIDTH * GLYPH_HEIGHT]; 128],
+}
+
+impl GlyphAtlas {
+    fn new() -> Self {
+        let mut masks = [[false; GLYPH_WIDTH * GLYPH_HEIGHT]; 128];
+        for (code, mask) in masks.iter_mut().enumerate() {
+            for (y, row) in glyph(code as u8 as char).into_iter().enumerate() {
+                for x in 0..GLYPH_WIDTH {
+                    mask[y * GLYPH_WIDTH + x] = row & (0b100 >> x) != 0;
+                }
+            }
+        }
+        GlyphAtlas { masks }
+    }
+
+    fn mask(&self, byte: u8) -> &[bool; GLYPH_WIDTH * GLYPH_HEIGHT] {
+        &self.masks[byte as usize & 127]
+    }
+}
+
+/// A panel in the top left corner showing timing statistics of the daemon
+///
+/// Drawn into the frame after the renderer each tick, the covered pixels are kept aside and put
+/// back before the next render so renderers which only draw on changes are not disturbed.
+pub struct DebugOverlay {
+    atlas: GlyphAtlas,
+    /// The frame pixels below the panel, row by row
+    covered: Vec<u8>,
+    /// The frame size `covered` was taken from, none if nothing is covered
+    covered_size: Option<(u32, u32)>,
+    last_tick: Option<Instant>,
+    tick_interval: Duration,
+    render_time: Duration,
+    window_start: Instant,
+    window_changes: u32,
+    /// Frame changes per second over the last completed window
+    change_rate: f32,
+}
+
+impl DebugOverlay {
+    pub fn new() -> Self {
+        DebugOverlay {
+            atlas: GlyphAtlas::new(),
+            covered: vec![0; PANEL_WIDTH * PANEL_HEIGHT * 4],
+            covered_size: None,
+            last_tick: None,
+            tick_interval: Duration::ZERO,
+            render_time: Duration::ZERO,
+            window_start: Instant::now(),
+            window_changes: 0,
+            change_rate: 0.0,
+        }
+    }
+
+    /// Put back the frame pixels covered by the panel
+    pub fn restore(&mut self, frame: &mut [u8], width: u32, height: u32) {
+        if self.covered_size.take() != Some((width, height)) {
+            return;
+        }
+        let (panel_width, panel_height) = panel_size(width, height);
+        for y in 0..panel_height {
+            let frame_row = ((MARGIN + y) * width as usize + MARGIN) * 4;
+            let covered_row = y * PANEL_WIDTH * 4;
+            frame[frame_row..frame_row + panel_width * 4]
+                .copy_from_slice(&self.covered[covered_row..covered_row + panel_width * 4]);
+        }
+    }
+
+    /// Record a render which started at `started` and took `render_time`
+    pub fn record(&mut self, started: Instant, render_time: Duration, changed: bool) {
+        if let Some(last_tick) = self.last_tick.replace(started) {
+            self.tick_interval = started.duration_since(last_tick);
+        }
+        self.render_time = render_time;
+
+        self.window_changes += changed as u32;
+        let elapsed = started.duration_since(self.window_start);
+        if elapsed >= RATE_WINDOW {
+            self.change_rate = self.window_changes as f32 / elapsed.as_secs_f32();
+            self.window_changes = 0;
+            self.window_start = started;
+        }
+    }
+
+    /// Draw the panel into the rgba `frame`, keeping the pixels below it for [`Self::restore`]
+    pub fn draw(
+        &mut self,
+        frame: &mut [u8],
+        width: u32,
+        height: u32,
+        load_time: Option<Duration>,
+        buffered_frames: Option<usize>,
+        tick: Duration,
+    ) {
+        let (panel_width, panel_height) = panel_size(width, height);
+        for y in 0..panel_height {
+            let frame_row = ((MARGIN + y) * width as usize + MARGIN) * 4;
+            let covered_row = y * PANEL_WIDTH * 4;
+            self.covered[covered_row..covered_row + panel_width * 4]
+                .copy_from_slice(&frame[frame_row..frame_row + panel_width * 4]);
+            frame[frame_row..frame_row + panel_width * 4]
+                .chunks_exact_mut(4)
+                .for_each(|pixel| blend_pixel(pixel, &PANEL_COLOR));
+        }
+        self.covered_size = Some((width, height));
+
+        let mut line = [b' '; COLUMNS];
+        for idx in 0..LINES {
+            line.fill(b' ');
+            let mut cursor = Cursor::new(&mut line[..]);
+            // Overlong values are cut off at the panel border
+            let _ = match idx {
+                0 => write!(cursor, "TICK {:>8.1}MS", millis(self.tick_interval)),
+                1 => write!(cursor, "RENDER {:>6.1}MS", millis(self.render_time)),
+                2 => match load_time {
+                    Some(load_time) => write!(cursor, "LOAD {:>8.1}MS", millis(load_time)),
+                    None => write!(cursor, "LOAD {:>10}", "-"),
+                },
+                3 => match buffered_frames {
+                    Some(buffered) => write!(cursor, "BUFFERED {buffered:>6}"),
+                    None => write!(cursor, "BUFFERED {:>6}", "-"),
+                },
+                4 => write!(cursor, "CHANGES {:>5.1}/S", self.change_rate),
+                // The measured rate next to the configured one
+                _ => write!(
+                    cursor,
+                    "FPS {:>5.1}/{:<5.1}",
+                    fps(self.tick_interval),
+                    fps(tick)
+                ),
+            };
+            self.draw_line(frame, width, (panel_width, panel_height), idx, &line);
+        }
+    }
+
+    /// Draw the `idx`th line of text, clipped to the visible part of the panel
+    fn draw_line(
+        &self,
+        frame: &mut [u8],
+        width: u32,
+        (panel_width, panel_height): (usize, usize),
+        idx: usize,
+        line: &[u8],
+    ) {
+        let top = PADDING + idx * LINE_HEIGHT;
+        for (column, byte) in line.iter().enumerate() {
+            let left = PADDING + column * ADVANCE;
+            let mask = self.atlas.mask(*byte);
+            for y in 0..GLYPH_HEIGHT * GLYPH_SCALE {
+                for x in 0..GLYPH_WIDTH * GLYPH_SCALE {
+                    let (panel_x, panel_y) = (left + x, top + y);
+                    if panel_x >= panel_width || panel_y >= panel_height {
+                        continue;
+                    }
+                    if mask[y / GLYPH_SCALE * GLYPH_WIDTH + x / GLYPH_SCALE] {
+                        let pixel = ((MARGIN + panel_y) * width as usize + MARGIN + panel_x) * 4;
+                        frame[pixel..pixel + 4].copy_from_slice(&TEXT_COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The part of the panel which fits into a `width` x `height` frame
+fn panel_size(width: u32, height: u32) -> (usize, usize) {
+    (
+        (width as usize).saturating_sub(MARGIN).min(PANEL_WIDTH),
+        (height as usize).saturating_sub(MARGIN).min(PANEL_HEIGHT),
+    )
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// How many times per second something taking `interval` happens, 0 before it happened twice
+fn fps(interval: Duration) -> f64 {
+    if interval.is_zero() {
+        return 0.0;
+    }
+    1.0 / interval.as_secs_f64()
+}
@@ -0,0 +1,149 @@
+use crate::json::Value;
+
+/// How many pixels of a frame are used to find its colors at most
+const MAX_SAMPLES: usize = 128 * 128;
+/// How often the colors are refined at most
+const MAX_ITERATIONS: usize = 20;
+
+/// The colors of every few pixels of an rgba `frame`, a downscaled copy of it
+pub fn sample(frame: &[u8], width: u32, height: u32) -> Vec<[u8; 3]> {
+    let pixels = width as usize * height as usize;
+    let stride = (pixels as f64 / MAX_SAMPLES as f64).sqrt().ceil().max(1.0) as usize;
+    (0..height as usize)
+        .step_by(stride)
+        .flat_map(|y| {
+            (0..width as usize)
+                .step_by(stride)
+                .map(move |x| (y * width as usize + x) * 4)
+        })
+        .filter_map(|offset| Some([*frame.get(offset)?, frame[offset + 1], frame[offset + 2]]))
+        .collect()
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3)
+        .map(|channel| (a[channel] - b[channel]).powi(2))
+        .sum()
+}
+
+/// The `count` dominant colors of `samples` found by k-means, ordered by how many samples they
+/// stand for, fewer if the samples have fewer distinct colors
+pub fn extract(samples: &[[u8; 3]], count: usize) -> Vec<[u8; 3]> {
+    let samples: Vec<[f32; 3]> = samples
+        .iter()
+        .map(|color| color.map(|channel| channel as f32))
+        .collect();
+    if samples.is_empty() || count == 0 {
+        return Vec::new();
+    }
+
+    // Seeded so the same frame always gives the same palette, k-means++ spreads the start
+    let mut rng = fastrand::Rng::with_seed(0);
+    let mut centers = vec![samples[rng.usize(..samples.len())]];
+    while centers.len() < count {
+        let distances: Vec<f32> = samples
+            .iter()
+            .map(|sample| {
+                centers
+                    .iter()
+                    .map(|center| distance(*sample, *center))
+                    .fold(f32::MAX, f32::min)
+            })
+            .collect();
+        let total: f32 = distances.iter().sum();
+        if total == 0.0 {
+            break;
+        }
+        let mut target = rng.f32() * total;
+        let next = distances
+            .iter()
+            .position(|distance| {
+                target -= distance;
+                target <= 0.0
+            })
+            .unwrap_or(samples.len() - 1);
+        centers.push(samples[next]);
+    }
+
+    let mut sizes = vec![0; centers.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let mut sums = vec![[0.0f64; 3]; centers.len()];
+        sizes.fill(0);
+        for sample in &samples {
+            let nearest = (0..centers.len())
+                .min_by(|a, b| {
+                    distance(*sample, centers[*a]).total_cmp(&distance(*sample, centers[*b]))
+                })
+                .unwrap();
+            sizes[nearest] += 1;
+            for channel in 0..3 {
+                sums[nearest][channel] += sample[channel] as f64;
+            }
+        }
+
+        let mut moved = false;
+        for (center, (sum, size)) in centers.iter_mut().zip(sums.iter().zip(&sizes)) {
+            if *size == 0 {
+                continue;
+            }
+            let mean = sum.map(|channel| (channel / *size as f64) as f32);
+            moved |= distance(*center, mean) > 0.25;
+            *center = mean;
+        }
+        if !moved {
+            break;
+        }
+    }
+
+    let mut colors: Vec<(usize, [u8; 3])> = sizes
+        .into_iter()
+        .zip(centers)
+        .filter(|(size, _)| *size > 0)
+        .map(|(size, center)| (size, center.map(|channel| channel.round() as u8)))
+        .collect();
+    colors.sort_by_key(|(size, _)| std::cmp::Reverse(*size));
+    colors.into_iter().map(|(_, color)| color).collect()
+}
+
+/// The colors as `#rrggbb` hex codes, one per line or as a json array
+pub fn describe(colors: &[[u8; 3]], json: bool) -> String {
+    let hex = colors
+        .iter()
+        .map(|[r, g, b]| format!("#{r:02x}{g:02x}{b:02x}"));
+    if !json {
+        return hex.collect::<Vec<_>>().join("\n");
+    }
+    let mut out = String::new();
+    Value::Array(hex.map(Value::String).collect()).write(&mut out, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_colors_by_coverage() {
+        // Three quarters red, one quarter blue
+        let frame: Vec<u8> = (0..64 * 64)
+            .flat_map(|idx| {
+                if idx % 64 < 48 {
+                    [200, 10, 10, 255]
+                } else {
+                    [10, 10, 200, 255]
+                }
+            })
+            .collect();
+        let colors = extract(&sample(&frame, 64, 64), 4);
+        assert_eq!(colors, [[200, 10, 10], [10, 10, 200]]);
+        assert_eq!(describe(&colors, false), "#c80a0a\n#0a0ac8");
+        assert!(describe(&colors, true).contains("\"#0a0ac8\""));
+    }
+
+    #[test]
+    fn samples_large_frames_sparsely() {
+        let frame = vec![0; 3840 * 2160 * 4];
+        let samples = sample(&frame, 3840, 2160);
+        assert!(samples.len() <= MAX_SAMPLES && samples.len() > MAX_SAMPLES / 2);
+    }
+}
@@ -0,0 +1,326 @@
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read},
+    process::{Child, ChildStderr, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use image::RgbaImage;
+
+use crate::{render::blit, signals};
+
+/// How long to wait before restarting a command which exited, doubled after every quick exit
+const MIN_RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+/// How long a command has to run to count as started successfully, resetting the restart delay
+const STABLE_RUN_TIME: Duration = Duration::from_secs(10);
+
+/// State shared between the renderer and the thread reading the frames
+struct Shared {
+    /// The latest complete frame which wasn't shown yet
+    latest: Mutex<Option<Vec<u8>>>,
+    /// The running command, killed when the pipe is dropped
+    child: Mutex<Option<Child>>,
+    stop: AtomicBool,
+    received: AtomicU64,
+    /// Frames replaced by a newer one before they were shown
+    dropped: AtomicU64,
+    restarts: AtomicU64,
+}
+
+/// Reads raw rgba frames from the standard output of a command on a background thread
+///
+/// The command is run by `sh -c` with the frame size in the `WIDTH` and `HEIGHT` environment
+/// variables and writes `WIDTH * HEIGHT * 4` bytes per frame. Frames coming faster than they are
+/// shown are dropped, an incomplete frame at the end of the output is ignored and the last
+/// complete frame stays on screen after the command exits. Its standard error is logged.
+pub struct FramePipe {
+    command: String,
+    shared: Arc<Shared>,
+    /// The minimum time between two shown frames
+    frame_interval: Duration,
+    last_shown: Option<Instant>,
+    /// The size of the frames the command produces
+    width: u32,
+    height: u32,
+    /// The frame shown last, copied again on a redraw
+    frame: Option<RgbaImage>,
+    redraw: bool,
+}
+
+impl FramePipe {
+    /// Start `command` producing `width` x `height` frames, shown at most `fps` times per second
+    ///
+    /// With `restart` the command is started again whenever it exits.
+    pub fn spawn(
+        command: String,
+        fps: u32,
+        restart: bool,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let shared = Arc::new(Shared {
+            latest: Mutex::new(None),
+            child: Mutex::new(None),
+            stop: AtomicBool::new(false),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            restarts: AtomicU64::new(0),
+        });
+        // The first start reports its error to the client, later ones are only logged
+        let stdout = start(&command, width, height, &shared)?;
+
+        let frame_size = width as usize * height as usize * 4;
+        let thread_shared = shared.clone();
+        let thread_command = command.clone();
+        thread::spawn(move || {
+            let shared = thread_shared;
+            let command = thread_command;
+            let mut stdout = Some(stdout);
+            let mut delay = MIN_RESTART_DELAY;
+            loop {
+                if let Some(stdout) = stdout.take() {
+                    let started = Instant::now();
+                    read_frames(stdout, frame_size, &shared);
+                    if started.elapsed() >= STABLE_RUN_TIME {
+                        delay = MIN_RESTART_DELAY;
+                    }
+                }
+                if shared.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let status = shared
+                    .child
+                    .lock()
+                    .unwrap()
+                    .take()
+                    .map(|mut child| child.wait());
+                let status = match status {
+                    Some(Ok(status)) => status.to_string(),
+                    Some(Err(error)) => error.to_string(),
+                    None => "not started".to_string(),
+                };
+                if !restart {
+                    eprintln!("pipe command {command:?} ended ({status}), keeping its last frame");
+                    break;
+                }
+                eprintln!("pipe command {command:?} ended ({status}), restarting it in {delay:?}");
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+
+                if shared.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                shared.restarts.fetch_add(1, Ordering::Relaxed);
+                match start(&command, width, height, &shared) {
+                    Ok(restarted) => stdout = Some(restarted),
+                    Err(error) => eprintln!("warning: {error:#}"),
+                }
+            }
+        });
+
+        Ok(FramePipe {
+            command,
+            shared,
+            frame_interval: Duration::from_secs(1) / fps.max(1),
+            last_shown: None,
+            width,
+            height,
+            frame: None,
+            redraw: false,
+        })
+    }
+
+    /// Copy the latest frame into the `width` x `height` `frame` if one arrived and the frame rate
+    /// allows showing it, returns whether the frame changed
+    ///
+    /// A frame of another size than the command produces is drawn centered by [`blit`].
+    pub fn poll(&mut self, frame: &mut [u8], width: u32, height: u32) -> bool {
+        let now = Instant::now();
+        let due = self
+            .last_shown
+            .is_none_or(|shown| now.duration_since(shown) >= self.frame_interval);
+        if due {
+            if let Some(latest) = self.shared.latest.lock().unwrap().take() {
+                // The frames are read in chunks of exactly this size
+                self.frame = RgbaImage::from_raw(self.width, self.height, latest);
+                self.last_shown = Some(now);
+                self.redraw = true;
+            }
+        }
+
+        match &self.frame {
+            Some(shown) if self.redraw => {
+                self.redraw = false;
+                blit(frame, width, height, shown, "the frame of the pipe")
+            }
+            _ => false,
+        }
+    }
+
+    /// Copy the shown frame again on the next poll
+    pub fn redraw(&mut self) {
+        self.redraw = true;
+    }
+
+    /// A human readable description of the pipe
+    pub fn status(&self) -> String {
+        format!(
+            "command: {command:?}\n\
+             frame rate: {fps}\n\
+             frames received: {received}\n\
+             frames dropped: {dropped}\n\
+             restarts: {restarts}",
+            command = self.command,
+            fps = (1.0 / self.frame_interval.as_secs_f64()).round(),
+            received = self.shared.received.load(Ordering::Relaxed),
+            dropped = self.shared.dropped.load(Ordering::Relaxed),
+            restarts = self.shared.restarts.load(Ordering::Relaxed),
+        )
+    }
+}
+
+impl Drop for FramePipe {
+    fn drop(&mut self) {
+        self.shared.stop.store(true, Ordering::Relaxed);
+        if let Some(mut child) = self.shared.child.lock().unwrap().take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Start `command` and log its standard error, returns its standard output
+fn start(
+    command: &str,
+    width: u32,
+    height: u32,
+    shared: &Shared,
+) -> anyhow::Result<impl Read + Send + 'static> {
+    let mut child_slot = shared.child.lock().unwrap();
+    if shared.stop.load(Ordering::Relaxed) {
+        anyhow::bail!("the pipe was stopped");
+    }
+
+    let mut child = signals::unblocked(
+        Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("WIDTH", width.to_string())
+            .env("HEIGHT", height.to_string())
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )
+    .spawn()
+    .with_context(|| format!("could not run the pipe command {command:?}"))?;
+    let stdout = child.stdout.take().unwrap();
+    if let Some(stderr) = child.stderr.take() {
+        forward_stderr(command.to_string(), stderr);
+    }
+    *child_slot = Some(child);
+
+    Ok(stdout)
+}
+
+/// Log the lines the command writes to its standard error, until it closes it
+fn forward_stderr(command: String, stderr: ChildStderr) {
+    thread::spawn(move || {
+        for line in BufReader::new(stderr).lines() {
+            match line {
+                Ok(line) => eprintln!("pipe command {command:?}: {line}"),
+                Err(error) if error.kind() == ErrorKind::InvalidData => continue,
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+/// Read frames of `frame_size` bytes until the output ends, keeping only the latest one
+fn read_frames(mut stdout: impl Read, frame_size: usize, shared: &Shared) {
+    loop {
+        let mut frame = vec![0; frame_size];
+        match stdout.read_exact(&mut frame) {
+            Ok(()) => {
+                shared.received.fetch_add(1, Ordering::Relaxed);
+                if shared.latest.lock().unwrap().replace(frame).is_some() {
+                    shared.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            // Includes a frame cut short by the end of the output, which is never shown
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => {
+                eprintln!("warning: could not read from the pipe command: {error}");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 4;
+    const HEIGHT: u32 = 2;
+    const FRAME_SIZE: usize = WIDTH as usize * HEIGHT as usize * 4;
+
+    /// Poll `pipe` until it shows a frame or the command surely wrote everything
+    fn wait_for_frame(pipe: &mut FramePipe, frame: &mut [u8]) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if pipe.poll(frame, WIDTH, HEIGHT) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn shows_complete_frames_and_ignores_a_cut_off_one() {
+        // One frame of 0xff bytes followed by half a frame of zeros
+        let command = "head -c $((WIDTH * HEIGHT * 4)) /dev/zero | tr '\\0' '\\377'; \
+                       head -c $((WIDTH * HEIGHT * 2)) /dev/zero"
+            .to_string();
+        let mut pipe = FramePipe::spawn(command, 1000, false, WIDTH, HEIGHT).unwrap();
+        let mut frame = vec![0; FRAME_SIZE];
+
+        assert!(wait_for_frame(&mut pipe, &mut frame));
+        assert!(frame.iter().all(|byte| *byte == 0xff));
+        thread::sleep(Duration::from_millis(200));
+        assert!(!pipe.poll(&mut frame, WIDTH, HEIGHT));
+        assert_eq!(pipe.shared.received.load(Ordering::Relaxed), 1);
+
+        pipe.redraw();
+        frame.fill(0);
+        assert!(pipe.poll(&mut frame, WIDTH, HEIGHT));
+        assert!(frame.iter().all(|byte| *byte == 0xff));
+
+        // A frame of another size, which the output was resized to, gets the frame centered
+        pipe.redraw();
+        let mut wider = vec![0; FRAME_SIZE * 2];
+        assert!(pipe.poll(&mut wider, WIDTH * 2, HEIGHT));
+        assert_eq!(wider[..8], [0, 0, 0, 255, 0, 0, 0, 255]);
+        assert!(wider[8..24].iter().all(|byte| *byte == 0xff));
+    }
+
+    #[test]
+    fn drops_frames_which_come_faster_than_shown() {
+        let command = "head -c $((WIDTH * HEIGHT * 4 * 10)) /dev/zero".to_string();
+        let mut pipe = FramePipe::spawn(command, 1, false, WIDTH, HEIGHT).unwrap();
+        let mut frame = vec![1; FRAME_SIZE];
+
+        thread::sleep(Duration::from_millis(200));
+        assert!(wait_for_frame(&mut pipe, &mut frame));
+        assert_eq!(pipe.shared.received.load(Ordering::Relaxed), 10);
+        assert_eq!(pipe.shared.dropped.load(Ordering::Relaxed), 9);
+    }
+}
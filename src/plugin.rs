@@ -0,0 +1,218 @@
+//! Renderer plugins, shared libraries drawing a background through a small C interface
+//!
+//! A plugin exports these symbols:
+//!
+//! ```c
+//! // Has to equal ABI_VERSION, plugins built for another version are refused
+//! const uint32_t desktop_background_abi_version;
+//!
+//! // Create the state of a plugin drawing a width x height background, params is the
+//! // NUL-terminated string given to the plugin command. Returns NULL if the plugin can't start.
+//! void *desktop_background_init(uint32_t width, uint32_t height, const char *params);
+//!
+//! // Render into the width x height rgba frame of width * height * 4 bytes, which holds the
+//! // previously rendered frame. time_millis is the time in milliseconds since the Unix epoch.
+//! // Returns 1 if the frame changed, 0 if it didn't and a negative number on failure.
+//! int32_t desktop_background_render(void *state, uint8_t *frame, uint32_t width,
+//!                                   uint32_t height, int64_t time_millis);
+//!
+//! // Free the state returned by init
+//! void desktop_background_destroy(void *state);
+//! ```
+//!
+//! All functions are called from the thread rendering the background. A plugin which crashes
+//! takes the daemon with it, so only load plugins you trust like any other program.
+
+use std::{
+    ffi::{c_char, c_void, CString},
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+use anyhow::{bail, Context};
+use libloading::Library;
+
+/// The version of the plugin interface implemented by this daemon
+pub const ABI_VERSION: u32 = 1;
+
+/// The NUL-terminated name of the ABI version a plugin exports
+pub const ABI_VERSION_SYMBOL: &[u8] = b"desktop_background_abi_version\0";
+/// The NUL-terminated name of the [`InitFn`] a plugin exports
+pub const INIT_SYMBOL: &[u8] = b"desktop_background_init\0";
+/// The NUL-terminated name of the [`RenderFn`] a plugin exports
+pub const RENDER_SYMBOL: &[u8] = b"desktop_background_render\0";
+/// The NUL-terminated name of the [`DestroyFn`] a plugin exports
+pub const DESTROY_SYMBOL: &[u8] = b"desktop_background_destroy\0";
+
+/// The type of `desktop_background_init`
+pub type InitFn =
+    unsafe extern "C" fn(width: u32, height: u32, params: *const c_char) -> *mut c_void;
+/// The type of `desktop_background_render`
+pub type RenderFn = unsafe extern "C" fn(
+    state: *mut c_void,
+    frame: *mut u8,
+    width: u32,
+    height: u32,
+    time_millis: i64,
+) -> i32;
+/// The type of `desktop_background_destroy`
+pub type DestroyFn = unsafe extern "C" fn(state: *mut c_void);
+
+/// A loaded plugin together with the state it renders with
+pub struct Plugin {
+    path: PathBuf,
+    params: String,
+    state: NonNull<c_void>,
+    render: RenderFn,
+    destroy: DestroyFn,
+    /// The frame the plugin renders into, kept between renders
+    frame: Vec<u8>,
+    /// Whether the frame has to be copied even if the plugin didn't change it
+    redraw: bool,
+    /// Why the plugin was disabled after failing to render
+    failure: Option<String>,
+    /// Unloaded last, after the state was destroyed
+    _library: Library,
+}
+
+impl Plugin {
+    /// Load the plugin at `path` and start it for a `width` x `height` background
+    pub fn load(path: &Path, params: &str, width: u32, height: u32) -> anyhow::Result<Self> {
+        let c_params =
+            CString::new(params).context("the plugin parameters can't contain NUL bytes")?;
+
+        // SAFETY: loading a library runs its initializers, plugins are trusted like programs
+        let library = unsafe { Library::new(path) }
+            .with_context(|| format!("could not load the plugin {path}", path = path.display()))?;
+        let symbol = |name: &[u8]| {
+            format!(
+                "{path} is not a plugin, the symbol {symbol} is missing",
+                path = path.display(),
+                symbol = String::from_utf8_lossy(&name[..name.len() - 1]),
+            )
+        };
+
+        // SAFETY: the symbols have the types the plugin interface defines
+        let (init, render, destroy) = unsafe {
+            let version = library
+                .get::<*const u32>(ABI_VERSION_SYMBOL)
+                .with_context(|| symbol(ABI_VERSION_SYMBOL))?;
+            let version = **version;
+            if version != ABI_VERSION {
+                bail!(
+                    "the plugin {path} implements version {version} of the plugin interface, \
+                     expected version {ABI_VERSION}",
+                    path = path.display()
+                );
+            }
+            (
+                *library
+                    .get::<InitFn>(INIT_SYMBOL)
+                    .with_context(|| symbol(INIT_SYMBOL))?,
+                *library
+                    .get::<RenderFn>(RENDER_SYMBOL)
+                    .with_context(|| symbol(RENDER_SYMBOL))?,
+                *library
+                    .get::<DestroyFn>(DESTROY_SYMBOL)
+                    .with_context(|| symbol(DESTROY_SYMBOL))?,
+            )
+        };
+
+        // SAFETY: params is a valid string for the duration of the call
+        let state =
+            NonNull::new(unsafe { init(width, height, c_params.as_ptr()) }).with_context(|| {
+                format!(
+                    "the plugin {path} failed to start with the parameters {params:?}",
+                    path = path.display()
+                )
+            })?;
+
+        Ok(Plugin {
+            path: path.to_path_buf(),
+            params: params.to_string(),
+            state,
+            render,
+            destroy,
+            frame: vec![0; width as usize * height as usize * 4],
+            redraw: true,
+            failure: None,
+            _library: library,
+        })
+    }
+
+    /// Let the plugin render for `time_millis` since the Unix epoch and copy its frame into
+    /// `frame` if it changed, returns whether it did
+    ///
+    /// A plugin failing to render is disabled and its last frame kept rather than stopping the
+    /// daemon.
+    pub fn render(&mut self, frame: &mut [u8], width: u32, height: u32, time_millis: i64) -> bool {
+        if self.failure.is_some() {
+            return false;
+        }
+        if self.frame.len() != frame.len() {
+            self.fail(format!(
+                "the plugin was started for another size than {width}x{height}"
+            ));
+            return false;
+        }
+
+        // SAFETY: the frame holds width * height rgba pixels and the state is alive until drop
+        let result = unsafe {
+            (self.render)(
+                self.state.as_ptr(),
+                self.frame.as_mut_ptr(),
+                width,
+                height,
+                time_millis,
+            )
+        };
+        if result < 0 {
+            self.fail(format!("rendering failed with {result}"));
+            return false;
+        }
+
+        if result > 0 || self.redraw {
+            frame.copy_from_slice(&self.frame);
+            self.redraw = false;
+            return true;
+        }
+        false
+    }
+
+    fn fail(&mut self, reason: String) {
+        eprintln!(
+            "warning: disabling the plugin {path}: {reason}",
+            path = self.path.display()
+        );
+        self.failure = Some(reason);
+    }
+
+    /// Copy the frame on the next render, even if the plugin didn't change it
+    pub fn redraw(&mut self) {
+        self.redraw = true;
+    }
+
+    /// A human readable description of the plugin
+    pub fn status(&self) -> String {
+        format!(
+            "plugin: {path}\n\
+             params: {params:?}\n\
+             state: {state}",
+            path = self.path.display(),
+            params = self.params,
+            state = self
+                .failure
+                .as_ref()
+                .map_or("running".to_string(), |failure| format!(
+                    "disabled, {failure}"
+                )),
+        )
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        // SAFETY: the state came from init and is destroyed only once
+        unsafe { (self.destroy)(self.state.as_ptr()) }
+    }
+}
@@ -0,0 +1,55 @@
+/// How many buffers the pool keeps, one for decoding and one for placing a frame in the common
+/// case of one load per evicted frame
+const MAX_POOLED: usize = 2;
+
+/// Buffers of frames which were shown, handed out again to decode and place the next ones instead
+/// of allocating new ones
+///
+/// Only buffers of exactly the requested length are handed out, and the oldest buffer is dropped
+/// once the pool is full, so buffers of a previous resolution don't outlive a few loads.
+#[derive(Default)]
+pub struct BufferPool {
+    buffers: Vec<Vec<u8>>,
+}
+
+impl BufferPool {
+    /// A buffer of `len` bytes with unspecified contents
+    pub fn take(&mut self, len: usize) -> Vec<u8> {
+        match self.buffers.iter().position(|buffer| buffer.len() == len) {
+            Some(idx) => self.buffers.remove(idx),
+            None => vec![0; len],
+        }
+    }
+
+    /// Keep `buffer` for a later [`BufferPool::take`]
+    pub fn give(&mut self, buffer: Vec<u8>) {
+        if self.buffers.len() == MAX_POOLED {
+            self.buffers.remove(0);
+        }
+        self.buffers.push(buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hands_out_buffers_of_the_requested_length() {
+        let mut pool = BufferPool::default();
+        let buffer = vec![7; 16];
+        let address = buffer.as_ptr();
+        pool.give(buffer);
+        assert_eq!(pool.take(8), vec![0; 8]);
+        let taken = pool.take(16);
+        assert_eq!(taken.as_ptr(), address);
+        assert_eq!(taken, vec![7; 16]);
+
+        // Buffers of an old resolution are dropped as new ones come in
+        pool.give(vec![0; 16]);
+        pool.give(vec![0; 32]);
+        pool.give(vec![0; 32]);
+        assert_eq!(pool.buffers.len(), MAX_POOLED);
+        assert!(pool.buffers.iter().all(|buffer| buffer.len() == 32));
+    }
+}
@@ -0,0 +1,396 @@
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration as StdDuration, Instant},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, Timelike};
+use image::RgbaImage;
+use winit::{
+    event::{ElementState, Event, KeyEvent, WindowEvent},
+    event_loop::ControlFlow,
+    keyboard::{Key, NamedKey},
+};
+
+use crate::{
+    args::ResolutionArg,
+    clock::{Clock, MockClock},
+    command::{
+        format_time_of_day, parse_time_of_day, Command, PreviewArgs, RenderArgs, RenderAtArgs,
+    },
+    daemon::build_event_loop,
+    gpu::GpuOptions,
+    output::{open_preview_window, OutputWindow},
+};
+
+/// The shortest time between two renders of the preview window
+const PREVIEW_TICK: StdDuration = StdDuration::from_millis(16);
+
+/// Render the background of `args` at the requested times into PNG files, without a window
+///
+/// The renderer is created like the daemon creates it, reading the time from a clock which is
+/// advanced by the step between frames. Returns the paths of the written files.
+pub fn render_frames(args: RenderArgs) -> anyhow::Result<Vec<PathBuf>> {
+    let RenderArgs {
+        width,
+        height,
+        background,
+        out,
+        at,
+        count,
+        step,
+    } = args;
+    if !background.is_background() {
+        anyhow::bail!("only background commands can be rendered to files");
+    }
+
+    let start = match at {
+        Some(millis) => time_on(Local::now().date_naive(), millis)?,
+        None => Local::now(),
+    };
+    let clock = Arc::new(MockClock::new(start));
+    let mut renderer = background.into_renderer_with_clock(width, height, None, clock.clone())?;
+
+    std::fs::create_dir_all(&out)
+        .with_context(|| format!("could not create {out}", out = out.display()))?;
+    let mut frame = vec![0; width as usize * height as usize * 4];
+    let mut paths = Vec::new();
+    for idx in 0..count {
+        let now = clock.now();
+        renderer.render(&mut frame, width, height)?;
+
+        let path = out.join(format!(
+            "frame_{idx:04}_{hour:02}{minute:02}{second:02}_{millis:03}.png",
+            hour = now.hour(),
+            minute = now.minute(),
+            second = now.second(),
+            millis = now.timestamp_subsec_millis(),
+        ));
+        RgbaImage::from_raw(width, height, frame.clone())
+            .unwrap()
+            .save(&path)
+            .with_context(|| format!("could not write {path}", path = path.display()))?;
+        paths.push(path);
+
+        clock.advance(Duration::milliseconds(step.0 as i64));
+    }
+
+    Ok(paths)
+}
+
+/// Render the frame the background of `args` shows at a time of day today into a PNG file,
+/// without a window, returns the path of the file
+pub fn render_at(args: RenderAtArgs) -> anyhow::Result<PathBuf> {
+    let RenderAtArgs {
+        time,
+        background,
+        out,
+        resolution: ResolutionArg { width, height },
+    } = args;
+    if !background.is_background() {
+        anyhow::bail!("only background commands can be rendered to files");
+    }
+    background.validate()?;
+
+    let summary = background.summary();
+    let frame = render_frame(
+        *background,
+        width,
+        height,
+        time_on(Local::now().date_naive(), time)?,
+        &[],
+    )
+    .with_context(|| format!("could not render {summary}"))?;
+    frame
+        .save(&out)
+        .with_context(|| format!("could not write {out}", out = out.display()))?;
+    Ok(out)
+}
+
+/// Render `background` like the daemon does at `at`, then once more after each of `advances`,
+/// returns the last frame
+pub fn render_frame(
+    background: Command,
+    width: u32,
+    height: u32,
+    at: DateTime<Local>,
+    advances: &[Duration],
+) -> anyhow::Result<RgbaImage> {
+    let clock = Arc::new(MockClock::new(at));
+    let mut renderer = background.into_renderer_with_clock(width, height, None, clock.clone())?;
+    let mut frame = vec![0; width as usize * height as usize * 4];
+    renderer.render(&mut frame, width, height)?;
+    for advance in advances {
+        clock.advance(*advance);
+        renderer.render(&mut frame, width, height)?;
+    }
+    Ok(RgbaImage::from_raw(width, height, frame).unwrap())
+}
+
+/// The local time `millis` after midnight on `date`
+fn time_on(date: NaiveDate, millis: u32) -> anyhow::Result<DateTime<Local>> {
+    let time =
+        NaiveTime::from_num_seconds_from_midnight_opt(millis / 1000, millis % 1000 * 1_000_000)
+            .unwrap();
+    date.and_time(time)
+        .and_local_timezone(Local)
+        .earliest()
+        .with_context(|| format!("{time} does not exist on {date} in the local timezone"))
+}
+
+/// How the time passes in the preview window, changed with the keyboard
+struct PreviewTime {
+    clock: Arc<MockClock>,
+    speed: f64,
+    paused: bool,
+    /// The time of day typed so far to jump to
+    typed: String,
+    last_advance: Instant,
+}
+
+impl PreviewTime {
+    /// Advance the clock by the real time passed since the last advance times the speed
+    fn advance(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - std::mem::replace(&mut self.last_advance, now);
+        if !self.paused {
+            self.clock.advance(
+                Duration::from_std(elapsed.mul_f64(self.speed)).unwrap_or(Duration::max_value()),
+            );
+        }
+    }
+
+    /// Handle a pressed key, returns whether the clock jumped
+    ///
+    /// `+` and `-` double and halve the speed, space pauses, `n` jumps to now and a time of day
+    /// like `17:42` typed and confirmed with enter jumps to that time.
+    fn key(&mut self, key: Key<&str>) -> anyhow::Result<bool> {
+        self.advance();
+        match key {
+            Key::Character("+" | "=") => self.speed *= 2.0,
+            Key::Character("-") => self.speed /= 2.0,
+            Key::Named(NamedKey::Space) => self.paused = !self.paused,
+            Key::Character("n") => {
+                self.typed.clear();
+                self.clock.set(Local::now());
+                return Ok(true);
+            }
+            Key::Character(character)
+                if character
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || c == ':' || c == '.') =>
+            {
+                self.typed.push_str(character)
+            }
+            Key::Named(NamedKey::Backspace) => {
+                self.typed.pop();
+            }
+            Key::Named(NamedKey::Escape) => self.typed.clear(),
+            Key::Named(NamedKey::Enter) if !self.typed.is_empty() => {
+                let typed = std::mem::take(&mut self.typed);
+                let millis = parse_time_of_day(&typed).map_err(anyhow::Error::msg)?;
+                self.clock
+                    .set(time_on(self.clock.now().date_naive(), millis)?);
+                return Ok(true);
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    /// The window title showing the time, the speed and what was typed
+    fn title(&self) -> String {
+        let now = self.clock.now();
+        let mut title = format!(
+            "desktop-background preview {time}",
+            time = format_time_of_day(now.num_seconds_from_midnight() * 1000),
+        );
+        if self.paused {
+            title.push_str(", paused");
+        } else if self.speed != 1.0 {
+            title.push_str(&format!(", {speed}x speed", speed = self.speed));
+        }
+        if !self.typed.is_empty() {
+            title.push_str(&format!(", jump to {typed}_", typed = self.typed));
+        }
+        title
+    }
+}
+
+/// Show the background of `args` in a regular window until it is closed, the time passing `speed`
+/// times faster than real time
+///
+/// The window renders like the outputs of the desktop program do, reading the time from a clock
+/// advanced by the keyboard, see [`PreviewTime::key`].
+pub fn show(args: PreviewArgs) -> anyhow::Result<()> {
+    let PreviewArgs {
+        width,
+        height,
+        background,
+        at,
+        speed,
+        backend,
+    } = args;
+    if !background.is_background() {
+        anyhow::bail!("only background commands can be previewed");
+    }
+    if !(speed.is_finite() && speed > 0.0) {
+        anyhow::bail!("the speed must be a positive number, not {speed}");
+    }
+    background.validate()?;
+
+    let start = match at {
+        Some(millis) => time_on(Local::now().date_naive(), millis)?,
+        None => Local::now(),
+    };
+    let mut time = PreviewTime {
+        clock: Arc::new(MockClock::new(start)),
+        speed,
+        paused: false,
+        typed: String::new(),
+        last_advance: Instant::now(),
+    };
+    let gpu = GpuOptions::default();
+    let event_loop = build_event_loop(backend)?;
+    let window = open_preview_window(&event_loop, width, height, &time.title())?;
+    let mut output = OutputWindow::new(window, width, height, false, false, gpu, false)?;
+    output.clock = time.clock.clone();
+    output.renderer = output.create_renderer((*background).clone())?;
+    output.command = Some(*background);
+
+    let mut title = time.title();
+    event_loop.run(move |event, elwt| match event {
+        Event::WindowEvent {
+            event: WindowEvent::CloseRequested,
+            ..
+        } => elwt.exit(),
+        Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    event:
+                        KeyEvent {
+                            logical_key,
+                            state: ElementState::Pressed,
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } => match time.key(logical_key.as_ref()) {
+            Ok(true) => {
+                // Whatever was buffered is for the time before the jump
+                output.renderer.resync();
+                output.tick_now();
+            }
+            Ok(false) => {}
+            Err(error) => eprintln!("{error:#}"),
+        },
+        Event::WindowEvent { event, .. } => {
+            if let Err(error) = output.handle_event(event, gpu, true) {
+                eprintln!("{error}");
+                elwt.exit();
+            }
+        }
+        Event::AboutToWait => {
+            time.advance();
+            // The ticks of the output are in real time, the clock passes at its own speed
+            output.tick_now();
+            let render_start = Instant::now();
+            let next_tick = match output.update(gpu, PREVIEW_TICK, true, None) {
+                Ok(next_tick) => next_tick,
+                Err(error) => {
+                    eprintln!("{error:#}");
+                    elwt.exit();
+                    return;
+                }
+            };
+            let shown = time.title();
+            if shown != title {
+                output.window.set_title(&shown);
+                title = shown;
+            }
+            // The title shows the seconds, the renderer's next frame is due sooner in real time
+            // the faster the time passes
+            elwt.set_control_flow(if time.paused {
+                ControlFlow::Wait
+            } else {
+                let due = next_tick.map_or(StdDuration::from_secs(1), |next_tick| {
+                    (next_tick - render_start).div_f64(time.speed)
+                });
+                ControlFlow::WaitUntil(
+                    render_start + due.clamp(PREVIEW_TICK, StdDuration::from_secs(1)),
+                )
+            });
+        }
+        _ => {}
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::parse_batch;
+
+    #[test]
+    fn keys_change_the_speed_and_jump_to_a_time_of_day() {
+        let start = time_on(NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(), 0).unwrap();
+        let mut time = PreviewTime {
+            clock: Arc::new(MockClock::new(start)),
+            speed: 1.0,
+            paused: true,
+            typed: String::new(),
+            last_advance: Instant::now(),
+        };
+
+        assert!(!time.key(Key::Character("+")).unwrap());
+        assert!(!time.key(Key::Character("+")).unwrap());
+        assert_eq!(time.speed, 4.0);
+        for key in ["1", "7", ":", "4", "3", "x"] {
+            assert!(!time.key(Key::Character(key)).unwrap());
+        }
+        assert!(!time.key(Key::Named(NamedKey::Backspace)).unwrap());
+        assert!(!time.key(Key::Character("2")).unwrap());
+        assert_eq!(
+            time.title(),
+            "desktop-background preview 00:00, paused, jump to 17:42_"
+        );
+        assert!(time.key(Key::Named(NamedKey::Enter)).unwrap());
+        assert_eq!(
+            time.clock.now(),
+            time_on(start.date_naive(), 63_720_000).unwrap()
+        );
+        assert!(time.typed.is_empty());
+
+        time.key(Key::Character("2")).unwrap();
+        time.key(Key::Character("5")).unwrap();
+        assert!(time.key(Key::Named(NamedKey::Enter)).is_err());
+    }
+
+    #[test]
+    fn renders_the_frame_at_a_time_of_day() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        RgbaImage::from_pixel(4, 2, image::Rgba([0x10, 0x20, 0x30, 255]))
+            .save(dir.join("color.png"))
+            .unwrap();
+        let render = |background: &str| {
+            let background = background.replace("DIR", &dir.to_string_lossy());
+            render_at(RenderAtArgs {
+                time: 14 * 3_600_000,
+                background: Box::new(parse_batch(&background)?.remove(0).command),
+                out: dir.join("frame.png"),
+                resolution: "16x8".parse().unwrap(),
+            })
+        };
+
+        let path = render("static-image DIR/missing.png").unwrap_err();
+        assert!(format!("{path:#}").contains("missing.png"), "{path:#}");
+        let path = render("static-image DIR/color.png --scaling stretch").unwrap();
+        let frame = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(frame.dimensions(), (16, 8));
+        assert_eq!(frame.get_pixel(3, 3).0, [0x10, 0x20, 0x30, 255]);
+        assert!(render("ping").is_err());
+    }
+}
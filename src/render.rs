@@ -1,134 +1,2330 @@
 use std::{
-    collections::VecDeque,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
-use chrono::{Local, Timelike};
-use color::{color_space::Srgb, Deg, Hsv, ToRgb};
-use image::RgbaImage;
-use pixels::Pixels;
+use ab_glyph::FontVec;
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Local, NaiveDate, Timelike};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::{ColorArg, DurationArg},
+    cache,
+    clock::Clock,
+    command::{format_time_of_day, ScheduleEntry},
+    damage::Damage,
+    decode,
+    hands::ClockHands,
+    latest::LatestWatcher,
+    pipe::FramePipe,
+    plugin::Plugin,
+    pool::BufferPool,
+    script::Script,
+    shm::SharedFrame,
+    slideshow::Playlist,
+    source::FrameSource,
+    span::OutputSpan,
+    template::{FrameTemplate, HourDirs},
+    text::{draw_text, TextStyle, TextTemplate},
+    tint::ClockColor,
+    watch::ImageWatcher,
+    weekly::{day_name, day_renderer, missing_days},
+};
 
 const PRE_BUFFERED_IMAGES: usize = 10;
-const MILLIS_PER_SECOND: u32 = 1000;
-const MILLIS_PER_MINUTE: u32 = 60 * MILLIS_PER_SECOND;
-const MILLIS_PER_HOUR: u32 = 60 * MILLIS_PER_MINUTE;
-const MILLIS_TOTAL: u32 = 12 * MILLIS_PER_HOUR;
+/// A second in the milliseconds clock frames are addressed by
+pub const MILLIS_PER_SECOND: u32 = 1000;
+/// A minute in the milliseconds clock frames are addressed by
+pub const MILLIS_PER_MINUTE: u32 = 60 * MILLIS_PER_SECOND;
+/// An hour in the milliseconds clock frames are addressed by
+pub const MILLIS_PER_HOUR: u32 = 60 * MILLIS_PER_MINUTE;
+/// How many clock steps may pass between two renders before the clock resynchronizes
+const MAX_SKIPPED_STEPS: u32 = 5;
+/// How many bytes of a frame are interpolated per parallel work item
+const LERP_CHUNK_SIZE: usize = 64 * 1024;
+/// How many clock steps to look back for a replacement of a missing frame
+const MAX_FALLBACK_PROBES: u32 = 50;
+/// How many random frames per hour folder are checked to exist when a clock is started
+const FRAME_PROBES_PER_HOUR: usize = 3;
+/// How often a missing frame is reported for the same millis value
+const MISSING_FRAME_WARN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How a source image is fit into the desktop resolution
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Scaling {
+    /// Stretch the image to the desktop resolution, ignoring its aspect ratio
+    #[default]
+    Stretch,
+    /// Scale the image to fit inside the desktop, leaving margins
+    Fit,
+    /// Scale the image to cover the whole desktop, cropping the overflow
+    Fill,
+    /// Keep the image at its original size
+    Center,
+}
+
+/// The length of the clock cycle
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ClockHours {
+    /// Hour folders "0" to "11"
+    #[default]
+    #[value(name = "12")]
+    Twelve,
+    /// Hour folders "0" to "23"
+    #[value(name = "24")]
+    TwentyFour,
+}
+
+impl ClockHours {
+    /// The number of hours in the cycle
+    pub fn count(self) -> u32 {
+        match self {
+            ClockHours::Twelve => 12,
+            ClockHours::TwentyFour => 24,
+        }
+    }
+
+    /// The cycle length in milliseconds
+    pub fn cycle_millis(self) -> u32 {
+        self.count() * MILLIS_PER_HOUR
+    }
+}
+
+/// Check that the clock step is positive and divides one hour evenly
+pub fn validate_clock_step(clock_step: u32) -> anyhow::Result<()> {
+    if clock_step == 0 || !MILLIS_PER_HOUR.is_multiple_of(clock_step) {
+        anyhow::bail!(
+            "clock step of {clock_step}ms should be positive and divide one hour \
+             ({MILLIS_PER_HOUR}ms) evenly"
+        );
+    }
+    Ok(())
+}
+
+/// The clock step of the frames in the folder of the first hour of the cycle, the most common
+/// difference between the clock times their names hold
+///
+/// Missing frames only leave larger gaps, a frame set whose gaps aren't all multiples of the most
+/// common one has no single clock step.
+pub fn detect_clock_step(dir: &Path, file_template: &FrameTemplate) -> anyhow::Result<u32> {
+    let first = file_template.path(dir, 0);
+    let folder = first.parent().unwrap_or(dir);
+    let entries = std::fs::read_dir(folder).with_context(|| {
+        format!(
+            "could not read {folder} to detect the clock step from",
+            folder = folder.display()
+        )
+    })?;
+    let mut times: Vec<u32> = entries
+        .flatten()
+        .filter_map(|entry| file_template.millis_of(dir, &entry.path()))
+        .filter(|millis| *millis < MILLIS_PER_HOUR)
+        .collect();
+    times.sort_unstable();
+    times.dedup();
+
+    let mut steps: HashMap<u32, usize> = HashMap::new();
+    for pair in times.windows(2) {
+        *steps.entry(pair[1] - pair[0]).or_default() += 1;
+    }
+    // Of equally common steps the smaller one, the larger ones may be gaps of missing frames
+    let Some((&step, _)) = steps
+        .iter()
+        .max_by_key(|(step, count)| (**count, std::cmp::Reverse(**step)))
+    else {
+        anyhow::bail!(
+            "could not detect the clock step, {count} frames of the first hour in {folder} match \
+             the file template {file_template}, pass the clock step",
+            count = times.len(),
+            folder = folder.display(),
+        );
+    };
+    if let Some(irregular) = steps.keys().find(|gap| *gap % step != 0) {
+        anyhow::bail!(
+            "could not detect the clock step, the frames of the first hour in {folder} are mostly \
+             {step}ms apart but also {irregular}ms, pass the clock step",
+            folder = folder.display(),
+        );
+    }
+    validate_clock_step(step).context("the detected clock step doesn't fit the clock")?;
+    Ok(step)
+}
+
+/// Check that the clock step fits the hour and that `dir` contains the folders of every hour of
+/// the cycle, when `probe_frames` is set also that a sample of the frames exists
+///
+/// If even the first hour folder, or the first frame without hour folders, is missing the error
+/// suggests the hour folder naming `dir` looks like it uses.
+pub fn validate_clock_dir(
+    dir: &Path,
+    file_template: &FrameTemplate,
+    hours: ClockHours,
+    clock_step: u32,
+    probe_frames: bool,
+) -> anyhow::Result<()> {
+    validate_clock_step(clock_step)?;
+
+    if let Some(hour_dirs) = file_template.hour_dirs() {
+        if *hour_dirs == HourDirs::ClockFace && hours != ClockHours::Twelve {
+            anyhow::bail!("hour folders 1-12 only fit a 12 hour clock");
+        }
+        if !first_frame_exists(dir, file_template) {
+            let first = file_template.path(dir, 0);
+            let (what, first) = match hour_dirs {
+                HourDirs::Flat => ("frame", first.as_path()),
+                _ => ("hour folder", first.parent().unwrap_or(dir)),
+            };
+            let hint = match guess_hour_dirs(dir, file_template, hours) {
+                Some(guess) => {
+                    format!("the clock directory looks like it uses --hour-dirs {guess}")
+                }
+                None => "pick the naming of the clock directory with --hour-dirs".to_string(),
+            };
+            anyhow::bail!(
+                "missing the first {what} {first} for hour folders {hour_dirs}, {hint}",
+                first = first.display(),
+            );
+        }
+    }
+
+    let missing: Vec<String> = (0..hours.count())
+        .filter_map(|hour| {
+            let folder = file_template.path(dir, hour * MILLIS_PER_HOUR);
+            let folder = folder.parent()?;
+            (!folder.is_dir()).then(|| folder.display().to_string())
+        })
+        .collect();
+    if !missing.is_empty() {
+        anyhow::bail!(
+            "missing the frame folders {missing} for a {count} hour clock",
+            missing = missing.join(", "),
+            count = hours.count(),
+        );
+    }
+
+    if !probe_frames {
+        return Ok(());
+    }
+
+    // Check the first and last frame of every hour plus a few random ones in between
+    let frames_per_hour = MILLIS_PER_HOUR / clock_step;
+    for hour in 0..hours.count() {
+        let first = hour * MILLIS_PER_HOUR;
+        let samples = [0, frames_per_hour - 1]
+            .into_iter()
+            .chain((0..FRAME_PROBES_PER_HOUR).map(|_| fastrand::u32(0..frames_per_hour)));
+        for frame in samples {
+            let path = file_template.path(dir, first + frame * clock_step);
+            if !path.is_file() {
+                anyhow::bail!(
+                    "missing clock frame {path}, check the file template and clock step or pass \
+                     --no-validate to skip this check",
+                    path = path.display(),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the first hour folder of the template exists in `dir`, the first frame for templates
+/// without hour folders
+fn first_frame_exists(dir: &Path, file_template: &FrameTemplate) -> bool {
+    let first = file_template.path(dir, 0);
+    match file_template.hour_dirs() {
+        Some(HourDirs::Flat) => first.is_file(),
+        _ => first.parent().is_some_and(Path::is_dir),
+    }
+}
+
+/// The hour folder naming whose first folder or frame exists in `dir`, tried are the fixed
+/// namings and patterns made from the numbered folders in `dir`
+fn guess_hour_dirs(
+    dir: &Path,
+    file_template: &FrameTemplate,
+    hours: ClockHours,
+) -> Option<HourDirs> {
+    let mut guesses = vec![HourDirs::ZeroBased, HourDirs::Flat];
+    if hours == ClockHours::Twelve {
+        guesses.push(HourDirs::ClockFace);
+    }
+    let folders = std::fs::read_dir(dir).into_iter().flatten().flatten();
+    for folder in folders.filter(|entry| entry.path().is_dir()) {
+        let name = folder.file_name().to_string_lossy().replace('%', "%%");
+        let Some(end) = name.rfind(|c: char| c.is_ascii_digit()) else {
+            continue;
+        };
+        let start = name[..end]
+            .rfind(|c: char| !c.is_ascii_digit())
+            .map_or(0, |start| start + 1);
+        let placeholder = match end + 1 - start {
+            1 => "%d".to_string(),
+            width => format!("%0{width}d"),
+        };
+        let pattern = HourDirs::Pattern(format!(
+            "{}{placeholder}{}",
+            &name[..start],
+            &name[end + 1..]
+        ));
+        if !guesses.contains(&pattern) {
+            guesses.push(pattern);
+        }
+    }
+
+    guesses
+        .into_iter()
+        .filter(|guess| Some(guess) != file_template.hour_dirs())
+        .find(|guess| {
+            file_template
+                .with_hour_dirs(guess)
+                .is_ok_and(|template| first_frame_exists(dir, &template))
+        })
+}
+
+/// How the clock color is applied to the clock frames
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ColorizeMode {
+    /// Multiply the frame colors with the clock color
+    #[default]
+    Multiply,
+    /// Use the frame as a mask blending between a base background and the clock color
+    Mask,
+}
+
+/// Which part of a clock frame is used as mask
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum MaskSource {
+    /// The alpha channel
+    #[default]
+    Alpha,
+    /// The luminance of the colors, weighted by the alpha channel
+    Luminance,
+}
+
+/// How the clock color is applied, see [`ColorizeMode`]
+pub enum Colorize {
+    /// Multiply the frame colors with the clock color
+    Multiply {
+        /// What semi-transparent tinted pixels are blended over
+        base: [u8; 3],
+    },
+    /// Blend between `base` and the clock color through a mask
+    Mask {
+        /// Which part of the frame is the mask
+        source: MaskSource,
+        /// Shown where the mask is empty
+        base: RgbaImage,
+    },
+}
+
+/// Where a scaled source image is placed inside the frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placement {
+    width: u32,
+    height: u32,
+    x: i64,
+    y: i64,
+}
+
+impl Placement {
+    fn new(scaling: Scaling, (src_width, src_height): (u32, u32), width: u32, height: u32) -> Self {
+        let (scaled_width, scaled_height) = match scaling {
+            Scaling::Stretch => (width, height),
+            Scaling::Center => (src_width, src_height),
+            Scaling::Fit | Scaling::Fill => {
+                let scale_x = width as f64 / src_width as f64;
+                let scale_y = height as f64 / src_height as f64;
+                let scale = if scaling == Scaling::Fit {
+                    scale_x.min(scale_y)
+                } else {
+                    scale_x.max(scale_y)
+                };
+                (
+                    ((src_width as f64 * scale).round() as u32).max(1),
+                    ((src_height as f64 * scale).round() as u32).max(1),
+                )
+            }
+        };
+
+        Placement {
+            width: scaled_width,
+            height: scaled_height,
+            x: (width as i64 - scaled_width as i64) / 2,
+            y: (height as i64 - scaled_height as i64) / 2,
+        }
+    }
+
+    fn covers(&self, width: u32, height: u32) -> bool {
+        self.x <= 0
+            && self.y <= 0
+            && self.x + self.width as i64 >= width as i64
+            && self.y + self.height as i64 >= height as i64
+    }
+}
+
+/// Places source images into full sized frames according to a [`Scaling`]
+#[derive(Clone)]
+pub struct FrameLayout {
+    scaling: Scaling,
+    /// Computed from the first placed image and reused for all following ones
+    placement: Option<Placement>,
+    /// What shows through the margins left by the placed image
+    base: RgbaImage,
+}
+
+impl FrameLayout {
+    /// Place images over `base`, which has the size of the frames
+    pub fn new(scaling: Scaling, base: RgbaImage) -> Self {
+        FrameLayout {
+            scaling,
+            placement: None,
+            base,
+        }
+    }
+
+    /// A layout whose margins are filled with a solid color
+    pub fn with_color(scaling: Scaling, color: [u8; 3], width: u32, height: u32) -> Self {
+        let base = if matches!(scaling, Scaling::Stretch | Scaling::Fill) {
+            RgbaImage::new(0, 0)
+        } else {
+            RgbaImage::from_pixel(width, height, Rgba([color[0], color[1], color[2], 255]))
+        };
+        FrameLayout::new(scaling, base)
+    }
+
+    /// The size a source image of `source_size` is scaled to when placed into a `width` x
+    /// `height` frame
+    pub fn scaled_size(&mut self, source_size: (u32, u32), width: u32, height: u32) -> (u32, u32) {
+        let placement = *self
+            .placement
+            .get_or_insert_with(|| Placement::new(self.scaling, source_size, width, height));
+        (placement.width, placement.height)
+    }
+
+    /// Where the first placed image ended up in the frame and its size there, `None` before an
+    /// image was placed
+    pub(crate) fn placed(&self) -> Option<((i64, i64), (u32, u32))> {
+        self.placement.map(|placement| {
+            (
+                (placement.x, placement.y),
+                (placement.width, placement.height),
+            )
+        })
+    }
+
+    /// A hash of the scaling and the margins, which tells apart layouts placing the same image
+    /// differently into frames of the same size
+    pub fn fingerprint(&self) -> u64 {
+        let hash = cache::fnv(cache::FNV_OFFSET, &[self.scaling as u8]);
+        let hash = [self.base.width(), self.base.height()]
+            .iter()
+            .fold(hash, |hash, size| cache::fnv(hash, &size.to_le_bytes()));
+        cache::fnv(hash, &self.base)
+    }
+
+    /// Decode the image at `path` and place it inside a frame of `width` x `height` pixels,
+    /// decoding it no larger than it is shown if it can be
+    pub fn load(&mut self, path: &Path, width: u32, height: u32) -> anyhow::Result<RgbaImage> {
+        let image = decode::open_scaled(path, |size| self.scaled_size(size, width, height))?;
+        Ok(self.place(&image, width, height))
+    }
+
+    /// Scale and position `image` inside a frame of `width` x `height` pixels
+    pub fn place(&mut self, image: &DynamicImage, width: u32, height: u32) -> RgbaImage {
+        let placement = *self
+            .placement
+            .get_or_insert_with(|| Placement::new(self.scaling, image.dimensions(), width, height));
+
+        let resized = if (placement.width, placement.height) == image.dimensions() {
+            image.to_rgba8()
+        } else {
+            resize(image, placement)
+        };
+        self.frame(
+            placement,
+            resized,
+            width,
+            height,
+            &mut BufferPool::default(),
+        )
+    }
+
+    /// Scale and position `image` like [`FrameLayout::place`], into a buffer from `pool`
+    ///
+    /// An rgba `image` which needs no scaling becomes the frame, the buffers of other images are
+    /// returned to the pool.
+    pub(crate) fn place_pooled(
+        &mut self,
+        image: DynamicImage,
+        width: u32,
+        height: u32,
+        pool: &mut BufferPool,
+    ) -> RgbaImage {
+        let placement = *self
+            .placement
+            .get_or_insert_with(|| Placement::new(self.scaling, image.dimensions(), width, height));
+
+        let resized = match image {
+            DynamicImage::ImageRgba8(image)
+                if (placement.width, placement.height) == image.dimensions() =>
+            {
+                image
+            }
+            image => {
+                let resized = if (placement.width, placement.height) == image.dimensions() {
+                    image.to_rgba8()
+                } else {
+                    resize(&image, placement)
+                };
+                if let Some(buffer) = decode::into_buffer(image) {
+                    pool.give(buffer);
+                }
+                resized
+            }
+        };
+        self.frame(placement, resized, width, height, pool)
+    }
+
+    /// Crop `resized` to the frame or lay it over the margins
+    fn frame(
+        &self,
+        placement: Placement,
+        resized: RgbaImage,
+        width: u32,
+        height: u32,
+        pool: &mut BufferPool,
+    ) -> RgbaImage {
+        if placement.covers(width, height) {
+            if (placement.width, placement.height) == (width, height) {
+                return resized;
+            }
+            let mut frame = pool.take(width as usize * height as usize * 4);
+            let (x, y) = ((-placement.x) as usize, (-placement.y) as usize);
+            let stride = placement.width as usize * 4;
+            for (row, line) in frame.chunks_exact_mut(width as usize * 4).enumerate() {
+                let start = (y + row) * stride + x * 4;
+                line.copy_from_slice(&resized.as_raw()[start..start + line.len()]);
+            }
+            return RgbaImage::from_raw(width, height, frame).unwrap();
+        }
+
+        let mut frame = pool.take(self.base.len());
+        frame.copy_from_slice(&self.base);
+        let mut frame = RgbaImage::from_raw(self.base.width(), self.base.height(), frame).unwrap();
+        image::imageops::overlay(&mut frame, &resized, placement.x, placement.y);
+        frame
+    }
+}
 
+/// Scale `image` to the size of its placement
+fn resize(image: &DynamicImage, placement: Placement) -> RgbaImage {
+    image::imageops::resize(
+        image,
+        placement.width,
+        placement.height,
+        image::imageops::FilterType::Triangle,
+    )
+}
+
+/// Bookkeeping for clock frames which could not be loaded
+#[derive(Default)]
+pub struct MissingFrames {
+    /// How many frames have been replaced by an earlier one
+    substituted: u64,
+    /// When a failure was last reported for each millis value
+    last_warned: HashMap<u32, Instant>,
+}
+
+impl MissingFrames {
+    fn warn(&mut self, millis: u32, error: &anyhow::Error) {
+        let now = Instant::now();
+        if self
+            .last_warned
+            .get(&millis)
+            .is_some_and(|last| now.duration_since(*last) < MISSING_FRAME_WARN_INTERVAL)
+        {
+            return;
+        }
+        self.last_warned.insert(millis, now);
+        eprintln!(
+            "warning: could not load clock frame {millis:08}, substituting an earlier one: {error}"
+        );
+    }
+}
+
+/// Draws a background into the frames of a window, created by [`crate::Command::into_renderer`]
+#[allow(clippy::large_enum_variant)]
 pub enum BackgroundRenderer {
+    /// Draws nothing, before the first background command
     None,
+    /// An image placed once, reloaded when its file changes if watched
+    StaticImage {
+        /// The image file or url
+        path: PathBuf,
+        /// The image placed into a frame of the desktop resolution
+        image: RgbaImage,
+        /// Whether the image still has to be drawn into the frame
+        redraw: bool,
+        /// Reloads the image when its file changes, if watched
+        watcher: Option<ImageWatcher>,
+    },
+    /// The clock frame of the current time, buffered ahead on a background thread
     ClockImage {
-        dir: PathBuf,
-        file_template: String,
+        /// Where the clock frames are loaded from
+        source: FrameSource,
+        /// The time between two clock frames in milliseconds
         clock_step: u32,
+        /// The cycle length in milliseconds
+        cycle: u32,
+        /// Added to the current time, less than one cycle
+        offset: u32,
+        /// The zone the clock shows the time of, the local one if not set
+        timezone: Option<Tz>,
+        /// The frames loaded ahead by their clock time, the next one first
         buffered_images: VecDeque<(u32, RgbaImage)>,
-        rainbow: bool,
-        color: Option<[f32; 3]>,
+        /// The color the frames are tinted with
+        color: Option<ClockColor>,
+        /// How the frames are placed into the desktop resolution
+        layout: FrameLayout,
+        /// How the color is applied to the frames
+        colorize: Colorize,
+        /// The missing frames which were already reported
+        missing_frames: MissingFrames,
+        /// The fraction of a clock step spent fading from the previous frame to the next one
+        interpolate: Option<f32>,
+        /// The frame which was shown before the current one, faded out during the transition
+        previous_image: Option<RgbaImage>,
+        /// Whether the last rendered frame was part of a transition
+        fading: bool,
+        /// The clock time of the last render, to notice jumps eg after a suspend
+        last_millis: Option<u32>,
+        /// How long loading the last clock frame took
+        load_time: Option<Duration>,
+        /// Where the current time is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// Hand images turned over a clock face, drawn again where they moved
+    ClockHands {
+        /// The hands and the face they turn over
+        hands: ClockHands,
+        /// Where the current time is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// Text drawn again whenever its expanded template changes
+    TextOverlay {
+        /// The text with its time placeholders
+        template: TextTemplate,
+        /// The font the text is drawn in
+        font: FontVec,
+        /// The size, color and place of the text
+        style: TextStyle,
+        /// The last drawn text
+        text: Option<String>,
+        /// Where the frame holds the last drawn text, cleared before the next one is drawn
+        drawn: Damage,
+        /// Where the current time is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// A background drawn by a plugin library
+    Plugin {
+        /// The loaded plugin and its state
+        plugin: Plugin,
+        /// Where the time passed to the plugin is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// A background drawn by a Lua script
+    Script {
+        /// The loaded script, reloaded when its file changes
+        script: Script,
+        /// Where the time passed to the script is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// The latest frame written by a command to its standard output
+    Pipe {
+        /// The command and the latest frame read from it
+        pipe: FramePipe,
+    },
+    /// The latest frame another process wrote into a shared memory file
+    Shm {
+        /// The mapped file and the sequence number of the frame shown
+        shared: SharedFrame,
+    },
+    /// Renderers drawn on top of each other, the first one at the bottom
+    Stack {
+        /// Each layer together with the frame it renders into
+        layers: Vec<(BackgroundRenderer, Vec<u8>)>,
+    },
+    /// The newest image matching a pattern, switched when a newer one appears if watched
+    LatestImage {
+        /// The glob pattern the images match
+        pattern: PathBuf,
+        /// The newest image file, the one shown
+        path: PathBuf,
+        /// The image placed into a frame of the desktop resolution
+        image: RgbaImage,
+        /// Whether the image still has to be drawn into the frame
+        redraw: bool,
+        /// Notices new images matching the pattern, if watched
+        watcher: Option<LatestWatcher>,
+    },
+    /// The images of a directory, one after another
+    Slideshow {
+        /// The images of the directory and which one comes next
+        playlist: Playlist,
+        /// How long each image is shown
+        interval: DurationArg,
+        /// How the images are scaled to the desktop resolution
+        scaling: Scaling,
+        /// The color of margins left uncovered by the images
+        margin_color: Option<ColorArg>,
+        /// The place of the output among all outputs if the images span them
+        span: Option<OutputSpan>,
+        /// The image shown
+        path: PathBuf,
+        /// When the image was switched to
+        shown_at: DateTime<Local>,
+        /// The renderer of the image shown
+        renderer: Box<BackgroundRenderer>,
+        /// Where the current time is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// The image of the current day of the week
+    Weekly {
+        /// The directory with the images of the days
+        dir: PathBuf,
+        /// How the images are scaled to the desktop resolution
+        scaling: Scaling,
+        /// The color of margins left uncovered by the images
+        margin_color: Option<ColorArg>,
+        /// The days shown with the default image
+        missing_days: Vec<&'static str>,
+        /// The day the image is shown for, the image is switched once the date changes
+        date: NaiveDate,
+        /// The image shown
+        path: PathBuf,
+        /// The renderer of the image shown
+        renderer: Box<BackgroundRenderer>,
+        /// Where the current date is read from
+        clock: Arc<dyn Clock>,
+    },
+    /// The background of the schedule entry whose time window holds the current time of day
+    Schedule {
+        /// The entries ordered by their start
+        entries: Vec<ScheduleEntry>,
+        /// The index of the entry shown, or failed to load
+        active: usize,
+        /// The renderer of the entry shown
+        renderer: Box<BackgroundRenderer>,
+        /// The frame the renderer of the entry draws into
+        entry_frame: Vec<u8>,
+        /// How long to cross-fade after switching entries
+        fade: DurationArg,
+        /// The frame shown before the last switch and when it happened, while fading it out
+        fade_from: Option<(Vec<u8>, DateTime<Local>)>,
+        /// The place of the output among all outputs, passed on to the entries
+        span: Option<OutputSpan>,
+        /// Where the current time is read from
+        clock: Arc<dyn Clock>,
     },
 }
 
 impl BackgroundRenderer {
-    pub fn render(&mut self, pixels: &mut Pixels, width: u32, height: u32) -> anyhow::Result<()> {
+    /// A human readable description of the renderer state
+    pub fn status(&self) -> String {
         match self {
-            BackgroundRenderer::None => {}
+            BackgroundRenderer::None => "renderer: none".to_string(),
+            BackgroundRenderer::StaticImage { path, watcher, .. } => format!(
+                "renderer: static image\n\
+                 image: {path}\n\
+                 watching: {watching}",
+                path = path.display(),
+                watching = watcher.is_some(),
+            ),
             BackgroundRenderer::ClockImage {
+                source,
+                clock_step,
+                cycle,
+                offset,
+                timezone,
+                buffered_images,
+                color,
+                missing_frames,
+                ..
+            } => format!(
+                "renderer: clock image\n\
+                 frames: {path}\n\
+                 clock step: {clock_step}ms\n\
+                 cycle: {hours}h\n\
+                 offset: {offset}ms\n\
+                 timezone: {timezone}\n\
+                 clock color: {color}\n\
+                 buffered frames: {buffered}\n\
+                 frames substituted: {substituted}",
+                path = source.describe(),
+                hours = cycle / MILLIS_PER_HOUR,
+                timezone = timezone.map_or("local", Tz::name),
+                color = color
+                    .as_ref()
+                    .map_or("none".to_string(), ClockColor::to_string),
+                buffered = buffered_images.len(),
+                substituted = missing_frames.substituted,
+            ),
+            BackgroundRenderer::ClockHands { hands, .. } => {
+                format!("renderer: clock hands\n{status}", status = hands.status())
+            }
+            BackgroundRenderer::TextOverlay { text, .. } => format!(
+                "renderer: text overlay\n\
+                 text: {text:?}",
+                text = text.as_deref().unwrap_or_default(),
+            ),
+            BackgroundRenderer::Plugin { plugin, .. } => {
+                format!("renderer: plugin\n{status}", status = plugin.status())
+            }
+            BackgroundRenderer::Script { script, .. } => {
+                format!("renderer: script\n{status}", status = script.status())
+            }
+            BackgroundRenderer::Pipe { pipe } => {
+                format!("renderer: pipe\n{status}", status = pipe.status())
+            }
+            BackgroundRenderer::Shm { shared } => {
+                format!(
+                    "renderer: shared memory\n{status}",
+                    status = shared.status()
+                )
+            }
+            BackgroundRenderer::Stack { layers } => layers.iter().enumerate().fold(
+                "renderer: stack".to_string(),
+                |status, (idx, (layer, _))| {
+                    format!(
+                        "{status}\nlayer {idx}:\n  {layer}",
+                        layer = layer.status().replace('\n', "\n  ")
+                    )
+                },
+            ),
+            BackgroundRenderer::LatestImage {
+                pattern,
+                path,
+                watcher,
+                ..
+            } => format!(
+                "renderer: latest image\n\
+                 pattern: {pattern}\n\
+                 image: {path}\n\
+                 watching: {watching}",
+                pattern = pattern.display(),
+                path = path.display(),
+                watching = watcher.is_some(),
+            ),
+            BackgroundRenderer::Slideshow {
+                playlist,
+                interval,
+                path,
+                shown_at,
+                clock,
+                ..
+            } => format!(
+                "renderer: slideshow\n\
+                 {playlist}\n\
+                 image: {path}\n\
+                 next image in: {next}",
+                playlist = playlist.status(),
+                path = path.display(),
+                // Whole seconds are enough to tell when the image changes
+                next = DurationArg(
+                    (interval.0 as i64 - (clock.now() - *shown_at).num_milliseconds()).max(0)
+                        as u64
+                        / 1000
+                        * 1000
+                ),
+            ),
+            BackgroundRenderer::Weekly {
                 dir,
-                file_template,
+                missing_days,
+                date,
+                path,
+                ..
+            } => format!(
+                "renderer: weekly\n\
+                 images: {dir}\n\
+                 day: {day}\n\
+                 image: {path}\n\
+                 days without an image: {missing}",
+                dir = dir.display(),
+                day = day_name(date.weekday()),
+                path = path.display(),
+                missing = if missing_days.is_empty() {
+                    "none".to_string()
+                } else {
+                    format!(
+                        "{days} (showing the default image)",
+                        days = missing_days.join(", ")
+                    )
+                },
+            ),
+            BackgroundRenderer::Schedule {
+                entries,
+                active,
+                renderer,
+                ..
+            } => format!(
+                "renderer: schedule\n\
+                 entries: {entries}\n\
+                 active entry: {start}\n\
+                 next switch: {next}\n\
+                 entry:\n  {entry}",
+                entries = entries
+                    .iter()
+                    .map(|entry| format_time_of_day(entry.start))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                start = format_time_of_day(entries[*active].start),
+                next = format_time_of_day(entries[(*active + 1) % entries.len()].start),
+                entry = renderer.status().replace('\n', "\n  "),
+            ),
+        }
+    }
+
+    /// The image file the renderer picked to show, for renderers choosing one themselves
+    pub fn chosen_file(&self) -> Option<&Path> {
+        match self {
+            BackgroundRenderer::LatestImage { path, .. }
+            | BackgroundRenderer::Slideshow { path, .. }
+            | BackgroundRenderer::Weekly { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// How long until rendering again may draw something new, zero if it may every tick and
+    /// `None` if nothing changes until a command or a resize replaces the renderer
+    ///
+    /// Rendering earlier is harmless, it just draws nothing new.
+    pub fn next_frame_in(&self) -> Option<Duration> {
+        match self {
+            BackgroundRenderer::None => None,
+            BackgroundRenderer::StaticImage {
+                redraw, watcher, ..
+            } => (*redraw || watcher.is_some()).then_some(Duration::ZERO),
+            BackgroundRenderer::LatestImage {
+                redraw, watcher, ..
+            } => (*redraw || watcher.is_some()).then_some(Duration::ZERO),
+            BackgroundRenderer::ClockImage {
                 clock_step,
+                cycle,
+                offset,
+                timezone,
+                buffered_images,
+                fading,
+                clock,
+                ..
+            } => {
+                // The read-ahead is filled on the render after a jump
+                if *fading || buffered_images.len() < PRE_BUFFERED_IMAGES {
+                    return Some(Duration::ZERO);
+                }
+                let day_millis = day_millis(clock.now(), timezone.as_ref());
+                let exact_millis = clock_millis(day_millis, *cycle, *offset, 1);
+                // The last step is shorter if the step doesn't divide the cycle
+                let due = (clock_step - exact_millis % clock_step).min(cycle - exact_millis);
+                Some(Duration::from_millis(due.into()))
+            }
+            BackgroundRenderer::ClockHands { hands, clock } => {
+                Some(hands.next_frame_in(clock.now()))
+            }
+            BackgroundRenderer::Stack { layers } => layers
+                .iter()
+                .filter_map(|(layer, _)| layer.next_frame_in())
+                .min(),
+            BackgroundRenderer::Slideshow {
+                interval,
+                shown_at,
+                renderer,
+                clock,
+                ..
+            } => {
+                let shown_for = (clock.now() - *shown_at).num_milliseconds();
+                let left = (interval.0 as i64 - shown_for).max(0);
+                sooner(
+                    renderer.next_frame_in(),
+                    Some(Duration::from_millis(left as u64)),
+                )
+            }
+            BackgroundRenderer::Weekly {
+                renderer, clock, ..
+            } => {
+                let left = 24 * MILLIS_PER_HOUR - day_millis(clock.now(), None);
+                sooner(
+                    renderer.next_frame_in(),
+                    Some(Duration::from_millis(left.into())),
+                )
+            }
+            BackgroundRenderer::Schedule {
+                entries,
+                renderer,
+                fade_from,
+                clock,
+                ..
+            } => {
+                if fade_from.is_some() {
+                    return Some(Duration::ZERO);
+                }
+                let day_millis = day_millis(clock.now(), None);
+                let left = entries
+                    .iter()
+                    .map(|entry| {
+                        (entry.start + 24 * MILLIS_PER_HOUR - day_millis) % (24 * MILLIS_PER_HOUR)
+                    })
+                    .filter(|&left| left > 0)
+                    .min()
+                    .unwrap_or(24 * MILLIS_PER_HOUR);
+                sooner(
+                    renderer.next_frame_in(),
+                    Some(Duration::from_millis(left.into())),
+                )
+            }
+            BackgroundRenderer::TextOverlay { .. }
+            | BackgroundRenderer::Plugin { .. }
+            | BackgroundRenderer::Script { .. }
+            | BackgroundRenderer::Pipe { .. }
+            | BackgroundRenderer::Shm { .. } => Some(Duration::ZERO),
+        }
+    }
+
+    /// How long loading the last clock frame took, the longest of all layers
+    pub fn load_time(&self) -> Option<Duration> {
+        match self {
+            BackgroundRenderer::ClockImage { load_time, .. } => *load_time,
+            BackgroundRenderer::Stack { layers } => layers
+                .iter()
+                .filter_map(|(layer, _)| layer.load_time())
+                .max(),
+            BackgroundRenderer::Slideshow { renderer, .. }
+            | BackgroundRenderer::Weekly { renderer, .. }
+            | BackgroundRenderer::Schedule { renderer, .. } => renderer.load_time(),
+            BackgroundRenderer::None
+            | BackgroundRenderer::StaticImage { .. }
+            | BackgroundRenderer::LatestImage { .. }
+            | BackgroundRenderer::ClockHands { .. }
+            | BackgroundRenderer::TextOverlay { .. }
+            | BackgroundRenderer::Plugin { .. }
+            | BackgroundRenderer::Script { .. }
+            | BackgroundRenderer::Pipe { .. }
+            | BackgroundRenderer::Shm { .. } => None,
+        }
+    }
+
+    /// How many clock frames are buffered ahead, summed over all layers
+    pub fn buffered_frames(&self) -> Option<usize> {
+        match self {
+            BackgroundRenderer::ClockImage {
+                buffered_images, ..
+            } => Some(buffered_images.len()),
+            BackgroundRenderer::Stack { layers } => layers
+                .iter()
+                .filter_map(|(layer, _)| layer.buffered_frames())
+                .reduce(|sum, buffered| sum + buffered),
+            BackgroundRenderer::Slideshow { renderer, .. }
+            | BackgroundRenderer::Weekly { renderer, .. }
+            | BackgroundRenderer::Schedule { renderer, .. } => renderer.buffered_frames(),
+            BackgroundRenderer::None
+            | BackgroundRenderer::StaticImage { .. }
+            | BackgroundRenderer::LatestImage { .. }
+            | BackgroundRenderer::ClockHands { .. }
+            | BackgroundRenderer::TextOverlay { .. }
+            | BackgroundRenderer::Plugin { .. }
+            | BackgroundRenderer::Script { .. }
+            | BackgroundRenderer::Pipe { .. }
+            | BackgroundRenderer::Shm { .. } => None,
+        }
+    }
+
+    /// Draw the whole frame on the next render, even if nothing changed
+    pub fn redraw(&mut self) {
+        match self {
+            BackgroundRenderer::StaticImage { redraw, .. }
+            | BackgroundRenderer::LatestImage { redraw, .. } => *redraw = true,
+            // Copies the shown frame again like at the end of a transition
+            BackgroundRenderer::ClockImage { fading, .. } => *fading = true,
+            BackgroundRenderer::ClockHands { hands, .. } => hands.redraw(),
+            BackgroundRenderer::TextOverlay { text, drawn, .. } => {
+                *text = None;
+                *drawn = Damage::Full;
+            }
+            BackgroundRenderer::Plugin { plugin, .. } => plugin.redraw(),
+            BackgroundRenderer::Script { script, .. } => script.redraw(),
+            BackgroundRenderer::Pipe { pipe } => pipe.redraw(),
+            BackgroundRenderer::Shm { shared } => shared.redraw(),
+            BackgroundRenderer::Stack { layers } => {
+                layers.iter_mut().for_each(|(layer, _)| layer.redraw())
+            }
+            BackgroundRenderer::Slideshow { renderer, .. }
+            | BackgroundRenderer::Weekly { renderer, .. }
+            | BackgroundRenderer::Schedule { renderer, .. } => renderer.redraw(),
+            BackgroundRenderer::None => {}
+        }
+    }
+
+    /// Drop buffered frames so the next render shows the current time again
+    pub fn resync(&mut self) {
+        match self {
+            BackgroundRenderer::ClockImage {
+                buffered_images,
+                previous_image,
+                fading,
+                ..
+            } => {
+                buffered_images.clear();
+                *previous_image = None;
+                *fading = false;
+            }
+            BackgroundRenderer::Stack { layers } => {
+                layers.iter_mut().for_each(|(layer, _)| layer.resync())
+            }
+            BackgroundRenderer::Slideshow { renderer, .. }
+            | BackgroundRenderer::Weekly { renderer, .. } => renderer.resync(),
+            BackgroundRenderer::Schedule {
+                renderer,
+                fade_from,
+                ..
+            } => {
+                renderer.resync();
+                *fade_from = None;
+            }
+            BackgroundRenderer::None
+            | BackgroundRenderer::StaticImage { .. }
+            | BackgroundRenderer::LatestImage { .. }
+            | BackgroundRenderer::ClockHands { .. }
+            | BackgroundRenderer::TextOverlay { .. }
+            | BackgroundRenderer::Plugin { .. }
+            | BackgroundRenderer::Script { .. }
+            | BackgroundRenderer::Pipe { .. }
+            | BackgroundRenderer::Shm { .. } => {}
+        }
+    }
+
+    /// Render into the rgba `frame`, returns whether the frame changed
+    pub fn render(&mut self, frame: &mut [u8], width: u32, height: u32) -> anyhow::Result<bool> {
+        Ok(self.render_damage(frame, width, height)? != Damage::None)
+    }
+
+    /// Render into the rgba `frame`, returns which part of it changed
+    ///
+    /// The frame has to hold what the last render left in it, only the damaged part is drawn.
+    pub fn render_damage(
+        &mut self,
+        frame: &mut [u8],
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Damage> {
+        match self {
+            BackgroundRenderer::None => Ok(Damage::None),
+            BackgroundRenderer::StaticImage {
+                path,
+                image,
+                redraw,
+                watcher,
+            } => {
+                if let Some(reloaded) = watcher.as_ref().and_then(ImageWatcher::poll) {
+                    *image = reloaded;
+                    *redraw = true;
+                }
+
+                if *redraw {
+                    *redraw = false;
+                    let what = format_args!("the image {path}", path = path.display());
+                    return Ok(Damage::changed(blit(frame, width, height, image, what)));
+                }
+                Ok(Damage::None)
+            }
+            BackgroundRenderer::LatestImage {
+                path,
+                image,
+                redraw,
+                watcher,
+                ..
+            } => {
+                if let Some((newer, newer_image)) = watcher.as_ref().and_then(LatestWatcher::poll) {
+                    eprintln!("showing the newer image {newer}", newer = newer.display());
+                    *path = newer;
+                    *image = newer_image;
+                    *redraw = true;
+                }
+
+                if *redraw {
+                    *redraw = false;
+                    let what = format_args!("the image {path}", path = path.display());
+                    return Ok(Damage::changed(blit(frame, width, height, image, what)));
+                }
+                Ok(Damage::None)
+            }
+            BackgroundRenderer::ClockImage {
+                source,
+                clock_step,
+                cycle,
+                offset,
+                timezone,
                 buffered_images,
-                rainbow,
                 color,
+                layout,
+                colorize,
+                missing_frames,
+                interpolate,
+                previous_image,
+                fading,
+                last_millis,
+                load_time,
+                clock,
             } => {
-                let current_millis = clock_millis(*clock_step);
+                let day_millis = day_millis(clock.now(), timezone.as_ref());
+                let exact_millis = clock_millis(day_millis, *cycle, *offset, 1);
+                let current_millis = clock_millis(day_millis, *cycle, *offset, *clock_step);
                 let mut redraw = false;
 
-                while buffered_images
-                    .back()
-                    .is_some_and(|(time, _)| time.abs_diff(current_millis) >= *clock_step)
-                {
-                    buffered_images.pop_back();
+                // After a jump the buffered frames are useless, show the current time right away
+                // and fill the read-ahead over the next ticks
+                let jumped = last_millis.replace(current_millis).is_some_and(|last| {
+                    forward_millis(last, current_millis, *cycle) > MAX_SKIPPED_STEPS * *clock_step
+                });
+                if jumped {
+                    for (_, image) in buffered_images.drain(..) {
+                        source.recycle(image);
+                    }
+                    *previous_image = None;
                 }
+                let buffer_target = if jumped { 1 } else { PRE_BUFFERED_IMAGES };
 
-                while buffered_images.len() < PRE_BUFFERED_IMAGES {
+                // Frames are compared the shorter way around the cycle, the frames buffered before
+                // the wrap are behind those after it
+                while buffered_images.back().is_some_and(|(time, _)| {
+                    let behind = forward_millis(*time, current_millis, *cycle);
+                    behind >= *clock_step && behind <= *cycle / 2
+                }) {
+                    let (_, evicted) = buffered_images.pop_back().unwrap();
+                    let unused = match interpolate {
+                        Some(_) => previous_image.replace(evicted),
+                        None => Some(evicted),
+                    };
+                    if let Some(unused) = unused {
+                        source.recycle(unused);
+                    }
+                }
+
+                while buffered_images.len() < buffer_target {
                     redraw = true;
 
                     let image_millis = buffered_images
                         .front()
-                        .map(|t| (t.0 + *clock_step) % MILLIS_TOTAL)
+                        .map(|t| (t.0 + *clock_step) % *cycle)
                         .unwrap_or(current_millis);
 
-                    let image = load_clock_image(dir, file_template, image_millis, width, height)?;
+                    let load_start = Instant::now();
+                    let image = match source.load(image_millis, layout, width, height) {
+                        Ok(image) => image,
+                        Err(error) => {
+                            missing_frames.warn(image_millis, &error);
+                            missing_frames.substituted += 1;
+                            substitute_clock_image(
+                                source,
+                                image_millis,
+                                *clock_step,
+                                *cycle,
+                                buffered_images,
+                                layout,
+                                width,
+                                height,
+                            )
+                            .map_err(|probe_error| error.context(probe_error.to_string()))?
+                        }
+                    };
+                    *load_time = Some(load_start.elapsed());
 
                     buffered_images.push_front((image_millis, image));
                 }
 
-                if redraw {
-                    let color = if *rainbow {
-                        Some(
-                            *Hsv::<f32, Srgb>::new(
-                                Deg(current_millis as f32 / MILLIS_TOTAL as f32 * 360.0),
-                                1.0,
-                                1.0,
-                            )
-                            .to_rgb::<f32>()
-                            .as_ref(),
-                        )
-                    } else {
-                        color.map(|c| c)
-                    };
+                let fade_progress = interpolate
+                    .map(|fraction| {
+                        (exact_millis - current_millis) as f32 / (fraction * *clock_step as f32)
+                    })
+                    .filter(|progress| *progress < 1.0);
+                let (shown_millis, shown) = buffered_images.back().unwrap();
+                let frame_size = (width, height);
+                let previous = previous_image
+                    .as_ref()
+                    .filter(|previous| previous.dimensions() == frame_size)
+                    .filter(|_| shown.dimensions() == frame_size && frame.len() == shown.len());
 
-                    if let Some(color) = color {
-                        pixels
-                            .frame_mut()
-                            .iter_mut()
-                            .zip(buffered_images.back().unwrap().1.iter())
-                            .enumerate()
-                            .for_each(|(idx, (dst, src))| {
-                                if (idx + 1) % 4 == 0 {
-                                    *dst = 255;
-                                } else {
-                                    *dst = (*src as f32 * color[idx % 4]) as u8;
-                                }
-                            });
-                    } else {
-                        pixels
-                            .frame_mut()
-                            .copy_from_slice(&buffered_images.back().unwrap().1)
+                if let (Some(progress), Some(previous)) = (fade_progress, previous) {
+                    lerp_frames(frame, previous, shown, progress);
+                    *fading = true;
+                } else if redraw || *fading {
+                    *fading = false;
+                    let path = source.path(*shown_millis);
+                    let what = format_args!("the clock frame {path}", path = path.display());
+                    if !blit(frame, width, height, shown, what) {
+                        return Ok(Damage::None);
                     }
+                } else {
+                    return Ok(Damage::None);
                 }
+
+                let color = color
+                    .as_ref()
+                    .map(|color| color.at(current_millis, day_millis));
+
+                match colorize {
+                    Colorize::Multiply { base } => {
+                        if let Some(color) = color {
+                            tint(frame, color, *base);
+                        }
+                    }
+                    Colorize::Mask { source, base } => {
+                        if base.dimensions() != (width, height) {
+                            // Fitted once, the warning isn't repeated for every frame
+                            let mut fitted = RgbaImage::new(width, height);
+                            blit(&mut fitted, width, height, base, "the mask base");
+                            *base = fitted;
+                        }
+                        colorize_mask(frame, base, color.unwrap_or([1.0; 4]), *source)
+                    }
+                }
+                Ok(Damage::Full)
+            }
+            BackgroundRenderer::ClockHands { hands, clock } => {
+                Ok(hands.render(frame, width, height, clock.now()))
+            }
+            BackgroundRenderer::TextOverlay {
+                template,
+                font,
+                style,
+                text,
+                drawn,
+                clock,
+            } => {
+                let expanded = template.expand(clock.now());
+                if text.as_ref() == Some(&expanded) {
+                    return Ok(Damage::None);
+                }
+
+                let cleared = *drawn;
+                *drawn = draw_text(frame, width, height, font, style, &expanded, cleared);
+                *text = Some(expanded);
+                Ok(cleared.union(*drawn))
+            }
+            BackgroundRenderer::Plugin { plugin, clock } => {
+                let changed = plugin.render(frame, width, height, clock.now().timestamp_millis());
+                Ok(Damage::changed(changed))
+            }
+            BackgroundRenderer::Script { script, clock } => {
+                let changed = script.render(frame, width, height, clock.now().timestamp_millis());
+                Ok(Damage::changed(changed))
+            }
+            BackgroundRenderer::Pipe { pipe } => {
+                Ok(Damage::changed(pipe.poll(frame, width, height)))
+            }
+            BackgroundRenderer::Shm { shared } => {
+                Ok(Damage::changed(shared.poll(frame, width, height)))
+            }
+            BackgroundRenderer::Stack { layers } => {
+                let mut damage = Damage::None;
+                for (layer, layer_frame) in layers.iter_mut() {
+                    damage = damage.union(layer.render_damage(layer_frame, width, height)?);
+                }
+
+                // Only the damaged rows are composited again, the rest of the frame still holds
+                // the layers as they were
+                for row in damage.rows(width, frame.len()) {
+                    let mut layer_frames = layers.iter().map(|(_, layer_frame)| layer_frame);
+                    if let Some(bottom) = layer_frames.next() {
+                        frame[row.clone()].copy_from_slice(&bottom[row.clone()]);
+                    }
+                    for layer_frame in layer_frames {
+                        blend_over(&mut frame[row.clone()], &layer_frame[row.clone()]);
+                    }
+                }
+                Ok(damage)
+            }
+            BackgroundRenderer::Slideshow {
+                playlist,
+                interval,
+                scaling,
+                margin_color,
+                span,
+                path,
+                shown_at,
+                renderer,
+                clock,
+            } => {
+                let now = clock.now();
+                let shown_for = (now - *shown_at).num_milliseconds();
+                // A clock set back shows the next image too instead of waiting for its old time
+                if shown_for >= interval.0 as i64 || shown_for < 0 {
+                    *shown_at = now;
+                    match playlist.next_renderer(*scaling, margin_color, *span, width, height) {
+                        Ok((next_path, next)) => {
+                            *path = next_path;
+                            **renderer = next;
+                        }
+                        Err(error) => eprintln!(
+                            "warning: keeping the current image of the slideshow: {error:#}"
+                        ),
+                    }
+                }
+                renderer.render_damage(frame, width, height)
+            }
+            BackgroundRenderer::Weekly {
+                dir,
+                scaling,
+                margin_color,
+                missing_days: missing,
+                date,
+                path,
+                renderer,
+                clock,
+            } => {
+                // Compared every render so the switch happens however long the daemon runs and
+                // after the clock jumped
+                let today = clock.now().date_naive();
+                if today != *date {
+                    *date = today;
+                    *missing = missing_days(dir);
+                    match day_renderer(
+                        dir,
+                        today.weekday(),
+                        *scaling,
+                        margin_color.clone(),
+                        width,
+                        height,
+                    ) {
+                        Ok((day_path, day)) => {
+                            *path = day_path;
+                            **renderer = day;
+                        }
+                        Err(error) => eprintln!(
+                            "warning: could not show the image of {day}, keeping the previous \
+                             one: {error:#}",
+                            day = day_name(today.weekday()),
+                        ),
+                    }
+                }
+                renderer.render_damage(frame, width, height)
+            }
+            BackgroundRenderer::Schedule {
+                entries,
+                active,
+                renderer,
+                entry_frame,
+                fade,
+                fade_from,
+                span,
+                clock,
+            } => {
+                let now = clock.now();
+                let scheduled = ScheduleEntry::active(entries, day_millis(now, None));
+                if scheduled != *active {
+                    *active = scheduled;
+                    let entry = &entries[scheduled];
+                    match entry.background.clone().into_renderer_with_clock(
+                        width,
+                        height,
+                        *span,
+                        clock.clone(),
+                    ) {
+                        Ok(scheduled) => {
+                            **renderer = scheduled;
+                            entry_frame.fill(0);
+                            *fade_from = (fade.0 > 0).then(|| (frame.to_vec(), now));
+                        }
+                        Err(error) => eprintln!(
+                            "warning: could not show the background scheduled at {start}, \
+                             keeping the previous one: {error:#}",
+                            start = format_time_of_day(entry.start),
+                        ),
+                    }
+                }
+
+                let mut damage = renderer.render_damage(entry_frame, width, height)?;
+                if let Some((from, switched)) = fade_from {
+                    let progress = (now - *switched).num_milliseconds() as f32 / fade.0 as f32;
+                    if (0.0..1.0).contains(&progress) {
+                        lerp_frames(frame, from, entry_frame, progress);
+                        return Ok(Damage::Full);
+                    }
+                    *fade_from = None;
+                    damage = Damage::Full;
+                }
+                damage.copy(frame, entry_frame, width);
+                Ok(damage)
             }
         }
-        Ok(())
     }
 }
 
-fn clock_millis(clock_step: u32) -> u32 {
-    let now = Local::now();
-    let time = now.time();
-    (((time.hour() % 12) * MILLIS_PER_HOUR
+/// Multiply the colors of `frame` with `color` and blend them over `base` by their alpha
+///
+/// The alpha of `color` is the strength of the tint, blending between the untinted colors at 0
+/// and the fully tinted ones at 1.
+pub fn tint(frame: &mut [u8], color: [f32; 4], base: [u8; 3]) {
+    let [r, g, b, strength] = color;
+    let factors = [r, g, b].map(|c| 1.0 - strength + c * strength);
+    frame.chunks_exact_mut(4).for_each(|pixel| {
+        let alpha = pixel[3] as f32 / 255.0;
+        for channel in 0..3 {
+            let tinted = pixel[channel] as f32 * factors[channel];
+            pixel[channel] = (tinted * alpha + base[channel] as f32 * (1.0 - alpha)).round() as u8;
+        }
+        pixel[3] = 255;
+    });
+}
+
+/// Replace each pixel of `frame` by a blend between `base` and `color` weighted by the mask
+/// times the alpha of `color`
+fn colorize_mask(frame: &mut [u8], base: &[u8], color: [f32; 4], source: MaskSource) {
+    let [r, g, b, strength] = color;
+    let color = [r, g, b].map(|c| c * 255.0);
+    frame
+        .par_chunks_mut(LERP_CHUNK_SIZE)
+        .zip(base.par_chunks(LERP_CHUNK_SIZE))
+        .for_each(|(frame, base)| {
+            frame
+                .chunks_exact_mut(4)
+                .zip(base.chunks_exact(4))
+                .for_each(|(dst, base)| {
+                    let alpha = dst[3] as f32 / 255.0;
+                    let mask = strength
+                        * match source {
+                            MaskSource::Alpha => alpha,
+                            MaskSource::Luminance => {
+                                (0.2126 * dst[0] as f32
+                                    + 0.7152 * dst[1] as f32
+                                    + 0.0722 * dst[2] as f32)
+                                    / 255.0
+                                    * alpha
+                            }
+                        };
+                    for channel in 0..3 {
+                        dst[channel] =
+                            (base[channel] as f32 * (1.0 - mask) + color[channel] * mask) as u8;
+                    }
+                    dst[3] = 255;
+                });
+        });
+}
+
+/// Copy the rgba `image` into `frame`, which is `width` x `height` pixels, returning whether
+/// anything was drawn
+///
+/// An image of another size, eg a clock frame rendered for another resolution, is drawn centered
+/// on black, cropped where it is larger, after a warning naming `what` was drawn. A frame of the
+/// wrong length is left as it is.
+pub fn blit(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    image: &RgbaImage,
+    what: impl Display,
+) -> bool {
+    let len = width as usize * height as usize * 4;
+    if frame.len() != len {
+        eprintln!(
+            "warning: not drawing {what}, the {width}x{height} frame holds {actual} bytes instead \
+             of {len}",
+            actual = frame.len()
+        );
+        return false;
+    }
+    let (image_width, image_height) = image.dimensions();
+    if (image_width, image_height) == (width, height) {
+        frame.copy_from_slice(image);
+        return true;
+    }
+    eprintln!(
+        "warning: {what} is {image_width}x{image_height} pixels instead of {width}x{height}, \
+         drawing it centered"
+    );
+    for pixel in frame.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&[0, 0, 0, 255]);
+    }
+    let (x, src_x, columns) = center(width, image_width);
+    let (y, src_y, rows) = center(height, image_height);
+    let image = image.as_raw();
+    for row in 0..rows as usize {
+        let dst = ((y as usize + row) * width as usize + x as usize) * 4;
+        let src = ((src_y as usize + row) * image_width as usize + src_x as usize) * 4;
+        let len = columns as usize * 4;
+        frame[dst..dst + len].copy_from_slice(&image[src..src + len]);
+    }
+    true
+}
+
+/// Where a line of `inner` pixels centered on one of `outer` pixels starts on each, and how many
+/// pixels of it are shown
+fn center(outer: u32, inner: u32) -> (u32, u32, u32) {
+    if inner <= outer {
+        ((outer - inner) / 2, 0, inner)
+    } else {
+        (0, (inner - outer) / 2, outer)
+    }
+}
+
+/// Linearly interpolate between the rgba frames `from` and `to` into `frame`
+pub(crate) fn lerp_frames(frame: &mut [u8], from: &[u8], to: &[u8], progress: f32) {
+    let weight = (progress.clamp(0.0, 1.0) * 256.0) as u32;
+    frame
+        .par_chunks_mut(LERP_CHUNK_SIZE)
+        .zip(from.par_chunks(LERP_CHUNK_SIZE))
+        .zip(to.par_chunks(LERP_CHUNK_SIZE))
+        .for_each(|((frame, from), to)| {
+            frame
+                .iter_mut()
+                .zip(from.iter().zip(to))
+                .for_each(|(dst, (from, to))| {
+                    *dst = ((*from as u32 * (256 - weight) + *to as u32 * weight) >> 8) as u8;
+                });
+        });
+}
+
+/// Alpha blend the rgba pixels of `src` over those of `dst`
+fn blend_over(dst: &mut [u8], src: &[u8]) {
+    dst.chunks_exact_mut(4)
+        .zip(src.chunks_exact(4))
+        .for_each(|(dst, src)| blend_pixel(dst, src));
+}
+
+/// Alpha blend a single rgba pixel `src` over `dst`
+pub fn blend_pixel(dst: &mut [u8], src: &[u8]) {
+    match src[3] {
+        0 => {}
+        255 => dst.copy_from_slice(src),
+        src_alpha => {
+            let src_alpha = src_alpha as u32;
+            let dst_alpha = dst[3] as u32 * (255 - src_alpha) / 255;
+            let out_alpha = src_alpha + dst_alpha;
+            for channel in 0..3 {
+                dst[channel] = ((src[channel] as u32 * src_alpha + dst[channel] as u32 * dst_alpha)
+                    / out_alpha) as u8;
+            }
+            dst[3] = out_alpha as u8;
+        }
+    }
+}
+
+/// The time of day of `now` in milliseconds since midnight, in the given zone or the local one
+pub fn day_millis(now: DateTime<Local>, timezone: Option<&Tz>) -> u32 {
+    // The offset of the zone is looked up anew for every time, following its daylight saving time
+    let time = match timezone {
+        Some(timezone) => now.with_timezone(timezone).time(),
+        None => now.time(),
+    };
+    time.hour() * MILLIS_PER_HOUR
         + time.minute() * MILLIS_PER_MINUTE
         + time.second() * MILLIS_PER_SECOND
-        + now.timestamp_subsec_millis())
-        / clock_step)
-        * clock_step
+        + now.timestamp_subsec_millis()
 }
 
-fn load_clock_image(
-    dir: &Path,
-    file_template: &str,
+/// The earlier of two times until the next frame, `None` standing for never
+fn sooner(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// How far the clock time `to` is ahead of `from`, going forward around the cycle
+fn forward_millis(from: u32, to: u32, cycle: u32) -> u32 {
+    (to + cycle - from) % cycle
+}
+
+/// The clock time for the given time of day shifted by `offset`, rounded down to the clock step
+fn clock_millis(day_millis: u32, cycle: u32, offset: u32, clock_step: u32) -> u32 {
+    (((day_millis % cycle + offset) % cycle) / clock_step) * clock_step
+}
+
+/// Find the nearest earlier frame which can be loaded in place of the frame at `millis`
+#[allow(clippy::too_many_arguments)]
+fn substitute_clock_image(
+    source: &mut FrameSource,
     millis: u32,
+    clock_step: u32,
+    cycle: u32,
+    buffered_images: &VecDeque<(u32, RgbaImage)>,
+    layout: &mut FrameLayout,
     width: u32,
     height: u32,
 ) -> anyhow::Result<RgbaImage> {
-    let mut path = dir.to_path_buf();
-    path.push(format!(
-        "{hour}/{file}",
-        hour = millis / MILLIS_PER_HOUR,
-        file = file_template.replace("%m", &format!("{millis:08}")),
-    ));
-    Ok(image::imageops::resize(
-        &image::open(path)?,
-        width,
-        height,
-        image::imageops::FilterType::Triangle,
-    ))
+    if let Some((_, image)) = buffered_images.front() {
+        return Ok(image.clone());
+    }
+
+    for probe in 1..=MAX_FALLBACK_PROBES {
+        let probe_millis = (millis + cycle - (probe * clock_step) % cycle) % cycle;
+        if let Ok(image) = source.load(probe_millis, layout, width, height) {
+            return Ok(image);
+        }
+    }
+
+    anyhow::bail!("no clock frame found within {MAX_FALLBACK_PROBES} steps before {millis:08}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use chrono::{Duration, Local, TimeZone};
+    use image::{Rgba, RgbaImage};
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::{clock::MockClock, command::Command};
+
+    const STEP: u32 = 100;
+    const CYCLE: u32 = 12 * MILLIS_PER_HOUR;
+
+    /// A directory with single pixel clock frames whose color encodes the clock step they show,
+    /// which is removed with the guard
+    fn frame_dir(millis: impl IntoIterator<Item = u32>) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("frames")).unwrap();
+        for millis in millis {
+            let [_, r, g, b] = (millis / STEP).to_be_bytes();
+            RgbaImage::from_pixel(1, 1, Rgba([r, g, b, 255]))
+                .save(dir.path().join(format!("frames/{millis}.png")))
+                .unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn suggests_the_hour_folder_naming_of_the_clock_dir() {
+        let temp = frame_dir([]);
+        let dir = temp.path();
+        for hour in 0..12 {
+            std::fs::create_dir_all(dir.join(format!("hour_{hour:02}"))).unwrap();
+        }
+        let validate = |hour_dirs: HourDirs, hours| {
+            let template = FrameTemplate::parse("%S.png", &hour_dirs)?;
+            validate_clock_dir(dir, &template, hours, STEP, false)
+        };
+
+        let error = validate(HourDirs::ZeroBased, ClockHours::Twelve).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("looks like it uses --hour-dirs hour_%02d"),
+            "{error}"
+        );
+        validate(
+            HourDirs::Pattern("hour_%02d".to_string()),
+            ClockHours::Twelve,
+        )
+        .unwrap();
+        assert!(validate(HourDirs::ClockFace, ClockHours::TwentyFour).is_err());
+    }
+
+    #[test]
+    fn detects_the_clock_step_of_the_first_hour() {
+        // Every 250ms with one frame missing, and frames of later hours which are ignored
+        let temp = frame_dir(
+            (0..20)
+                .map(|frame| frame * 250)
+                .filter(|millis| *millis != 1000)
+                .chain([MILLIS_PER_HOUR, MILLIS_PER_HOUR + 100]),
+        );
+        let dir = temp.path();
+        let detect = |template: &str| {
+            let template = FrameTemplate::parse(template, &HourDirs::Flat)?;
+            detect_clock_step(dir, &template)
+        };
+        assert_eq!(detect("frames/%m.png").unwrap(), 250);
+        // Padded names don't match the unpadded files
+        assert!(detect("frames/%05m.png").is_err());
+
+        std::fs::write(dir.join("frames/1100.png"), []).unwrap();
+        let error = detect("frames/%m.png").unwrap_err();
+        assert!(error.to_string().contains("but also"), "{error}");
+    }
+
+    fn clock_renderer(dir: PathBuf, clock: &Arc<MockClock>) -> BackgroundRenderer {
+        Command::ClockImage {
+            dir,
+            file_template: "frames/%m.png".to_string(),
+            clock_step: STEP,
+            hours: ClockHours::Twelve,
+            hour_dirs: Default::default(),
+            offset: None,
+            timezone: None,
+            clock_color: None,
+            color_cycle: None,
+            scaling: Scaling::Stretch,
+            margin_color: None,
+            underlay: None,
+            colorize: ColorizeMode::Multiply,
+            mask_source: MaskSource::Alpha,
+            interpolate: None,
+            sheets: false,
+            no_validate: true,
+        }
+        .into_renderer_with_clock(1, 1, None, clock.clone())
+        .unwrap()
+    }
+
+    /// Render a frame and return the clock time it shows
+    fn render_millis(renderer: &mut BackgroundRenderer) -> u32 {
+        let mut frame = [0; 4];
+        renderer.render(&mut frame, 1, 1).unwrap();
+        u32::from_be_bytes([0, frame[0], frame[1], frame[2]]) * STEP
+    }
+
+    fn at(hour: u32, minute: u32, second: u32, millis: i64) -> chrono::DateTime<Local> {
+        Local
+            .with_ymd_and_hms(2024, 1, 15, hour, minute, second)
+            .unwrap()
+            + Duration::milliseconds(millis)
+    }
+
+    #[test]
+    fn tints_by_the_strength_of_the_color() {
+        let pixel = [200, 100, 50, 255];
+        let tinted = |color: [f32; 4]| {
+            let mut frame = pixel.to_vec();
+            tint(&mut frame, color, [0; 3]);
+            frame
+        };
+        assert_eq!(tinted([0.5, 1.0, 0.0, 0.0]), pixel);
+        assert_eq!(tinted([0.5, 1.0, 0.0, 1.0]), [100, 100, 0, 255]);
+        assert_eq!(tinted([0.5, 1.0, 0.0, 0.5]), [150, 100, 25, 255]);
+        assert_eq!(tinted([0.5, 1.0, 0.0, 0.25]), [175, 100, 38, 255]);
+
+        let mask = |strength: f32| {
+            let mut frame = vec![255, 255, 255, 255, 0, 0, 0, 0];
+            let base = [0, 0, 0, 255, 100, 100, 100, 255];
+            colorize_mask(
+                &mut frame,
+                &base,
+                [1.0, 0.0, 0.0, strength],
+                MaskSource::Alpha,
+            );
+            frame
+        };
+        assert_eq!(mask(1.0), [255, 0, 0, 255, 100, 100, 100, 255]);
+        assert_eq!(mask(0.0), [0, 0, 0, 255, 100, 100, 100, 255]);
+        assert_eq!(mask(128.0 / 255.0), [128, 0, 0, 255, 100, 100, 100, 255]);
+    }
+
+    #[test]
+    fn tints_transparent_and_opaque_pixels() {
+        let base = [10, 20, 30];
+        let tinted = |color: [f32; 4]| {
+            let mut frame = vec![200, 100, 50, 0, 200, 100, 50, 128, 200, 100, 50, 255];
+            tint(&mut frame, color, base);
+            frame
+        };
+        let transparent = [10, 20, 30, 255];
+
+        // Transparent pixels show the base, translucent ones are blended over it
+        let fixed = ClockColor::parse("#FF8000", 720.0).unwrap();
+        assert_eq!(
+            tinted(fixed.at(0, 0)),
+            [transparent, [105, 35, 15, 255], [200, 50, 0, 255]].concat()
+        );
+
+        let rainbow = ClockColor::parse("RAINBOW", 60.0).unwrap();
+        assert_eq!(
+            tinted(rainbow.at(0, 0)),
+            [transparent, [105, 10, 15, 255], [200, 0, 0, 255]].concat()
+        );
+        // A third of the way through the hues
+        assert_eq!(
+            tinted(rainbow.at(20 * 60 * 1000, 0)),
+            [transparent, [5, 60, 15, 255], [0, 100, 0, 255]].concat()
+        );
+    }
+
+    #[test]
+    fn reports_when_the_next_frame_is_due() {
+        let clock = Arc::new(MockClock::new(at(10, 0, 0, 0)));
+        let temp = frame_dir([0, 10 * MILLIS_PER_HOUR]);
+        let dir = temp.path();
+        let still = || Command::StaticImage {
+            path: dir.join("frames/0.png"),
+            scaling: Scaling::Stretch,
+            margin_color: None,
+            watch: false,
+            span: false,
+        };
+        let mut stack = Command::Layer {
+            layers: vec![still(), still()],
+        }
+        .into_renderer_with_clock(1, 1, None, clock.clone())
+        .unwrap();
+        assert_eq!(BackgroundRenderer::None.next_frame_in(), None);
+        assert_eq!(stack.next_frame_in(), Some(std::time::Duration::ZERO));
+        stack.render(&mut [0; 4], 1, 1).unwrap();
+        assert_eq!(stack.next_frame_in(), None);
+
+        // Due at the next step boundary, however often it is rendered before
+        let mut clock_image = clock_renderer(dir.to_path_buf(), &clock);
+        clock_image.render(&mut [0; 4], 1, 1).unwrap();
+        clock.advance(Duration::milliseconds(30));
+        clock_image.render(&mut [0; 4], 1, 1).unwrap();
+        assert_eq!(
+            clock_image.next_frame_in(),
+            Some(std::time::Duration::from_millis(70))
+        );
+        clock.advance(Duration::milliseconds(70));
+        assert_eq!(
+            clock_image.next_frame_in(),
+            Some(std::time::Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn draws_images_of_the_wrong_size_centered() {
+        // Larger images are cropped, smaller ones surrounded by black
+        let wide = RgbaImage::from_fn(4, 1, |x, _| Rgba([x as u8, 0, 0, 255]));
+        let mut frame = [9; 8];
+        assert!(blit(&mut frame, 2, 1, &wide, "a wide image"));
+        assert_eq!(frame, [1, 0, 0, 255, 2, 0, 0, 255]);
+        let dot = RgbaImage::from_pixel(1, 1, Rgba([7, 7, 7, 255]));
+        let mut frame = [9; 12];
+        assert!(blit(&mut frame, 1, 3, &dot, "a dot"));
+        assert_eq!(frame, [0, 0, 0, 255, 7, 7, 7, 255, 0, 0, 0, 255]);
+        // A frame of the wrong length is left alone
+        let mut frame = [9; 4];
+        assert!(!blit(&mut frame, 2, 1, &wide, "a wide image"));
+        assert_eq!(frame, [9; 4]);
+
+        let mut still = BackgroundRenderer::StaticImage {
+            path: PathBuf::from("wide.png"),
+            image: wide,
+            redraw: true,
+            watcher: None,
+        };
+        let mut frame = [0; 8];
+        assert!(still.render(&mut frame, 2, 1).unwrap());
+        assert_eq!(frame, [1, 0, 0, 255, 2, 0, 0, 255]);
+
+        // Clock frames of the wrong size are drawn instead of fading from a previous frame of yet
+        // another size
+        let clock = Arc::new(MockClock::new(at(10, 0, 0, 0)));
+        let temp = frame_dir([10 * MILLIS_PER_HOUR]);
+        let dir = temp.path();
+        let mut clock_image = clock_renderer(dir.to_path_buf(), &clock);
+        clock_image.render(&mut [0; 4], 1, 1).unwrap();
+        if let BackgroundRenderer::ClockImage {
+            buffered_images,
+            previous_image,
+            interpolate,
+            fading,
+            ..
+        } = &mut clock_image
+        {
+            for (_, image) in buffered_images.iter_mut() {
+                *image = RgbaImage::from_pixel(3, 1, Rgba([5, 5, 5, 255]));
+            }
+            *previous_image = Some(RgbaImage::new(2, 2));
+            *interpolate = Some(1.0);
+            *fading = true;
+        }
+        let mut frame = [0; 4];
+        assert!(clock_image.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame, [5, 5, 5, 255]);
+
+        // So is the base a masked clock frame is colorized over
+        if let BackgroundRenderer::ClockImage {
+            colorize, fading, ..
+        } = &mut clock_image
+        {
+            *colorize = Colorize::Mask {
+                source: MaskSource::Alpha,
+                base: RgbaImage::from_fn(3, 1, |x, _| Rgba([x as u8, 0, 0, 255])),
+            };
+            *fading = true;
+        }
+        assert!(clock_image.render(&mut frame, 1, 1).unwrap());
+        let BackgroundRenderer::ClockImage {
+            colorize: Colorize::Mask { base, .. },
+            ..
+        } = &clock_image
+        else {
+            unreachable!()
+        };
+        assert_eq!(base.as_raw(), &[1, 0, 0, 255]);
+    }
+
+    #[test]
+    fn clock_millis_rounds_down_to_the_step() {
+        assert_eq!(clock_millis(0, CYCLE, 0, STEP), 0);
+        assert_eq!(clock_millis(99, CYCLE, 0, STEP), 0);
+        assert_eq!(clock_millis(100, CYCLE, 0, STEP), 100);
+        assert_eq!(clock_millis(CYCLE - 1, CYCLE, 0, STEP), CYCLE - STEP);
+        // Steps which don't divide the cycle round down to the last whole step
+        assert_eq!(clock_millis(CYCLE - 1, CYCLE, 0, 7000), 43_197_000);
+    }
+
+    #[test]
+    fn clock_millis_wraps_the_cycle() {
+        assert_eq!(clock_millis(CYCLE, CYCLE, 0, STEP), 0);
+        assert_eq!(clock_millis(CYCLE + 250, CYCLE, 0, STEP), 200);
+        assert_eq!(clock_millis(CYCLE - 50, CYCLE, 100, STEP), 0);
+        assert_eq!(
+            clock_millis(CYCLE - 50, CYCLE, CYCLE - 1, STEP),
+            CYCLE - 100
+        );
+    }
+
+    #[test]
+    fn day_millis_reads_the_clock() {
+        assert_eq!(day_millis(at(0, 0, 0, 0), None), 0);
+        assert_eq!(
+            day_millis(at(23, 59, 59, 999), None),
+            24 * MILLIS_PER_HOUR - 1
+        );
+        assert_eq!(
+            day_millis(at(13, 2, 3, 4), None),
+            13 * MILLIS_PER_HOUR + 2 * MILLIS_PER_MINUTE + 3 * MILLIS_PER_SECOND + 4
+        );
+    }
+
+    #[test]
+    fn evicts_frames_across_the_cycle_wrap() {
+        let frames = (CYCLE - 10 * STEP..CYCLE)
+            .chain(0..20 * STEP)
+            .step_by(STEP as usize);
+        let clock = Arc::new(MockClock::new(at(11, 59, 59, 950)));
+        let temp = frame_dir(frames);
+        let dir = temp.path();
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+
+        assert_eq!(render_millis(&mut renderer), CYCLE - STEP);
+        assert_eq!(renderer.buffered_frames(), Some(PRE_BUFFERED_IMAGES));
+
+        clock.advance(Duration::milliseconds(STEP as i64));
+        assert_eq!(render_millis(&mut renderer), 0);
+        // Only the frame before midnight was dropped, the buffer was refilled by one
+        assert_eq!(renderer.buffered_frames(), Some(PRE_BUFFERED_IMAGES));
+
+        clock.advance(Duration::milliseconds(STEP as i64));
+        assert_eq!(render_millis(&mut renderer), STEP);
+    }
+
+    /// Render each of the clock `frames` at its time, checking it is shown, and remove the files of
+    /// buffered frames so a frame loaded twice would be substituted
+    fn step_through(
+        renderer: &mut BackgroundRenderer,
+        clock: &MockClock,
+        dir: &Path,
+        frames: &[u32],
+    ) {
+        for (idx, &expected) in frames.iter().enumerate() {
+            assert_eq!(render_millis(renderer), expected, "frame {idx}");
+            let BackgroundRenderer::ClockImage {
+                buffered_images,
+                missing_frames,
+                ..
+            } = renderer
+            else {
+                unreachable!();
+            };
+            assert_eq!(
+                missing_frames.substituted, 0,
+                "frame {idx} was loaded twice"
+            );
+            for (millis, _) in buffered_images.iter() {
+                let _ = std::fs::remove_file(dir.join(format!("frames/{millis}.png")));
+            }
+            if let Some(next) = frames.get(idx + 1) {
+                clock.advance(Duration::milliseconds(
+                    ((next + CYCLE - expected) % CYCLE) as i64,
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn loads_every_frame_once_across_the_cycle_wrap() {
+        let frames: Vec<u32> = (CYCLE - 20 * STEP..CYCLE)
+            .chain(0..20 * STEP)
+            .step_by(STEP as usize)
+            .collect();
+        let temp = frame_dir(frames.iter().copied().chain((20..30).map(|idx| idx * STEP)));
+        let dir = temp.path();
+        let clock = Arc::new(MockClock::new(at(11, 59, 58, 0)));
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+        step_through(&mut renderer, &clock, dir, &frames);
+    }
+
+    #[test]
+    fn loads_every_frame_once_skipping_steps_across_the_cycle_wrap() {
+        let frames: Vec<u32> = (CYCLE - 20 * STEP..CYCLE)
+            .chain(0..40 * STEP)
+            .step_by(STEP as usize)
+            .collect();
+        let temp = frame_dir(frames.iter().copied());
+        let dir = temp.path();
+        let clock = Arc::new(MockClock::new(at(11, 59, 58, 0)));
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+        let shown: Vec<u32> = frames[..30].iter().step_by(3).copied().collect();
+        step_through(&mut renderer, &clock, dir, &shown);
+    }
+
+    #[test]
+    fn measures_clock_times_around_the_cycle() {
+        assert_eq!(forward_millis(CYCLE - STEP, 0, CYCLE), STEP);
+        assert_eq!(forward_millis(0, CYCLE - STEP, CYCLE), CYCLE - STEP);
+        assert_eq!(forward_millis(500, 500, CYCLE), 0);
+    }
+
+    #[test]
+    fn keeps_the_buffer_when_skipping_a_few_steps() {
+        let clock = Arc::new(MockClock::new(at(1, 0, 0, 0)));
+        let start = MILLIS_PER_HOUR;
+        let frames = (start..start + 20 * STEP).step_by(STEP as usize);
+        let temp = frame_dir(frames);
+        let dir = temp.path();
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+
+        assert_eq!(render_millis(&mut renderer), start);
+        clock.advance(Duration::milliseconds((MAX_SKIPPED_STEPS * STEP) as i64));
+        assert_eq!(
+            render_millis(&mut renderer),
+            start + MAX_SKIPPED_STEPS * STEP
+        );
+        assert_eq!(renderer.buffered_frames(), Some(PRE_BUFFERED_IMAGES));
+    }
+
+    #[test]
+    fn resyncs_after_a_forward_jump() {
+        let clock = Arc::new(MockClock::new(at(1, 0, 0, 0)));
+        let frames = [
+            MILLIS_PER_HOUR,
+            3 * MILLIS_PER_HOUR + 30 * MILLIS_PER_MINUTE,
+        ];
+        let temp = frame_dir(frames);
+        let dir = temp.path();
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+        render_millis(&mut renderer);
+
+        clock.set(at(3, 30, 0, 0));
+        assert_eq!(render_millis(&mut renderer), frames[1]);
+        // Only the current frame is loaded right after a jump
+        assert_eq!(renderer.buffered_frames(), Some(1));
+    }
+
+    #[test]
+    fn resyncs_after_a_backward_jump() {
+        let clock = Arc::new(MockClock::new(at(5, 0, 0, 0)));
+        let frames = [
+            5 * MILLIS_PER_HOUR,
+            4 * MILLIS_PER_HOUR + 59 * MILLIS_PER_MINUTE,
+        ];
+        let temp = frame_dir(frames);
+        let dir = temp.path();
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+        render_millis(&mut renderer);
+
+        clock.advance(Duration::minutes(-1));
+        assert_eq!(render_millis(&mut renderer), frames[1]);
+        assert_eq!(renderer.buffered_frames(), Some(1));
+    }
+
+    /// Render a clock whose time of day jumps by `jump` two steps after `start`, by the offset of
+    /// `timezone` changing or by the system clock being stepped without one, checking every frame
+    /// shows the time of day of the zone
+    fn cross_time_jump(timezone: Option<Tz>, start: chrono::DateTime<Local>, jump: Duration) {
+        let start = start - Duration::milliseconds(2 * STEP as i64);
+        let shown = |now| clock_millis(day_millis(now, timezone.as_ref()), CYCLE, 0, STEP);
+        let frames = (0..20)
+            .flat_map(|idx| [start, start + jump].map(|time| (time, idx)))
+            .map(|(time, idx)| shown(time + Duration::milliseconds((idx * STEP) as i64)));
+        let temp = frame_dir(frames);
+        let dir = temp.path();
+
+        let clock = Arc::new(MockClock::new(start));
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+        if let BackgroundRenderer::ClockImage { timezone: zone, .. } = &mut renderer {
+            *zone = timezone;
+        }
+        for step in 0..6 {
+            assert_eq!(
+                render_millis(&mut renderer),
+                shown(clock.now()),
+                "step {step}"
+            );
+            if step == 2 {
+                // The buffered frames are of the time before the jump, only the current one is
+                // loaded
+                assert_eq!(renderer.buffered_frames(), Some(1));
+            }
+            let stepped = if timezone.is_none() && step == 1 {
+                jump
+            } else {
+                Duration::zero()
+            };
+            clock.advance(Duration::milliseconds(STEP as i64) + stepped);
+        }
+        assert_eq!(renderer.buffered_frames(), Some(PRE_BUFFERED_IMAGES));
+    }
+
+    /// The local time of a utc date and time
+    fn utc(month: u32, day: u32, hour: u32) -> chrono::DateTime<Local> {
+        chrono::Utc
+            .with_ymd_and_hms(2024, month, day, hour, 0, 0)
+            .unwrap()
+            .with_timezone(&Local)
+    }
+
+    #[test]
+    fn resyncs_when_daylight_saving_time_starts() {
+        // Summer time in Berlin started at 01:00 utc on March 31st 2024
+        cross_time_jump(Some(Tz::Europe__Berlin), utc(3, 31, 1), Duration::hours(1));
+    }
+
+    #[test]
+    fn resyncs_when_daylight_saving_time_ends() {
+        // The clock runs an hour backwards, at 01:00 utc on October 27th 2024 in Berlin
+        cross_time_jump(
+            Some(Tz::Europe__Berlin),
+            utc(10, 27, 1),
+            Duration::hours(-1),
+        );
+    }
+
+    #[test]
+    fn resyncs_after_a_time_adjustment() {
+        // Like ntp stepping a clock which ran two seconds ahead
+        cross_time_jump(None, at(1, 0, 0, 0), Duration::seconds(-2));
+    }
+
+    #[test]
+    fn resyncs_after_jumping_a_whole_cycle_minus_a_step() {
+        // Going back a single step looks like almost a whole cycle forward
+        let clock = Arc::new(MockClock::new(at(2, 0, 0, 0)));
+        let frames = [2 * MILLIS_PER_HOUR - STEP, 2 * MILLIS_PER_HOUR];
+        let temp = frame_dir(frames);
+        let dir = temp.path();
+        let mut renderer = clock_renderer(dir.to_path_buf(), &clock);
+        assert_eq!(render_millis(&mut renderer), frames[1]);
+
+        clock.advance(Duration::milliseconds(-(STEP as i64)));
+        assert_eq!(render_millis(&mut renderer), frames[0]);
+        assert_eq!(renderer.buffered_frames(), Some(1));
+    }
+
+    /// A schedule of single pixel images in `dir`, a dark one from 19:00 and a light one from 07:00
+    fn day_night_schedule(dir: &Path, fade: u64, clock: &Arc<MockClock>) -> BackgroundRenderer {
+        let image = |name: &str, value: u8| {
+            let path = dir.join(name);
+            RgbaImage::from_pixel(1, 1, Rgba([value, value, value, 255]))
+                .save(&path)
+                .unwrap();
+            Command::StaticImage {
+                path,
+                scaling: Scaling::Stretch,
+                margin_color: None,
+                watch: false,
+                span: false,
+            }
+        };
+        let entries = vec![
+            ScheduleEntry {
+                start: 7 * MILLIS_PER_HOUR,
+                background: image("light.png", 200),
+            },
+            ScheduleEntry {
+                start: 19 * MILLIS_PER_HOUR,
+                background: image("dark.png", 0),
+            },
+        ];
+        Command::Schedule {
+            entries,
+            fade: DurationArg(fade),
+        }
+        .into_renderer_with_clock(1, 1, None, clock.clone())
+        .unwrap()
+    }
+
+    #[test]
+    fn schedule_shows_the_entry_of_the_time_of_day() {
+        let temp = frame_dir([]);
+        let dir = temp.path();
+        let clock = Arc::new(MockClock::new(at(3, 0, 0, 0)));
+        let mut renderer = day_night_schedule(dir, 0, &clock);
+        let mut frame = [0; 4];
+
+        // Before the first entry the last one of the previous day is shown
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 0);
+        assert!(!renderer.render(&mut frame, 1, 1).unwrap());
+
+        clock.set(at(7, 0, 0, 0));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 200);
+
+        clock.set(at(18, 59, 59, 999));
+        assert!(!renderer.render(&mut frame, 1, 1).unwrap());
+        clock.set(at(19, 0, 0, 0));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 0);
+    }
+
+    #[test]
+    fn schedule_cross_fades_between_entries() {
+        let temp = frame_dir([]);
+        let dir = temp.path();
+        let clock = Arc::new(MockClock::new(at(6, 59, 59, 0)));
+        let mut renderer = day_night_schedule(dir, 1000, &clock);
+        let mut frame = [0; 4];
+        renderer.render(&mut frame, 1, 1).unwrap();
+
+        clock.advance(Duration::seconds(1));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 0);
+        clock.advance(Duration::milliseconds(500));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 100);
+        clock.advance(Duration::milliseconds(500));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 200);
+        assert!(!renderer.render(&mut frame, 1, 1).unwrap());
+    }
+
+    #[test]
+    fn weekly_switches_at_midnight_after_weeks() {
+        let temp = frame_dir([]);
+        let dir = temp.path();
+        for (name, value) in [("monday.png", 10), ("default.png", 99)] {
+            RgbaImage::from_pixel(1, 1, Rgba([value, value, value, 255]))
+                .save(dir.join(name))
+                .unwrap();
+        }
+        // 2024-01-15 is a monday
+        let clock = Arc::new(MockClock::new(at(23, 59, 59, 900)));
+        let mut renderer = Command::Weekly {
+            dir: dir.to_path_buf(),
+            scaling: Scaling::Stretch,
+            margin_color: None,
+        }
+        .into_renderer_with_clock(1, 1, None, clock.clone())
+        .unwrap();
+        let mut frame = [0; 4];
+
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 10);
+        clock.advance(Duration::milliseconds(100));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 99);
+        assert!(renderer.status().contains("tuesday, wednesday"));
+
+        clock.advance(Duration::weeks(3) + Duration::days(6));
+        assert!(renderer.render(&mut frame, 1, 1).unwrap());
+        assert_eq!(frame[0], 10);
+        assert!(!renderer.render(&mut frame, 1, 1).unwrap());
+    }
 }
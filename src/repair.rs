@@ -0,0 +1,248 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use rayon::prelude::*;
+
+use crate::{
+    cache::Progress, check::expected_frames, command::RepairArgs, decode, render::lerp_frames,
+};
+
+/// The file in a clock directory listing the frames written by `repair-clock-dir`, one path
+/// relative to the directory per line
+pub const INTERPOLATED_LIST: &str = ".interpolated-frames";
+
+/// What repairing a clock directory did
+pub struct RepairReport {
+    /// The file the written frames were added to
+    pub list: PathBuf,
+    /// The frames which were interpolated
+    pub written: Vec<PathBuf>,
+    /// The first frame and length of the gaps longer than the maximum, which stay missing
+    pub skipped: Vec<(PathBuf, usize)>,
+    /// The first frame of the gaps which couldn't be interpolated, with the error
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+/// Consecutive missing frames, by the index of the first one
+#[derive(Debug, PartialEq)]
+struct Gap {
+    start: usize,
+    len: usize,
+}
+
+/// The gaps between the `present` frames of a cycle, a gap at the end continues at the start
+///
+/// There are none if no frame is present, as there is nothing to interpolate from.
+fn gaps(present: &[bool]) -> Vec<Gap> {
+    let count = present.len();
+    let Some(first) = present.iter().position(|present| *present) else {
+        return Vec::new();
+    };
+    let mut gaps = Vec::new();
+    let mut len = 0;
+    // Ends at the first present frame again, closing a gap which wraps around
+    for offset in 1..=count {
+        let index = (first + offset) % count;
+        if !present[index] {
+            len += 1;
+        } else if len > 0 {
+            gaps.push(Gap {
+                start: (index + count - len) % count,
+                len,
+            });
+            len = 0;
+        }
+    }
+    gaps
+}
+
+/// Write the frames of `gap` blended from the frames before and after it, weighted by how close
+/// they are to each, adding the written ones to `written`
+fn fill(
+    frames: &[PathBuf],
+    gap: &Gap,
+    progress: &Progress,
+    written: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let count = frames.len();
+    let open = |path: &Path| anyhow::Ok(decode::open(path)?.into_rgba8());
+    let (before, after) = (
+        &frames[(gap.start + count - 1) % count],
+        &frames[(gap.start + gap.len) % count],
+    );
+    let (from, to) = (open(before)?, open(after)?);
+    if from.dimensions() != to.dimensions() {
+        bail!(
+            "{before} and {after} have different sizes, {from:?} and {to:?}",
+            before = before.display(),
+            after = after.display(),
+            from = from.dimensions(),
+            to = to.dimensions(),
+        );
+    }
+
+    let mut frame = RgbaImage::new(from.width(), from.height());
+    for step in 1..=gap.len {
+        let path = &frames[(gap.start + step - 1) % count];
+        lerp_frames(&mut frame, &from, &to, step as f32 / (gap.len + 1) as f32);
+        write_frame(path, &frame)?;
+        written.push(path.clone());
+        progress.advance();
+    }
+    Ok(())
+}
+
+/// Save `frame` to `path` in the format its extension names
+///
+/// The frame is written to a partial file which is renamed once it is complete, so an interrupted
+/// repair never leaves a truncated frame.
+fn write_frame(path: &Path, frame: &RgbaImage) -> anyhow::Result<()> {
+    let format = ImageFormat::from_path(path)
+        .with_context(|| format!("could not tell the image format of {}", path.display()))?;
+    let image = match format {
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(frame.clone()).into())
+        }
+        _ => DynamicImage::ImageRgba8(frame.clone()),
+    };
+    if let Some(folder) = path.parent() {
+        std::fs::create_dir_all(folder)
+            .with_context(|| format!("could not create {}", folder.display()))?;
+    }
+    let partial = path.with_file_name(format!(
+        ".{name}.partial",
+        name = path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    image
+        .save_with_format(&partial, format)
+        .with_context(|| format!("could not write {}", partial.display()))?;
+    std::fs::rename(&partial, path).with_context(|| format!("could not write {}", path.display()))
+}
+
+/// Interpolate the missing frames of the clock of `args` from the frames around them, at the
+/// size of those frames, and add them to the [`INTERPOLATED_LIST`] of the clock directory
+pub fn repair(args: RepairArgs) -> anyhow::Result<RepairReport> {
+    let RepairArgs {
+        frames: clock,
+        max_gap,
+    } = args;
+    let frames = expected_frames(&clock)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(clock.jobs.unwrap_or(0))
+        .build()
+        .context("could not start the workers")?;
+    let present: Vec<bool> = pool.install(|| frames.par_iter().map(|path| path.exists()).collect());
+    let (gaps, skipped): (Vec<Gap>, Vec<Gap>) = gaps(&present)
+        .into_iter()
+        .partition(|gap| gap.len <= max_gap);
+    let missing = gaps.iter().map(|gap| gap.len).sum();
+    if missing > 0 {
+        eprintln!(
+            "interpolating {missing} frames in {dir}",
+            dir = clock.dir.display()
+        );
+    }
+
+    let progress = Progress::new(missing);
+    let results: Vec<(Vec<PathBuf>, anyhow::Result<()>)> = pool.install(|| {
+        gaps.par_iter()
+            .map(|gap| {
+                let mut written = Vec::new();
+                let filled = fill(&frames, gap, &progress, &mut written);
+                (written, filled)
+            })
+            .collect()
+    });
+
+    let mut report = RepairReport {
+        list: clock.dir.join(INTERPOLATED_LIST),
+        written: Vec::new(),
+        skipped: skipped
+            .into_iter()
+            .map(|gap| (frames[gap.start].clone(), gap.len))
+            .collect(),
+        failed: Vec::new(),
+    };
+    for (gap, (written, filled)) in gaps.iter().zip(results) {
+        report.written.extend(written);
+        if let Err(error) = filled {
+            report
+                .failed
+                .push((frames[gap.start].clone(), format!("{error:#}")));
+        }
+    }
+
+    if !report.written.is_empty() {
+        let mut list = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&report.list)
+            .with_context(|| format!("could not open {}", report.list.display()))?;
+        for path in &report.written {
+            let path = path.strip_prefix(&clock.dir).unwrap_or(path);
+            writeln!(list, "{path}", path = path.display())
+                .with_context(|| format!("could not write {}", report.list.display()))?;
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::{command::CheckArgs, render::ClockHours, template::HourDirs};
+
+    #[test]
+    fn finds_gaps_around_the_cycle() {
+        let present = |frames: &str| frames.chars().map(|c| c == '#').collect::<Vec<_>>();
+        assert_eq!(
+            gaps(&present("#..#.#")),
+            [Gap { start: 1, len: 2 }, Gap { start: 4, len: 1 }]
+        );
+        assert_eq!(gaps(&present("..##.")), [Gap { start: 4, len: 3 }]);
+        assert_eq!(gaps(&present("###")), []);
+        assert_eq!(gaps(&present("...")), []);
+    }
+
+    #[test]
+    fn blends_missing_frames_from_their_neighbors() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        // Half hour steps through 12 hours, with frame 3 missing, frames 10 to 14 missing and the
+        // last frame missing, which is blended with the first one
+        for frame in (0..24).filter(|frame| ![3, 10, 11, 12, 13, 14, 23].contains(frame)) {
+            RgbaImage::from_pixel(4, 2, Rgba([frame * 10, 0, 0, 255]))
+                .save(dir.join(format!("{:02}{:02}.png", frame / 2, frame % 2 * 30)))
+                .unwrap();
+        }
+        let mut report = repair(RepairArgs {
+            frames: CheckArgs {
+                dir: dir.to_path_buf(),
+                file_template: "%H%M.png".to_string(),
+                clock_step: 30 * 60 * 1000,
+                hours: ClockHours::Twelve,
+                hour_dirs: HourDirs::Flat,
+                jobs: Some(2),
+            },
+            max_gap: 3,
+        })
+        .unwrap();
+        report.written.sort();
+        assert_eq!(report.written, [dir.join("0130.png"), dir.join("1130.png")]);
+        assert_eq!(report.skipped, [(dir.join("0500.png"), 5)]);
+        assert!(report.failed.is_empty());
+        let red = |name: &str| image::open(dir.join(name)).unwrap().to_rgba8()[(0, 0)][0];
+        assert_eq!(red("0130.png"), 30);
+        assert_eq!(red("1130.png"), 110);
+        let list = std::fs::read_to_string(dir.join(INTERPOLATED_LIST)).unwrap();
+        assert_eq!(list.lines().count(), 2);
+        assert!(list.contains("0130.png\n"));
+    }
+}
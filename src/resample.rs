@@ -0,0 +1,328 @@
+use std::{
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use image::{
+    codecs::png::{self, PngDecoder, PngEncoder},
+    imageops::FilterType,
+    DynamicImage, ImageFormat,
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{cache::Progress, command::ResampleArgs, decode};
+
+/// The filter frames are resized with
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum ResampleFilter {
+    /// The nearest pixel, the fastest
+    Nearest,
+    /// Linear, like the desktop program scales frames
+    Triangle,
+    /// Cubic, sharper than linear
+    CatmullRom,
+    /// A gaussian blur, the softest
+    Gaussian,
+    /// The sharpest and slowest
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResampleFilter> for FilterType {
+    fn from(filter: ResampleFilter) -> Self {
+        match filter {
+            ResampleFilter::Nearest => FilterType::Nearest,
+            ResampleFilter::Triangle => FilterType::Triangle,
+            ResampleFilter::CatmullRom => FilterType::CatmullRom,
+            ResampleFilter::Gaussian => FilterType::Gaussian,
+            ResampleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// The format resampled frames are written in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Encoding {
+    /// The format of the source frame
+    #[default]
+    Keep,
+    /// QOI files with the extension `.qoi`, which decode several times faster than PNG
+    Qoi,
+    /// PNG files with the fastest compression and no filtering
+    Png,
+}
+
+impl Encoding {
+    /// The format and extension a frame read from `path` is written in
+    fn of(self, path: &Path) -> anyhow::Result<(ImageFormat, &'static str)> {
+        match self {
+            Encoding::Keep => {
+                let format = ImageFormat::from_path(path).with_context(|| {
+                    format!("could not tell the image format of {}", path.display())
+                })?;
+                Ok((format, ""))
+            }
+            Encoding::Qoi => Ok((ImageFormat::Qoi, "qoi")),
+            Encoding::Png => Ok((ImageFormat::Png, "png")),
+        }
+    }
+}
+
+/// What resampling a clock directory did
+pub struct ResampleReport {
+    /// How many frames were resampled
+    pub written: usize,
+    /// How many frames were resampled before and haven't changed since
+    pub skipped: usize,
+    /// Animated files, which are copied unchanged
+    pub copied: usize,
+    /// The frames which couldn't be resampled, with the error
+    pub failed: Vec<(PathBuf, String)>,
+    /// How many bytes of source frames were read
+    pub read: u64,
+    /// How long resampling took
+    pub elapsed: Duration,
+}
+
+/// The smallest size of the aspect ratio of `size` covering `width` x `height`, or `size` if it
+/// is that small already
+///
+/// Frames of this size are scaled down or cropped by the desktop program with any scaling, never
+/// up.
+fn cover_size(size: (u32, u32), width: u32, height: u32) -> (u32, u32) {
+    let scale = (width as f64 / size.0 as f64).max(height as f64 / size.1 as f64);
+    if scale >= 1.0 {
+        return size;
+    }
+    (
+        ((size.0 as f64 * scale).ceil() as u32).max(width),
+        ((size.1 as f64 * scale).ceil() as u32).max(height),
+    )
+}
+
+/// The paths of the frame files in `dir` and its folders relative to `root`, leaving out hidden
+/// files like the partial files of interrupted runs
+fn frame_files(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let entries =
+        std::fs::read_dir(dir).with_context(|| format!("could not read {}", dir.display()))?;
+    for entry in entries {
+        let path = entry
+            .with_context(|| format!("could not read {}", dir.display()))?
+            .path();
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            frame_files(root, &path, files)?;
+        } else if ImageFormat::from_path(&path).is_ok() {
+            files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file at `path` is an animated GIF or APNG file
+fn is_animated(path: &Path) -> anyhow::Result<bool> {
+    Ok(match ImageFormat::from_path(path) {
+        Ok(ImageFormat::Gif) => true,
+        Ok(ImageFormat::Png) => PngDecoder::new(Cursor::new(decode::read(path)?))?.is_apng()?,
+        _ => false,
+    })
+}
+
+/// Whether `target` was written since `source` was last changed
+///
+/// Files changed within the resolution of the file times look the same age, the target counts as
+/// up to date then as it is only written after its source.
+fn is_up_to_date(source: &Path, target: &Path) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    matches!(
+        (modified(source), modified(target)),
+        (Ok(source), Ok(target)) if target >= source
+    )
+}
+
+/// Encode `image` in `format`, PNG files with the fastest compression
+fn encode(image: DynamicImage, format: ImageFormat) -> anyhow::Result<Vec<u8>> {
+    let image = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.to_rgb8()),
+        _ if image.color().has_alpha() => DynamicImage::ImageRgba8(image.to_rgba8()),
+        _ => DynamicImage::ImageRgb8(image.to_rgb8()),
+    };
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new_with_quality(
+            &mut bytes,
+            png::CompressionType::Fast,
+            png::FilterType::NoFilter,
+        ))?,
+        format => image.write_to(&mut Cursor::new(&mut bytes), format)?,
+    }
+    Ok(bytes)
+}
+
+/// Write `bytes` to a partial file which is renamed to `path` once it is complete, so an
+/// interrupted run never leaves a truncated frame
+fn write(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let partial = path.with_file_name(format!(
+        ".{name}.partial",
+        name = path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    std::fs::write(&partial, bytes)
+        .with_context(|| format!("could not write {}", partial.display()))?;
+    std::fs::rename(&partial, path).with_context(|| format!("could not write {}", path.display()))
+}
+
+/// How resampling one frame file went
+enum Resampled {
+    Written,
+    Skipped,
+    Copied,
+}
+
+/// Resample the frame at `relative` within `src` into the same place within `dst`
+fn resample_frame(
+    args: &ResampleArgs,
+    relative: &Path,
+    read: &AtomicU64,
+) -> anyhow::Result<Resampled> {
+    let source = args.src.join(relative);
+    let (format, extension) = args.encode.of(&source)?;
+    let animated = is_animated(&source)?;
+    let mut target = args.dst.join(relative);
+    if !animated && !extension.is_empty() {
+        target.set_extension(extension);
+    }
+    if is_up_to_date(&source, &target) {
+        return Ok(Resampled::Skipped);
+    }
+    if let Some(folder) = target.parent() {
+        std::fs::create_dir_all(folder)
+            .with_context(|| format!("could not create {}", folder.display()))?;
+    }
+    read.fetch_add(
+        std::fs::metadata(&source).map_or(0, |metadata| metadata.len()),
+        Ordering::Relaxed,
+    );
+
+    // Resizing would keep only the first frame
+    if animated {
+        write(&target, &decode::read(&source)?)?;
+        return Ok(Resampled::Copied);
+    }
+
+    let image = decode::open_scaled(&source, |size| cover_size(size, args.width, args.height))?;
+    let (width, height) = cover_size((image.width(), image.height()), args.width, args.height);
+    let image = if (image.width(), image.height()) == (width, height) {
+        image
+    } else {
+        image.resize_exact(width, height, args.filter.into())
+    };
+    write(&target, &encode(image, format)?)?;
+    Ok(Resampled::Written)
+}
+
+/// Resize every frame in the clock directory `src` to cover the resolution of `args` and write it
+/// to the same folder and name in `dst`
+///
+/// Frames written before whose source hasn't changed since are skipped, so an interrupted run
+/// continues where it stopped.
+pub fn resample(args: ResampleArgs) -> anyhow::Result<ResampleReport> {
+    let started = Instant::now();
+    let mut files = Vec::new();
+    frame_files(&args.src, &args.src, &mut files)?;
+    files.sort();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.unwrap_or(0))
+        .build()
+        .context("could not start the workers")?;
+    let progress = Progress::new(files.len());
+    let read = AtomicU64::new(0);
+    let results: Vec<anyhow::Result<Resampled>> = pool.install(|| {
+        files
+            .par_iter()
+            .map(|relative| {
+                let resampled = resample_frame(&args, relative, &read);
+                progress.advance();
+                resampled
+            })
+            .collect()
+    });
+
+    let mut report = ResampleReport {
+        written: 0,
+        skipped: 0,
+        copied: 0,
+        failed: Vec::new(),
+        read: read.into_inner(),
+        elapsed: Duration::ZERO,
+    };
+    for (relative, resampled) in files.into_iter().zip(results) {
+        match resampled {
+            Ok(Resampled::Written) => report.written += 1,
+            Ok(Resampled::Skipped) => report.skipped += 1,
+            Ok(Resampled::Copied) => report.copied += 1,
+            Err(error) => report
+                .failed
+                .push((args.src.join(relative), format!("{error:#}"))),
+        }
+    }
+    report.elapsed = started.elapsed();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn resamples_frames_into_the_same_folders() {
+        assert_eq!(cover_size((3840, 2160), 2560, 1440), (2560, 1440));
+        assert_eq!(cover_size((1000, 1000), 400, 200), (400, 400));
+        assert_eq!(cover_size((100, 50), 400, 200), (100, 50));
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("src/0")).unwrap();
+        for minute in 0..2 {
+            RgbaImage::from_pixel(8, 4, Rgba([200, 100, 0, 255]))
+                .save(dir.join(format!("src/0/{minute:02}.png")))
+                .unwrap();
+        }
+        let args = |encode| ResampleArgs {
+            src: dir.join("src"),
+            dst: dir.join("dst"),
+            width: 4,
+            height: 2,
+            filter: ResampleFilter::Triangle,
+            encode,
+            jobs: Some(2),
+        };
+
+        let report = resample(args(Encoding::Keep)).unwrap();
+        assert_eq!((report.written, report.skipped), (2, 0));
+        let frame = image::open(dir.join("dst/0/01.png")).unwrap();
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+        assert_eq!(frame.to_rgba8()[(1, 1)], Rgba([200, 100, 0, 255]));
+
+        // Frames converted before are skipped
+        let report = resample(args(Encoding::Keep)).unwrap();
+        assert_eq!((report.written, report.skipped), (0, 2));
+
+        let report = resample(args(Encoding::Qoi)).unwrap();
+        assert_eq!(report.written, 2);
+        let frame = image::open(dir.join("dst/0/00.qoi")).unwrap();
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    convert::Infallible,
+    ffi::OsString,
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::{net::UnixStream, process::CommandExt},
+    },
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use interprocess::local_socket::LocalSocketStream;
+
+use crate::{
+    ipc::{write_message, Reply},
+    signals,
+    state::SavedState,
+};
+
+/// The file descriptor of the connection the restart was requested on, answered by the restarted
+/// daemon once it shows the background
+const REPLY_VAR: &str = "DESKTOP_BACKGROUND_RESTART_REPLY_FD";
+/// The backgrounds shown before the restart, in the format of the state file
+const STATE_VAR: &str = "DESKTOP_BACKGROUND_RESTART_STATE";
+
+/// What a daemon started by [`exec`] takes over from the one it replaced
+pub struct Handover {
+    pub state: Option<SavedState>,
+    reply: Option<UnixStream>,
+}
+
+/// Read a file descriptor number from the environment variable `name`
+fn take_fd(name: &str) -> Option<RawFd> {
+    let fd = std::env::var(name).ok()?.parse().ok();
+    std::env::remove_var(name);
+    fd
+}
+
+impl Handover {
+    /// Take the handover out of the environment if the process was started by [`exec`], the
+    /// variables are removed so programs started by the daemon don't see them
+    ///
+    /// Must be called before any other thread is started.
+    pub fn take() -> Option<Self> {
+        let reply = take_fd(REPLY_VAR);
+        let state = std::env::var(STATE_VAR).ok();
+        std::env::remove_var(STATE_VAR);
+        if reply.is_none() && state.is_none() {
+            return None;
+        }
+
+        // SAFETY: the descriptor was left open by the replaced daemon for this process alone
+        let reply = reply.map(|fd| unsafe { UnixStream::from_raw_fd(fd) });
+        let state = state.and_then(|json| {
+            SavedState::from_json(&json, "the replaced desktop program")
+                .map_err(|error| {
+                    eprintln!("warning: could not take over the backgrounds: {error:#}")
+                })
+                .ok()
+        });
+        Some(Handover { state, reply })
+    }
+
+    /// Answer the restart request, once the restarted daemon is running or failed to start
+    pub fn reply(&mut self, reply: &Reply) {
+        if let Some(mut stream) = self.reply.take() {
+            if let Err(error) = write_message(&mut stream, reply) {
+                eprintln!("could not send reply: {error}");
+            }
+        }
+    }
+}
+
+/// Set or clear the close-on-exec flag of `fd`
+fn set_cloexec(fd: RawFd, cloexec: bool) -> std::io::Result<()> {
+    // SAFETY: fcntl only reads and changes the flags of the descriptor
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let flags = if cloexec {
+            flags | libc::FD_CLOEXEC
+        } else {
+            flags & !libc::FD_CLOEXEC
+        };
+        if libc::fcntl(fd, libc::F_SETFD, flags) < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The program the daemon was started as and its arguments
+///
+/// The program is taken from the command line rather than the running executable, which is
+/// already gone if the binary was replaced by an upgrade.
+fn command_line() -> anyhow::Result<(OsString, Vec<OsString>)> {
+    let mut args = std::env::args_os();
+    let program = args
+        .next()
+        .context("the desktop program was started without a program name")?;
+    Ok((program, args.collect()))
+}
+
+/// Check that the program the daemon was started as still runs, by asking it for its version
+fn check_program(program: &OsString) -> anyhow::Result<()> {
+    let output = signals::unblocked(Command::new(program).arg("--version").stdin(Stdio::null()))
+        .output()
+        .with_context(|| format!("could not run {program:?}"))?;
+    if !output.status.success() {
+        bail!(
+            "{program:?} fails to run: {error}",
+            error = String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Replace the daemon by the program it was started as, with the same arguments, handing over the
+/// backgrounds and the connection of the restart request
+///
+/// The control socket and the windows are closed with their descriptors when the process is
+/// replaced, the socket file is left for the new daemon to bind again. The handled signals stay
+/// blocked, a signal arriving before the new daemon waits for them is handled by it. Only returns if the
+/// program can't be started, the daemon keeps running then.
+pub fn exec(saved: &SavedState, stream: &LocalSocketStream) -> anyhow::Result<Infallible> {
+    let (program, args) = command_line()?;
+    check_program(&program)?;
+    let state = saved.to_json()?;
+
+    let reply = stream.as_raw_fd();
+    set_cloexec(reply, false).context("could not hand over the connection")?;
+    let error = Command::new(&program)
+        .args(args)
+        .env(REPLY_VAR, reply.to_string())
+        .env(STATE_VAR, state)
+        .exec();
+    let _ = set_cloexec(reply, true);
+    Err(error).with_context(|| format!("could not restart as {program:?}"))
+}
@@ -0,0 +1,434 @@
+//! Backgrounds drawn by Lua scripts, for experiments too small for a plugin
+//!
+//! A script defines `render(frame, time_millis)`, called on every tick with the frame it drew last
+//! and the time in milliseconds since the Unix epoch. It returns `false` if it left the frame
+//! unchanged. An optional `setup(width, height)` is called once after the script is loaded.
+//!
+//! The frame has the fields `width` and `height` and the methods `set_pixel(x, y, r, g, b, a)`,
+//! `fill_rect(x, y, width, height, r, g, b, a)` and `get_pixel(x, y)` returning `r, g, b, a`.
+//! Colors go from 0 to 255 and the alpha can be left out for opaque colors. Pixels outside of the
+//! frame are skipped when drawing and read as nothing.
+//!
+//! The script is loaded again whenever its file changes. A script failing to render is disabled
+//! until then, keeping its last frame.
+
+use std::path::Path;
+#[cfg(feature = "lua")]
+use std::path::PathBuf;
+
+#[cfg(feature = "lua")]
+use std::{cell::Cell, rc::Rc, time::Duration, time::Instant};
+
+#[cfg(feature = "lua")]
+use anyhow::Context;
+#[cfg(feature = "lua")]
+use mlua::{
+    AnyUserData, Function, HookTriggers, Lua, RegistryKey, UserData, UserDataFields,
+    UserDataMethods, Value, Variadic,
+};
+
+#[cfg(feature = "lua")]
+use crate::watch::FileChanges;
+
+/// How long a call of the script may run before it is stopped
+#[cfg(feature = "lua")]
+const MAX_CALL_TIME: Duration = Duration::from_secs(1);
+/// How much memory the Lua state of a script may take
+#[cfg(feature = "lua")]
+const MAX_MEMORY: usize = 256 * 1024 * 1024;
+/// How many instructions the script runs between two checks of [`MAX_CALL_TIME`]
+#[cfg(feature = "lua")]
+const INSTRUCTIONS_PER_CHECK: u32 = 10_000;
+
+/// The rgba frame a script draws into, handed to it as userdata
+#[cfg(feature = "lua")]
+struct LuaFrame {
+    pixels: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+#[cfg(feature = "lua")]
+impl LuaFrame {
+    /// The byte offset of the pixel at `x`, `y`, none outside of the frame
+    fn offset(&self, x: f64, y: f64) -> Option<usize> {
+        let (x, y) = (x.floor(), y.floor());
+        let inside =
+            (0.0..self.width as f64).contains(&x) && (0.0..self.height as f64).contains(&y);
+        inside.then(|| (y as usize * self.width as usize + x as usize) * 4)
+    }
+
+    /// Fill the rectangle clipped to the frame with `rgba`
+    fn fill_rect(&mut self, x: f64, y: f64, width: f64, height: f64, rgba: [u8; 4]) {
+        let clip = |start: f64, len: f64, max: u32| {
+            let end = (start + len).floor().clamp(0.0, max as f64) as usize;
+            (start.floor().clamp(0.0, max as f64) as usize, end)
+        };
+        let (left, right) = clip(x, width, self.width);
+        let (top, bottom) = clip(y, height, self.height);
+        let stride = self.width as usize * 4;
+        for row in top..bottom {
+            for pixel in
+                self.pixels[row * stride + left * 4..row * stride + right * 4].chunks_exact_mut(4)
+            {
+                pixel.copy_from_slice(&rgba);
+            }
+        }
+    }
+}
+
+/// The color of the components a script passed, opaque without an alpha
+#[cfg(feature = "lua")]
+fn rgba(r: f64, g: f64, b: f64, a: Option<f64>) -> [u8; 4] {
+    [r, g, b, a.unwrap_or(255.0)].map(|c| c.round().clamp(0.0, 255.0) as u8)
+}
+
+#[cfg(feature = "lua")]
+impl UserData for LuaFrame {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("width", |_, frame| Ok(frame.width));
+        fields.add_field_method_get("height", |_, frame| Ok(frame.height));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method_mut(
+            "set_pixel",
+            |_, frame, (x, y, r, g, b, a): (f64, f64, f64, f64, f64, Option<f64>)| {
+                if let Some(offset) = frame.offset(x, y) {
+                    frame.pixels[offset..offset + 4].copy_from_slice(&rgba(r, g, b, a));
+                }
+                Ok(())
+            },
+        );
+        methods.add_method_mut(
+            "fill_rect",
+            |_, frame, args: (f64, f64, f64, f64, f64, f64, f64, Option<f64>)| {
+                let (x, y, width, height, r, g, b, a) = args;
+                frame.fill_rect(x, y, width, height, rgba(r, g, b, a));
+                Ok(())
+            },
+        );
+        methods.add_method("get_pixel", |_, frame, (x, y): (f64, f64)| {
+            Ok(frame
+                .offset(x, y)
+                .into_iter()
+                .flat_map(|offset| frame.pixels[offset..offset + 4].to_vec())
+                .collect::<Variadic<u8>>())
+        });
+    }
+}
+
+/// A loaded script in a Lua state of its own
+#[cfg(feature = "lua")]
+struct Runtime {
+    lua: Lua,
+    /// The [`LuaFrame`] passed to `render`
+    frame: RegistryKey,
+    /// When the running call has to return, checked by a hook every [`INSTRUCTIONS_PER_CHECK`]
+    deadline: Rc<Cell<Instant>>,
+}
+
+#[cfg(feature = "lua")]
+impl Runtime {
+    /// Load the script at `path` and set it up for a `width` x `height` background
+    fn start(path: &Path, width: u32, height: u32) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read the script {path}", path = path.display()))?;
+
+        let lua = Lua::new();
+        lua.set_memory_limit(MAX_MEMORY)?;
+        let deadline = Rc::new(Cell::new(Instant::now()));
+        let hook_deadline = deadline.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(INSTRUCTIONS_PER_CHECK),
+            move |_, _| {
+                if Instant::now() > hook_deadline.get() {
+                    return Err(mlua::Error::runtime(format!(
+                        "the script ran for longer than {seconds}s",
+                        seconds = MAX_CALL_TIME.as_secs()
+                    )));
+                }
+                Ok(())
+            },
+        );
+        let frame = lua.create_registry_value(LuaFrame {
+            pixels: vec![0; width as usize * height as usize * 4],
+            width,
+            height,
+        })?;
+        let runtime = Runtime {
+            lua,
+            frame,
+            deadline,
+        };
+
+        runtime
+            .call(|lua| {
+                lua.load(source.as_str())
+                    .set_name(format!("@{path}", path = path.display()))
+                    .exec()?;
+                let globals = lua.globals();
+                if globals.get::<_, Option<Function>>("render")?.is_none() {
+                    return Err(mlua::Error::runtime(
+                        "the script defines no render(frame, time_millis) function",
+                    ));
+                }
+                if let Some(setup) = globals.get::<_, Option<Function>>("setup")? {
+                    setup.call::<_, ()>((width, height))?;
+                }
+                Ok(())
+            })
+            .with_context(|| format!("the script {path} failed to start", path = path.display()))?;
+        Ok(runtime)
+    }
+
+    /// Call into the script through `f`, stopping it once it runs for longer than
+    /// [`MAX_CALL_TIME`]
+    fn call<R>(&self, f: impl FnOnce(&Lua) -> mlua::Result<R>) -> mlua::Result<R> {
+        self.deadline.set(Instant::now() + MAX_CALL_TIME);
+        f(&self.lua)
+    }
+
+    /// Let the script draw for `time_millis` and copy its frame into `frame` if it changed or
+    /// `copy` is set, returns whether it was copied
+    fn render(&self, frame: &mut [u8], time_millis: i64, copy: bool) -> mlua::Result<bool> {
+        let script_frame: AnyUserData = self.lua.registry_value(&self.frame)?;
+        let changed = self.call(|lua| {
+            let render: Function = lua.globals().get("render")?;
+            let changed = render.call::<_, Value>((script_frame.clone(), time_millis))?;
+            Ok(!matches!(changed, Value::Boolean(false)))
+        })?;
+        if !changed && !copy {
+            return Ok(false);
+        }
+        frame.copy_from_slice(&script_frame.borrow::<LuaFrame>()?.pixels);
+        Ok(true)
+    }
+}
+
+/// A Lua script drawing a background, loaded again when its file changes
+#[cfg(feature = "lua")]
+pub struct Script {
+    path: PathBuf,
+    runtime: Runtime,
+    width: u32,
+    height: u32,
+    changes: FileChanges,
+    /// Whether the frame has to be copied even if the script didn't change it
+    redraw: bool,
+    /// Why the script was disabled after failing to render
+    failure: Option<String>,
+}
+
+#[cfg(feature = "lua")]
+impl Script {
+    /// Load the script at `path` and set it up for a `width` x `height` background
+    pub fn load(path: &Path, width: u32, height: u32) -> anyhow::Result<Self> {
+        Ok(Script {
+            runtime: Runtime::start(path, width, height)?,
+            path: path.to_path_buf(),
+            width,
+            height,
+            changes: FileChanges::new(path.to_path_buf())?,
+            redraw: true,
+            failure: None,
+        })
+    }
+
+    /// Let the script render for `time_millis` since the Unix epoch and copy its frame into
+    /// `frame` if it changed, returns whether it did
+    ///
+    /// A script failing to render is disabled and its last frame kept until its file changes.
+    pub fn render(&mut self, frame: &mut [u8], width: u32, height: u32, time_millis: i64) -> bool {
+        if self.changes.poll() {
+            self.reload();
+        }
+        if self.failure.is_some() {
+            return false;
+        }
+        if (width, height) != (self.width, self.height) {
+            self.fail(format!(
+                "the script was started for another size than {width}x{height}"
+            ));
+            return false;
+        }
+
+        match self.runtime.render(frame, time_millis, self.redraw) {
+            Ok(copied) => {
+                self.redraw = false;
+                copied
+            }
+            Err(error) => {
+                self.fail(error.to_string());
+                false
+            }
+        }
+    }
+
+    /// Start the changed script, keeping the running one if it fails to start
+    fn reload(&mut self) {
+        match Runtime::start(&self.path, self.width, self.height) {
+            Ok(runtime) => {
+                eprintln!("reloaded the script {path}", path = self.path.display());
+                self.runtime = runtime;
+                self.failure = None;
+                self.redraw = true;
+            }
+            Err(error) => eprintln!(
+                "warning: could not reload the script {path}, keeping the previous one: {error:#}",
+                path = self.path.display()
+            ),
+        }
+    }
+
+    fn fail(&mut self, reason: String) {
+        eprintln!(
+            "warning: disabling the script {path} until it changes: {reason}",
+            path = self.path.display()
+        );
+        self.failure = Some(reason);
+    }
+
+    /// Copy the frame on the next render, even if the script didn't change it
+    pub fn redraw(&mut self) {
+        self.redraw = true;
+    }
+
+    /// A human readable description of the script
+    pub fn status(&self) -> String {
+        format!(
+            "script: {path}\n\
+             state: {state}",
+            path = self.path.display(),
+            state = self
+                .failure
+                .as_ref()
+                .map_or("running".to_string(), |failure| format!(
+                    "disabled, {failure}"
+                )),
+        )
+    }
+}
+
+/// A Lua script, which this build can't run
+#[cfg(not(feature = "lua"))]
+pub struct Script(std::convert::Infallible);
+
+#[cfg(not(feature = "lua"))]
+impl Script {
+    /// Fail to load the script at `path`, this build has no Lua support
+    pub fn load(path: &Path, _width: u32, _height: u32) -> anyhow::Result<Self> {
+        anyhow::bail!(
+            "can't run the script {path}, this build has no Lua support",
+            path = path.display()
+        )
+    }
+
+    /// Never called, there is no script
+    pub fn render(&mut self, _: &mut [u8], _: u32, _: u32, _: i64) -> bool {
+        match self.0 {}
+    }
+
+    /// Never called, there is no script
+    pub fn redraw(&mut self) {
+        match self.0 {}
+    }
+
+    /// Never called, there is no script
+    pub fn status(&self) -> String {
+        match self.0 {}
+    }
+}
+
+#[cfg(all(test, feature = "lua"))]
+mod tests {
+    use std::thread;
+
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::watch::DEBOUNCE;
+
+    /// A script file with `source` in a directory of its own, which is removed with the guard
+    fn script(source: &str) -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("background.lua");
+        std::fs::write(&path, source).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn draws_with_the_frame_helpers() {
+        let (_dir, path) = script(
+            "function setup(width, height) size = width * height end\n\
+             function render(frame, time_millis)\n\
+                 if time_millis > 0 then return false end\n\
+                 frame:fill_rect(-1, 0, 2.5, frame.height, 255, 0, 0)\n\
+                 frame:set_pixel(2, 1, 0, 0, 255, 128)\n\
+                 frame:set_pixel(9, 9, 0, 255, 0)\n\
+                 local r, g, b, a = frame:get_pixel(2, 1)\n\
+                 frame:set_pixel(3, 0, size, g, b + 1, a)\n\
+             end",
+        );
+        let mut script = Script::load(&path, 4, 2).unwrap();
+        let mut frame = [0; 4 * 2 * 4];
+        assert!(script.render(&mut frame, 4, 2, 0));
+        #[rustfmt::skip]
+        assert_eq!(frame, [
+            255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 0, 0, 8, 0, 255, 128,
+            255, 0, 0, 255, 0, 0, 0, 0, 0, 0, 255, 128, 0, 0, 0, 0,
+        ]);
+
+        // Unchanged frames are only copied again when asked to
+        assert!(!script.render(&mut frame, 4, 2, 1));
+        script.redraw();
+        assert!(script.render(&mut [0; 4 * 2 * 4], 4, 2, 1));
+    }
+
+    #[test]
+    fn reports_broken_scripts_and_disables_failing_ones_until_they_change() {
+        let (_dir, path) = script("function render(frame) frame:set_pixel( end");
+        let error = format!("{:#}", Script::load(&path, 1, 1).err().unwrap());
+        assert!(error.contains("failed to start"), "{error}");
+        std::fs::write(&path, "x = 1").unwrap();
+        let error = format!("{:#}", Script::load(&path, 1, 1).err().unwrap());
+        assert!(error.contains("no render"), "{error}");
+
+        std::fs::write(
+            &path,
+            "function render(frame, time_millis)\n\
+                 if time_millis > 0 then error('boom') end\n\
+                 frame:set_pixel(0, 0, 255, 255, 255)\n\
+             end",
+        )
+        .unwrap();
+        let mut script = Script::load(&path, 1, 1).unwrap();
+        let mut frame = [0; 4];
+        assert!(script.render(&mut frame, 1, 1, 0));
+        assert!(!script.render(&mut frame, 1, 1, 1));
+        assert!(script.status().contains("disabled"), "{}", script.status());
+        assert_eq!(frame, [255; 4]);
+
+        // Running again once the file changed, the change is picked up after it settled
+        std::fs::write(
+            &path,
+            "function render(frame) frame:set_pixel(0, 0, 0, 0, 0) end -- fixed",
+        )
+        .unwrap();
+        let started = Instant::now();
+        while started.elapsed() < DEBOUNCE * 4 {
+            if script.render(&mut frame, 1, 1, 1) {
+                break;
+            }
+            thread::sleep(DEBOUNCE / 8);
+        }
+        assert_eq!(frame, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn stops_scripts_running_too_long() {
+        let (_dir, path) = script("while true do end");
+        let error = format!("{:#}", Script::load(&path, 1, 1).err().unwrap());
+        assert!(error.contains("longer than"), "{error}");
+    }
+}
@@ -0,0 +1,396 @@
+//! Frames shared by another process through a memory mapped file
+//!
+//! The file, eg in `/dev/shm` or a memfd passed as `/proc/<pid>/fd/<fd>`, starts with a header
+//! of [`HEADER_SIZE`] bytes in native byte order:
+//!
+//! | offset | type  | value                                               |
+//! |--------|-------|-----------------------------------------------------|
+//! | 0      | `u32` | [`MAGIC`]                                           |
+//! | 4      | `u32` | [`VERSION`]                                         |
+//! | 8      | `u32` | the width of the frames                             |
+//! | 12     | `u32` | the height of the frames                            |
+//! | 16     | `u64` | the sequence number of the latest complete frame    |
+//!
+//! Two buffers of `width * height * 4` bytes of rgba pixels follow, buffer `n % 2` holds frame
+//! `n`. A producer writes frame `n + 1` into the buffer not holding frame `n` and then stores
+//! `n + 1` as the sequence number with release ordering. Sequence number 0 means no frame was
+//! written yet. The file may not shrink while it is mapped.
+//!
+//! Frames of another size than the background are centered on it without scaling.
+
+use std::{
+    fs::File,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    sync::atomic::{fence, AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+
+/// "DBGF" in the first bytes of a shared frame file
+pub const MAGIC: u32 = u32::from_le_bytes(*b"DBGF");
+/// The version of the memory layout
+pub const VERSION: u32 = 1;
+/// The bytes before the first frame buffer
+pub const HEADER_SIZE: usize = 64;
+/// Where the sequence number of the latest complete frame is in the header
+pub const SEQUENCE_OFFSET: usize = 16;
+
+/// How often copying the latest frame is attempted in a tick while the producer overwrites it
+const MAX_COPY_ATTEMPTS: usize = 3;
+/// How long the sequence number may stay the same before the producer is reported as gone
+const STALE_AFTER: Duration = Duration::from_secs(5);
+
+/// The size of a shared frame file holding two `width` x `height` buffers
+pub fn file_size(width: u32, height: u32) -> usize {
+    HEADER_SIZE + 2 * width as usize * height as usize * 4
+}
+
+/// A shared frame file mapped into memory, unmapped when dropped
+pub struct SharedFrame {
+    path: PathBuf,
+    file: File,
+    mapping: NonNull<u8>,
+    len: usize,
+    width: u32,
+    height: u32,
+    /// A copy of the latest complete frame, so a torn read never reaches the screen
+    frame: Vec<u8>,
+    /// The sequence number of the frame in `frame`
+    shown_sequence: u64,
+    redraw: bool,
+    /// When the sequence number changed last
+    last_change: Instant,
+    stale: bool,
+    /// Whether the file shrank below the mapping, which stops reading it
+    truncated: bool,
+    /// Frames which were overwritten in every copy attempt of a tick
+    torn: u64,
+}
+
+impl SharedFrame {
+    /// Map the shared frame file at `path`, whose header has to declare `width` x `height` frames
+    pub fn open(path: &Path, width: u32, height: u32) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("could not open {path}", path = path.display()))?;
+        let len = file_size(width, height);
+        let file_len = file.metadata()?.len();
+        if file_len < len as u64 {
+            bail!(
+                "{path} holds {file_len} bytes, {width}x{height} frames need {len} bytes",
+                path = path.display()
+            );
+        }
+
+        // SAFETY: a fresh read only mapping of a file at least `len` bytes long
+        let mapping = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if mapping == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error())
+                .with_context(|| format!("could not map {path}", path = path.display()));
+        }
+        let shared = SharedFrame {
+            path: path.to_path_buf(),
+            file,
+            mapping: NonNull::new(mapping.cast()).unwrap(),
+            len,
+            width,
+            height,
+            frame: vec![0; width as usize * height as usize * 4],
+            shown_sequence: 0,
+            redraw: false,
+            last_change: Instant::now(),
+            stale: false,
+            truncated: false,
+            torn: 0,
+        };
+
+        let header = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&shared.bytes()[offset..offset + 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        if header(0) != MAGIC {
+            bail!("{path} is not a shared frame file", path = path.display());
+        }
+        if header(4) != VERSION {
+            bail!(
+                "{path} has version {version} of the shared frame layout, expected version {VERSION}",
+                path = path.display(),
+                version = header(4)
+            );
+        }
+        if (header(8), header(12)) != (width, height) {
+            bail!(
+                "{path} holds {file_width}x{file_height} frames, not {width}x{height}",
+                path = path.display(),
+                file_width = header(8),
+                file_height = header(12)
+            );
+        }
+
+        Ok(shared)
+    }
+
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` bytes long and lives as long as self
+        unsafe { std::slice::from_raw_parts(self.mapping.as_ptr(), self.len) }
+    }
+
+    fn sequence(&self) -> &AtomicU64 {
+        // SAFETY: the page aligned mapping holds an aligned u64 at the sequence offset
+        unsafe {
+            &*self
+                .mapping
+                .as_ptr()
+                .add(SEQUENCE_OFFSET)
+                .cast::<AtomicU64>()
+        }
+    }
+
+    /// Copy the latest complete frame into the `width` x `height` rgba `frame` if a new one was
+    /// written, returns whether the frame changed
+    pub fn poll(&mut self, frame: &mut [u8], width: u32, height: u32) -> bool {
+        if self.truncated {
+            return false;
+        }
+        // Reading beyond the end of a shrunk file would kill the daemon with SIGBUS
+        if self
+            .file
+            .metadata()
+            .map_or(true, |metadata| metadata.len() < self.len as u64)
+        {
+            eprintln!(
+                "warning: {path} shrank below {len} bytes, keeping the last frame",
+                path = self.path.display(),
+                len = self.len
+            );
+            self.truncated = true;
+            return false;
+        }
+
+        let sequence = self.sequence().load(Ordering::Acquire);
+        if sequence != self.shown_sequence && sequence != 0 && self.copy_latest() {
+            self.last_change = Instant::now();
+            if self.stale {
+                eprintln!("{path} is updated again", path = self.path.display());
+                self.stale = false;
+            }
+            self.redraw = true;
+        } else if self.shown_sequence != 0
+            && !self.stale
+            && self.last_change.elapsed() >= STALE_AFTER
+        {
+            eprintln!(
+                "warning: {path} wasn't updated for {STALE_AFTER:?}, keeping the last frame",
+                path = self.path.display()
+            );
+            self.stale = true;
+        }
+
+        if !std::mem::take(&mut self.redraw) {
+            return false;
+        }
+        blit_centered(frame, width, height, &self.frame, self.width, self.height);
+        true
+    }
+
+    /// Copy the latest complete frame, returns false if it was overwritten during every attempt
+    fn copy_latest(&mut self) -> bool {
+        let frame_size = self.frame.len();
+        for _ in 0..MAX_COPY_ATTEMPTS {
+            let sequence = self.sequence().load(Ordering::Acquire);
+            let start = HEADER_SIZE + (sequence % 2) as usize * frame_size;
+            // SAFETY: both buffers lie within the mapping
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    self.mapping.as_ptr().add(start),
+                    self.frame.as_mut_ptr(),
+                    frame_size,
+                );
+            }
+            // The producer only writes this buffer again after publishing the next frame
+            fence(Ordering::Acquire);
+            if self.sequence().load(Ordering::Relaxed) == sequence {
+                self.shown_sequence = sequence;
+                return true;
+            }
+        }
+        self.torn += 1;
+        false
+    }
+
+    /// Copy the shown frame again on the next poll
+    pub fn redraw(&mut self) {
+        self.redraw = self.shown_sequence != 0;
+    }
+
+    /// A human readable description of the shared frame
+    pub fn status(&self) -> String {
+        let state = if self.truncated {
+            "truncated"
+        } else if self.shown_sequence == 0 {
+            "waiting for the first frame"
+        } else if self.stale {
+            "stale"
+        } else {
+            "live"
+        };
+        format!(
+            "shared frames: {path}\n\
+             size: {width}x{height}\n\
+             sequence: {sequence}\n\
+             frames overwritten while copying: {torn}\n\
+             state: {state}",
+            path = self.path.display(),
+            width = self.width,
+            height = self.height,
+            sequence = self.shown_sequence,
+            torn = self.torn,
+        )
+    }
+}
+
+impl Drop for SharedFrame {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created with this length and isn't used afterwards
+        unsafe { libc::munmap(self.mapping.as_ptr().cast(), self.len) };
+    }
+}
+
+/// Copy the rgba image `src` centered into `dst`, cropping what doesn't fit and filling the
+/// margins with black
+fn blit_centered(
+    dst: &mut [u8],
+    dst_width: u32,
+    dst_height: u32,
+    src: &[u8],
+    src_width: u32,
+    src_height: u32,
+) {
+    if (dst_width, dst_height) == (src_width, src_height) {
+        dst.copy_from_slice(src);
+        return;
+    }
+
+    let (dst_width, dst_height) = (dst_width as usize, dst_height as usize);
+    let (src_width, src_height) = (src_width as usize, src_height as usize);
+    let row_len = dst_width.min(src_width) * 4;
+    let (dst_x, src_x) = (
+        dst_width.saturating_sub(src_width) / 2,
+        src_width.saturating_sub(dst_width) / 2,
+    );
+    let (dst_y, src_y) = (
+        dst_height.saturating_sub(src_height) / 2,
+        src_height.saturating_sub(dst_height) / 2,
+    );
+
+    dst.chunks_exact_mut(4)
+        .for_each(|pixel| pixel.copy_from_slice(&[0, 0, 0, 255]));
+    for row in 0..dst_height.min(src_height) {
+        let dst_start = ((dst_y + row) * dst_width + dst_x) * 4;
+        let src_start = ((src_y + row) * src_width + src_x) * 4;
+        dst[dst_start..dst_start + row_len].copy_from_slice(&src[src_start..src_start + row_len]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::OpenOptions, os::unix::fs::FileExt, path::Path};
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    /// A shared frame file with its header written, removed once the guard is dropped
+    fn shared_file(width: u32, height: u32) -> NamedTempFile {
+        let shared = NamedTempFile::new().unwrap();
+        let file = shared.as_file();
+        file.set_len(file_size(width, height) as u64).unwrap();
+        for (offset, value) in [(0, MAGIC), (4, VERSION), (8, width), (12, height)] {
+            file.write_all_at(&value.to_ne_bytes(), offset).unwrap();
+        }
+        shared
+    }
+
+    /// Write frame `sequence` filled with `value` like a producer would
+    fn publish(path: &Path, width: u32, height: u32, sequence: u64, value: u8) {
+        let file = OpenOptions::new().write(true).open(path).unwrap();
+        let frame_size = width as usize * height as usize * 4;
+        let start = HEADER_SIZE + (sequence % 2) as usize * frame_size;
+        file.write_all_at(&vec![value; frame_size], start as u64)
+            .unwrap();
+        file.write_all_at(&sequence.to_ne_bytes(), SEQUENCE_OFFSET as u64)
+            .unwrap();
+    }
+
+    #[test]
+    fn shows_the_latest_published_frame() {
+        let file = shared_file(2, 2);
+        let path = file.path();
+        let mut shared = SharedFrame::open(path, 2, 2).unwrap();
+        let mut frame = vec![0; 16];
+        assert!(!shared.poll(&mut frame, 2, 2));
+
+        publish(path, 2, 2, 1, 10);
+        assert!(shared.poll(&mut frame, 2, 2));
+        assert_eq!(frame, [10; 16]);
+        assert!(!shared.poll(&mut frame, 2, 2));
+
+        publish(path, 2, 2, 2, 20);
+        publish(path, 2, 2, 3, 30);
+        assert!(shared.poll(&mut frame, 2, 2));
+        assert_eq!(frame, [30; 16]);
+    }
+
+    #[test]
+    fn centers_frames_of_another_size() {
+        let file = shared_file(1, 1);
+        let path = file.path();
+        let mut shared = SharedFrame::open(path, 1, 1).unwrap();
+        publish(path, 1, 1, 1, 99);
+
+        let mut frame = vec![0; 3 * 3 * 4];
+        assert!(shared.poll(&mut frame, 3, 3));
+        assert_eq!(frame[16..20], [99; 4]);
+        assert_eq!(frame[..4], [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn rejects_files_not_matching_the_declared_size() {
+        let file = shared_file(2, 2);
+        let path = file.path();
+        let error = SharedFrame::open(path, 3, 2).err().unwrap();
+        assert!(error.to_string().contains("need"), "{error:#}");
+        let error = SharedFrame::open(path, 1, 2).err().unwrap();
+        assert!(error.to_string().contains("not 1x2"), "{error:#}");
+    }
+
+    #[test]
+    fn stops_reading_a_truncated_file() {
+        let file = shared_file(2, 2);
+        let path = file.path();
+        let mut shared = SharedFrame::open(path, 2, 2).unwrap();
+        publish(path, 2, 2, 1, 10);
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_len(HEADER_SIZE as u64)
+            .unwrap();
+
+        let mut frame = vec![0; 16];
+        assert!(!shared.poll(&mut frame, 2, 2));
+        assert!(shared.status().contains("state: truncated"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    os::unix::process::CommandExt,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::daemon::DaemonEvent;
+
+/// A second shutdown signal within this time exits without cleaning up
+const FORCE_EXIT_GRACE: Duration = Duration::from_secs(5);
+
+const SIGNALS: [libc::c_int; 3] = [libc::SIGTERM, libc::SIGINT, libc::SIGHUP];
+
+fn signal_set() -> libc::sigset_t {
+    // SAFETY: sigemptyset initializes the set before it is read
+    unsafe {
+        let mut set = std::mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in SIGNALS {
+            libc::sigaddset(&mut set, signal);
+        }
+        set
+    }
+}
+
+/// Block the handled signals for the calling thread and all threads spawned by it afterwards, so
+/// they are only received by [`spawn`]
+///
+/// Must be called before any other thread is started.
+pub fn block() -> std::io::Result<()> {
+    let set = signal_set();
+    // SAFETY: the set is initialized and the old mask is not requested
+    match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) } {
+        0 => Ok(()),
+        error => Err(std::io::Error::from_raw_os_error(error)),
+    }
+}
+
+/// Let the program started by `command` receive the handled signals
+///
+/// Children inherit the mask set by [`block`] across fork and exec, without this a pipe command
+/// or curl would ignore SIGTERM, SIGINT and Ctrl-C.
+pub fn unblocked(command: &mut Command) -> &mut Command {
+    // SAFETY: the hook only calls sigemptyset, sigaddset and pthread_sigmask, which are
+    // async-signal-safe, and allocates nothing
+    unsafe {
+        command.pre_exec(|| {
+            let set = signal_set();
+            match libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut()) {
+                0 => Ok(()),
+                error => Err(std::io::Error::from_raw_os_error(error)),
+            }
+        })
+    }
+}
+
+/// Wait for the blocked signals on a background thread and forward them with `send`, usually to
+/// the event loop, SIGHUP requests a reload and SIGTERM and SIGINT a shutdown
+///
+/// `send` returns false once nothing receives the events any more.
+pub fn spawn(send: impl Fn(DaemonEvent) -> bool + Send + 'static) {
+    thread::spawn(move || {
+        let set = signal_set();
+        let mut shutdown_requested: Option<Instant> = None;
+        loop {
+            let mut signal = 0;
+            // SAFETY: the set is initialized and the signal is written to a valid location
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                continue;
+            }
+
+            if signal == libc::SIGHUP {
+                if !send(DaemonEvent::Reload) {
+                    return;
+                }
+                continue;
+            }
+
+            if shutdown_requested.is_some_and(|since| since.elapsed() < FORCE_EXIT_GRACE) {
+                eprintln!("received a second shutdown signal, exiting immediately");
+                std::process::exit(128 + signal);
+            }
+            shutdown_requested = Some(Instant::now());
+            if !send(DaemonEvent::Shutdown(signal)) {
+                std::process::exit(128 + signal);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the child exits on SIGTERM within a moment
+    fn terminates(mut child: std::process::Child) -> bool {
+        // SAFETY: the child was not waited for yet, so its pid is still its own
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+        thread::sleep(Duration::from_millis(200));
+        let exited = child.try_wait().unwrap().is_some();
+        child.kill().unwrap();
+        child.wait().unwrap();
+        exited
+    }
+
+    #[test]
+    fn starts_children_which_receive_signals() {
+        // Blocked on this test thread only, which the children are started from
+        block().unwrap();
+        let sleep = || {
+            let mut command = Command::new("sleep");
+            command.arg("30");
+            command
+        };
+        assert!(!terminates(sleep().spawn().unwrap()));
+        assert!(terminates(unblocked(&mut sleep()).spawn().unwrap()));
+    }
+}
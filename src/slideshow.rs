@@ -0,0 +1,268 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    args::ColorArg,
+    command::Command,
+    render::{BackgroundRenderer, Scaling},
+    span::OutputSpan,
+};
+
+/// The order the images of a slideshow are shown in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Order {
+    /// Sorted by file name
+    #[default]
+    Sequential,
+    /// A random image each time, never the same one twice in a row
+    Random,
+    /// Every image once in a random order, then again in a new order
+    Shuffle,
+}
+
+/// The images of a directory and which one comes next, picking up files added or removed while
+/// the slideshow runs
+pub struct Playlist {
+    dir: PathBuf,
+    order: Order,
+    /// The images of the directory when it was last listed, sorted
+    images: Vec<PathBuf>,
+    /// The current permutation of the images in shuffle order
+    queue: Vec<PathBuf>,
+    /// The position of the next image in the queue
+    position: usize,
+    /// The image shown last
+    last: Option<PathBuf>,
+}
+
+/// The image files in `dir`, sorted by name
+fn list_images(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut images = std::fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && image::ImageFormat::from_path(path).is_ok())
+        .collect::<Vec<_>>();
+    images.sort();
+    Ok(images)
+}
+
+impl Playlist {
+    /// The playlist of the images in `dir`, failing if there are none
+    pub fn new(dir: PathBuf, order: Order) -> anyhow::Result<Self> {
+        let mut playlist = Playlist {
+            dir,
+            order,
+            images: Vec::new(),
+            queue: Vec::new(),
+            position: 0,
+            last: None,
+        };
+        playlist.rescan()?;
+        if playlist.images.is_empty() {
+            anyhow::bail!("{dir} contains no images", dir = playlist.dir.display());
+        }
+        Ok(playlist)
+    }
+
+    /// List the directory again, removed images are left out of the queue and new ones are
+    /// inserted at random positions which have not been shown yet
+    fn rescan(&mut self) -> anyhow::Result<()> {
+        self.images = list_images(&self.dir)
+            .with_context(|| format!("could not list {dir}", dir = self.dir.display()))?;
+        if self.order != Order::Shuffle {
+            return Ok(());
+        }
+
+        let shown_removed = self.queue[..self.position]
+            .iter()
+            .filter(|image| self.images.binary_search(image).is_err())
+            .count();
+        self.position -= shown_removed;
+        self.queue
+            .retain(|image| self.images.binary_search(image).is_ok());
+
+        let queued = self.queue.iter().cloned().collect::<HashSet<_>>();
+        for image in &self.images {
+            if !queued.contains(image) {
+                let at = fastrand::usize(self.position..=self.queue.len());
+                self.queue.insert(at, image.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// The image to show next, `None` if the directory has no images any more
+    pub fn next(&mut self) -> anyhow::Result<Option<PathBuf>> {
+        self.rescan()?;
+        if self.images.is_empty() {
+            return Ok(None);
+        }
+
+        let next = match self.order {
+            Order::Sequential => self
+                .images
+                .iter()
+                .find(|image| self.last.as_ref().is_none_or(|last| *image > last))
+                .unwrap_or(&self.images[0])
+                .clone(),
+            Order::Random => {
+                let candidates = self
+                    .images
+                    .iter()
+                    .filter(|image| self.images.len() == 1 || Some(*image) != self.last.as_ref())
+                    .collect::<Vec<_>>();
+                candidates[fastrand::usize(..candidates.len())].clone()
+            }
+            Order::Shuffle => {
+                if self.position >= self.queue.len() {
+                    self.reshuffle();
+                }
+                self.position += 1;
+                self.queue[self.position - 1].clone()
+            }
+        };
+        self.last = Some(next.clone());
+        Ok(Some(next))
+    }
+
+    /// Start a new cycle in a new order, which doesn't begin with the image shown last
+    fn reshuffle(&mut self) {
+        fastrand::shuffle(&mut self.queue);
+        if self.queue.len() > 1 && self.queue.first() == self.last.as_ref() {
+            let swap = fastrand::usize(1..self.queue.len());
+            self.queue.swap(0, swap);
+        }
+        self.position = 0;
+    }
+
+    /// The renderer of the next image which can be loaded and its file, images which can't are
+    /// skipped with a warning
+    pub fn next_renderer(
+        &mut self,
+        scaling: Scaling,
+        margin_color: &Option<ColorArg>,
+        span: Option<OutputSpan>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<(PathBuf, BackgroundRenderer)> {
+        for _ in 0..self.images.len().max(1) {
+            let Some(path) = self.next()? else {
+                break;
+            };
+            let renderer = Command::StaticImage {
+                path: path.clone(),
+                scaling,
+                margin_color: margin_color.clone(),
+                watch: false,
+                span: span.is_some(),
+            }
+            .into_renderer(width, height, span);
+            match renderer {
+                Ok(renderer) => return Ok((path, renderer)),
+                Err(error) => eprintln!(
+                    "warning: skipping {path} in the slideshow: {error:#}",
+                    path = path.display()
+                ),
+            }
+        }
+        anyhow::bail!(
+            "{dir} contains no images which can be shown",
+            dir = self.dir.display()
+        )
+    }
+
+    /// A human readable description of the playlist
+    pub fn status(&self) -> String {
+        let position = match self.order {
+            Order::Shuffle => self.position,
+            _ => self
+                .last
+                .as_ref()
+                .and_then(|last| self.images.iter().position(|image| image == last))
+                .map_or(0, |idx| idx + 1),
+        };
+        format!(
+            "images: {dir}\n\
+             order: {order}\n\
+             position: {position} of {count}",
+            dir = self.dir.display(),
+            order = self.order.to_possible_value().unwrap().get_name(),
+            count = self.images.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn image_dir(count: usize) -> TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for idx in 0..count {
+            std::fs::write(dir.join(format!("{idx:02}.png")), []).unwrap();
+        }
+        std::fs::write(dir.join("notes.txt"), []).unwrap();
+        temp
+    }
+
+    #[test]
+    fn shuffle_shows_every_image_once_per_cycle() {
+        let temp = image_dir(6);
+        let dir = temp.path();
+        let mut playlist = Playlist::new(dir.to_path_buf(), Order::Shuffle).unwrap();
+
+        let mut last = None;
+        for _ in 0..20 {
+            let mut cycle = (0..6)
+                .map(|_| playlist.next().unwrap().unwrap())
+                .collect::<Vec<_>>();
+            assert_ne!(cycle.first(), last.as_ref());
+            last = cycle.last().cloned();
+            cycle.sort();
+            assert_eq!(cycle, list_images(dir).unwrap());
+        }
+    }
+
+    #[test]
+    fn shuffle_keeps_its_order_across_rescans() {
+        let temp = image_dir(4);
+        let dir = temp.path();
+        let mut playlist = Playlist::new(dir.to_path_buf(), Order::Shuffle).unwrap();
+        let first = playlist.next().unwrap().unwrap();
+        let second = playlist.next().unwrap().unwrap();
+
+        // A removed image is skipped and a new one comes later in the same cycle
+        let removed = playlist.queue[2].clone();
+        std::fs::remove_file(&removed).unwrap();
+        std::fs::write(dir.join("new.png"), []).unwrap();
+        let rest = (0..2)
+            .map(|_| playlist.next().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert!(!rest.contains(&removed));
+        assert!(rest.contains(&dir.join("new.png")));
+        assert!(!rest.contains(&first) && !rest.contains(&second));
+        assert!(playlist.status().contains("position: 4 of 4"));
+    }
+
+    #[test]
+    fn sequential_wraps_around() {
+        let temp = image_dir(2);
+        let dir = temp.path();
+        let mut playlist = Playlist::new(dir.to_path_buf(), Order::Sequential).unwrap();
+        let shown = (0..3)
+            .map(|_| playlist.next().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shown,
+            [dir.join("00.png"), dir.join("01.png"), dir.join("00.png")]
+        );
+    }
+}
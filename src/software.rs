@@ -0,0 +1,191 @@
+use std::num::NonZeroU32;
+
+use anyhow::Context as _;
+use softbuffer::{Context, Surface};
+use winit::{
+    dpi::PhysicalSize,
+    raw_window_handle::{
+        DisplayHandle, HandleError, HasDisplayHandle, HasWindowHandle, RawDisplayHandle,
+        RawWindowHandle, WindowHandle,
+    },
+    window::Window,
+};
+
+/// The raw handles of a window, so the surface doesn't borrow the window it presents to
+///
+/// Like the surface of the graphics adapter, the surface has to be dropped before its window.
+#[derive(Clone, Copy)]
+struct WindowHandles {
+    display: RawDisplayHandle,
+    window: RawWindowHandle,
+}
+
+impl HasDisplayHandle for WindowHandles {
+    fn display_handle(&self) -> Result<DisplayHandle<'_>, HandleError> {
+        // SAFETY: the handle stays valid as long as the window, which outlives the surface
+        Ok(unsafe { DisplayHandle::borrow_raw(self.display) })
+    }
+}
+
+impl HasWindowHandle for WindowHandles {
+    fn window_handle(&self) -> Result<WindowHandle<'_>, HandleError> {
+        // SAFETY: the window outlives the surface
+        Ok(unsafe { WindowHandle::borrow_raw(self.window) })
+    }
+}
+
+/// Frames presented by the CPU, for systems without a usable graphics adapter
+///
+/// The buffer is scaled up by the largest whole factor fitting the surface and centered on it, like
+/// the graphics adapter presents it.
+pub struct SoftwarePresenter {
+    surface: Surface<WindowHandles, WindowHandles>,
+    frame: Vec<u8>,
+    width: u32,
+    height: u32,
+    surface_size: PhysicalSize<u32>,
+}
+
+impl SoftwarePresenter {
+    /// Present a `width` x `height` buffer on a surface of `surface_size` covering `window`
+    pub fn new(
+        window: &Window,
+        surface_size: PhysicalSize<u32>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let handles = WindowHandles {
+            display: window.display_handle()?.as_raw(),
+            window: window.window_handle()?.as_raw(),
+        };
+        let context = Context::new(handles)
+            .map_err(|error| anyhow::anyhow!("could not connect to the display: {error}"))?;
+        let surface = Surface::new(&context, handles).map_err(|error| {
+            anyhow::anyhow!("could not create a surface for the window: {error}")
+        })?;
+        let mut presenter = SoftwarePresenter {
+            surface,
+            frame: Vec::new(),
+            width: 0,
+            height: 0,
+            surface_size,
+        };
+        presenter.resize_buffer(width, height);
+        presenter.resize_surface(surface_size.width, surface_size.height)?;
+        Ok(presenter)
+    }
+
+    /// The rgba buffer presented
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// The rgba buffer presented
+    pub fn frame_mut(&mut self) -> &mut [u8] {
+        &mut self.frame
+    }
+
+    /// The width and height of the buffer
+    pub fn buffer_size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Present the buffer on a surface of `width` x `height` pixels from now on
+    pub fn resize_surface(&mut self, width: u32, height: u32) -> anyhow::Result<()> {
+        let (Some(surface_width), Some(surface_height)) =
+            (NonZeroU32::new(width), NonZeroU32::new(height))
+        else {
+            anyhow::bail!("the surface size {width}x{height} is empty");
+        };
+        self.surface
+            .resize(surface_width, surface_height)
+            .map_err(|error| anyhow::anyhow!("{error}"))
+            .context("could not resize the surface")?;
+        self.surface_size = PhysicalSize::new(width, height);
+        Ok(())
+    }
+
+    /// Make the buffer `width` x `height` pixels, cleared to transparent black
+    pub fn resize_buffer(&mut self, width: u32, height: u32) {
+        self.frame = vec![0; width as usize * height as usize * 4];
+        (self.width, self.height) = (width, height);
+    }
+
+    /// Present the buffer on the surface
+    pub fn render(&mut self) -> Result<(), softbuffer::SoftBufferError> {
+        let mut target = self.surface.buffer_mut()?;
+        scale_into(
+            &self.frame,
+            self.width,
+            self.height,
+            &mut target,
+            self.surface_size.width,
+            self.surface_size.height,
+        );
+        target.present()
+    }
+}
+
+/// Draw the rgba `frame` into the `target_width` x `target_height` surface pixels `target` of the
+/// format `0x00RRGGBB`, scaled up by the largest whole factor which fits and centered on black
+fn scale_into(
+    frame: &[u8],
+    width: u32,
+    height: u32,
+    target: &mut [u32],
+    target_width: u32,
+    target_height: u32,
+) {
+    target.fill(0);
+    if width == 0 || height == 0 {
+        return;
+    }
+    let factor = (target_width / width).min(target_height / height).max(1) as usize;
+    let (width, height) = (width as usize, height as usize);
+    let (target_width, target_height) = (target_width as usize, target_height as usize);
+    let left = target_width.saturating_sub(width * factor) / 2;
+    let top = target_height.saturating_sub(height * factor) / 2;
+
+    for (row, target_row) in target
+        .chunks_exact_mut(target_width)
+        .enumerate()
+        .skip(top)
+        .take(height * factor)
+    {
+        let source_row = &frame[(row - top) / factor * width * 4..][..width * 4];
+        for (column, pixel) in target_row[left..]
+            .iter_mut()
+            .take(width * factor)
+            .enumerate()
+        {
+            let rgba = &source_row[column / factor * 4..][..4];
+            *pixel = u32::from_be_bytes([0, rgba[0], rgba[1], rgba[2]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_frames_up_by_whole_factors_and_centers_them() {
+        // Red and blue pixels side by side
+        let frame = [255, 0, 0, 255, 0, 0, 255, 255];
+        let mut target = [7; 5 * 4];
+        scale_into(&frame, 2, 1, &mut target, 5, 4);
+        let (red, blue) = (0xFF0000, 0x0000FF);
+        #[rustfmt::skip]
+        assert_eq!(target, [
+            0, 0, 0, 0, 0,
+            red, red, blue, blue, 0,
+            red, red, blue, blue, 0,
+            0, 0, 0, 0, 0,
+        ]);
+
+        // A surface smaller than the frame shows its top left part
+        let mut target = [7; 1];
+        scale_into(&frame, 2, 1, &mut target, 1, 1);
+        assert_eq!(target, [red]);
+    }
+}
@@ -0,0 +1,434 @@
+use std::{
+    collections::VecDeque,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder},
+    AnimationDecoder, DynamicImage, Frames, GenericImage, GenericImageView, RgbaImage,
+};
+
+use crate::{
+    cache::FrameCache, decode, pool::BufferPool, render::FrameLayout, template::FrameTemplate,
+};
+
+/// How many decoded sprite sheets are kept in memory, the current and the upcoming one
+const MAX_CACHED_SHEETS: usize = 2;
+
+/// Where the frames of a clock are loaded from
+pub struct FrameSource {
+    dir: PathBuf,
+    template: FrameTemplate,
+    /// Set if the template names sprite sheets instead of single frames
+    sheets: Option<SheetCache>,
+    /// The animated file the last frame was taken from
+    animation: Option<Animation>,
+    /// Frames placed before by the `warm-cache` command
+    cache: Option<FrameCache>,
+    /// The buffers of frames which were shown, to load the next ones into
+    pool: BufferPool,
+}
+
+/// What warming the cache with a frame did
+pub enum Warmed {
+    Stored,
+    /// The frame was cached already
+    Cached,
+    /// The frame is part of an animated file, which isn't cached
+    Animated,
+}
+
+impl FrameSource {
+    /// Frames are looked up in the default frame cache if it exists
+    pub fn new(dir: PathBuf, template: FrameTemplate, sheets: bool) -> Self {
+        FrameSource {
+            dir,
+            template,
+            sheets: sheets.then(SheetCache::default),
+            animation: None,
+            cache: FrameCache::open(),
+            pool: BufferPool::default(),
+        }
+    }
+
+    /// Look up frames in `cache` instead of the default frame cache
+    pub fn with_cache(self, cache: Option<FrameCache>) -> Self {
+        FrameSource { cache, ..self }
+    }
+
+    /// Decode the frame for the clock time `millis`, place it into a `width` x `height` frame and
+    /// store it in the cache, unless it is cached already
+    pub fn warm(
+        &mut self,
+        millis: u32,
+        layout: &mut FrameLayout,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Warmed> {
+        let path = self.template.path(&self.dir, millis);
+        let cache = self.cache.as_mut().context("the frames have no cache")?;
+        if cache.contains(&path, layout, width, height)? {
+            return Ok(Warmed::Cached);
+        }
+        match open_frame_file(
+            &path,
+            |size| layout.scaled_size(size, width, height),
+            &mut self.pool,
+        )
+        .with_context(|| format!("could not decode {}", path.display()))?
+        {
+            FrameFile::Still(image) => {
+                let frame = layout.place_pooled(image, width, height, &mut self.pool);
+                cache.store(&path, layout, &frame)?;
+                self.recycle(frame);
+                Ok(Warmed::Stored)
+            }
+            FrameFile::Animated(_) => Ok(Warmed::Animated),
+        }
+    }
+
+    /// The file holding the frame for the clock time `millis`
+    pub fn path(&self, millis: u32) -> PathBuf {
+        self.template.path(&self.dir, millis)
+    }
+
+    /// A description of the frame paths for status output
+    pub fn describe(&self) -> String {
+        let path = self.dir.join(self.template.to_string());
+        match self.sheets {
+            Some(_) => format!("{path} (sprite sheets)", path = path.display()),
+            None => path.display().to_string(),
+        }
+    }
+
+    /// Keep the buffer of a frame which isn't shown any more to load the next one into
+    pub fn recycle(&mut self, frame: RgbaImage) {
+        self.pool.give(frame.into_raw());
+    }
+
+    /// Load the frame for the clock time `millis` placed into a `width` x `height` frame
+    pub fn load(
+        &mut self,
+        millis: u32,
+        layout: &mut FrameLayout,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<RgbaImage> {
+        let path = self.template.path(&self.dir, millis);
+        let file_millis = millis % self.template.granularity();
+        if let Some(sheets) = &mut self.sheets {
+            let sheet = sheets.get(path, layout, width, height)?;
+            let frame = sheet.frame(file_millis, &mut self.pool)?;
+            return Ok(layout.place_pooled(
+                DynamicImage::ImageRgba8(frame),
+                width,
+                height,
+                &mut self.pool,
+            ));
+        }
+
+        if self
+            .animation
+            .as_ref()
+            .is_none_or(|animation| animation.path != path)
+        {
+            if let Some(frame) = self
+                .cache
+                .as_mut()
+                .and_then(|cache| cache.lookup(&path, layout, width, height, &mut self.pool))
+            {
+                return Ok(frame);
+            }
+            match open_frame_file(
+                &path,
+                |size| layout.scaled_size(size, width, height),
+                &mut self.pool,
+            )? {
+                FrameFile::Still(image) => {
+                    return Ok(layout.place_pooled(image, width, height, &mut self.pool))
+                }
+                FrameFile::Animated(frames) => {
+                    self.animation = Some(Animation::new(path, frames));
+                }
+            }
+        }
+        let animation = self.animation.as_mut().unwrap();
+        let frame = animation.frame_at(file_millis)?;
+        let mut copy = self.pool.take(frame.len());
+        copy.copy_from_slice(frame);
+        let frame = RgbaImage::from_raw(frame.width(), frame.height(), copy).unwrap();
+        Ok(layout.place_pooled(
+            DynamicImage::ImageRgba8(frame),
+            width,
+            height,
+            &mut self.pool,
+        ))
+    }
+}
+
+enum FrameFile {
+    Still(DynamicImage),
+    Animated(Frames<'static>),
+}
+
+/// Open a frame file, telling animated GIF and APNG files apart from still images, which may be
+/// decoded at a reduced size no smaller than `target` maps their size to, into a buffer from `pool`
+fn open_frame_file(
+    path: &Path,
+    target: impl FnOnce((u32, u32)) -> (u32, u32),
+    pool: &mut BufferPool,
+) -> anyhow::Result<FrameFile> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_ascii_lowercase());
+    let reader = || -> anyhow::Result<_> { Ok(Cursor::new(decode::read(path)?)) };
+
+    Ok(
+        match extension.as_ref().and_then(|extension| extension.to_str()) {
+            Some("gif") => FrameFile::Animated(GifDecoder::new(reader()?)?.into_frames()),
+            Some("png" | "apng") => {
+                let decoder = PngDecoder::new(reader()?)?;
+                if decoder.is_apng()? {
+                    FrameFile::Animated(decoder.apng()?.into_frames())
+                } else {
+                    FrameFile::Still(decode::from_decoder(decoder, path, pool)?)
+                }
+            }
+            _ => FrameFile::Still(decode::open_scaled_pooled(path, target, pool)?),
+        },
+    )
+}
+
+/// An animated file whose frames are shown at their cumulative delay, decoded on demand
+struct Animation {
+    path: PathBuf,
+    frames: Frames<'static>,
+    /// When the next frame to decode starts, relative to the start of the file
+    next_start: u32,
+    /// The last decoded frame and the time it is shown from
+    current: Option<(u32, RgbaImage)>,
+}
+
+impl Animation {
+    fn new(path: PathBuf, frames: Frames<'static>) -> Self {
+        Animation {
+            path,
+            frames,
+            next_start: 0,
+            current: None,
+        }
+    }
+
+    /// The frame shown `millis` after the start of the file, the last frame is held once the
+    /// animation ends
+    fn frame_at(&mut self, millis: u32) -> anyhow::Result<&RgbaImage> {
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(start, _)| *start > millis)
+        {
+            // Going backwards needs decoding from the start again
+            let FrameFile::Animated(frames) =
+                open_frame_file(&self.path, |size| size, &mut BufferPool::default())?
+            else {
+                bail!("{} is no longer animated", self.path.display());
+            };
+            *self = Animation::new(std::mem::take(&mut self.path), frames);
+        }
+
+        while self.current.is_none() || self.next_start <= millis {
+            let Some(frame) = self.frames.next() else {
+                break;
+            };
+            let frame = frame?;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let start = self.next_start;
+            self.next_start = start.saturating_add(numerator / denominator.max(1));
+            self.current = Some((start, frame.into_buffer()));
+        }
+
+        match &self.current {
+            Some((_, image)) => Ok(image),
+            None => bail!("{} contains no frames", self.path.display()),
+        }
+    }
+}
+
+/// The grid of frames in a sprite sheet, read from a metadata file next to the sheet
+///
+/// The metadata file has the path of the sheet with the extension `.sheet` and contains
+/// `key = value` lines for `columns`, `rows`, `frame_duration` in milliseconds and optionally
+/// `frame_width` and `frame_height` in pixels, which default to the sheet size divided by the
+/// grid.
+struct SheetGrid {
+    columns: u32,
+    rows: u32,
+    frame_width: Option<u32>,
+    frame_height: Option<u32>,
+    frame_duration: u32,
+}
+
+impl SheetGrid {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let metadata = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read sprite sheet metadata {}", path.display()))?;
+
+        let mut grid = SheetGrid {
+            columns: 0,
+            rows: 0,
+            frame_width: None,
+            frame_height: None,
+            frame_duration: 0,
+        };
+        for (idx, line) in metadata.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("{}:{}: expected `key = value`", path.display(), idx + 1);
+            };
+            let value: u32 = value.trim().parse().with_context(|| {
+                format!(
+                    "{}:{}: {:?} is not a number",
+                    path.display(),
+                    idx + 1,
+                    value.trim()
+                )
+            })?;
+            match key.trim() {
+                "columns" => grid.columns = value,
+                "rows" => grid.rows = value,
+                "frame_width" => grid.frame_width = Some(value),
+                "frame_height" => grid.frame_height = Some(value),
+                "frame_duration" => grid.frame_duration = value,
+                key => bail!("{}:{}: unknown key {key:?}", path.display(), idx + 1),
+            }
+        }
+
+        if grid.columns == 0 || grid.rows == 0 || grid.frame_duration == 0 {
+            bail!(
+                "{} should set positive columns, rows and frame_duration",
+                path.display()
+            );
+        }
+        Ok(grid)
+    }
+}
+
+/// A decoded sprite sheet, already scaled so its frames have the placed size
+struct Sheet {
+    path: PathBuf,
+    image: RgbaImage,
+    grid: SheetGrid,
+    /// The size of one frame within the scaled sheet
+    frame_size: (u32, u32),
+}
+
+impl Sheet {
+    fn load(
+        path: PathBuf,
+        layout: &mut FrameLayout,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let grid = SheetGrid::load(&path.with_extension("sheet"))?;
+        let image = decode::open(&path)?;
+        let (sheet_width, sheet_height) = image.dimensions();
+        let source_size = (
+            grid.frame_width.unwrap_or(sheet_width / grid.columns),
+            grid.frame_height.unwrap_or(sheet_height / grid.rows),
+        );
+        if source_size.0 * grid.columns > sheet_width || source_size.1 * grid.rows > sheet_height {
+            bail!(
+                "{path} is smaller than its grid of {columns}x{rows} frames",
+                path = path.display(),
+                columns = grid.columns,
+                rows = grid.rows,
+            );
+        }
+
+        // Scale the whole sheet once so the frames only need to be cropped
+        let frame_size = layout.scaled_size(source_size, width, height);
+        let image = if frame_size == source_size {
+            image.to_rgba8()
+        } else {
+            let scale_x = frame_size.0 as f64 / source_size.0 as f64;
+            let scale_y = frame_size.1 as f64 / source_size.1 as f64;
+            image::imageops::resize(
+                &image,
+                ((sheet_width as f64 * scale_x).round() as u32).max(frame_size.0 * grid.columns),
+                ((sheet_height as f64 * scale_y).round() as u32).max(frame_size.1 * grid.rows),
+                image::imageops::FilterType::Triangle,
+            )
+        };
+
+        Ok(Sheet {
+            path,
+            image,
+            grid,
+            frame_size,
+        })
+    }
+
+    /// The frame shown `millis` after the start of the sheet, copied into a buffer from `pool`
+    fn frame(&self, millis: u32, pool: &mut BufferPool) -> anyhow::Result<RgbaImage> {
+        let idx = millis / self.grid.frame_duration;
+        if idx >= self.grid.columns * self.grid.rows {
+            bail!(
+                "{path} has no frame for {millis}ms, it only holds {count} frames of {duration}ms",
+                path = self.path.display(),
+                count = self.grid.columns * self.grid.rows,
+                duration = self.grid.frame_duration,
+            );
+        }
+
+        let (frame_width, frame_height) = self.frame_size;
+        let mut frame = RgbaImage::from_raw(
+            frame_width,
+            frame_height,
+            pool.take(frame_width as usize * frame_height as usize * 4),
+        )
+        .unwrap();
+        frame.copy_from(
+            &*image::imageops::crop_imm(
+                &self.image,
+                idx % self.grid.columns * frame_width,
+                idx / self.grid.columns * frame_height,
+                frame_width,
+                frame_height,
+            ),
+            0,
+            0,
+        )?;
+        Ok(frame)
+    }
+}
+
+/// The most recently used sprite sheets
+#[derive(Default)]
+struct SheetCache {
+    sheets: VecDeque<Sheet>,
+}
+
+impl SheetCache {
+    fn get(
+        &mut self,
+        path: PathBuf,
+        layout: &mut FrameLayout,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<&Sheet> {
+        if let Some(idx) = self.sheets.iter().position(|sheet| sheet.path == path) {
+            let sheet = self.sheets.remove(idx).unwrap();
+            self.sheets.push_front(sheet);
+        } else {
+            let sheet = Sheet::load(path, layout, width, height)?;
+            self.sheets.truncate(MAX_CACHED_SHEETS - 1);
+            self.sheets.push_front(sheet);
+        }
+        Ok(&self.sheets[0])
+    }
+}
@@ -0,0 +1,170 @@
+use image::{DynamicImage, GenericImageView};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::MonitorHandle,
+    window::Window,
+};
+
+/// Where the output showing the background lies within the bounding box of all outputs
+///
+/// Positions and sizes are fractions of the bounding box, so the same region of an image is picked
+/// whatever its resolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputSpan {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    /// The width of the bounding box divided by its height
+    aspect: f64,
+}
+
+/// The position and size of an output in logical pixels
+fn logical_rect(monitor: &MonitorHandle) -> (f64, f64, f64, f64) {
+    to_logical(monitor.position(), monitor.size(), monitor.scale_factor())
+}
+
+/// The position and size of an output in logical pixels from its physical ones at `scale`
+fn to_logical(
+    position: PhysicalPosition<i32>,
+    size: PhysicalSize<u32>,
+    scale: f64,
+) -> (f64, f64, f64, f64) {
+    let position = position.to_logical::<f64>(scale);
+    let size = size.to_logical::<f64>(scale);
+    (position.x, position.y, size.width, size.height)
+}
+
+impl OutputSpan {
+    /// The span of the output showing `window` in the layout of all connected outputs, none while
+    /// the compositor has not placed the window yet
+    pub fn of(window: &Window) -> Option<Self> {
+        let current = window.current_monitor()?;
+        // Outputs with different scale factors report their positions at their own density, so
+        // the layout is compared in logical pixels
+        Self::in_layout(
+            logical_rect(&current),
+            window
+                .available_monitors()
+                .map(|monitor| logical_rect(&monitor)),
+        )
+    }
+
+    /// The span of the output at the logical rectangle `current` among the logical rectangles of
+    /// all `outputs`
+    fn in_layout(
+        current: (f64, f64, f64, f64),
+        outputs: impl Iterator<Item = (f64, f64, f64, f64)>,
+    ) -> Option<Self> {
+        let (left, top, right, bottom) = outputs.fold(
+            (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+            |(left, top, right, bottom), (x, y, width, height)| {
+                (
+                    left.min(x),
+                    top.min(y),
+                    right.max(x + width),
+                    bottom.max(y + height),
+                )
+            },
+        );
+        let (x, y, width, height) = current;
+        let (box_width, box_height) = (right - left, bottom - top);
+        if box_width <= 0.0 || box_height <= 0.0 {
+            return None;
+        }
+
+        Some(OutputSpan {
+            x: (x - left) / box_width,
+            y: (y - top) / box_height,
+            width: width / box_width,
+            height: height / box_height,
+            aspect: box_width / box_height,
+        })
+    }
+
+    /// The part of `image` shown on this output when the image covers the bounding box of all
+    /// outputs, cropping its overflow like [`crate::render::Scaling::Fill`]
+    pub fn crop(&self, image: &DynamicImage) -> DynamicImage {
+        let (image_width, image_height) = image.dimensions();
+        let (image_width, image_height) = (image_width as f64, image_height as f64);
+        let (box_width, box_height) = if image_width / image_height > self.aspect {
+            (image_height * self.aspect, image_height)
+        } else {
+            (image_width, image_width / self.aspect)
+        };
+
+        let left = (image_width - box_width) / 2.0 + self.x * box_width;
+        let top = (image_height - box_height) / 2.0 + self.y * box_height;
+        image.crop_imm(
+            left.round() as u32,
+            top.round() as u32,
+            (self.width * box_width).round().max(1.0) as u32,
+            (self.height * box_height).round().max(1.0) as u32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgba};
+
+    use super::*;
+
+    /// A 4K output at scale 2 left of a full HD output at scale 1, each 1920x1080 logical pixels
+    fn mixed_layout() -> [(f64, f64, f64, f64); 2] {
+        [
+            to_logical(
+                PhysicalPosition::new(0, 0),
+                PhysicalSize::new(3840, 2160),
+                2.0,
+            ),
+            to_logical(
+                PhysicalPosition::new(1920, 0),
+                PhysicalSize::new(1920, 1080),
+                1.0,
+            ),
+        ]
+    }
+
+    /// The first pixel and the size of `image` cropped to `span`, an image whose pixels hold
+    /// their own coordinates
+    fn crop(span: &OutputSpan, width: u32, height: u32) -> ([u16; 2], (u32, u32)) {
+        let image = ImageBuffer::from_fn(width, height, |x, y| Rgba([x as u16, y as u16, 0, 0]));
+        let cropped = span.crop(&DynamicImage::ImageRgba16(image));
+        let Rgba([x, y, ..]) = *cropped.to_rgba16().get_pixel(0, 0);
+        ([x, y], cropped.dimensions())
+    }
+
+    #[test]
+    fn places_outputs_of_mixed_scale_factors_by_logical_size() {
+        let [left, right] = mixed_layout();
+        let span = OutputSpan::in_layout(right, [left, right].into_iter()).unwrap();
+        assert_eq!(
+            span,
+            OutputSpan {
+                x: 0.5,
+                y: 0.0,
+                width: 0.5,
+                height: 1.0,
+                aspect: 3840.0 / 1080.0,
+            }
+        );
+
+        assert_eq!(OutputSpan::in_layout(left, std::iter::empty()), None);
+    }
+
+    #[test]
+    fn crops_images_of_other_aspect_ratios_like_fill() {
+        let layout = mixed_layout();
+        let [left, right] =
+            layout.map(|output| OutputSpan::in_layout(output, layout.into_iter()).unwrap());
+
+        // Narrower than the layout, the top and bottom overflow
+        assert_eq!(crop(&left, 320, 180), ([0, 45], (160, 90)));
+        assert_eq!(crop(&right, 320, 180), ([160, 45], (160, 90)));
+
+        // Wider than the layout, the sides overflow
+        assert_eq!(crop(&left, 80, 10), ([22, 0], (18, 10)));
+        assert_eq!(crop(&right, 80, 10), ([40, 0], (18, 10)));
+    }
+}
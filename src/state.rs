@@ -0,0 +1,290 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    command::Command,
+    json::{self, Value},
+};
+
+/// The version of the state file format, files of other versions are ignored
+///
+/// Version 2 keeps the slideshow interval in milliseconds instead of seconds.
+const STATE_VERSION: u64 = 2;
+
+/// The backgrounds the daemon shows, kept across restarts
+#[derive(Debug, Default)]
+pub struct SavedState {
+    /// The background sent to all outputs
+    pub default: Option<Command>,
+    /// Backgrounds sent to single outputs by name, shown instead of the default there
+    pub outputs: BTreeMap<String, Command>,
+}
+
+#[derive(Serialize)]
+struct StateFile<'a> {
+    version: u64,
+    default: Option<&'a Command>,
+    outputs: &'a BTreeMap<String, Command>,
+}
+
+/// The directory the state is kept in, in `$XDG_STATE_HOME` or `~/.local/state`
+fn state_dir() -> Option<PathBuf> {
+    let state_home = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+        })?;
+    Some(state_home.join("desktop-background"))
+}
+
+/// The state file of the daemon listening on `socket_name`
+pub fn state_path(socket_name: &str) -> Option<PathBuf> {
+    Some(state_dir()?.join(format!("{name}.json", name = socket_name.replace('/', "_"))))
+}
+
+/// The directory where the daemons on abstract sockets advertise their names, those sockets
+/// have no file to be listed by
+fn abstract_dir() -> Option<PathBuf> {
+    Some(state_dir()?.join("abstract"))
+}
+
+/// Advertise the abstract socket `socket_name` of a running daemon, returns the file to remove
+/// once it stops
+pub fn advertise(socket_name: &str) -> anyhow::Result<PathBuf> {
+    let dir = abstract_dir().context("neither XDG_STATE_HOME nor HOME is set")?;
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("could not create {dir}", dir = dir.display()))?;
+    let path = dir.join(socket_name.replace('/', "_"));
+    std::fs::write(&path, socket_name)
+        .with_context(|| format!("could not write {path}", path = path.display()))?;
+    Ok(path)
+}
+
+/// The advertised abstract socket names with the files advertising them, left behind by daemons
+/// which died as well
+pub fn advertised() -> Vec<(String, PathBuf)> {
+    let Some(entries) = abstract_dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = std::fs::read_to_string(&path).ok()?;
+            name.starts_with('@').then_some((name, path))
+        })
+        .collect()
+}
+
+/// The directory of the profiles, shared by all daemons of the user
+fn profile_dir() -> anyhow::Result<PathBuf> {
+    Ok(state_dir()
+        .context("neither XDG_STATE_HOME nor HOME is set, profiles can't be kept")?
+        .join("profiles"))
+}
+
+/// The file of the profile `name`
+pub fn profile_path(name: &str) -> anyhow::Result<PathBuf> {
+    Ok(profile_dir()?.join(format!("{name}.json")))
+}
+
+/// The names of the saved profiles, sorted
+pub fn profiles() -> anyhow::Result<Vec<String>> {
+    let dir = profile_dir()?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("could not list {dir}", dir = dir.display()))
+        }
+    };
+
+    let mut names = entries
+        .map(|entry| {
+            let name = entry?.file_name();
+            Ok(name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .map(str::to_string))
+        })
+        .filter_map(Result::transpose)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    names.sort();
+    Ok(names)
+}
+
+impl SavedState {
+    /// Read the state saved at `path`, commands which can't be read any more are left out
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(SavedState::default());
+        }
+        Self::load_existing(path)
+    }
+
+    /// Read the state saved at `path` like [`Self::load`], failing if there is no file
+    pub fn load_existing(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("could not read {path}", path = path.display()))?;
+        Self::from_json(&json, &path.display().to_string())
+    }
+
+    /// Read the state from `json` in the format of the state file, `source` names where it
+    /// comes from in messages
+    pub fn from_json(json: &str, source: &str) -> anyhow::Result<Self> {
+        let file = json::parse(json).with_context(|| format!("could not parse {source}"))?;
+
+        match file.get("version") {
+            Some(Value::UInt(STATE_VERSION)) => {}
+            Some(Value::UInt(version)) => anyhow::bail!(
+                "{source} has version {version} of the state format, expected {STATE_VERSION}"
+            ),
+            _ => anyhow::bail!("{source} has no state format version"),
+        }
+
+        let command = |value: &Value, name: &str| match json::from_value(value.clone()) {
+            Ok(command) => Some(command),
+            Err(error) => {
+                eprintln!(
+                    "warning: could not read the saved background {name} from {source}: {error}"
+                );
+                None
+            }
+        };
+        let default = file
+            .get("default")
+            .filter(|value| **value != Value::Null)
+            .and_then(|value| command(value, "of all outputs"));
+        let outputs = match file.get("outputs") {
+            Some(Value::Object(entries)) => entries
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.clone(), command(value, &format!("of output {name}"))?))
+                })
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+
+        Ok(SavedState { default, outputs })
+    }
+
+    /// The state in the format of the state file
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(json::to_string(&StateFile {
+            version: STATE_VERSION,
+            default: self.default.as_ref(),
+            outputs: &self.outputs,
+        })?)
+    }
+
+    /// Write the state to `path`, replacing the previous file at once
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let json = self.to_json()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("could not create {dir}", dir = dir.display()))?;
+        }
+        let temporary = path.with_extension("json.tmp");
+        std::fs::write(&temporary, json)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .with_context(|| format!("could not write {path}", path = path.display()))
+    }
+
+    /// Remember `command` as the background of the outputs it was sent to, all if `output` is
+    /// not set
+    pub fn record(&mut self, command: &Command, output: Option<&str>) {
+        match output {
+            Some(output) => {
+                self.outputs.insert(output.to_string(), command.clone());
+            }
+            None => {
+                self.default = Some(command.clone());
+                self.outputs.clear();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn text(template: &str) -> Command {
+        Command::TextOverlay {
+            template: template.to_string(),
+            font: PathBuf::from("/fonts/font.ttf"),
+            size: 48.0,
+            color: "FFFFFF".parse().unwrap(),
+            position: Default::default(),
+            align: Default::default(),
+            offset_x: -3,
+            offset_y: 0,
+            margin: 32,
+        }
+    }
+
+    /// A state file in a folder which doesn't exist yet, within a directory removed with the guard
+    fn temporary() -> (TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state/state.json");
+        (dir, path)
+    }
+
+    #[test]
+    fn keeps_the_backgrounds_of_all_outputs_and_single_ones() {
+        let (_dir, path) = temporary();
+        let mut saved = SavedState::default();
+        saved.record(&text("DP-1"), Some("DP-1"));
+        saved.record(&text("all"), None);
+        saved.record(&text("HDMI-1"), Some("HDMI-1"));
+        saved.save(&path).unwrap();
+
+        let loaded = SavedState::load(&path).unwrap();
+        assert_eq!(format!("{loaded:?}"), format!("{saved:?}"));
+        assert_eq!(loaded.outputs.keys().collect::<Vec<_>>(), ["HDMI-1"]);
+    }
+
+    #[test]
+    fn leaves_out_backgrounds_which_cant_be_read() {
+        let (_dir, path) = temporary();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut file = json::to_value(&StateFile {
+            version: STATE_VERSION,
+            default: Some(&text("all")),
+            outputs: &BTreeMap::from([("DP-1".to_string(), text("DP-1"))]),
+        })
+        .unwrap();
+        if let Value::Object(entries) = &mut file {
+            entries[1].1 = Value::Object(vec![("Removed".to_string(), Value::Null)]);
+        }
+        let mut json = String::new();
+        file.write(&mut json, 0);
+        std::fs::write(&path, json).unwrap();
+
+        let loaded = SavedState::load(&path).unwrap();
+        assert!(loaded.default.is_none());
+        assert!(loaded.outputs.contains_key("DP-1"));
+    }
+
+    #[test]
+    fn ignores_other_versions_and_missing_files() {
+        let (_dir, path) = temporary();
+        assert!(SavedState::load(&path).unwrap().default.is_none());
+
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(
+            &path,
+            "{\"version\": 1, \"default\": null, \"outputs\": {}}",
+        )
+        .unwrap();
+        let error = SavedState::load(&path).unwrap_err();
+        assert!(error.to_string().contains("version 1"), "{error:#}");
+    }
+}
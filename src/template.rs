@@ -0,0 +1,683 @@
+use std::{
+    collections::HashSet,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::render::{ClockHours, MILLIS_PER_HOUR, MILLIS_PER_MINUTE, MILLIS_PER_SECOND};
+
+/// The widest zero-padding accepted for a placeholder
+const MAX_WIDTH: usize = 16;
+/// The extension of files mapping hours to templates, which are given instead of a template
+const MAPPING_EXTENSION: &str = ".toml";
+
+/// A part of the frame time a placeholder is replaced with
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    /// The hour of the clock cycle
+    Hour,
+    /// The hour on a 12 hour clock face, `12` for the hour after midnight and noon
+    ClockFaceHour,
+    Minute,
+    Second,
+    /// Milliseconds within the hour
+    HourMillis,
+    /// Milliseconds within the clock cycle
+    Millis,
+}
+
+impl Field {
+    fn of(self, millis: u32) -> u32 {
+        match self {
+            Field::Hour => millis / MILLIS_PER_HOUR,
+            Field::ClockFaceHour => (millis / MILLIS_PER_HOUR + 11) % 12 + 1,
+            Field::Minute => millis % MILLIS_PER_HOUR / MILLIS_PER_MINUTE,
+            Field::Second => millis % MILLIS_PER_MINUTE / MILLIS_PER_SECOND,
+            Field::HourMillis => millis % MILLIS_PER_HOUR,
+            Field::Millis => millis,
+        }
+    }
+
+    /// How long the value stays the same
+    fn unit(self) -> u32 {
+        match self {
+            Field::Hour | Field::ClockFaceHour => MILLIS_PER_HOUR,
+            Field::Minute => MILLIS_PER_MINUTE,
+            Field::Second => MILLIS_PER_SECOND,
+            Field::HourMillis | Field::Millis => 1,
+        }
+    }
+
+    /// The zero-padding used when the placeholder gives no width
+    fn default_width(self) -> usize {
+        match self {
+            Field::Hour | Field::ClockFaceHour | Field::Minute | Field::Second => 2,
+            Field::HourMillis => 7,
+            Field::Millis => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Text(String),
+    /// A field of the frame time zero-padded to `width` digits
+    Number {
+        field: Field,
+        width: usize,
+    },
+}
+
+/// How the hour folders are named that templates without a directory are looked up in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HourDirs {
+    /// `0` to `11`, `0` to `23` for 24 hour clocks
+    #[default]
+    ZeroBased,
+    /// `01` to `12` like the hours on a clock face, `12` for the hour after midnight and noon
+    ClockFace,
+    /// A printf like pattern with one `%d` for the hour of the cycle, eg `hour_%02d`
+    Pattern(String),
+    /// No hour folders, the frames are directly in the clock directory
+    Flat,
+}
+
+impl HourDirs {
+    /// The frame template placeholders of a pattern, `%0Nd` becomes `%0NH`
+    fn placeholders(pattern: &str) -> anyhow::Result<String> {
+        let mut template = String::new();
+        let mut hours = 0;
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '/' => bail!("hour folder pattern {pattern} should name a single folder"),
+                '%' if chars.next_if_eq(&'%').is_some() => template.push_str("%%"),
+                '%' => {
+                    let mut width = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        width.push(digit);
+                    }
+                    if chars.next() != Some('d') {
+                        bail!(
+                            "hour folder pattern {pattern} should only hold %d or %% placeholders"
+                        );
+                    }
+                    if !width.is_empty() && !width.starts_with('0') {
+                        bail!("padding in hour folder pattern {pattern} should start with a 0 like %0{width}d");
+                    }
+                    if width.is_empty() {
+                        width.push('0');
+                    }
+                    template.push_str(&format!("%{width}H"));
+                    hours += 1;
+                }
+                c => template.push(c),
+            }
+        }
+        if hours != 1 {
+            bail!("hour folder pattern {pattern} should hold one %d for the hour, like hour_%02d");
+        }
+        Ok(template)
+    }
+}
+
+impl std::str::FromStr for HourDirs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0-11" => Ok(HourDirs::ZeroBased),
+            "1-12" => Ok(HourDirs::ClockFace),
+            "flat" => Ok(HourDirs::Flat),
+            pattern if pattern.contains('%') => {
+                HourDirs::placeholders(pattern)?;
+                Ok(HourDirs::Pattern(pattern.to_string()))
+            }
+            _ => bail!(
+                "unknown hour folders {s}, expected 0-11, 1-12, flat or a pattern like hour_%02d"
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for HourDirs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HourDirs::ZeroBased => f.write_str("0-11"),
+            HourDirs::ClockFace => f.write_str("1-12"),
+            HourDirs::Pattern(pattern) => f.write_str(pattern),
+            HourDirs::Flat => f.write_str("flat"),
+        }
+    }
+}
+
+/// A clock frame path relative to the clock directory with placeholders for the frame time
+///
+/// Supported placeholders are `%H` (hour), `%M` (minute), `%S` (second), `%r` (milliseconds
+/// within the hour), `%m` (milliseconds within the cycle) and `%%` for a literal percent. The
+/// zero-padding of a placeholder can be set like `%08m`. Templates without a directory are looked
+/// up in the hour folders named by [`HourDirs`], the folder and the file name are filled in from
+/// the same time: `%m` keeps counting across the hour folders of the cycle while `%r` starts over
+/// in each of them.
+///
+/// Frame sets mixing namings give a TOML file instead, with the template of most hours and the
+/// templates replacing it in some hours of the cycle:
+///
+/// ```toml
+/// default = "%H/%M.png"
+///
+/// [overrides]
+/// 22 = "night_%m.png"
+/// ```
+#[derive(Clone)]
+pub struct FrameTemplate {
+    template: String,
+    segments: Vec<Segment>,
+    /// The naming of the hour folders, `None` if the template names its own folders
+    hour_dirs: Option<HourDirs>,
+    /// The templates used instead of this one in some hours of the cycle, by the hour
+    overrides: Vec<(u32, FrameTemplate)>,
+    /// The file the template and its overrides were read from
+    mapping: Option<String>,
+}
+
+impl FrameTemplate {
+    /// Parse `template`, or read the templates from the file within `dir` it names if it ends in
+    /// `.toml`
+    ///
+    /// The hours of the overrides have to be part of the cycle of `hours` and the templates of a
+    /// file have to change within the hour, so each frame of an hour isn't the same file.
+    pub fn load(
+        dir: &Path,
+        template: &str,
+        hour_dirs: &HourDirs,
+        hours: ClockHours,
+    ) -> anyhow::Result<Self> {
+        if !FrameTemplate::is_mapping(template) {
+            return FrameTemplate::parse(template, hour_dirs);
+        }
+        let path = dir.join(template);
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("could not read {}", path.display()))?;
+        let (default, overrides) =
+            parse_mapping(&text).with_context(|| format!("could not parse {}", path.display()))?;
+
+        let parse = |template: &str, hour: Option<u32>| {
+            let parsed = FrameTemplate::parse(template, hour_dirs)?;
+            if parsed.granularity() >= MILLIS_PER_HOUR {
+                bail!(
+                    "the template {template}{of} in {path} names one file for the whole hour, it \
+                     needs a placeholder for the time within the hour like %m, %r, %M or %S",
+                    of = hour.map_or(String::new(), |hour| format!(" of hour {hour}")),
+                    path = path.display(),
+                );
+            }
+            Ok(parsed)
+        };
+        let mut parsed = parse(&default, None)?;
+        for (hour, template) in overrides {
+            if hour >= hours.count() {
+                bail!(
+                    "hour {hour} in {path} is not part of a {count} hour clock, which has the \
+                     hours 0 to {last}",
+                    path = path.display(),
+                    count = hours.count(),
+                    last = hours.count() - 1,
+                );
+            }
+            parsed.overrides.push((hour, parse(&template, Some(hour))?));
+        }
+        parsed.mapping = Some(template.to_string());
+        Ok(parsed)
+    }
+
+    /// Whether `template` names a file mapping hours to templates rather than being one
+    pub fn is_mapping(template: &str) -> bool {
+        template.ends_with(MAPPING_EXTENSION)
+    }
+
+    pub fn parse(template: &str, hour_dirs: &HourDirs) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let folders = !template.contains('/');
+        if folders {
+            match hour_dirs {
+                HourDirs::ZeroBased => segments.push(Segment::Number {
+                    field: Field::Hour,
+                    width: 0,
+                }),
+                HourDirs::ClockFace => segments.push(Segment::Number {
+                    field: Field::ClockFaceHour,
+                    width: 2,
+                }),
+                HourDirs::Pattern(pattern) => {
+                    segments.extend(parse_segments(&HourDirs::placeholders(pattern)?)?)
+                }
+                HourDirs::Flat => {}
+            }
+            if *hour_dirs != HourDirs::Flat {
+                segments.push(Segment::Text("/".to_string()));
+            }
+        }
+        segments.extend(parse_segments(template)?);
+
+        Ok(FrameTemplate {
+            template: template.to_string(),
+            segments,
+            hour_dirs: folders.then(|| hour_dirs.clone()),
+            overrides: Vec::new(),
+            mapping: None,
+        })
+    }
+
+    /// The same templates with the hour folders named by `hour_dirs`
+    pub fn with_hour_dirs(&self, hour_dirs: &HourDirs) -> anyhow::Result<Self> {
+        let mut template = FrameTemplate::parse(&self.template, hour_dirs)?;
+        for (hour, replaced) in &self.overrides {
+            template
+                .overrides
+                .push((*hour, replaced.with_hour_dirs(hour_dirs)?));
+        }
+        template.mapping.clone_from(&self.mapping);
+        Ok(template)
+    }
+
+    /// The naming of the hour folders, `None` if the template names its own folders
+    pub fn hour_dirs(&self) -> Option<&HourDirs> {
+        self.hour_dirs.as_ref()
+    }
+
+    /// How many milliseconds of consecutive clock times share the same path, [`u32::MAX`] if all
+    /// do
+    pub fn granularity(&self) -> u32 {
+        self.segments
+            .iter()
+            .filter_map(|segment| match segment {
+                Segment::Number { field, .. } => Some(field.unit()),
+                Segment::Text(_) => None,
+            })
+            .chain(
+                self.overrides
+                    .iter()
+                    .map(|(_, template)| template.granularity()),
+            )
+            .fold(u32::MAX, u32::min)
+    }
+
+    /// The path of the frame for the given clock time within `dir`
+    pub fn path(&self, dir: &Path, millis: u32) -> PathBuf {
+        let hour = millis / MILLIS_PER_HOUR;
+        if let Some((_, template)) = self.overrides.iter().find(|(of, _)| *of == hour) {
+            return template.path(dir, millis);
+        }
+
+        let mut path = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => path.push_str(text),
+                Segment::Number { field, width } => {
+                    let _ = write!(path, "{:0width$}", field.of(millis));
+                }
+            }
+        }
+        dir.join(path)
+    }
+
+    /// The clock time of the frame at `path` within `dir`, the reverse of [`FrameTemplate::path`]
+    ///
+    /// `None` if the path doesn't match the template, or the time it holds doesn't name the same
+    /// path again, so a frame found this way is the one the clock loads at that time.
+    pub fn millis_of(&self, dir: &Path, path: &Path) -> Option<u32> {
+        let relative = path.strip_prefix(dir).ok()?.to_str()?;
+        // The templates of an hour may leave the hour out
+        let overridden = self.overrides.iter().filter_map(|(hour, template)| {
+            let millis = template.parse_millis(relative)?;
+            Some(hour * MILLIS_PER_HOUR + millis % MILLIS_PER_HOUR)
+        });
+        self.parse_millis(relative)
+            .into_iter()
+            .chain(overridden)
+            .find(|millis| self.path(dir, *millis) == path)
+    }
+
+    /// The clock time the fields of the `relative` path add up to, numbers followed by another
+    /// number take exactly their padding in digits
+    fn parse_millis(&self, relative: &str) -> Option<u32> {
+        let mut rest = relative;
+        let mut values = Vec::new();
+        for (index, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Text(text) => rest = rest.strip_prefix(text.as_str())?,
+                Segment::Number { field, width } => {
+                    let digits = match self.segments.get(index + 1) {
+                        Some(Segment::Number { .. }) if *width > 0 => *width,
+                        _ => rest
+                            .find(|c: char| !c.is_ascii_digit())
+                            .unwrap_or(rest.len()),
+                    };
+                    let value = rest.get(..digits)?.parse::<u32>().ok()?;
+                    values.push((*field, value));
+                    rest = &rest[digits..];
+                }
+            }
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+
+        let value = |wanted: fn(&Field) -> bool| {
+            values
+                .iter()
+                .find(|(field, _)| wanted(field))
+                .map(|(_, value)| *value)
+        };
+        if let Some(millis) = value(|field| matches!(field, Field::Millis)) {
+            return Some(millis);
+        }
+        let hour = match value(|field| matches!(field, Field::Hour)) {
+            Some(hour) => hour,
+            None => {
+                value(|field| matches!(field, Field::ClockFaceHour)).map_or(0, |hour| hour % 12)
+            }
+        };
+        let within = match value(|field| matches!(field, Field::HourMillis)) {
+            Some(millis) => millis,
+            None => {
+                value(|field| matches!(field, Field::Minute)).unwrap_or(0) * MILLIS_PER_MINUTE
+                    + value(|field| matches!(field, Field::Second)).unwrap_or(0) * MILLIS_PER_SECOND
+            }
+        };
+        hour.checked_mul(MILLIS_PER_HOUR)?.checked_add(within)
+    }
+
+    /// Every frame file a clock stepping by `clock_step` through a `cycle` long cycle loads from
+    /// `dir`, with the first clock time it is loaded for
+    ///
+    /// Templates coarser than the clock step name the same file for several steps, which is
+    /// listed once.
+    pub fn frames(&self, dir: &Path, clock_step: u32, cycle: u32) -> Vec<(u32, PathBuf)> {
+        let mut paths = HashSet::new();
+        (0..cycle)
+            .step_by(clock_step as usize)
+            .map(|millis| (millis, self.path(dir, millis)))
+            .filter(|(_, path)| paths.insert(path.clone()))
+            .collect()
+    }
+}
+
+/// The segments of a template without the hour folder
+fn parse_segments(template: &str) -> anyhow::Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+
+        let mut width = String::new();
+        while let Some((_, digit)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+            width.push(digit);
+        }
+        if !width.is_empty() && !width.starts_with('0') {
+            bail!(
+                "padding at position {start} in file template should start with a 0 like \
+                 %0{width}m"
+            );
+        }
+
+        let field = match chars.next() {
+            Some((_, '%')) if width.is_empty() => {
+                text.push('%');
+                continue;
+            }
+            Some((_, 'H')) => Field::Hour,
+            Some((_, 'M')) => Field::Minute,
+            Some((_, 'S')) => Field::Second,
+            Some((_, 'r')) => Field::HourMillis,
+            Some((_, 'm')) => Field::Millis,
+            Some((_, c)) => bail!(
+                "unknown placeholder %{width}{c} at position {start} in file template, \
+                 expected one of %H, %M, %S, %r, %m or %%"
+            ),
+            None => bail!("dangling % at the end of file template, write %% for a percent"),
+        };
+        let width = match width.parse::<usize>() {
+            Ok(width) if width > MAX_WIDTH => bail!(
+                "padding of {width} digits at position {start} in file template is longer \
+                 than {MAX_WIDTH}"
+            ),
+            Ok(width) => width,
+            Err(_) => field.default_width(),
+        };
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(&mut text)));
+        }
+        segments.push(Segment::Number { field, width });
+    }
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    Ok(segments)
+}
+
+/// Read a file mapping hours to templates, the subset of TOML it needs: a `default` template
+/// and an `[overrides]` table of templates by hour, with `#` comments
+fn parse_mapping(text: &str) -> anyhow::Result<(String, Vec<(u32, String)>)> {
+    let mut default = None;
+    let mut overrides: Vec<(u32, String)> = Vec::new();
+    let mut in_overrides = false;
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(table) = line.strip_prefix('[') {
+            let (name, rest) = table
+                .split_once(']')
+                .with_context(|| format!("line {line_number}: missing ] after the table name"))?;
+            if name.trim() != "overrides" {
+                bail!("line {line_number}: unknown table [{name}], expected [overrides]");
+            }
+            end_of_line(rest, line_number)?;
+            in_overrides = true;
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .with_context(|| format!("line {line_number}: expected a key = \"template\" pair"))?;
+        let key = key.trim();
+        let key = key
+            .strip_prefix('"')
+            .and_then(|key| key.strip_suffix('"'))
+            .unwrap_or(key);
+        let (value, rest) = parse_string(value.trim())
+            .with_context(|| format!("line {line_number}: the template of {key}"))?;
+        end_of_line(rest, line_number)?;
+
+        if !in_overrides {
+            if key != "default" {
+                bail!("line {line_number}: unknown key {key}, expected default or [overrides]");
+            }
+            if default.replace(value).is_some() {
+                bail!("line {line_number}: the default template is given twice");
+            }
+            continue;
+        }
+        let hour = key
+            .parse::<u32>()
+            .with_context(|| format!("line {line_number}: {key} is not an hour"))?;
+        if overrides.iter().any(|(of, _)| *of == hour) {
+            bail!("line {line_number}: hour {hour} is given twice");
+        }
+        overrides.push((hour, value));
+    }
+    let default = default.context("missing the default template, like default = \"%H/%M.png\"")?;
+    Ok((default, overrides))
+}
+
+/// Parse a TOML basic string in double quotes or a literal string in single quotes at the start of
+/// `text`, returning it and the rest of `text`
+fn parse_string(text: &str) -> anyhow::Result<(String, &str)> {
+    if let Some(literal) = text.strip_prefix('\'') {
+        let (string, rest) = literal
+            .split_once('\'')
+            .context("missing the closing ' of the string")?;
+        return Ok((string.to_string(), rest));
+    }
+    let Some(basic) = text.strip_prefix('"') else {
+        bail!("expected a string in quotes");
+    };
+    let mut string = String::new();
+    let mut chars = basic.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &basic[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, c @ ('"' | '\\'))) => string.push(c),
+                Some((_, 't')) => string.push('\t'),
+                Some((_, c)) => bail!("unsupported escape \\{c} in the string"),
+                None => break,
+            },
+            c => string.push(c),
+        }
+    }
+    bail!("missing the closing \" of the string")
+}
+
+/// Check that only a comment follows on a line
+fn end_of_line(rest: &str, line_number: usize) -> anyhow::Result<()> {
+    let rest = rest.trim();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        bail!("line {line_number}: unexpected {rest} at the end of the line");
+    }
+    Ok(())
+}
+
+impl std::fmt::Display for FrameTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.mapping.as_deref().unwrap_or(&self.template))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_hour_folders_by_the_scheme() {
+        let dir = Path::new("clock");
+        let path = |hour_dirs: &str, millis| {
+            let template = FrameTemplate::parse("%M.png", &hour_dirs.parse().unwrap()).unwrap();
+            template.path(dir, millis)
+        };
+        let eleven = 11 * MILLIS_PER_HOUR + 5 * MILLIS_PER_MINUTE;
+        assert_eq!(path("0-11", eleven), dir.join("11/05.png"));
+        assert_eq!(path("1-12", 0), dir.join("12/00.png"));
+        assert_eq!(path("1-12", MILLIS_PER_HOUR), dir.join("01/00.png"));
+        assert_eq!(path("1-12", 13 * MILLIS_PER_HOUR), dir.join("01/00.png"));
+        assert_eq!(path("hour_%02d", eleven), dir.join("hour_11/05.png"));
+        assert_eq!(path("%d%%", MILLIS_PER_HOUR), dir.join("1%/00.png"));
+        assert_eq!(path("flat", eleven), dir.join("05.png"));
+
+        let own = FrameTemplate::parse("h%H/%M.png", &HourDirs::Flat).unwrap();
+        assert_eq!(own.hour_dirs(), None);
+        assert_eq!(own.path(dir, eleven), dir.join("h11/05.png"));
+
+        for invalid in ["0-12", "hour", "hour_%02d_%d", "%s", "%2d", "a/%d"] {
+            assert!(invalid.parse::<HourDirs>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn reads_the_clock_time_back_from_a_path() {
+        let dir = Path::new("clock");
+        let millis_of = |template: &str, hour_dirs: &str, path: &str| {
+            FrameTemplate::parse(template, &hour_dirs.parse().unwrap())
+                .unwrap()
+                .millis_of(dir, &dir.join(path))
+        };
+        let time = |hour, minute, second| {
+            hour * MILLIS_PER_HOUR + minute * MILLIS_PER_MINUTE + second * MILLIS_PER_SECOND
+        };
+        assert_eq!(
+            millis_of("%M_%S.png", "0-11", "3/07_30.png"),
+            Some(time(3, 7, 30))
+        );
+        assert_eq!(
+            millis_of("%M.png", "1-12", "12/07.png"),
+            Some(time(0, 7, 0))
+        );
+        assert_eq!(
+            millis_of("%H%M.png", "flat", "0207.png"),
+            Some(time(2, 7, 0))
+        );
+        assert_eq!(millis_of("%08m.png", "flat", "00001500.png"), Some(1500));
+        assert_eq!(
+            millis_of("%r.png", "0-11", "1/0001500.png"),
+            Some(time(1, 0, 1) + 500)
+        );
+        // Not padded like the template pads, or not matching it at all
+        assert_eq!(millis_of("%M.png", "0-11", "3/7.png"), None);
+        assert_eq!(millis_of("%M.png", "0-11", "3/07.jpg"), None);
+        assert_eq!(millis_of("%M.png", "0-11", "3/75.png"), None);
+    }
+
+    #[test]
+    fn overrides_the_template_of_some_hours() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let load = |mapping: &str| {
+            std::fs::write(dir.join("hours.toml"), mapping).unwrap();
+            FrameTemplate::load(dir, "hours.toml", &HourDirs::Flat, ClockHours::Twelve)
+        };
+
+        let template = load(
+            "# night frames by another artist\n\
+             default = \"%H/%M.png\"\n\
+             \n\
+             [overrides]\n\
+             22 = 'night_%m.png' # only part of 24 hour clocks\n",
+        );
+        assert!(template.is_err());
+        let template = load(
+            "default = \"%H/%M.png\"\n\
+             [overrides]\n\
+             \"10\" = \"night_%05r.png\"\n",
+        )
+        .unwrap();
+        let millis = |hour| hour * MILLIS_PER_HOUR + 5 * MILLIS_PER_MINUTE;
+        assert_eq!(template.path(dir, millis(9)), dir.join("09/05.png"));
+        assert_eq!(template.path(dir, millis(10)), dir.join("night_300000.png"));
+        assert_eq!(template.granularity(), 1);
+        assert_eq!(
+            template.millis_of(dir, &dir.join("night_300000.png")),
+            Some(millis(10))
+        );
+        assert_eq!(template.millis_of(dir, &dir.join("10/05.png")), None);
+        assert_eq!(template.to_string(), "hours.toml");
+        let folders = template.with_hour_dirs(&HourDirs::ZeroBased).unwrap();
+        assert_eq!(
+            folders.path(dir, millis(10)),
+            dir.join("10/night_300000.png")
+        );
+
+        for invalid in [
+            "[overrides]\n1 = \"%m.png\"",
+            "default = \"%H.png\"",
+            "default = \"%m.png\"\n[overrides]\n1 = \"night.png\"",
+            "default = \"%m.png\"\n[overrides]\n1 = \"%m.png\"\n1 = \"%r.png\"",
+            "default = \"%m.png\"\n[hours]",
+            "default = \"%m.png",
+            "default = %m.png",
+        ] {
+            assert!(load(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use anyhow::bail;
+use chrono::{format::StrftimeItems, DateTime, Local};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    damage::{Damage, Rect},
+    render::blend_pixel,
+};
+
+/// Where text is anchored on the desktop
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Anchor {
+    /// In the top left corner
+    TopLeft,
+    /// At the middle of the top edge
+    Top,
+    /// In the top right corner
+    TopRight,
+    /// At the middle of the left edge
+    Left,
+    /// In the center
+    Center,
+    /// At the middle of the right edge
+    Right,
+    /// In the bottom left corner
+    BottomLeft,
+    /// At the middle of the bottom edge
+    #[default]
+    Bottom,
+    /// In the bottom right corner
+    BottomRight,
+}
+
+impl Anchor {
+    /// The fraction of the free space placed before the text, horizontally and vertically
+    fn factors(self) -> (i64, i64) {
+        match self {
+            Anchor::TopLeft => (0, 0),
+            Anchor::Top => (1, 0),
+            Anchor::TopRight => (2, 0),
+            Anchor::Left => (0, 1),
+            Anchor::Center => (1, 1),
+            Anchor::Right => (2, 1),
+            Anchor::BottomLeft => (0, 2),
+            Anchor::Bottom => (1, 2),
+            Anchor::BottomRight => (2, 2),
+        }
+    }
+}
+
+/// How the lines of a multi-line text are aligned to each other
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Align {
+    /// Lines start at the same column
+    Left,
+    /// Lines are centered on each other
+    #[default]
+    Center,
+    /// Lines end at the same column
+    Right,
+}
+
+enum Segment {
+    Text(String),
+    /// A chrono format string formatted with the current local time
+    Time(String),
+    Hostname,
+    Uptime,
+}
+
+/// A text with placeholders which is expanded every tick
+///
+/// Supported placeholders are `{time:<format>}`, `{date:<format>}`, `{hostname}` and `{uptime}`,
+/// literal braces are written as `{{` and `}}`.
+pub struct TextTemplate {
+    segments: Vec<Segment>,
+}
+
+impl TextTemplate {
+    /// Parse the placeholders of `template`
+    pub fn parse(template: &str) -> anyhow::Result<Self> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|(_, c)| *c == '{').is_some() => text.push('{'),
+                '}' if chars.next_if(|(_, c)| *c == '}').is_some() => text.push('}'),
+                '}' => bail!("unmatched '}}' at position {start} in text template"),
+                '{' => {
+                    let Some(end) = template[start..].find('}').map(|end| start + end) else {
+                        bail!("unclosed placeholder at position {start} in text template");
+                    };
+                    let placeholder = &template[start + 1..end];
+                    while chars.next_if(|(idx, _)| *idx <= end).is_some() {}
+
+                    let (name, format) = match placeholder.split_once(':') {
+                        Some((name, format)) => (name, Some(format)),
+                        None => (placeholder, None),
+                    };
+                    let segment = match (name, format) {
+                        ("time", format) | ("date", format) => {
+                            let format =
+                                format.unwrap_or(if name == "time" { "%H:%M" } else { "%A %d %B" });
+                            if StrftimeItems::new(format)
+                                .any(|item| item == chrono::format::Item::Error)
+                            {
+                                bail!(
+                                    "invalid {name} format in placeholder {{{placeholder}}} at \
+                                     position {start}"
+                                );
+                            }
+                            Segment::Time(format.to_string())
+                        }
+                        ("hostname", None) => Segment::Hostname,
+                        ("uptime", None) => Segment::Uptime,
+                        _ => bail!("unknown placeholder {{{placeholder}}} at position {start}"),
+                    };
+
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(segment);
+                }
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+
+        Ok(TextTemplate { segments })
+    }
+
+    /// Replace all placeholders with their values at the time `now`
+    pub fn expand(&self, now: DateTime<Local>) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Time(format) => now.format(format).to_string(),
+                Segment::Hostname => hostname(),
+                Segment::Uptime => uptime(),
+            })
+            .collect()
+    }
+}
+
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .map(|name| name.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn uptime() -> String {
+    let seconds = std::fs::read_to_string("/proc/uptime")
+        .ok()
+        .and_then(|uptime| uptime.split_whitespace().next()?.parse::<f64>().ok())
+        .unwrap_or_default() as u64;
+
+    let (days, hours, minutes) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m")
+    }
+}
+
+/// How and where text is drawn
+pub struct TextStyle {
+    /// The height of the font in pixels
+    pub size: f32,
+    /// The color of the text, drawn with a dark shadow
+    pub color: [u8; 3],
+    /// Where the text is anchored on the desktop
+    pub anchor: Anchor,
+    /// How the lines are aligned to each other
+    pub align: Align,
+    /// Moves the text right and down from its anchor in pixels
+    pub offset: (i32, i32),
+    /// The distance of the text from the edges it is anchored at in pixels
+    pub margin: u32,
+}
+
+/// Clear the `stale` part of the rgba `frame`, where the previous text was drawn, and draw `text`
+/// with a drop shadow onto it, returns the part holding the drawn text
+pub fn draw_text(
+    frame: &mut [u8],
+    width: u32,
+    height: u32,
+    font: &FontVec,
+    style: &TextStyle,
+    text: &str,
+    stale: Damage,
+) -> Damage {
+    for row in stale.rows(width, frame.len()) {
+        frame[row].fill(0);
+    }
+
+    let scale = PxScale::from(style.size);
+    let font = font.as_scaled(scale);
+    let line_height = font.height() + font.line_gap();
+
+    let line_widths: Vec<f32> = text
+        .lines()
+        .map(|line| {
+            let mut previous = None;
+            line.chars()
+                .map(|c| {
+                    let glyph = font.glyph_id(c);
+                    let kern = previous.map_or(0.0, |previous| font.kern(previous, glyph));
+                    previous = Some(glyph);
+                    kern + font.h_advance(glyph)
+                })
+                .sum()
+        })
+        .collect();
+    let box_width = line_widths.iter().copied().fold(0.0, f32::max).ceil() as i64;
+    let box_height = (line_height * line_widths.len() as f32).ceil() as i64;
+
+    let (factor_x, factor_y) = style.anchor.factors();
+    let margin = style.margin as i64;
+    let origin_x =
+        margin + (width as i64 - 2 * margin - box_width) * factor_x / 2 + style.offset.0 as i64;
+    let origin_y =
+        margin + (height as i64 - 2 * margin - box_height) * factor_y / 2 + style.offset.1 as i64;
+
+    let shadow_offset = (style.size / 24.0).max(1.0);
+    let mut drawn = Damage::None;
+    for (offset, color, alpha) in [(shadow_offset, [0, 0, 0], 0.6), (0.0, style.color, 1.0)] {
+        for (line_idx, (line, line_width)) in text.lines().zip(&line_widths).enumerate() {
+            let line_x = origin_x as f32
+                + match style.align {
+                    Align::Left => 0.0,
+                    Align::Center => (box_width as f32 - line_width) / 2.0,
+                    Align::Right => box_width as f32 - line_width,
+                };
+            let baseline = origin_y as f32 + font.ascent() + line_height * line_idx as f32;
+
+            let mut caret = line_x;
+            let mut previous = None;
+            for c in line.chars() {
+                let glyph_id = font.glyph_id(c);
+                caret += previous.map_or(0.0, |previous| font.kern(previous, glyph_id));
+                previous = Some(glyph_id);
+
+                let glyph = glyph_id
+                    .with_scale_and_position(scale, point(caret + offset, baseline + offset));
+                caret += font.h_advance(glyph_id);
+
+                let Some(outline) = font.outline_glyph(glyph) else {
+                    continue;
+                };
+                let bounds = outline.px_bounds();
+                if let Some(rect) = Rect::clipped(
+                    (bounds.min.x.floor() as i64, bounds.min.y.floor() as i64),
+                    (bounds.max.x.ceil() as i64, bounds.max.y.ceil() as i64),
+                    width,
+                    height,
+                ) {
+                    drawn = drawn.union(Damage::Rect(rect));
+                }
+                outline.draw(|x, y, coverage| {
+                    let x = bounds.min.x as i64 + x as i64;
+                    let y = bounds.min.y as i64 + y as i64;
+                    if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                        return;
+                    }
+                    let idx = (y as usize * width as usize + x as usize) * 4;
+                    let alpha = (coverage.clamp(0.0, 1.0) * alpha * 255.0) as u8;
+                    blend_pixel(
+                        &mut frame[idx..idx + 4],
+                        &[color[0], color[1], color[2], alpha],
+                    );
+                });
+            }
+        }
+    }
+    drawn
+}
@@ -0,0 +1,567 @@
+use std::fmt::Display;
+
+use anyhow::{bail, Context};
+use color::{color_space::Srgb, Deg, Hsv, ToRgb};
+
+use crate::colors::{parse_color, parse_opaque_color};
+
+/// The color the clock frames are tinted with
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClockColor {
+    /// The same color all the time, its alpha is the strength of the tint
+    Fixed([f32; 4]),
+    /// A color cycling through all hues
+    Rainbow(Rainbow),
+    /// A color fading from one color to another across a time window of the day
+    Gradient(Gradient),
+    /// A color stepping through a list of colors
+    Cycle(ColorCycle),
+}
+
+/// A color cycling through all hues
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rainbow {
+    /// The saturation of the hues from 0 to 1
+    pub saturation: f32,
+    /// The brightness of the hues from 0 to 1
+    pub value: f32,
+    /// How long one full hue rotation takes
+    pub period_minutes: f32,
+    /// The hue at the start of the clock cycle
+    pub phase_degrees: f32,
+    /// The strength of the tint from 0 to 1
+    pub alpha: f32,
+}
+
+impl Rainbow {
+    /// A fully saturated rainbow rotating once per `period_minutes`
+    fn with_period(period_minutes: f32) -> Self {
+        Rainbow {
+            saturation: 1.0,
+            value: 1.0,
+            period_minutes,
+            phase_degrees: 0.0,
+            alpha: 1.0,
+        }
+    }
+}
+
+/// A color fading from one color to another across a time window of the day
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    /// The color before the window
+    pub from: [f32; 3],
+    /// The color after the window
+    pub to: [f32; 3],
+    /// Start of the window in milliseconds since midnight
+    pub start: u32,
+    /// End of the window in milliseconds since midnight, may be before the start to cross
+    /// midnight
+    pub end: u32,
+    /// The strength of the tint from 0 to 1
+    pub alpha: f32,
+}
+
+impl Gradient {
+    /// How far into the fade the given time of day is, clamped outside of the window
+    fn progress(&self, day_millis: u32) -> f32 {
+        let window = (self.end + MILLIS_PER_DAY - self.start) % MILLIS_PER_DAY;
+        let window = if window == 0 { MILLIS_PER_DAY } else { window };
+        let elapsed = (day_millis + MILLIS_PER_DAY - self.start) % MILLIS_PER_DAY;
+
+        if elapsed <= window {
+            elapsed as f32 / window as f32
+        } else if elapsed - window < (MILLIS_PER_DAY - window) / 2 {
+            // Closer to the end of the window than to the next start
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A color stepping through a list of colors over and over, fading from each to the next
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorCycle {
+    /// The colors stepped through in order
+    pub colors: Vec<[f32; 3]>,
+    /// How long one round through all colors takes
+    pub period_minutes: f32,
+    /// The strength of the tint from 0 to 1
+    pub alpha: f32,
+}
+
+impl ColorCycle {
+    /// The color `millis` into the clock cycle, fading between neighboring colors in OkLCh along
+    /// the shorter way around the hue circle
+    fn at(&self, millis: u32) -> [f32; 3] {
+        let period_millis = self.period_minutes as f64 * 60.0 * 1000.0;
+        let position = (millis as f64 / period_millis).fract() * self.colors.len() as f64;
+        let idx = position as usize % self.colors.len();
+        let progress = position.fract() as f32;
+        let from = oklab_to_oklch(srgb_to_oklab(self.colors[idx]));
+        let to = oklab_to_oklch(srgb_to_oklab(self.colors[(idx + 1) % self.colors.len()]));
+
+        // Grays have no hue of their own, they take the hue of the color they fade with
+        let (from_hue, to_hue) = match (from[1] < ACHROMATIC, to[1] < ACHROMATIC) {
+            (true, false) => (to[2], to[2]),
+            (false, true) => (from[2], from[2]),
+            _ => (from[2], to[2]),
+        };
+        let turn = (to_hue - from_hue + 180.0).rem_euclid(360.0) - 180.0;
+        oklab_to_srgb(oklch_to_oklab([
+            from[0] + (to[0] - from[0]) * progress,
+            from[1] + (to[1] - from[1]) * progress,
+            from_hue + turn * progress,
+        ]))
+    }
+}
+
+/// The chroma below which a color is treated as gray
+const ACHROMATIC: f32 = 1e-4;
+
+const MILLIS_PER_DAY: u32 = 24 * 60 * 60 * 1000;
+
+impl ClockColor {
+    /// The format of clock colors, shown in the help of the clock color option
+    pub const FORMAT: &'static str =
+        "< RAINBOW[:<saturation>,<value>,<period_minutes>,<phase_degrees>][@<AA>] \
+         | GRADIENT:<color>-<color>[:<hh:mm>-<hh:mm>][@<AA>] | <color>[,<color>...][@<AA>] >";
+
+    /// Parse a clock color of the format [`ClockColor::FORMAT`], a plain rainbow rotates and a
+    /// list of colors is stepped through once per `cycle_minutes`
+    ///
+    /// The alpha of a fixed color or the hex digits after an `@` are the strength of the tint.
+    pub fn parse(string: &str, cycle_minutes: f32) -> anyhow::Result<Self> {
+        let Some((color, alpha)) = string.rsplit_once('@') else {
+            return Self::parse_kind(string, cycle_minutes);
+        };
+        let alpha = u8::from_str_radix(alpha, 16)
+            .ok()
+            .filter(|_| alpha.len() == 2)
+            .with_context(|| {
+                format!("the alpha after @ should be 2 hex digits like @80, got {alpha:?}")
+            })?;
+        let mut parsed = Self::parse_kind(color, cycle_minutes)?;
+        let strength = parsed.alpha_mut();
+        if *strength < 1.0 {
+            bail!(
+                "the clock color {color:?} has an alpha already, give it either there or after @"
+            );
+        }
+        *strength = alpha as f32 / 255.0;
+        Ok(parsed)
+    }
+
+    /// The strength of the tint
+    fn alpha_mut(&mut self) -> &mut f32 {
+        match self {
+            ClockColor::Fixed([.., alpha])
+            | ClockColor::Rainbow(Rainbow { alpha, .. })
+            | ClockColor::Gradient(Gradient { alpha, .. })
+            | ClockColor::Cycle(ColorCycle { alpha, .. }) => alpha,
+        }
+    }
+
+    /// Parse a clock color without an alpha after @
+    fn parse_kind(string: &str, cycle_minutes: f32) -> anyhow::Result<Self> {
+        let (name, params) = match string.split_once(':') {
+            Some((name, params)) => (name, Some(params)),
+            None => (string, None),
+        };
+
+        if name.eq_ignore_ascii_case("GRADIENT") {
+            return Self::parse_gradient(params.unwrap_or_default());
+        }
+
+        if !name.eq_ignore_ascii_case("RAINBOW") {
+            let colors = split_list(string);
+            if colors.len() > 1 {
+                return Ok(ClockColor::Cycle(ColorCycle {
+                    colors: colors
+                        .into_iter()
+                        .map(|color| {
+                            parse_opaque_color(color.trim())
+                                .with_context(|| format!("in the list of clock colors {string:?}"))
+                        })
+                        .collect::<anyhow::Result<_>>()?,
+                    period_minutes: cycle_minutes,
+                    alpha: 1.0,
+                }));
+            }
+            let color = parse_color(string)
+                .with_context(|| format!("clock-color should be of the format {}", Self::FORMAT))?;
+            return Ok(ClockColor::Fixed(color));
+        }
+
+        let Some(params) = params else {
+            return Ok(ClockColor::Rainbow(Rainbow::with_period(cycle_minutes)));
+        };
+        let params = params
+            .split(',')
+            .map(|param| {
+                param.trim().parse::<f32>().with_context(|| {
+                    format!(
+                        "rainbow parameter {param:?} is not a number, expected {}",
+                        Self::FORMAT
+                    )
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let [saturation, value, period_minutes, phase_degrees] = params[..] else {
+            bail!(
+                "rainbow expects 4 parameters but got {count}, expected {format}",
+                count = params.len(),
+                format = Self::FORMAT,
+            );
+        };
+
+        if !(0.0..=1.0).contains(&saturation) {
+            bail!("rainbow saturation should be in the range 0 to 1, got {saturation}");
+        }
+        if !(0.0..=1.0).contains(&value) {
+            bail!("rainbow value should be in the range 0 to 1, got {value}");
+        }
+        if !(period_minutes > 0.0 && period_minutes.is_finite()) {
+            bail!("rainbow period should be a positive number of minutes, got {period_minutes}");
+        }
+        if !phase_degrees.is_finite() {
+            bail!("rainbow phase should be a number of degrees, got {phase_degrees}");
+        }
+
+        Ok(ClockColor::Rainbow(Rainbow {
+            saturation,
+            value,
+            period_minutes,
+            phase_degrees,
+            alpha: 1.0,
+        }))
+    }
+
+    fn parse_gradient(params: &str) -> anyhow::Result<Self> {
+        let (colors, window) = match params.split_once(':') {
+            Some((colors, window)) => (colors, Some(window)),
+            None => (params, None),
+        };
+
+        let Some((from, to)) = colors.split_once('-') else {
+            bail!("gradient colors should be of the format <color>-<color>, got {colors:?}");
+        };
+        let (start, end) = match window {
+            Some(window) => {
+                let Some((start, end)) = window.split_once('-') else {
+                    bail!(
+                        "gradient window should be of the format <hh:mm>-<hh:mm>, got {window:?}"
+                    );
+                };
+                (parse_time_of_day(start)?, parse_time_of_day(end)?)
+            }
+            None => (0, 0),
+        };
+
+        Ok(ClockColor::Gradient(Gradient {
+            from: parse_opaque_color(from)?,
+            to: parse_opaque_color(to)?,
+            start,
+            end,
+            alpha: 1.0,
+        }))
+    }
+
+    /// The color at the given clock time and time of day in milliseconds, with the strength of
+    /// the tint as alpha
+    pub fn at(&self, millis: u32, day_millis: u32) -> [f32; 4] {
+        match self {
+            ClockColor::Fixed(color) => *color,
+            ClockColor::Gradient(gradient) => {
+                let progress = gradient.progress(day_millis);
+                let from = srgb_to_oklab(gradient.from);
+                let to = srgb_to_oklab(gradient.to);
+                let [r, g, b] = oklab_to_srgb(
+                    [0, 1, 2].map(|idx| from[idx] + (to[idx] - from[idx]) * progress),
+                );
+                [r, g, b, gradient.alpha]
+            }
+            ClockColor::Rainbow(Rainbow {
+                saturation,
+                value,
+                period_minutes,
+                phase_degrees,
+                alpha,
+            }) => {
+                let period_millis = *period_minutes as f64 * 60.0 * 1000.0;
+                let hue = (*phase_degrees as f64 + millis as f64 / period_millis * 360.0)
+                    .rem_euclid(360.0);
+                let [r, g, b] = *Hsv::<f32, Srgb>::new(Deg(hue as f32), *saturation, *value)
+                    .to_rgb::<f32>()
+                    .as_ref();
+                [r, g, b, *alpha]
+            }
+            ClockColor::Cycle(cycle) => {
+                let [r, g, b] = cycle.at(millis);
+                [r, g, b, cycle.alpha]
+            }
+        }
+    }
+}
+
+impl Display for ClockColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hex = |color: &[f32]| -> String {
+            color
+                .iter()
+                .map(|c| format!("{:02X}", (c * 255.0).round() as u8))
+                .collect()
+        };
+        match self {
+            ClockColor::Fixed([r, g, b, 1.0]) => f.write_str(&hex(&[*r, *g, *b])),
+            ClockColor::Fixed(color) => f.write_str(&hex(color)),
+            ClockColor::Rainbow(Rainbow {
+                saturation,
+                value,
+                period_minutes,
+                phase_degrees,
+                alpha,
+            }) => {
+                write!(
+                    f,
+                    "RAINBOW:{saturation},{value},{period_minutes},{phase_degrees}"
+                )?;
+                if *alpha < 1.0 {
+                    write!(f, "@{alpha}", alpha = hex(&[*alpha]))?;
+                }
+                Ok(())
+            }
+            ClockColor::Gradient(Gradient {
+                from,
+                to,
+                start,
+                end,
+                alpha,
+            }) => {
+                write!(
+                    f,
+                    "GRADIENT:{from}-{to}:{start}-{end}",
+                    from = hex(from),
+                    to = hex(to),
+                    start = format_time_of_day(*start),
+                    end = format_time_of_day(*end),
+                )?;
+                if *alpha < 1.0 {
+                    write!(f, "@{alpha}", alpha = hex(&[*alpha]))?;
+                }
+                Ok(())
+            }
+            ClockColor::Cycle(ColorCycle { colors, alpha, .. }) => {
+                let colors: Vec<_> = colors.iter().map(|color| hex(color)).collect();
+                f.write_str(&colors.join(","))?;
+                if *alpha < 1.0 {
+                    write!(f, "@{alpha}", alpha = hex(&[*alpha]))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Split a list of colors at the commas outside of parentheses, which separate the channels of
+/// `rgb(..)` colors
+fn split_list(string: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (idx, c) in string.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(&string[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&string[start..]);
+    items
+}
+
+/// Parse a time of day of the format hh:mm into milliseconds since midnight
+fn parse_time_of_day(string: &str) -> anyhow::Result<u32> {
+    let parsed = string.split_once(':').and_then(|(hours, minutes)| {
+        Some((hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?))
+    });
+    match parsed {
+        Some((hours, minutes))
+            if hours <= 24 && minutes < 60 && hours * 60 + minutes <= 24 * 60 =>
+        {
+            Ok((hours * 60 + minutes) * 60 * 1000 % MILLIS_PER_DAY)
+        }
+        _ => bail!("time of day should be of the format hh:mm, got {string:?}"),
+    }
+}
+
+fn format_time_of_day(millis: u32) -> String {
+    let minutes = millis / 1000 / 60;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Convert an srgb color to the perceptual Oklab color space
+#[allow(clippy::excessive_precision)]
+fn srgb_to_oklab(color: [f32; 3]) -> [f32; 3] {
+    let [r, g, b] = color.map(srgb_to_linear);
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// Convert an Oklab color back to srgb
+#[allow(clippy::excessive_precision)]
+fn oklab_to_srgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+    .map(|c| linear_to_srgb(c).clamp(0.0, 1.0))
+}
+
+/// Convert an Oklab color to lightness, chroma and hue in degrees
+fn oklab_to_oklch([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    [lightness, a.hypot(b), b.atan2(a).to_degrees()]
+}
+
+fn oklch_to_oklab([lightness, chroma, hue]: [f32; 3]) -> [f32; 3] {
+    let (sin, cos) = hue.to_radians().sin_cos();
+    [lightness, chroma * cos, chroma * sin]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_strength_of_clock_colors() {
+        let parse = |string: &str| ClockColor::parse(string, 720.0).unwrap();
+        assert_eq!(parse("red").at(0, 0), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(parse("#FF000080").at(0, 0)[3], 128.0 / 255.0);
+        assert_eq!(parse("red@80").at(0, 0)[3], 128.0 / 255.0);
+        assert_eq!(parse("RAINBOW@80").at(0, 0), [1.0, 0.0, 0.0, 128.0 / 255.0]);
+        assert_eq!(parse("GRADIENT:red-blue@00").at(0, 0)[3], 0.0);
+        for string in [
+            "RAINBOW@80",
+            "RAINBOW:1,1,60,0@40",
+            "GRADIENT:red-blue:06:00-07:00@FF",
+            "FF0000,00FF00,0000FF@80",
+        ] {
+            let color = parse(string);
+            assert_eq!(parse(&color.to_string()), color, "{color}");
+        }
+        assert_eq!(parse("#FF000080").to_string(), "FF000080");
+
+        for invalid in [
+            "RAINBOW@",
+            "RAINBOW@800",
+            "red@GG",
+            "#FF000080@80",
+            "red,",
+            "red,#0000FF80",
+        ] {
+            assert!(ClockColor::parse(invalid, 720.0).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn fades_through_lists_of_colors() {
+        let cycle = ClockColor::parse("red, rgb(0,255,0) ,blue", 60.0).unwrap();
+        let minutes = |minutes: u32| minutes * 60 * 1000;
+        let close = |color: [f32; 4], expected: [f32; 3]| {
+            (0..3).all(|idx| (color[idx] - expected[idx]).abs() < 1e-3)
+        };
+        assert!(close(cycle.at(0, 0), [1.0, 0.0, 0.0]));
+        assert!(close(cycle.at(minutes(20), 0), [0.0, 1.0, 0.0]));
+        assert!(close(cycle.at(minutes(40), 0), [0.0, 0.0, 1.0]));
+        // Loops back to the first color, on the clock time rather than the time of day
+        assert!(close(cycle.at(minutes(60), 0), [1.0, 0.0, 0.0]));
+        assert_eq!(
+            cycle.at(minutes(75), 0),
+            cycle.at(minutes(15), minutes(600))
+        );
+
+        // Blue fades to red through purple rather than the long way through green
+        let [r, g, b, _] = cycle.at(minutes(50), 0);
+        assert!(r > 0.5 && b > 0.5 && g < 0.1, "{r} {g} {b}");
+        // Grays keep the hue of the other color
+        let gray = ClockColor::parse("gray,red", 60.0).unwrap();
+        let [r, g, b, _] = gray.at(minutes(15), 0);
+        let hue = |color| oklab_to_oklch(srgb_to_oklab(color))[2];
+        assert!((hue([r, g, b]) - hue([1.0, 0.0, 0.0])).abs() < 1.0);
+    }
+
+    #[test]
+    fn fades_gradients_across_their_window() {
+        let hours = |hours: u32, minutes: u32| (hours * 60 + minutes) * 60 * 1000;
+        let gradient = |string: &str| match ClockColor::parse(string, 720.0).unwrap() {
+            ClockColor::Gradient(gradient) => gradient,
+            color => panic!("{color:?} is no gradient"),
+        };
+
+        let morning = gradient("GRADIENT:red-blue:06:00-07:00");
+        assert_eq!(morning.progress(hours(6, 0)), 0.0);
+        assert_eq!(morning.progress(hours(6, 15)), 0.25);
+        assert_eq!(morning.progress(hours(6, 30)), 0.5);
+        assert_eq!(morning.progress(hours(7, 0)), 1.0);
+        // Outside of the window the color stays at the closer end, switching back to the start
+        // color halfway to the next window
+        assert_eq!(morning.progress(hours(8, 0)), 1.0);
+        assert_eq!(morning.progress(hours(18, 29)), 1.0);
+        assert_eq!(morning.progress(hours(18, 31)), 0.0);
+        assert_eq!(morning.progress(hours(5, 0)), 0.0);
+
+        let night = gradient("GRADIENT:red-blue:22:00-02:00");
+        assert_eq!(night.progress(hours(22, 0)), 0.0);
+        assert_eq!(night.progress(hours(23, 0)), 0.25);
+        assert_eq!(night.progress(0), 0.5);
+        assert_eq!(night.progress(hours(1, 0)), 0.75);
+        assert_eq!(night.progress(hours(2, 0)), 1.0);
+        assert_eq!(night.progress(hours(3, 0)), 1.0);
+        assert_eq!(night.progress(hours(11, 59)), 1.0);
+        assert_eq!(night.progress(hours(12, 1)), 0.0);
+        assert_eq!(night.progress(hours(21, 0)), 0.0);
+
+        // The ends are the given colors, the middle is faded in Oklab
+        let color = ClockColor::Gradient(night);
+        let close = |color: [f32; 4], expected: [f32; 4]| {
+            (0..4).all(|idx| (color[idx] - expected[idx]).abs() < 1e-3)
+        };
+        assert!(close(color.at(0, hours(21, 0)), [1.0, 0.0, 0.0, 1.0]));
+        assert!(close(color.at(0, hours(3, 0)), [0.0, 0.0, 1.0, 1.0]));
+        let [r, g, b, _] = color.at(0, 0);
+        assert!(r > g + 0.2 && b > g + 0.2, "{r} {g} {b}");
+        // A window from a time to itself spans the whole day
+        let day = gradient("GRADIENT:red-blue");
+        assert_eq!(day.progress(hours(12, 0)), 0.5);
+    }
+}
@@ -0,0 +1,222 @@
+#[cfg(any(test, feature = "lua"))]
+use std::time::Instant;
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use image::RgbaImage;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{
+    decode,
+    render::{FrameLayout, Scaling},
+    span::OutputSpan,
+};
+
+/// How long a file has to stay unchanged before it is reloaded
+pub const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch the directory holding `path` for files whose names `watched` accepts being written,
+/// created, renamed or removed, sending a message for each until the watcher is dropped
+///
+/// Watching the directory rather than the file sees the file being replaced by a rename too.
+pub fn watch_dir(
+    path: &Path,
+    watched: impl Fn(&OsStr) -> bool + Send + 'static,
+) -> anyhow::Result<(RecommendedWatcher, Receiver<()>)> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let (sender, changes) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let changed = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) && event
+            .paths
+            .iter()
+            .any(|path| path.file_name().is_some_and(&watched));
+        if changed {
+            let _ = sender.send(());
+        }
+    })
+    .context("could not set up a file watcher")?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .with_context(|| format!("could not watch {}", dir.display()))?;
+    Ok((watcher, changes))
+}
+
+/// Wait for the next change on `changes` and until none came for [`DEBOUNCE`], false once the
+/// watcher sending them is dropped
+pub fn settled(changes: &Receiver<()>) -> bool {
+    if changes.recv().is_err() {
+        return false;
+    }
+    loop {
+        match changes.recv_timeout(DEBOUNCE) {
+            Ok(()) => {}
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Notices when a file is written or replaced, once it stopped changing for [`DEBOUNCE`]
+#[cfg(feature = "lua")]
+pub struct FileChanges {
+    path: PathBuf,
+    _watcher: RecommendedWatcher,
+    changes: Receiver<()>,
+    /// When the file last changed, reported once it settled
+    changed: Option<Instant>,
+}
+
+#[cfg(feature = "lua")]
+impl FileChanges {
+    /// Watch the file at `path` for changes from its current version on
+    pub fn new(path: PathBuf) -> anyhow::Result<Self> {
+        let name = path.file_name().map(OsStr::to_os_string);
+        let (watcher, changes) = watch_dir(&path, move |changed| Some(changed) == name.as_deref())?;
+        Ok(FileChanges {
+            path,
+            _watcher: watcher,
+            changes,
+            changed: None,
+        })
+    }
+
+    /// Whether the file changed and settled since it last did, without waiting
+    ///
+    /// A removed file is only reported once it is back.
+    pub fn poll(&mut self) -> bool {
+        while let Ok(()) = self.changes.try_recv() {
+            self.changed = Some(Instant::now());
+        }
+        match self.changed {
+            Some(changed) if changed.elapsed() >= DEBOUNCE => {
+                self.changed = None;
+                self.path.exists()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Watches an image file on a background thread and decodes it again whenever it changes
+///
+/// The thread ends once the watcher is dropped, with the file watch it waits on.
+pub struct ImageWatcher {
+    frames: Receiver<RgbaImage>,
+    _watcher: RecommendedWatcher,
+}
+
+impl ImageWatcher {
+    pub fn spawn(
+        path: PathBuf,
+        scaling: Scaling,
+        margin_color: [u8; 3],
+        span: Option<OutputSpan>,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<Self> {
+        let name = path.file_name().map(OsStr::to_os_string);
+        let (watcher, changes) = watch_dir(&path, move |changed| Some(changed) == name.as_deref())?;
+        let (sender, frames) = mpsc::channel();
+
+        thread::spawn(move || {
+            while settled(&changes) {
+                // A removed file is only reloaded once it is back
+                if !path.exists() {
+                    continue;
+                }
+
+                let mut layout = FrameLayout::with_color(scaling, margin_color, width, height);
+                let frame = match span {
+                    // Cropping to the part of the output needs the image at its full size
+                    Some(span) => decode::open(&path)
+                        .map(|image| layout.place(&span.crop(&image), width, height)),
+                    None => layout.load(&path, width, height),
+                };
+                match frame {
+                    Ok(frame) => {
+                        if sender.send(frame).is_err() {
+                            break;
+                        }
+                    }
+                    Err(error) => eprintln!(
+                        "warning: could not reload {path}, keeping the previous image: {error}",
+                        path = path.display(),
+                    ),
+                }
+            }
+        });
+
+        Ok(ImageWatcher {
+            frames,
+            _watcher: watcher,
+        })
+    }
+
+    /// The most recently reloaded frame, if the file changed since the last call
+    pub fn poll(&self) -> Option<RgbaImage> {
+        let mut latest = None;
+        loop {
+            match self.frames.try_recv() {
+                Ok(frame) => latest = Some(frame),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return latest,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    /// The next frame `watcher` reloads, waiting a few debounce periods for it
+    fn reloaded(watcher: &ImageWatcher) -> Option<RgbaImage> {
+        let started = Instant::now();
+        while started.elapsed() < DEBOUNCE * 4 {
+            if let Some(frame) = watcher.poll() {
+                return Some(frame);
+            }
+            thread::sleep(DEBOUNCE / 8);
+        }
+        None
+    }
+
+    #[test]
+    fn reloads_images_replaced_by_a_rename() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("background.png");
+        RgbaImage::from_pixel(1, 1, Rgba([1, 2, 3, 255]))
+            .save(&path)
+            .unwrap();
+        let watcher =
+            ImageWatcher::spawn(path.clone(), Scaling::Stretch, [0; 3], None, 1, 1).unwrap();
+
+        // Other files of the directory are ignored
+        std::fs::write(temp.path().join("notes.txt"), "").unwrap();
+        assert_eq!(reloaded(&watcher), None);
+
+        let written = temp.path().join("background.png.tmp");
+        RgbaImage::from_pixel(1, 1, Rgba([4, 5, 6, 255]))
+            .save_with_format(&written, image::ImageFormat::Png)
+            .unwrap();
+        std::fs::rename(&written, &path).unwrap();
+        let frame = reloaded(&watcher).unwrap();
+        assert_eq!(frame.get_pixel(0, 0).0, [4, 5, 6, 255]);
+    }
+}
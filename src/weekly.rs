@@ -0,0 +1,124 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use chrono::Weekday;
+
+use crate::{
+    args::ColorArg,
+    command::Command,
+    render::{BackgroundRenderer, Scaling},
+};
+
+/// The names of the images or folders of the days, starting on monday
+const DAY_NAMES: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+/// The name of the image or folder of `weekday`
+pub fn day_name(weekday: Weekday) -> &'static str {
+    DAY_NAMES[weekday.num_days_from_monday() as usize]
+}
+
+/// The files in `dir`, sorted by name
+fn files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+/// The first file in `dir` named `stem` with any extension
+fn find_file(dir: &Path, stem: &str) -> Option<PathBuf> {
+    files(dir)
+        .into_iter()
+        .find(|path| path.file_stem().is_some_and(|name| name == stem))
+}
+
+/// The image of `weekday` in `dir`, `<day>.*` or a random image of the folder `<day>`
+pub fn day_image(dir: &Path, weekday: Weekday) -> Option<PathBuf> {
+    let name = day_name(weekday);
+    find_file(dir, name).or_else(|| {
+        let images = files(&dir.join(name));
+        (!images.is_empty()).then(|| images[fastrand::usize(..images.len())].clone())
+    })
+}
+
+/// The `default.*` image shown on days without an image of their own
+pub fn default_image(dir: &Path) -> Option<PathBuf> {
+    find_file(dir, "default")
+}
+
+/// The days which have no image of their own in `dir`
+pub fn missing_days(dir: &Path) -> Vec<&'static str> {
+    (0..7)
+        .map(|day| Weekday::try_from(day).unwrap())
+        .filter(|weekday| day_image(dir, *weekday).is_none())
+        .map(day_name)
+        .collect()
+}
+
+/// The renderer of the image shown on `weekday` and its file, the default image if the day has
+/// none of its own
+pub fn day_renderer(
+    dir: &Path,
+    weekday: Weekday,
+    scaling: Scaling,
+    margin_color: Option<ColorArg>,
+    width: u32,
+    height: u32,
+) -> anyhow::Result<(PathBuf, BackgroundRenderer)> {
+    let path = day_image(dir, weekday)
+        .or_else(|| default_image(dir))
+        .with_context(|| {
+            format!(
+                "{dir} has neither an image for {day} nor a default image",
+                dir = dir.display(),
+                day = day_name(weekday),
+            )
+        })?;
+    let renderer = Command::StaticImage {
+        path: path.clone(),
+        scaling,
+        margin_color,
+        watch: false,
+        span: false,
+    }
+    .into_renderer(width, height, None)?;
+    Ok((path, renderer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_day_images_and_folders() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        std::fs::create_dir_all(dir.join("friday")).unwrap();
+        for file in ["monday.png", "default.jpg", "friday/a.png", "friday/b.png"] {
+            std::fs::write(dir.join(file), []).unwrap();
+        }
+
+        assert_eq!(day_image(dir, Weekday::Mon), Some(dir.join("monday.png")));
+        assert!(day_image(dir, Weekday::Fri)
+            .unwrap()
+            .starts_with(dir.join("friday")));
+        assert_eq!(day_image(dir, Weekday::Tue), None);
+        assert_eq!(default_image(dir), Some(dir.join("default.jpg")));
+        assert_eq!(
+            missing_days(dir),
+            ["tuesday", "wednesday", "thursday", "saturday", "sunday"]
+        );
+    }
+}
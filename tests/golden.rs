@@ -0,0 +1,373 @@
+//! Renders every kind of background at a fixed time and compares the frames to the reference
+//! images in `tests/golden`, run with `BLESS=1 cargo test` to write the references again
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, Local, TimeZone};
+use desktop_background::{
+    clock::MockClock,
+    damage::Damage,
+    decode::{self, HighDepth},
+    preview,
+    render::{ClockHours, ColorizeMode, MaskSource, Scaling},
+    text::{Align, Anchor},
+    Command,
+};
+use image::{DynamicImage, ImageBuffer, Rgb, RgbaImage};
+use tempfile::TempDir;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 180;
+/// How far a channel may be off the reference, for float rounding between platforms
+const TOLERANCE: u8 = 2;
+
+fn data(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/data")
+        .join(name)
+}
+
+fn start_time() -> DateTime<Local> {
+    Local.with_ymd_and_hms(2024, 1, 15, 12, 34, 56).unwrap()
+}
+
+/// A directory with a clock frame for each second, the input image with its colors shifted by the
+/// second
+fn clock_dir() -> TempDir {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    std::fs::create_dir_all(dir.join("frames")).unwrap();
+    let input = image::open(data("input.png")).unwrap().to_rgba8();
+    for second in 0..60u32 {
+        let mut frame = input.clone();
+        for pixel in frame.pixels_mut() {
+            for channel in 0..3 {
+                pixel[channel] = pixel[channel].wrapping_add((second * 37) as u8);
+            }
+        }
+        frame
+            .save(dir.join(format!("frames/{second:02}.png")))
+            .unwrap();
+    }
+    temp
+}
+
+fn clock(dir: &TempDir) -> Command {
+    Command::ClockImage {
+        dir: dir.path().to_path_buf(),
+        file_template: "frames/%S.png".to_string(),
+        clock_step: 1000,
+        hours: ClockHours::Twelve,
+        hour_dirs: Default::default(),
+        offset: None,
+        timezone: None,
+        clock_color: None,
+        color_cycle: None,
+        scaling: Scaling::Fit,
+        margin_color: Some("203040".parse().unwrap()),
+        underlay: None,
+        colorize: ColorizeMode::Multiply,
+        mask_source: MaskSource::Alpha,
+        interpolate: None,
+        sheets: false,
+        no_validate: true,
+    }
+}
+
+fn static_image(scaling: Scaling) -> Command {
+    static_image_at(data("input.png"), scaling)
+}
+
+fn static_image_at(path: PathBuf, scaling: Scaling) -> Command {
+    Command::StaticImage {
+        path,
+        scaling,
+        margin_color: Some("402010".parse().unwrap()),
+        watch: false,
+        span: false,
+    }
+}
+
+fn text() -> Command {
+    Command::TextOverlay {
+        template: "{time:%H:%M}\nclock".to_string(),
+        font: data("Cantarell-Regular.ttf"),
+        size: 40.0,
+        color: "F0E0A0".parse().unwrap(),
+        position: Anchor::Center,
+        align: Align::Center,
+        offset_x: 10,
+        offset_y: -5,
+        margin: 16,
+    }
+}
+
+/// Render `command` at the start time, then once more after each of `advances`
+fn render(command: Command, advances: &[Duration]) -> RgbaImage {
+    preview::render_frame(command, WIDTH, HEIGHT, start_time(), advances).unwrap()
+}
+
+/// Compare `frame` to the reference `name`, or write it as the reference when blessing
+fn check(name: &str, frame: RgbaImage) {
+    let reference_path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+    if std::env::var_os("BLESS").is_some() {
+        frame.save(&reference_path).unwrap();
+        return;
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|error| {
+            panic!(
+                "no reference {path}, run BLESS=1 cargo test to create it: {error}",
+                path = reference_path.display()
+            )
+        })
+        .to_rgba8();
+    assert_eq!(reference.dimensions(), frame.dimensions(), "size of {name}");
+
+    let (mut differing, mut max_difference) = (0, 0);
+    for (expected, actual) in reference.pixels().zip(frame.pixels()) {
+        let difference = expected
+            .0
+            .iter()
+            .zip(actual.0)
+            .map(|(expected, actual)| expected.abs_diff(actual))
+            .max()
+            .unwrap();
+        if difference > TOLERANCE {
+            differing += 1;
+        }
+        max_difference = max_difference.max(difference);
+    }
+    if differing > 0 {
+        let actual_path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.png"));
+        frame.save(&actual_path).unwrap();
+        panic!(
+            "{differing} pixels of {name} differ from the reference by up to {max_difference}, \
+             the frame was written to {path}",
+            path = actual_path.display()
+        );
+    }
+}
+
+#[test]
+fn static_image_fit() {
+    check("static-image-fit", render(static_image(Scaling::Fit), &[]));
+}
+
+#[test]
+fn static_image_fill() {
+    check(
+        "static-image-fill",
+        render(static_image(Scaling::Fill), &[]),
+    );
+}
+
+#[test]
+fn static_image_center() {
+    check(
+        "static-image-center",
+        render(static_image(Scaling::Center), &[]),
+    );
+}
+
+#[test]
+fn static_images_are_shown_upright() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    for orientation in [3, 6, 8] {
+        // The image crate ignores the orientation, the pixels as stored turned by hand are the
+        // reference
+        let path = data(&format!("orientation-{orientation}.jpg"));
+        let upright = dir.join(format!("upright-{orientation}.png"));
+        let stored = image::open(&path).unwrap();
+        match orientation {
+            3 => stored.rotate180(),
+            6 => stored.rotate90(),
+            _ => stored.rotate270(),
+        }
+        .save(&upright)
+        .unwrap();
+
+        for scaling in [Scaling::Fit, Scaling::Fill] {
+            let frame = render(static_image_at(path.clone(), scaling), &[]);
+            let reference = render(static_image_at(upright.clone(), scaling), &[]);
+            assert!(frame == reference, "orientation {orientation}, {scaling:?}");
+        }
+    }
+}
+
+/// A subtle 16 bit sky gradient, which spans only a few 8 bit values from left to right
+fn sky_gradient() -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+    ImageBuffer::from_fn(WIDTH, HEIGHT, |x, y| {
+        let across = x as f32 / (WIDTH - 1) as f32;
+        let down = y as f32 / (HEIGHT - 1) as f32;
+        Rgb([
+            (20_000.0 + 1_500.0 * across) as u16,
+            (30_000.0 + 1_000.0 * across + 500.0 * down) as u16,
+            (50_000.0 + 2_000.0 * across) as u16,
+        ])
+    })
+}
+
+#[test]
+fn gradient_16_bit() {
+    let temp = tempfile::tempdir().unwrap();
+    let dir = temp.path();
+    let path = dir.join("sky.png");
+    sky_gradient().save(&path).unwrap();
+    let rounded = render(static_image_at(path, Scaling::Stretch), &[]);
+    check("gradient-16-bit", rounded.clone());
+
+    let dithered = HighDepth {
+        dither: true,
+        ..HighDepth::default()
+    };
+    let dithered = decode::reduce_depth(DynamicImage::ImageRgb16(sky_gradient()), dithered);
+    let dithered = dithered.to_rgba8();
+    check("gradient-16-bit-dithered", dithered.clone());
+
+    // Averaged over the dither pattern the dithered frame is closer to the gradient
+    let error = |frame: &RgbaImage| {
+        let source = sky_gradient();
+        let mut error = 0.0;
+        for (cell_x, cell_y) in (0..HEIGHT / 4).flat_map(|y| (0..WIDTH / 4).map(move |x| (x, y))) {
+            for channel in 0..3 {
+                let mean = |value: &dyn Fn(u32, u32) -> f64| {
+                    (0..16)
+                        .map(|idx| value(cell_x * 4 + idx % 4, cell_y * 4 + idx / 4))
+                        .sum::<f64>()
+                        / 16.0
+                };
+                let shown = mean(&|x, y| frame[(x, y)][channel] as f64);
+                let exact = mean(&|x, y| source[(x, y)][channel] as f64 / 257.0);
+                error += (shown - exact).abs();
+            }
+        }
+        error
+    };
+    assert!(error(&dithered) < error(&rounded) / 2.0);
+}
+
+#[test]
+fn clock_image() {
+    let dir = clock_dir();
+    check("clock-image", render(clock(&dir), &[]));
+}
+
+#[test]
+fn clock_image_after_a_step() {
+    let dir = clock_dir();
+    check(
+        "clock-image-step",
+        render(clock(&dir), &[Duration::seconds(1)]),
+    );
+}
+
+#[test]
+fn clock_image_tinted() {
+    let dir = clock_dir();
+    let mut command = clock(&dir);
+    if let Command::ClockImage { clock_color, .. } = &mut command {
+        *clock_color = Some("FF8040".to_string());
+    }
+    check("clock-image-tinted", render(command, &[]));
+}
+
+#[test]
+fn clock_image_rainbow() {
+    let dir = clock_dir();
+    let mut command = clock(&dir);
+    if let Command::ClockImage { clock_color, .. } = &mut command {
+        *clock_color = Some("RAINBOW".to_string());
+    }
+    check("clock-image-rainbow", render(command, &[]));
+}
+
+#[test]
+fn clock_image_mask() {
+    let dir = clock_dir();
+    let mut command = clock(&dir);
+    if let Command::ClockImage {
+        clock_color,
+        colorize,
+        mask_source,
+        underlay,
+        margin_color,
+        ..
+    } = &mut command
+    {
+        *clock_color = Some("40C0FF".to_string());
+        *colorize = ColorizeMode::Mask;
+        *mask_source = MaskSource::Luminance;
+        *underlay = Some(data("input.png"));
+        *margin_color = None;
+    }
+    check("clock-image-mask", render(command, &[]));
+}
+
+#[test]
+fn clock_image_interpolated() {
+    let dir = clock_dir();
+    let mut command = clock(&dir);
+    if let Command::ClockImage { interpolate, .. } = &mut command {
+        *interpolate = Some(0.5);
+    }
+    // Halfway through the fade from second 56 to 57
+    check(
+        "clock-image-interpolated",
+        render(command, &[Duration::milliseconds(1250)]),
+    );
+}
+
+#[test]
+fn text_overlay() {
+    check("text-overlay", render(text(), &[]));
+}
+
+#[test]
+fn layers() {
+    let command = Command::Layer {
+        layers: vec![static_image(Scaling::Fill), text()],
+    };
+    check("layers", render(command, &[]));
+}
+
+#[test]
+fn layers_redraw_only_the_changed_text() {
+    let command = Command::Layer {
+        layers: vec![static_image(Scaling::Fill), text()],
+    };
+    let clock = Arc::new(MockClock::new(start_time()));
+    let mut renderer = command
+        .clone()
+        .into_renderer_with_clock(WIDTH, HEIGHT, None, clock.clone())
+        .unwrap();
+    let mut frame = vec![0; WIDTH as usize * HEIGHT as usize * 4];
+    assert_eq!(
+        renderer.render_damage(&mut frame, WIDTH, HEIGHT).unwrap(),
+        Damage::Full
+    );
+
+    clock.advance(Duration::minutes(1));
+    let damage = renderer.render_damage(&mut frame, WIDTH, HEIGHT).unwrap();
+    let Damage::Rect(rect) = damage else {
+        panic!("the whole frame was drawn again: {damage:?}");
+    };
+    assert!(rect.width < WIDTH && rect.height < HEIGHT, "{rect:?}");
+
+    // Looks like the frame drawn from scratch
+    let mut fresh = vec![0; frame.len()];
+    let clock = Arc::new(MockClock::new(start_time() + Duration::minutes(1)));
+    command
+        .into_renderer_with_clock(WIDTH, HEIGHT, None, clock)
+        .unwrap()
+        .render(&mut fresh, WIDTH, HEIGHT)
+        .unwrap();
+    assert!(frame == fresh);
+}
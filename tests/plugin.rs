@@ -0,0 +1,111 @@
+//! Loads the example plugin in `plugins/color-cycler`, which is built first
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command as Process,
+    sync::{Arc, OnceLock},
+};
+
+use chrono::{Duration, Local, TimeZone};
+use desktop_background::{clock::MockClock, render::BackgroundRenderer, Command};
+
+const WIDTH: u32 = 16;
+const HEIGHT: u32 = 9;
+/// A time at the start of a minute, where the hue of the color cycler is 0
+const START_MILLIS: i64 = 1_700_000_040_000;
+
+/// Build the color cycler once and return the path of its library
+fn color_cycler() -> &'static Path {
+    static LIBRARY: OnceLock<PathBuf> = OnceLock::new();
+    LIBRARY.get_or_init(|| {
+        let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("plugins");
+        let status = Process::new(std::env::var_os("CARGO").unwrap_or("cargo".into()))
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("plugins/color-cycler/Cargo.toml"))
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "building the color cycler failed");
+        target_dir.join("debug/libcolor_cycler.so")
+    })
+}
+
+fn plugin(path: &Path, params: &str, clock: &Arc<MockClock>) -> anyhow::Result<BackgroundRenderer> {
+    Command::Plugin {
+        path: path.to_path_buf(),
+        params: params.to_string(),
+    }
+    .into_renderer_with_clock(WIDTH, HEIGHT, None, clock.clone())
+}
+
+fn clock() -> Arc<MockClock> {
+    Arc::new(MockClock::new(
+        Local.timestamp_millis_opt(START_MILLIS).unwrap(),
+    ))
+}
+
+fn assert_filled(frame: &[u8], color: [u8; 4]) {
+    assert!(
+        frame.chunks_exact(4).all(|pixel| pixel == color),
+        "expected every pixel to be {color:?}, the first one is {first:?}",
+        first = &frame[..4]
+    );
+}
+
+#[test]
+fn renders_with_the_example_plugin() {
+    let clock = clock();
+    let mut renderer = plugin(color_cycler(), "period=60", &clock).unwrap();
+    let mut frame = vec![0; WIDTH as usize * HEIGHT as usize * 4];
+
+    assert!(renderer.render(&mut frame, WIDTH, HEIGHT).unwrap());
+    assert_filled(&frame, [255, 0, 0, 255]);
+    assert!(!renderer.render(&mut frame, WIDTH, HEIGHT).unwrap());
+
+    // A sixth of the period later the hue turned to yellow
+    clock.advance(Duration::seconds(10));
+    assert!(renderer.render(&mut frame, WIDTH, HEIGHT).unwrap());
+    assert_filled(&frame, [255, 255, 0, 255]);
+
+    // The plugin's frame is copied again after a redraw although it didn't change
+    renderer.redraw();
+    frame.fill(0);
+    assert!(renderer.render(&mut frame, WIDTH, HEIGHT).unwrap());
+    assert_filled(&frame, [255, 255, 0, 255]);
+    assert!(renderer.status().contains("state: running"));
+}
+
+#[test]
+fn reports_plugins_failing_to_start() {
+    let error = plugin(color_cycler(), "period=soon", &clock())
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains("failed to start"),
+        "unexpected error: {error:#}"
+    );
+}
+
+#[test]
+fn refuses_libraries_without_the_plugin_interface() {
+    let error = plugin(Path::new("libc.so.6"), "", &clock()).err().unwrap();
+    assert!(
+        error
+            .to_string()
+            .contains("the symbol desktop_background_abi_version is missing"),
+        "unexpected error: {error:#}"
+    );
+}
+
+#[test]
+fn reports_missing_plugins() {
+    let error = plugin(Path::new("/nonexistent/plugin.so"), "", &clock())
+        .err()
+        .unwrap();
+    assert!(
+        error.to_string().contains("could not load the plugin"),
+        "unexpected error: {error:#}"
+    );
+}
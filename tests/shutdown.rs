@@ -0,0 +1,73 @@
+//! A daemon shuts down on SIGTERM, removing its socket file and exiting with 0
+//!
+//! The test binary runs itself as the daemon, which blocks the signals for the whole process and
+//! can't share it with other tests.
+
+use std::{
+    path::Path,
+    process::Command as Process,
+    thread,
+    time::{Duration, Instant},
+};
+
+use desktop_background::{command::parse_batch, Command, Daemon};
+
+/// Set for the child process playing the daemon, to the socket it listens on
+const DAEMON_SOCKET: &str = "DESKTOP_BACKGROUND_TEST_DAEMON_SOCKET";
+/// How long the daemon may take to start listening and to exit after the signal
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() {
+    match std::env::var_os(DAEMON_SOCKET) {
+        Some(socket) => run_daemon(Path::new(&socket)),
+        None => shuts_down_on_sigterm(),
+    }
+}
+
+fn run_daemon(socket: &Path) {
+    let Command::Start(start) = parse_batch("start 64 48 shutdown-test --no-restore")
+        .unwrap()
+        .remove(0)
+        .command
+    else {
+        unreachable!();
+    };
+    Daemon::bind(start, socket.to_str().unwrap())
+        .unwrap()
+        .wait_for_shutdown()
+        .unwrap();
+}
+
+/// Wait until `done` is true, failing the test after [`TIMEOUT`]
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !done() {
+        assert!(started.elapsed() < TIMEOUT, "{what} within {TIMEOUT:?}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn shuts_down_on_sigterm() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("desktop-background.sock");
+
+    let mut daemon = Process::new(std::env::current_exe().unwrap())
+        .env(DAEMON_SOCKET, &socket)
+        .spawn()
+        .unwrap();
+    wait_until("the daemon listens", || socket.exists());
+
+    // SAFETY: the pid is the one of the child, which wasn't waited for yet
+    assert_eq!(
+        unsafe { libc::kill(daemon.id() as libc::pid_t, libc::SIGTERM) },
+        0
+    );
+    let mut status = None;
+    wait_until("the daemon exits", || {
+        status = daemon.try_wait().unwrap();
+        status.is_some()
+    });
+    assert_eq!(status.unwrap().code(), Some(0));
+    assert!(!socket.exists(), "{} is left behind", socket.display());
+    println!("shuts_down_on_sigterm ... ok");
+}
@@ -0,0 +1,121 @@
+//! Claims the socket of a starting desktop program from nothing, from a daemon which died and from
+//! a running one, played by a listener on a thread answering for a child process
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command as Process, Stdio},
+    thread,
+};
+
+use desktop_background::{
+    ipc::{self, Claimed, Reply},
+    Command, Request,
+};
+use interprocess::local_socket::LocalSocketListener;
+use tempfile::TempDir;
+
+/// A socket path in a directory of its own, which is removed with the guard
+fn socket() -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("desktop-background.sock");
+    (dir, path)
+}
+
+fn name(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+/// Listen on `socket` like a daemon running as a child process, answering pings for it until a
+/// stop command kills the child and removes the socket file
+fn spawn_daemon(socket: &str) -> u32 {
+    let mut child = Process::new("sleep")
+        .arg("30")
+        .stdin(Stdio::null())
+        .spawn()
+        .unwrap();
+    let pid = child.id();
+    let listener = LocalSocketListener::bind(socket).unwrap();
+    let socket = socket.to_string();
+    thread::spawn(move || {
+        loop {
+            let mut stream = listener.accept().unwrap();
+            let request: Request = ipc::read_message(&mut stream).unwrap();
+            let reply: Reply = match request.command {
+                Command::Ping => Ok(format!("pid: {pid}\nversion: 0.1.0\nuptime: 90")),
+                Command::Stop { .. } => {
+                    // Like a daemon, which closes its socket before it exits
+                    drop(listener);
+                    child.kill().unwrap();
+                    child.wait().unwrap();
+                    if !socket.starts_with('@') {
+                        std::fs::remove_file(&socket).unwrap();
+                    }
+                    ipc::write_message(&mut stream, &Reply::Ok(String::new())).unwrap();
+                    return;
+                }
+                command => Err(format!("unexpected command {command:?}")),
+            };
+            ipc::write_message(&mut stream, &reply).unwrap();
+        }
+    });
+    pid
+}
+
+#[test]
+fn claims_a_fresh_socket() {
+    let (_dir, path) = socket();
+    assert_eq!(
+        ipc::claim_socket(name(&path), false).unwrap(),
+        Claimed::Fresh
+    );
+}
+
+#[test]
+fn removes_a_stale_socket() {
+    let (_dir, path) = socket();
+    // Dropping the listener leaves the file, like a daemon which was killed
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    assert_eq!(
+        ipc::claim_socket(name(&path), false).unwrap(),
+        Claimed::Stale
+    );
+    assert!(!path.exists());
+}
+
+#[test]
+fn names_or_replaces_a_running_daemon() {
+    let (_dir, path) = socket();
+    let pid = spawn_daemon(name(&path));
+
+    let error = ipc::claim_socket(name(&path), false)
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains(&format!(
+            "already running on {path} (pid {pid}, started ",
+            path = name(&path)
+        )),
+        "{error}"
+    );
+    assert!(path.exists());
+
+    assert_eq!(
+        ipc::claim_socket(name(&path), true).unwrap(),
+        Claimed::Replaced
+    );
+    assert!(!path.exists());
+}
+
+#[test]
+fn claims_abstract_sockets() {
+    let socket = format!("@desktop-background-test-{pid}", pid = std::process::id());
+    assert_eq!(ipc::claim_socket(&socket, false).unwrap(), Claimed::Fresh);
+
+    let pid = spawn_daemon(&socket);
+    let error = ipc::claim_socket(&socket, false).unwrap_err().to_string();
+    assert!(error.contains(&format!("(pid {pid}, ")), "{error}");
+    assert_eq!(ipc::claim_socket(&socket, true).unwrap(), Claimed::Replaced);
+    assert_eq!(ipc::claim_socket(&socket, false).unwrap(), Claimed::Fresh);
+}